@@ -35,13 +35,12 @@
 //! Run with: `cargo run --release --bin benchmark`
 
 use std::fs;
-use std::panic::{self, AssertUnwindSafe};
 use std::time::Instant;
 
-use hdmifiletransporter::options::AlgoFrame;
+use hdmifiletransporter::options::{AlgoFrame, Binarization};
 use hdmifiletransporter::{
     content_cell_xy, create_starting_frame, data_to_frames, frame_capacity, frames_to_data,
-    register_frame, ExtractOptions, InjectOptions, VideoFrame, HEADER_BITS,
+    register_frame, Compression, ExtractOptions, InjectOptions, VideoFrame, HEADER_BITS,
 };
 use opencv::core::{copy_make_border, Mat, Scalar, Size, Vec3b, Vector, BORDER_CONSTANT};
 use opencv::imgcodecs::{imdecode, imencode, IMREAD_COLOR, IMWRITE_JPEG_QUALITY};
@@ -273,6 +272,28 @@ fn synthetic_payload(len: usize) -> Vec<u8> {
     out
 }
 
+/// Deterministic CSV log (timestamp, level, sensor, reading), the kind of file
+/// `--compress` is for. The random `synthetic_payload` does not compress at all.
+fn synthetic_log(len: usize) -> Vec<u8> {
+    const LEVELS: [&str; 3] = ["INFO", "INFO", "WARN"];
+    let mut rng = Rng::new(0x5EED_1065);
+    let mut out = Vec::with_capacity(len + 64);
+    let mut timestamp = 1_700_000_000_000u64;
+    while out.len() < len {
+        timestamp += rng.next_u64() % 250;
+        let line = format!(
+            "{},{},sensor-{:02},{:.2}\n",
+            timestamp,
+            LEVELS[(rng.next_u64() % 3) as usize],
+            rng.next_u64() % 16,
+            20.0 + rng.next_f64() * 5.0
+        );
+        out.extend_from_slice(line.as_bytes());
+    }
+    out.truncate(len);
+    out
+}
+
 // --- Result records (resilience + speed matrix) -------------------------------
 
 struct ProfileResult {
@@ -331,12 +352,20 @@ fn algo_str(algo: AlgoFrame) -> &'static str {
 fn inject_options(width: u16, height: u16, size: u8, algo: AlgoFrame) -> InjectOptions {
     InjectOptions {
         file_path: String::new(),
+        extra_file_paths: Vec::new(),
         output_video_file: String::new(),
         fps: FPS as u8,
         width,
         height,
         size,
         algo,
+        fec: 0,
+        fountain: None,
+        pages: None,
+        compression: Compression::None,
+        session: 0,
+        lattice: false,
+        raw_output: None,
         show_progress: false,
     }
 }
@@ -346,10 +375,13 @@ fn extract_options(width: u16, height: u16, size: u8, algo: AlgoFrame) -> Extrac
         video_file_path: String::new(),
         extracted_file_path: String::new(),
         fps: FPS as u8,
-        width,
-        height,
-        size,
-        algo,
+        width: Some(width),
+        height: Some(height),
+        size: Some(size),
+        algo: Some(algo),
+        binarization: Binarization::Fixed,
+        session: None,
+        raw_input: None,
         show_progress: false,
     }
 }
@@ -357,7 +389,7 @@ fn extract_options(width: u16, height: u16, size: u8, algo: AlgoFrame) -> Extrac
 // --- One config (resilience + speed) ------------------------------------------
 
 fn bytes_per_frame(width: u16, height: u16, size: u8, algo: AlgoFrame) -> usize {
-    let capacity = frame_capacity(width, height, size);
+    let capacity = frame_capacity(width, height, size, false);
     match algo {
         AlgoFrame::RGB => capacity * 3,
         AlgoFrame::BW => capacity / 8,
@@ -381,8 +413,8 @@ fn run_config(
     let io = inject_options(width, height, size, algo);
 
     let t_enc = Instant::now();
-    let mut frames: Vec<VideoFrame> = vec![create_starting_frame(payload.len() as u64, &io)];
-    frames.extend(data_to_frames(&io, payload.to_vec()));
+    let mut frames: Vec<VideoFrame> = vec![create_starting_frame(payload, &io).ok()?];
+    frames.extend(data_to_frames(&io, payload.to_vec()).ok()?);
     let encode_ms = t_enc.elapsed().as_secs_f64() * 1000.0;
 
     let frame_count = frames.len();
@@ -407,9 +439,10 @@ fn run_config(
         let mut registered: Vec<VideoFrame> = Vec::with_capacity(frame_count);
         for f in &frames {
             let maybe = match &profile.perturb {
-                None => register_frame(&f.image, width, height, size),
+                None => register_frame(&f.to_mat().expect("frame to image"), width, height, size),
                 Some(p) => {
-                    let perturbed = simulate_capture(&f.image, p, &mut rng);
+                    let perturbed =
+                        simulate_capture(&f.to_mat().expect("frame to image"), p, &mut rng);
                     register_frame(&perturbed, width, height, size)
                 }
             };
@@ -419,7 +452,7 @@ fn run_config(
         }
         let registered_rate = registered.len() as f64 / frame_count as f64;
 
-        let decoded = panic::catch_unwind(AssertUnwindSafe(|| frames_to_data(&eo, registered)));
+        let decoded = frames_to_data(&eo, registered);
         let decode_ms = t_dec.elapsed().as_secs_f64() * 1000.0;
         let pass = matches!(&decoded, Ok(bytes) if bytes.as_slice() == payload);
 
@@ -528,7 +561,8 @@ fn measure_variance(p: &Perturb, levels: u32, rng: &mut Rng) -> f64 {
         noise_stddev: p.noise_stddev,
         jpeg_quality: p.jpeg_quality,
     };
-    let distorted_mat = simulate_capture(&frame.image, &value_only, rng);
+    let distorted_mat =
+        simulate_capture(&frame.to_mat().expect("frame to image"), &value_only, rng);
     let distorted = VideoFrame::from(distorted_mat, size).expect("same-size frame");
 
     let mut correct = 0u64;
@@ -911,6 +945,11 @@ const PLAN_FILE_SIZES: [(&str, u64); 3] =
 /// model, so higher fps is always faster - capped only by what the real
 /// display/capture path can carry without dropping or tearing frames.
 const PLAN_FPS: [u32; 3] = [30, 60, 120];
+/// Codecs (`--compress`) whose frame counts the planner reports next to the
+/// raw ones.
+const PLAN_CODECS: [Compression; 2] = [Compression::Deflate, Compression::Zstd];
+/// Size of the synthetic CSV log the codecs are measured on.
+const PLAN_LOG_BYTES: usize = 1 << 20;
 
 /// Bits carried by one channel symbol for a power-of-two `levels`.
 fn bits_per_channel(levels: u32) -> u32 {
@@ -940,15 +979,20 @@ fn encode_level_frame(
 ) -> VideoFrame {
     let b = bits_per_channel(levels);
     let spacing = 255.0 / (levels as f64 - 1.0);
-    let capacity = frame_capacity(width, height, size);
+    let capacity = frame_capacity(width, height, size, false);
 
+    let algo = if luma {
+        AlgoFrame::Brightness(levels)
+    } else {
+        AlgoFrame::Quantized(levels)
+    };
     let mut frame = VideoFrame::new(width, height);
-    frame.write_calibration(size);
+    frame.write_calibration(size, algo);
     // Deterministically blacken the reserved header cells (we score the payload
     // by ground truth, so no real header is needed, but the cells must not be
     // uninitialised memory).
     for i in 0..HEADER_BITS {
-        let (x, y) = content_cell_xy(i, width, size);
+        let (x, y) = content_cell_xy(i, width, height, size, false);
         frame.write(0, 0, 0, x, y, size);
     }
 
@@ -969,7 +1013,7 @@ fn encode_level_frame(
     };
 
     for cell in 0..capacity {
-        let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
+        let (x, y) = content_cell_xy(HEADER_BITS + cell, width, height, size, false);
         if luma {
             let v = (next_symbol(b) as f64 * spacing).round() as u8;
             frame.write(v, v, v, x, y, size);
@@ -997,12 +1041,12 @@ fn decode_level_frame(
 ) -> Vec<u8> {
     let b = bits_per_channel(levels);
     let spacing = 255.0 / (levels as f64 - 1.0);
-    let capacity = frame_capacity(width, height, size);
+    let capacity = frame_capacity(width, height, size, false);
     let needed_bits = n_bytes * 8;
 
     let mut bits: Vec<u8> = Vec::with_capacity(needed_bits + 24);
     'cells: for cell in 0..capacity {
-        let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
+        let (x, y) = content_cell_xy(HEADER_BITS + cell, width, height, size, false);
         let (r, g, bl) = sample_cell_center(frame, x, y, size);
         let (samples, count) = if luma {
             ([(r + g + bl) / 3.0, 0.0, 0.0], 1usize)
@@ -1083,7 +1127,7 @@ fn run_large_file_planner() -> Vec<PlanPoint> {
     let mut out = Vec::new();
 
     for &size in PLAN_SIZES.iter() {
-        let capacity = frame_capacity(width, height, size);
+        let capacity = frame_capacity(width, height, size, false);
         if capacity == 0 {
             continue;
         }
@@ -1118,7 +1162,11 @@ fn run_large_file_planner() -> Vec<PlanPoint> {
                         let frame = encode_level_frame(&payload, levels, size, width, height, luma);
                         encode_total += t0.elapsed().as_secs_f64() * 1000.0;
 
-                        let captured = simulate_capture(&frame.image, &perturb, &mut rng);
+                        let captured = simulate_capture(
+                            &frame.to_mat().expect("frame to image"),
+                            &perturb,
+                            &mut rng,
+                        );
 
                         let t1 = Instant::now();
                         let decoded = register_frame(&captured, width, height, size).map(|reg| {
@@ -1200,7 +1248,20 @@ fn fmt_duration(secs: f64) -> String {
     }
 }
 
-fn write_planner_markdown(points: &[PlanPoint]) -> String {
+/// Compressed size over raw size of the synthetic log, per codec.
+fn measure_compression() -> Vec<(Compression, f64)> {
+    let log = synthetic_log(PLAN_LOG_BYTES);
+    PLAN_CODECS
+        .iter()
+        .map(|&codec| {
+            let ratio = codec.compress(&log).len() as f64 / log.len() as f64;
+            println!("  planner compression={} ratio={:.3}", codec, ratio);
+            (codec, ratio)
+        })
+        .collect()
+}
+
+fn write_planner_markdown(points: &[PlanPoint], compression: &[(Compression, f64)]) -> String {
     let (width, height) = PLAN_RES;
     let mut out = String::new();
     out.push_str("# Large-file transfer planner\n\n");
@@ -1299,6 +1360,7 @@ fn write_planner_markdown(points: &[PlanPoint]) -> String {
                 "CLI: `--algo {} --levels {} --size {} --width {} --height {} --fps 60`.\n\n",
                 cli_algo, p.levels, p.size, width, height
             ));
+            write_compression_table(&mut out, p, compression);
         }
         None => {
             out.push_str(&format!(
@@ -1343,6 +1405,43 @@ fn write_planner_markdown(points: &[PlanPoint]) -> String {
     out
 }
 
+/// Raw and compressed frame counts (and time on the wire at 60 fps) of the
+/// recommended config `p`, for a file compressing like the synthetic log.
+fn write_compression_table(out: &mut String, p: &PlanPoint, compression: &[(Compression, f64)]) {
+    out.push_str("### With `--compress` (text, logs, CSV)\n\n");
+    out.push_str(&format!(
+        "Frame counts for a file that compresses like a {} MB synthetic CSV log ({}). Random or already compressed files (zip, media) do not shrink: use the raw counts.\n\n",
+        PLAN_LOG_BYTES >> 20,
+        compression
+            .iter()
+            .map(|(codec, ratio)| format!("{} to {:.1}%", codec, ratio * 100.0))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    out.push_str("| file | raw frames | raw @ 60fps |");
+    for (codec, _) in compression {
+        out.push_str(&format!(" {} frames | {} @ 60fps |", codec, codec));
+    }
+    out.push_str("\n|------|------------|-------------|");
+    for _ in compression {
+        out.push_str("--------|--------|");
+    }
+    out.push('\n');
+    let row = |bytes: u64| {
+        let n = bytes.div_ceil(p.bytes_per_frame as u64);
+        let on_wire = expected_passes(p.frame_survival, n) * n as f64 / 60.0;
+        format!(" {} | {} |", n, fmt_duration(on_wire))
+    };
+    for (label, bytes) in PLAN_FILE_SIZES.iter() {
+        out.push_str(&format!("| {} |{}", label, row(*bytes)));
+        for (_, ratio) in compression {
+            out.push_str(&row((*bytes as f64 * ratio).ceil() as u64));
+        }
+        out.push('\n');
+    }
+    out.push('\n');
+}
+
 fn write_planner_csv(points: &[PlanPoint]) -> String {
     let mut out = String::new();
     out.push_str(
@@ -1420,7 +1519,8 @@ fn run_matrix(payload: &[u8]) {
 fn run_planner() {
     println!("== Large-file transfer planner (N levels per channel) ==");
     let points = run_large_file_planner();
-    let md = write_planner_markdown(&points);
+    let compression = measure_compression();
+    let md = write_planner_markdown(&points, &compression);
     fs::write("planner_results.md", &md).expect("write planner_results.md");
     fs::write("planner_results.csv", write_planner_csv(&points))
         .expect("write planner_results.csv");
@@ -1436,18 +1536,6 @@ fn main() {
     let run_matrix_study = mode == "all" || mode == "matrix";
     let run_planner_study = mode == "all" || mode == "planner";
 
-    // Silence panic backtraces from the intentional `frames_to_data` failures
-    // caught inside `run_config`; restore the default hook afterwards.
-    let previous_hook = panic::take_hook();
-    if std::env::var("BENCH_DEBUG_PANICS").is_ok() {
-        // Debug aid: surface the real panic location/message instead of hiding it.
-        panic::set_hook(Box::new(|info| {
-            eprintln!("[panic] {}", info);
-        }));
-    } else {
-        panic::set_hook(Box::new(|_| {}));
-    }
-
     if run_matrix_study {
         let payload = match std::env::args().nth(1) {
             Some(path) if path != "matrix" && path != "planner" && path != "all" => {
@@ -1465,6 +1553,4 @@ fn main() {
     if run_planner_study {
         run_planner();
     }
-
-    panic::set_hook(previous_hook);
 }
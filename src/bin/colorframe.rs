@@ -1,4 +1,6 @@
-use hdmifiletransporter::{frames_to_video, options::AlgoFrame, InjectOptions, VideoFrame};
+use hdmifiletransporter::{
+    frames_to_video, options::AlgoFrame, Compression, InjectOptions, VideoFrame,
+};
 
 fn main() {
     let size = 1 as u8;
@@ -76,12 +78,20 @@ fn main() {
         InjectOptions {
             algo: AlgoFrame::RGB,
             file_path: "not_used".to_string(),
+            extra_file_paths: Vec::new(),
+            fec: 0,
+            fountain: None,
             fps: 30,
             height: height,
             width: width,
             output_video_file: "outputs/color_video.mkv".to_string(),
+            pages: None,
+            compression: Compression::None,
+            session: 0,
             show_progress: true,
             size: 1,
+            lattice: false,
+            raw_output: None,
         },
         frames,
     )
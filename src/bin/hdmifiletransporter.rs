@@ -17,7 +17,10 @@ fn main() {
                 std::process::exit(1);
             }
         }
-        Err(error) => panic!("{:?}", error),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
     };
     std::process::exit(0);
 }
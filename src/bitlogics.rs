@@ -72,6 +72,54 @@ pub fn value_to_symbol(value: u8, levels: u32) -> u32 {
     symbol.clamp(0.0, (levels - 1) as f64) as u32
 }
 
+/// Smallest gap between two neighbouring measured ramp levels that is still
+/// trusted. Anything closer (or out of order) means the ramp was not read back
+/// properly, e.g. a misregistered frame, so the even spacing is used instead.
+const MIN_RAMP_STEP: f32 = 2.0;
+
+/// Decision thresholds that map a measured 8-bit value back to one of `levels`
+/// symbols. Evenly spaced thresholds reproduce [`value_to_symbol`]; thresholds
+/// derived from the calibration ramp follow whatever range squeeze, gamma or
+/// contrast drift the capture applied to that frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelThresholds {
+    /// `levels - 1` ascending boundaries: a value at or above boundary `i` maps
+    /// to at least symbol `i + 1`.
+    bounds: Vec<f32>,
+}
+
+impl LevelThresholds {
+    /// Thresholds at the evenly spaced decision boundaries of
+    /// [`value_to_symbol`], so both decode identically.
+    pub fn even(levels: u32) -> LevelThresholds {
+        let bounds = (1..levels.max(1))
+            .map(|symbol| {
+                let first = (0..=255u8)
+                    .find(|&value| value_to_symbol(value, levels) >= symbol)
+                    .map_or(256.0, f32::from);
+                first - 0.5
+            })
+            .collect();
+        LevelThresholds { bounds }
+    }
+
+    /// Thresholds half-way between the values measured for each level, lowest
+    /// level first. Returns `None` unless the measurements are strictly
+    /// increasing by at least `MIN_RAMP_STEP`.
+    pub fn from_measured(measured: &[f32]) -> Option<LevelThresholds> {
+        if measured.len() < 2 || measured.windows(2).any(|w| w[1] - w[0] < MIN_RAMP_STEP) {
+            return None;
+        }
+        let bounds = measured.windows(2).map(|w| (w[0] + w[1]) / 2.0).collect();
+        Some(LevelThresholds { bounds })
+    }
+
+    /// The symbol whose level is closest to `value`.
+    pub fn symbol(&self, value: u8) -> u32 {
+        self.bounds.partition_point(|&bound| bound <= value as f32) as u32
+    }
+}
+
 /// Get a byte from a list of bit
 pub fn get_byte_from_bits(bits: [bool; 8]) -> u8 {
     let mut result: u8 = 0;
@@ -233,6 +281,41 @@ mod injectionlogics_tests {
         }
     }
 
+    #[test]
+    fn test_even_thresholds_match_value_to_symbol() {
+        for levels in [2u32, 4, 8, 16, 256] {
+            let thresholds = LevelThresholds::even(levels);
+            for value in 0..=255u8 {
+                assert_eq!(
+                    thresholds.symbol(value),
+                    value_to_symbol(value, levels),
+                    "levels {levels}, value {value}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_measured_thresholds_follow_limited_range() {
+        // Four levels captured in limited range (16..235) instead of 0..255.
+        let thresholds = LevelThresholds::from_measured(&[16.0, 89.0, 162.0, 235.0]).unwrap();
+        assert_eq!(thresholds.symbol(16), 0);
+        assert_eq!(thresholds.symbol(52), 0);
+        assert_eq!(thresholds.symbol(53), 1);
+        assert_eq!(thresholds.symbol(162), 2);
+        assert_eq!(thresholds.symbol(235), 3);
+        // Even spacing misreads a slightly dark top level; the ramp does not.
+        assert_eq!(value_to_symbol(200, 4), 2);
+        assert_eq!(thresholds.symbol(200), 3);
+    }
+
+    #[test]
+    fn test_measured_thresholds_reject_unordered_ramp() {
+        assert!(LevelThresholds::from_measured(&[0.0, 120.0, 119.0, 255.0]).is_none());
+        assert!(LevelThresholds::from_measured(&[0.0, 1.0]).is_none());
+        assert!(LevelThresholds::from_measured(&[128.0]).is_none());
+    }
+
     #[test]
     fn test_get_bytes_from_bits_1() {
         // 155 = 10011011
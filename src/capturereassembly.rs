@@ -0,0 +1,196 @@
+//! Reassembling a file from a capture read one frame at a time, shared by
+//! every input: a video file or capture device, a directory of images, or raw
+//! frames on stdin, with or without OpenCV.
+//!
+//! The input only hands over frames at canonical pixels: OpenCV registers
+//! captured images first, while frames written by `inject` (PNG frames, raw
+//! frames) are decoded as they are. Everything else is done here: resuming
+//! from the saved state, the live page coverage, and the state, missing page
+//! and timeline sidecars of an incomplete transfer.
+
+use indicatif::ProgressBar;
+
+use crate::error::HdmiError;
+use crate::extractionlogics::{PushOutcome, Reassembler};
+use crate::filemetadata::ExtractedFile;
+use crate::instructionlogics::EncodingConfig;
+use crate::options::ExtractOptions;
+use crate::pagereport::{format_page_ranges, missing_report_path};
+use crate::timeline::{timeline_report_path, CaptureTimeline, FrameOutcome};
+use crate::transferstate::{state_file_path, TransferState};
+use crate::videoframe::VideoFrame;
+
+/// Frames of a capture at canonical pixels, read one at a time.
+pub(crate) trait CapturedFrames {
+    /// The next frame at canonical pixels, or `None` once the capture is
+    /// exhausted. Frames that cannot be brought to canonical pixels are
+    /// skipped and recorded in `timeline`.
+    fn next_frame(
+        &mut self,
+        extract_options: &ExtractOptions,
+        timeline: &mut CaptureTimeline,
+    ) -> Option<VideoFrame>;
+
+    /// Configuration and session of a Start frame found while reading the
+    /// frames, for the reassembler to adopt.
+    fn take_config(&mut self) -> Option<(EncodingConfig, u32)> {
+        None
+    }
+
+    /// How the frames were brought to canonical pixels, shown with
+    /// `show_progress`, or `None` when they were read as they are.
+    fn registration_summary(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Frames already at canonical pixels, as written by `inject`, decoded without
+/// registration. With OpenCV every input is registered instead.
+#[cfg(not(feature = "opencv-backend"))]
+pub(crate) struct CanonicalFrames<I>(pub I);
+
+#[cfg(not(feature = "opencv-backend"))]
+impl<I: Iterator<Item = VideoFrame>> CapturedFrames for CanonicalFrames<I> {
+    fn next_frame(
+        &mut self,
+        _extract_options: &ExtractOptions,
+        _timeline: &mut CaptureTimeline,
+    ) -> Option<VideoFrame> {
+        self.0.next()
+    }
+}
+
+/// Reassemble the file from `frames` as they are read: each frame is decoded
+/// and dropped, keeping only the verified page bytes. Reading stops as soon as
+/// the transfer is complete. See `source_to_data` for the sidecar files.
+pub(crate) fn reassemble_capture(
+    mut frames: impl CapturedFrames,
+    extract_options: &ExtractOptions,
+) -> Result<ExtractedFile, HdmiError> {
+    let mut reassembler = Reassembler::new(extract_options);
+    let mut timeline = CaptureTimeline::new();
+    let show_progress = extract_options.show_progress;
+    let state_path = state_file_path(&extract_options.extracted_file_path);
+    if let Some(path) = &state_path {
+        match TransferState::load(path) {
+            Ok(Some((state, dropped))) => {
+                if show_progress && dropped > 0 {
+                    eprintln!(
+                        "Dropped {} corrupted pages from the saved state {:?}",
+                        dropped, path
+                    );
+                }
+                reassembler.resume(state);
+            }
+            Err(err) if show_progress => {
+                eprintln!("Ignoring the saved state {:?}: {}", path, err)
+            }
+            _ => {}
+        }
+    }
+    let coverage = ProgressBar::new(0);
+    let mut frames_read = 0u64;
+    while let Some(frame) = frames.next_frame(extract_options, &mut timeline) {
+        if let Some((config, session)) = frames.take_config() {
+            reassembler.adopt_config(config, session);
+        }
+        let outcome = reassembler.push(&frame);
+        timeline.record(FrameOutcome::of_push(outcome));
+        frames_read += 1;
+        if show_progress
+            && matches!(
+                outcome,
+                PushOutcome::Start { .. }
+                    | PushOutcome::NewPage { .. }
+                    | PushOutcome::NewSymbol { .. }
+            )
+        {
+            let expected = reassembler.expected_pages().unwrap_or(0);
+            coverage.set_length(expected);
+            coverage.set_position(reassembler.received_pages().min(expected));
+        }
+        if reassembler.is_complete() {
+            if show_progress {
+                coverage.finish_with_message("done");
+                eprintln!("Every page received after {} frames", frames_read);
+            }
+            break;
+        }
+    }
+    if show_progress {
+        eprintln!(
+            "Relevant (unique, valid) data frames: {}",
+            reassembler.received_pages()
+        );
+        eprintln!(
+            "Pages recovered by combining copies: {}",
+            reassembler.combined_pages()
+        );
+        if let Some(summary) = frames.registration_summary() {
+            eprintln!("Frames registered by binarization: {}", summary);
+        }
+    }
+
+    // Keep the verified pages of an incomplete transfer for the next run. Those
+    // of a complete one are dropped by `data_to_files`, once the file is written.
+    if let Some(path) = state_path.filter(|_| !reassembler.is_complete()) {
+        if let Some(state) = reassembler
+            .state()
+            .filter(|state| !state.pages.is_empty() || state.metadata.is_some())
+        {
+            match state.save(&path) {
+                Ok(()) if show_progress => eprintln!(
+                    "Saved {} verified pages to {:?}; run extract on another capture to resume",
+                    state.pages.len(),
+                    path
+                ),
+                Err(err) if show_progress => {
+                    eprintln!("Unable to save the state {:?}: {}", path, err)
+                }
+                _ => {}
+            }
+        }
+    }
+    // List the pages still missing, so the sender can loop a repair video with
+    // only those pages (`inject --pages`).
+    if let Some(path) = missing_report_path(&extract_options.extracted_file_path)
+        .filter(|_| !reassembler.is_complete())
+    {
+        if let Some(report) = reassembler
+            .missing_report()
+            .filter(|report| !report.missing.is_empty())
+        {
+            match report.save(&path) {
+                Ok(()) if show_progress => eprintln!(
+                    "Missing pages {} listed in {:?}; inject with --pages {:?} to render a repair video",
+                    format_page_ranges(&report.missing),
+                    path,
+                    path
+                ),
+                Err(err) if show_progress => {
+                    eprintln!("Unable to save the missing pages {:?}: {}", path, err)
+                }
+                _ => {}
+            }
+        }
+    }
+    // Tell frames the capture dropped from frames it could not decode, when
+    // something is missing or progress is asked for.
+    if let Some(path) = timeline_report_path(&extract_options.extracted_file_path)
+        .filter(|_| !reassembler.is_complete() || show_progress)
+    {
+        let expected = reassembler.expected_pages();
+        match timeline.save(&path, expected) {
+            Ok(()) if show_progress => eprintln!(
+                "Capture timeline saved to {:?}: {}",
+                path,
+                timeline.diagnosis(expected)
+            ),
+            Err(err) if show_progress => {
+                eprintln!("Unable to save the capture timeline {:?}: {}", path, err)
+            }
+            _ => {}
+        }
+    }
+    reassembler.finish_file()
+}
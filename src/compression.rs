@@ -0,0 +1,238 @@
+//! Optional compression of the transferred bytes before they are framed.
+//!
+//! Logs and CSVs shrink several times over, and every byte saved is one less to
+//! paint, so the video (and each loop of it) gets shorter. With `--compress`,
+//! `inject` streams the file through the codec and frames the compressed
+//! bytes: the Start frame's byte count and the pages are those of the
+//! compressed stream. The codec is recorded in the Start frame's
+//! [`EncodingConfig`](crate::instructionlogics::EncodingConfig), so `extract`
+//! decompresses once the pages are reassembled, without any flag. The SHA-256 stays the hash of the
+//! original file and is checked after decompression.
+
+use std::fmt;
+use std::io::{self, Read};
+
+use flate2::read::{DeflateDecoder, DeflateEncoder};
+
+use crate::error::HdmiError;
+
+/// zstd level: the library default, a good ratio at several hundred MB/s.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Codec applied to the bytes of the file before they are framed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Compression {
+    /// The bytes are framed as they are.
+    #[default]
+    None,
+    /// Raw deflate (RFC 1951) at the default level.
+    Deflate,
+    /// Zstandard, denser and faster than deflate.
+    Zstd,
+}
+
+impl Compression {
+    /// Every codec, in the order of their IDs.
+    pub const ALL: [Compression; 3] = [Self::None, Self::Deflate, Self::Zstd];
+
+    /// ID stored in the Start frame.
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Deflate => 1,
+            Self::Zstd => 2,
+        }
+    }
+
+    /// The codec of an ID stored in the Start frame, `None` for an unknown one.
+    pub(crate) fn from_id(id: u8) -> Option<Compression> {
+        Self::ALL.into_iter().find(|codec| codec.id() == id)
+    }
+
+    /// Compress `data` held in memory.
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        // Reading from a slice into a vector cannot fail.
+        self.reader(data)
+            .and_then(|mut reader| reader.read_to_end(&mut compressed))
+            .expect("compressing bytes held in memory");
+        compressed
+    }
+
+    /// Length of `data` held in memory once compressed, counted as the stream
+    /// is produced rather than from a compressed copy.
+    pub(crate) fn compressed_len(self, data: &[u8]) -> u64 {
+        self.reader(data)
+            .and_then(|mut reader| io::copy(&mut reader, &mut io::sink()))
+            .expect("compressing bytes held in memory")
+    }
+
+    /// The compressed stream of `source`, read on demand so a large file is
+    /// never held in memory. `source` itself for [`Compression::None`].
+    pub fn reader<'a>(self, source: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Self::None => Box::new(source),
+            Self::Deflate => Box::new(DeflateEncoder::new(source, flate2::Compression::default())),
+            Self::Zstd => Box::new(zstd::stream::read::Encoder::new(source, ZSTD_LEVEL)?),
+        })
+    }
+
+    /// Decompress the reassembled `data` into at most `limit` bytes, or
+    /// [`HdmiError::Decompress`] when it is not a valid stream of this codec or
+    /// inflates past `limit`, so a corrupt or hostile stream cannot exhaust
+    /// memory.
+    pub fn decompress(self, data: Vec<u8>, limit: u64) -> Result<Vec<u8>, HdmiError> {
+        let decompress_error = |source| HdmiError::Decompress {
+            codec: self,
+            source,
+        };
+        // One byte past the limit is enough to tell the stream is too long.
+        let mut decompressed = Vec::new();
+        match self {
+            Self::None => return Ok(data),
+            Self::Deflate => DeflateDecoder::new(data.as_slice())
+                .take(limit.saturating_add(1))
+                .read_to_end(&mut decompressed)
+                .map_err(decompress_error)?,
+            Self::Zstd => zstd::stream::read::Decoder::new(data.as_slice())
+                .and_then(|decoder| {
+                    decoder
+                        .take(limit.saturating_add(1))
+                        .read_to_end(&mut decompressed)
+                })
+                .map_err(decompress_error)?,
+        };
+        if decompressed.len() as u64 > limit {
+            return Err(decompress_error(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the stream inflates past the {limit} bytes announced"),
+            )));
+        }
+        Ok(decompressed)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::None => "none",
+            Self::Deflate => "deflate",
+            Self::Zstd => "zstd",
+        };
+        s.fmt(f)
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "deflate" => Ok(Self::Deflate),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(format!("Unknown compression: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod compression_tests {
+    use super::*;
+
+    fn csv(rows: usize) -> Vec<u8> {
+        (0..rows)
+            .map(|i| {
+                format!(
+                    "{},sensor-{},{}.{}\n",
+                    1_700_000_000 + i,
+                    i % 7,
+                    i % 40,
+                    i % 10
+                )
+            })
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn test_every_codec_round_trips() {
+        let data = csv(2000);
+        for codec in Compression::ALL {
+            let compressed = codec.compress(&data);
+            let limit = data.len() as u64;
+            assert_eq!(
+                codec.decompress(compressed, limit).unwrap(),
+                data,
+                "{codec}"
+            );
+        }
+        for codec in Compression::ALL {
+            assert_eq!(
+                codec.decompress(codec.compress(&[]), 0).unwrap(),
+                Vec::<u8>::new()
+            );
+        }
+    }
+
+    #[test]
+    fn test_text_shrinks() {
+        let data = csv(2000);
+        assert_eq!(Compression::None.compress(&data), data);
+        for codec in [Compression::Deflate, Compression::Zstd] {
+            assert!(codec.compress(&data).len() * 4 < data.len(), "{codec}");
+        }
+    }
+
+    #[test]
+    fn test_streamed_and_in_memory_compression_agree() {
+        let data = csv(500);
+        for codec in Compression::ALL {
+            let mut streamed = Vec::new();
+            codec
+                .reader(io::BufReader::new(data.as_slice()))
+                .unwrap()
+                .read_to_end(&mut streamed)
+                .unwrap();
+            assert_eq!(streamed, codec.compress(&data), "{codec}");
+            assert_eq!(codec.compressed_len(&data), streamed.len() as u64);
+        }
+    }
+
+    #[test]
+    fn test_garbage_does_not_decompress() {
+        let garbage = vec![0xFFu8; 64];
+        for codec in [Compression::Deflate, Compression::Zstd] {
+            assert!(matches!(
+                codec.decompress(garbage.clone(), 1 << 20),
+                Err(HdmiError::Decompress { codec: c, .. }) if c == codec
+            ));
+        }
+    }
+
+    #[test]
+    fn test_a_stream_inflating_past_the_limit_is_refused() {
+        let data = vec![0u8; 1 << 20];
+        for codec in [Compression::Deflate, Compression::Zstd] {
+            let compressed = codec.compress(&data);
+            assert!(matches!(
+                codec.decompress(compressed.clone(), data.len() as u64 - 1),
+                Err(HdmiError::Decompress { codec: c, .. }) if c == codec
+            ));
+            assert_eq!(
+                codec.decompress(compressed, data.len() as u64).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn test_ids_and_names_round_trip() {
+        for codec in Compression::ALL {
+            assert_eq!(Compression::from_id(codec.id()), Some(codec));
+            assert_eq!(codec.to_string().parse::<Compression>(), Ok(codec));
+        }
+        assert_eq!(Compression::from_id(3), None);
+        assert!("gzip".parse::<Compression>().is_err());
+    }
+}
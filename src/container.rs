@@ -0,0 +1,588 @@
+//! Several files, or a whole directory tree, sent as one transfer.
+//!
+//! `inject` used to take a single file, so a folder had to be zipped by hand
+//! first. When it is given a directory or several inputs, it now serializes
+//! them into this tar-like container and transfers the container as if it were
+//! one file (same Start frame, pages and whole-transfer SHA-256). A manifest of
+//! relative paths, sizes, modification times, permissions and SHA-256 hashes
+//! comes first, then the contents of the files back to back. The Metadata
+//! frame carries [`CONTAINER_MIME`], so `extract` knows the transfer is a
+//! container, checks every file against its manifest entry and rebuilds the
+//! tree under the `-o` directory.
+//!
+//! Layout (integers big-endian):
+//!
+//! ```text
+//!   bytes 0..8      magic (CONTAINER_MAGIC)
+//!   byte  8         container version (CONTAINER_VERSION)
+//!   bytes 9..13     entry count N
+//!   N entries:
+//!     2 bytes       path length P
+//!     P bytes       path relative to the container root (UTF-8, `/`-separated)
+//!     8 bytes       file size in bytes
+//!     8 bytes       modification time, seconds since the Unix epoch (signed)
+//!     4 bytes       nanoseconds of the modification time
+//!     4 bytes       Unix mode bits (0 when the sender has none)
+//!     32 bytes      SHA-256 of the file
+//!   then the contents of the N files, in entry order
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::HdmiError;
+use crate::filemetadata::{mime_hint, unix_time, FileMetadata};
+use crate::instructionlogics::{file_sha256, sha256_hex, SHA256_BYTES};
+
+/// First bytes of every container, checked when one is read.
+pub const CONTAINER_MAGIC: [u8; 8] = *b"HDMIFTC\0";
+
+/// Version of the container layout.
+const CONTAINER_VERSION: u8 = 1;
+
+/// MIME hint carried by the Metadata frame of a container transfer.
+pub const CONTAINER_MIME: &str = "application/x-hdmift-container";
+
+/// Name carried by the Metadata frame when several inputs are sent.
+const CONTAINER_NAME: &str = "transfer";
+
+/// Bytes of the container before its first entry.
+const HEADER_BYTES: usize = 13;
+
+/// Bytes of an entry besides its path.
+const ENTRY_FIXED_BYTES: usize = 2 + 8 + 8 + 4 + 4 + SHA256_BYTES;
+
+/// One file of a container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerEntry {
+    /// Size, modification time and permissions of the file. Its `name` is the
+    /// path relative to the container root, `/`-separated.
+    pub metadata: FileMetadata,
+    pub sha256: [u8; SHA256_BYTES],
+}
+
+/// A file of a container, with its bytes.
+pub type ContainerFile<'a> = (ContainerEntry, &'a [u8]);
+
+impl ContainerEntry {
+    /// Path of the file relative to the container root.
+    pub fn path(&self) -> &str {
+        &self.metadata.name
+    }
+
+    /// The path to write the file under, inside the output directory.
+    /// Returns [`HdmiError::UnsafeContainerPath`] for an empty or absolute
+    /// path, or one with a `.` or `..` component, a backslash or a NUL.
+    pub fn relative_path(&self) -> Result<PathBuf, HdmiError> {
+        let unsafe_path = || HdmiError::UnsafeContainerPath {
+            path: self.path().to_string(),
+        };
+        let mut relative = PathBuf::new();
+        for part in self.path().split('/') {
+            if part.contains(['\\', '\0']) {
+                return Err(unsafe_path());
+            }
+            let mut components = Path::new(part).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(name)), None) => relative.push(name),
+                _ => return Err(unsafe_path()),
+            }
+        }
+        Ok(relative)
+    }
+}
+
+/// The inputs of an `inject`, collected into a container that is streamed
+/// from the files themselves rather than built in memory.
+pub(crate) struct ContainerInput {
+    /// Name carried by the Metadata frame.
+    name: String,
+    /// Where each entry is read from, in manifest order.
+    sources: Vec<PathBuf>,
+    entries: Vec<ContainerEntry>,
+    manifest: Vec<u8>,
+}
+
+impl ContainerInput {
+    /// Walk `paths` (files, and directories recursively in name order) and hash
+    /// every file for the manifest. Each input is stored under its own name;
+    /// symbolic links to directories are skipped so a loop cannot be followed.
+    pub(crate) fn collect(paths: &[&str]) -> Result<ContainerInput, HdmiError> {
+        let mut files = Vec::new();
+        for path in paths {
+            let path = Path::new(path);
+            collect_files(path, input_name(path), &mut files)?;
+        }
+
+        let mut seen = HashSet::new();
+        let mut sources = Vec::with_capacity(files.len());
+        let mut entries = Vec::with_capacity(files.len());
+        for (source, relative) in files {
+            if !seen.insert(relative.clone()) {
+                return Err(HdmiError::DuplicateContainerPath { path: relative });
+            }
+            if relative.len() > u16::MAX as usize {
+                return Err(HdmiError::InvalidContainer {
+                    reason: format!("the path {} is too long", relative),
+                });
+            }
+            let mut metadata = FileMetadata::from_path(&source)?;
+            let (sha256, size) = hash_file(&source)?;
+            metadata.size = size;
+            metadata.mime = mime_hint(&relative).to_string();
+            metadata.name = relative;
+            sources.push(source);
+            entries.push(ContainerEntry { metadata, sha256 });
+        }
+
+        let name = match paths {
+            [path] => Some(input_name(Path::new(path))).filter(|name| !name.is_empty()),
+            _ => None,
+        };
+        Ok(ContainerInput {
+            name: name.unwrap_or_else(|| CONTAINER_NAME.to_string()),
+            manifest: manifest_bytes(&entries),
+            sources,
+            entries,
+        })
+    }
+
+    pub(crate) fn entries(&self) -> &[ContainerEntry] {
+        &self.entries
+    }
+
+    /// Size of the whole container: manifest and contents.
+    pub(crate) fn total_bytes(&self) -> u64 {
+        self.manifest.len() as u64
+            + self
+                .entries
+                .iter()
+                .map(|entry| entry.metadata.size)
+                .sum::<u64>()
+    }
+
+    /// What the Metadata frame says about the container.
+    pub(crate) fn metadata(&self) -> FileMetadata {
+        let (modified_secs, modified_nanos) = unix_time(SystemTime::now());
+        FileMetadata {
+            name: self.name.clone(),
+            size: self.total_bytes(),
+            modified_secs,
+            modified_nanos,
+            mode: 0,
+            mime: CONTAINER_MIME.to_string(),
+        }
+    }
+
+    /// The container bytes, read from the files as they are consumed.
+    pub(crate) fn reader(&self) -> ContainerReader<'_> {
+        ContainerReader {
+            input: self,
+            manifest: &self.manifest,
+            next: 0,
+            current: None,
+        }
+    }
+}
+
+/// Streams a [`ContainerInput`]: the manifest, then each file in turn.
+pub(crate) struct ContainerReader<'a> {
+    input: &'a ContainerInput,
+    /// What is left of the manifest.
+    manifest: &'a [u8],
+    /// Entry to open once the current one is exhausted.
+    next: usize,
+    current: Option<(usize, io::Take<fs::File>)>,
+}
+
+impl Read for ContainerReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.manifest.is_empty() {
+            return self.manifest.read(buf);
+        }
+        loop {
+            if let Some((index, file)) = &mut self.current {
+                let read = file.read(buf)?;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+                if file.limit() > 0 {
+                    // The manifest already declared its size and hash.
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "{} shrank while it was sent",
+                            self.input.sources[*index].display()
+                        ),
+                    ));
+                }
+                self.current = None;
+            }
+            if self.next == self.input.sources.len() {
+                return Ok(0);
+            }
+            let index = self.next;
+            self.next += 1;
+            let source = &self.input.sources[index];
+            let file = fs::File::open(source)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", source.display(), e)))?;
+            self.current = Some((index, file.take(self.input.entries[index].metadata.size)));
+        }
+    }
+}
+
+/// Name an input is stored under: its last path component, or the name of the
+/// directory it resolves to (for `.`). Empty for the root directory, whose
+/// files are then stored at the container root.
+fn input_name(path: &Path) -> String {
+    let name = match path.file_name() {
+        Some(name) => Some(name.to_os_string()),
+        None => fs::canonicalize(path)
+            .ok()
+            .and_then(|path| path.file_name().map(|name| name.to_os_string())),
+    };
+    name.map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Add the file at `path`, or every file below the directory at `path`, to
+/// `files` with its path relative to the container root.
+fn collect_files(
+    path: &Path,
+    relative: String,
+    files: &mut Vec<(PathBuf, String)>,
+) -> Result<(), HdmiError> {
+    let read_error = |source| HdmiError::ReadFile {
+        path: path.to_string_lossy().to_string(),
+        source,
+    };
+    let metadata = fs::metadata(path).map_err(read_error)?;
+    if metadata.is_file() {
+        files.push((path.to_path_buf(), relative));
+        return Ok(());
+    }
+    if !metadata.is_dir() {
+        return Ok(());
+    }
+    let mut children = fs::read_dir(path)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(read_error)?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let child_path = child.path();
+        let is_link = child.file_type().map_err(read_error)?.is_symlink();
+        if is_link && child_path.is_dir() {
+            continue;
+        }
+        let name = child.file_name().to_string_lossy().to_string();
+        let child_relative = if relative.is_empty() {
+            name
+        } else {
+            format!("{}/{}", relative, name)
+        };
+        collect_files(&child_path, child_relative, files)?;
+    }
+    Ok(())
+}
+
+/// SHA-256 and length of the file at `path`.
+fn hash_file(path: &Path) -> Result<([u8; SHA256_BYTES], u64), HdmiError> {
+    let read_error = |source| HdmiError::ReadFile {
+        path: path.to_string_lossy().to_string(),
+        source,
+    };
+    let file = fs::File::open(path).map_err(read_error)?;
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut BufReader::new(file), &mut hasher).map_err(read_error)?;
+    Ok((hasher.finalize().into(), size))
+}
+
+fn manifest_bytes(entries: &[ContainerEntry]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(
+        HEADER_BYTES
+            + entries
+                .iter()
+                .map(|entry| ENTRY_FIXED_BYTES + entry.path().len())
+                .sum::<usize>(),
+    );
+    bytes.extend_from_slice(&CONTAINER_MAGIC);
+    bytes.push(CONTAINER_VERSION);
+    bytes.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for entry in entries {
+        let metadata = &entry.metadata;
+        bytes.extend_from_slice(&(entry.path().len() as u16).to_be_bytes());
+        bytes.extend_from_slice(entry.path().as_bytes());
+        bytes.extend_from_slice(&metadata.size.to_be_bytes());
+        bytes.extend_from_slice(&metadata.modified_secs.to_be_bytes());
+        bytes.extend_from_slice(&metadata.modified_nanos.to_be_bytes());
+        bytes.extend_from_slice(&metadata.mode.to_be_bytes());
+        bytes.extend_from_slice(&entry.sha256);
+    }
+    bytes
+}
+
+/// The next `len` bytes of `data` from `*at`, advancing it.
+fn take<'a>(data: &'a [u8], at: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let bytes = data.get(*at..at.checked_add(len)?)?;
+    *at += len;
+    Some(bytes)
+}
+
+fn parse_entry(data: &[u8], at: &mut usize) -> Option<ContainerEntry> {
+    let path_len = u16::from_be_bytes(take(data, at, 2)?.try_into().ok()?) as usize;
+    let path = String::from_utf8(take(data, at, path_len)?.to_vec()).ok()?;
+    let size = u64::from_be_bytes(take(data, at, 8)?.try_into().ok()?);
+    let modified_secs = i64::from_be_bytes(take(data, at, 8)?.try_into().ok()?);
+    let modified_nanos = u32::from_be_bytes(take(data, at, 4)?.try_into().ok()?);
+    let mode = u32::from_be_bytes(take(data, at, 4)?.try_into().ok()?);
+    let sha256 = take(data, at, SHA256_BYTES)?.try_into().ok()?;
+    if modified_nanos >= 1_000_000_000 {
+        return None;
+    }
+    Some(ContainerEntry {
+        metadata: FileMetadata {
+            mime: mime_hint(&path).to_string(),
+            name: path,
+            size,
+            modified_secs,
+            modified_nanos,
+            mode,
+        },
+        sha256,
+    })
+}
+
+/// The files of the container `data`, each with its bytes.
+///
+/// Whether a transfer is a container is told by its Metadata frame
+/// ([`CONTAINER_MIME`]), not by its bytes: a plain file may well start with
+/// [`CONTAINER_MAGIC`]. Bytes announced as a container that do not start with
+/// it are rejected. Every path is checked to stay inside the output directory and every file
+/// against its SHA-256 before anything is returned, so a bad container writes
+/// nothing.
+pub fn read_container(data: &[u8]) -> Result<Vec<ContainerFile<'_>>, HdmiError> {
+    let invalid = |reason: &str| HdmiError::InvalidContainer {
+        reason: reason.to_string(),
+    };
+    if !data.starts_with(&CONTAINER_MAGIC) {
+        return Err(invalid("missing container magic"));
+    }
+    if data.get(CONTAINER_MAGIC.len()) != Some(&CONTAINER_VERSION) {
+        return Err(invalid("unsupported container version"));
+    }
+    let mut at = CONTAINER_MAGIC.len() + 1;
+    let count = take(data, &mut at, 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_be_bytes)
+        .ok_or_else(|| invalid("truncated manifest"))?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let entry = parse_entry(data, &mut at).ok_or_else(|| invalid("truncated manifest"))?;
+        entry.relative_path()?;
+        entries.push(entry);
+    }
+
+    let mut files = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let size = usize::try_from(entry.metadata.size).map_err(|_| invalid("truncated file"))?;
+        let bytes = take(data, &mut at, size).ok_or_else(|| invalid("truncated file"))?;
+        let actual = file_sha256(bytes);
+        if actual != entry.sha256 {
+            return Err(HdmiError::ContainerHashMismatch {
+                path: entry.path().to_string(),
+                expected: sha256_hex(&entry.sha256),
+                actual: sha256_hex(&actual),
+            });
+        }
+        files.push((entry, bytes));
+    }
+    if at != data.len() {
+        return Err(invalid("bytes after the last file"));
+    }
+    Ok(files)
+}
+
+/// Write the `files` of a container under `dir`, creating the directories on
+/// their paths, and restore their modification times and permission bits.
+pub fn unpack_container(files: &[ContainerFile], dir: &Path) -> Result<(), HdmiError> {
+    for (entry, bytes) in files {
+        let path = dir.join(entry.relative_path()?);
+        let write_error = |source| HdmiError::WriteFile {
+            path: path.to_string_lossy().to_string(),
+            source,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(write_error)?;
+        }
+        fs::write(&path, bytes).map_err(write_error)?;
+        entry.metadata.restore(&path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod container_tests {
+    use super::*;
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hdmift_{}_{}", label, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn container_bytes(input: &ContainerInput) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        input.reader().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_directory_round_trips_through_a_container() {
+        let dir = temp_dir("container_round_trip");
+        let tree = dir.join("photos");
+        fs::create_dir_all(tree.join("2024/empty")).unwrap();
+        fs::write(tree.join("b.txt"), b"second").unwrap();
+        fs::write(tree.join("a.jpg"), vec![7u8; 5000]).unwrap();
+        fs::write(tree.join("2024/c.bin"), b"").unwrap();
+        let single = dir.join("notes.md");
+        fs::write(&single, b"# notes").unwrap();
+
+        let tree_path = tree.to_string_lossy().to_string();
+        let single_path = single.to_string_lossy().to_string();
+        let input = ContainerInput::collect(&[&tree_path, &single_path]).unwrap();
+        let paths: Vec<&str> = input.entries().iter().map(|entry| entry.path()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "photos/2024/c.bin",
+                "photos/a.jpg",
+                "photos/b.txt",
+                "notes.md"
+            ]
+        );
+        assert_eq!(input.metadata().name, CONTAINER_NAME);
+        assert_eq!(input.metadata().mime, CONTAINER_MIME);
+
+        let bytes = container_bytes(&input);
+        assert_eq!(bytes.len() as u64, input.total_bytes());
+        let files = read_container(&bytes).unwrap();
+        assert_eq!(files.len(), 4);
+        assert_eq!(files[1].1, vec![7u8; 5000].as_slice());
+        assert_eq!(files[3].0.metadata.mime, "text/markdown");
+
+        let out = dir.join("received");
+        unpack_container(&files, &out).unwrap();
+        assert_eq!(fs::read(out.join("photos/b.txt")).unwrap(), b"second");
+        assert_eq!(fs::read(out.join("photos/2024/c.bin")).unwrap(), b"");
+        assert_eq!(fs::read(out.join("notes.md")).unwrap(), b"# notes");
+        let restored = FileMetadata::from_path(out.join("photos/a.jpg")).unwrap();
+        let sent = FileMetadata::from_path(tree.join("a.jpg")).unwrap();
+        assert_eq!(
+            (restored.modified_secs, restored.modified_nanos),
+            (sent.modified_secs, sent.modified_nanos)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bytes_without_the_magic_are_rejected() {
+        for data in [&b""[..], b"PK\x03\x04 a zip file"] {
+            assert!(matches!(
+                read_container(data),
+                Err(HdmiError::InvalidContainer { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_inputs_with_the_same_name_are_rejected() {
+        let dir = temp_dir("container_duplicate");
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::create_dir_all(dir.join("b")).unwrap();
+        fs::write(dir.join("a/same.txt"), b"a").unwrap();
+        fs::write(dir.join("b/same.txt"), b"b").unwrap();
+        let first = dir.join("a/same.txt").to_string_lossy().to_string();
+        let second = dir.join("b/same.txt").to_string_lossy().to_string();
+        let result = ContainerInput::collect(&[&first, &second]);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            matches!(result, Err(HdmiError::DuplicateContainerPath { ref path }) if path == "same.txt"),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[test]
+    fn test_corrupted_or_truncated_containers_are_rejected() {
+        let dir = temp_dir("container_corrupted");
+        fs::write(dir.join("data.bin"), vec![1u8; 100]).unwrap();
+        let path = dir.join("data.bin").to_string_lossy().to_string();
+        let bytes = container_bytes(&ContainerInput::collect(&[&path]).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 0xFF;
+        match read_container(&corrupted) {
+            Err(HdmiError::ContainerHashMismatch { path, .. }) => assert_eq!(path, "data.bin"),
+            other => panic!("expected a hash mismatch, got {:?}", other),
+        }
+        assert!(matches!(
+            read_container(&bytes[..bytes.len() - 1]),
+            Err(HdmiError::InvalidContainer { .. })
+        ));
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(matches!(
+            read_container(&longer),
+            Err(HdmiError::InvalidContainer { .. })
+        ));
+    }
+
+    #[test]
+    fn test_paths_cannot_leave_the_output_directory() {
+        let entry = |path: &str| ContainerEntry {
+            metadata: FileMetadata {
+                name: path.to_string(),
+                size: 0,
+                modified_secs: 0,
+                modified_nanos: 0,
+                mode: 0,
+                mime: String::new(),
+            },
+            sha256: [0; SHA256_BYTES],
+        };
+        assert_eq!(
+            entry("docs/a.txt").relative_path().unwrap(),
+            Path::new("docs").join("a.txt")
+        );
+        for path in [
+            "",
+            "/etc/passwd",
+            "../a",
+            "docs/../../a",
+            "docs//a",
+            "./a",
+            "a\\b",
+        ] {
+            assert!(
+                matches!(
+                    entry(path).relative_path(),
+                    Err(HdmiError::UnsafeContainerPath { .. })
+                ),
+                "{path}"
+            );
+        }
+
+        // Rejected while reading, before anything is written.
+        assert!(matches!(
+            read_container(&manifest_bytes(&[entry("../evil")])),
+            Err(HdmiError::UnsafeContainerPath { .. })
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+//! Errors returned by the library.
+
+use std::fmt;
+use std::io;
+
+use crate::compression::Compression;
+use crate::pagereport::format_page_ranges;
+
+/// Everything that can go wrong while injecting a file into a video or
+/// extracting it back. Each variant carries the context needed to report it.
+#[derive(Debug)]
+pub enum HdmiError {
+    /// No mode (`inject` or `extract`) was given.
+    MissingMode,
+    /// Injection needs an input file.
+    MissingInputFile,
+    /// Extraction reads a single video, capture device or image directory.
+    SeveralExtractInputs { count: usize },
+    /// `--levels` is not a power of two in `2..=256`.
+    InvalidLevels { levels: u32 },
+    /// `--fec` is above the strongest supported correction.
+    InvalidFec { fec: u8, max: u8 },
+    /// A frame dimension is not a whole number of cells.
+    UnalignedSize {
+        dimension: &'static str,
+        pixels: u32,
+        size: u8,
+    },
+    /// `--pages` is not a range string nor a report written by `extract`.
+    InvalidPageList { pages: String, reason: String },
+    /// `--pages` selects classic data pages, which fountain mode does not have.
+    PagesWithFountain,
+    /// A repair page past the end of the file.
+    PageOutOfRange { page: u64, pages: u64 },
+    /// Raw frames are read from stdin but their size was not given.
+    MissingRawSize,
+    /// `--raw-size` is not `WIDTHxHEIGHT`.
+    InvalidRawSize { size: String },
+    /// `--session` is not up to 8 hex digits.
+    InvalidSession { session: String },
+    /// The frame cannot hold the header and any payload (or, for the Start
+    /// frame, the encoding configuration).
+    FrameTooSmall {
+        width: u16,
+        height: u16,
+        size: u8,
+        fec: u8,
+    },
+    /// An image cannot be used as a frame: wrong pixel buffer length, pixel
+    /// format or dimensions.
+    InvalidImage {
+        width: u32,
+        height: u32,
+        reason: String,
+    },
+    /// The video file or capture device could not be opened.
+    VideoOpen { path: String, reason: String },
+    /// A frame could not be read from the video.
+    VideoRead { path: String, reason: String },
+    /// A frame could not be written to the video.
+    VideoWrite { path: String, reason: String },
+    /// The input file could not be read.
+    ReadFile { path: String, source: io::Error },
+    /// The extracted file (or a file next to it) could not be written.
+    WriteFile { path: String, source: io::Error },
+    /// A saved transfer state exists but cannot be parsed.
+    InvalidState { path: String },
+    /// No Start frame was received, so the file size and layout are unknown.
+    MissingStart,
+    /// The Start frame announced a Metadata frame that was never received with
+    /// a valid CRC, so the file name and kind are unknown.
+    MissingMetadata,
+    /// Some data pages were never received with a valid CRC.
+    MissingPages {
+        received: u64,
+        expected: u64,
+        missing: Vec<u64>,
+    },
+    /// Fountain mode: the symbols received do not cover every source block.
+    NotEnoughSymbols {
+        received: u64,
+        recovered_blocks: u64,
+        source_blocks: u64,
+    },
+    /// Every page was received but the reassembled file does not hash to the
+    /// SHA-256 declared by the Start frame (both in hexadecimal).
+    HashMismatch { expected: String, actual: String },
+    /// Every page was received but the reassembled bytes are not a valid
+    /// stream of the codec declared by the Start frame.
+    Decompress {
+        codec: Compression,
+        source: io::Error,
+    },
+    /// Two inputs of a multi-file transfer would be stored under the same
+    /// relative path.
+    DuplicateContainerPath { path: String },
+    /// The transfer is announced as a multi-file container but its bytes are
+    /// not one, or its manifest does not describe them.
+    InvalidContainer { reason: String },
+    /// A container path that is absolute or climbs out of the output
+    /// directory.
+    UnsafeContainerPath { path: String },
+    /// A file of a container does not hash to the value of its manifest entry.
+    ContainerHashMismatch {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+    /// The operation needs the `opencv-backend` feature.
+    NoVideoBackend { operation: &'static str },
+}
+
+impl fmt::Display for HdmiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HdmiError::MissingMode => write!(f, "Mode is required (use -m inject or -m extract)"),
+            HdmiError::MissingInputFile => write!(f, "Missing input file"),
+            HdmiError::SeveralExtractInputs { count } => write!(
+                f,
+                "extract reads one video, capture device or image directory (got {count} inputs)"
+            ),
+            HdmiError::InvalidLevels { levels } => write!(
+                f,
+                "--levels must be a power of two between 2 and 256 (got {levels})"
+            ),
+            HdmiError::InvalidFec { fec, max } => {
+                write!(f, "--fec must be between 0 and {max} (got {fec})")
+            }
+            HdmiError::UnalignedSize {
+                dimension,
+                pixels,
+                size,
+            } => write!(
+                f,
+                "The {dimension} ({pixels} pixels) is not a multiple of the size ({size})"
+            ),
+            HdmiError::InvalidPageList { pages, reason } => {
+                write!(f, "Invalid page list {:?}: {}", pages, reason)
+            }
+            HdmiError::PagesWithFountain => write!(
+                f,
+                "--pages selects classic data pages and cannot be combined with --fountain"
+            ),
+            HdmiError::PageOutOfRange { page, pages } => write!(
+                f,
+                "Page {page} is out of range: the file holds {pages} pages with these options"
+            ),
+            HdmiError::MissingRawSize => write!(
+                f,
+                "Reading raw frames from stdin needs --raw-size WIDTHxHEIGHT (or --width and --height)"
+            ),
+            HdmiError::InvalidRawSize { size } => write!(
+                f,
+                "Invalid raw frame size {:?}: expected WIDTHxHEIGHT, for example 1920x1080",
+                size
+            ),
+            HdmiError::InvalidSession { session } => write!(
+                f,
+                "Invalid session {:?}: expected up to 8 hex digits, for example 1a2b3c4d",
+                session
+            ),
+            HdmiError::FrameTooSmall {
+                width,
+                height,
+                size,
+                fec,
+            } => {
+                write!(
+                    f,
+                    "Frame of {width}x{height} pixels (cell size {size}) is too small to hold the header and any payload"
+                )?;
+                if *fec > 0 {
+                    write!(f, " with {fec} FEC parity bytes per block. Increase width/height or lower --fec.")
+                } else {
+                    write!(f, ". Increase width/height.")
+                }
+            }
+            HdmiError::InvalidImage {
+                width,
+                height,
+                reason,
+            } => write!(f, "Unusable {}x{} image: {}", width, height, reason),
+            HdmiError::VideoOpen { path, reason } => {
+                write!(f, "Could not open video {}: {}", path, reason)
+            }
+            HdmiError::VideoRead { path, reason } => {
+                write!(f, "Could not read a frame from video {}: {}", path, reason)
+            }
+            HdmiError::VideoWrite { path, reason } => {
+                write!(f, "Could not write a frame to video {}: {}", path, reason)
+            }
+            HdmiError::ReadFile { path, source } => {
+                write!(f, "Unable to read file: {} with error: {}", path, source)
+            }
+            HdmiError::WriteFile { path, source } => {
+                write!(f, "Unable to write file: {} with error: {}", path, source)
+            }
+            HdmiError::InvalidState { path } => {
+                write!(f, "{} is not a valid transfer state file", path)
+            }
+            HdmiError::MissingStart => {
+                write!(f, "Instruction not found while extracting data from video")
+            }
+            HdmiError::MissingMetadata => write!(
+                f,
+                "The Metadata frame naming the file was not received; extract another capture to resume"
+            ),
+            HdmiError::MissingPages {
+                received,
+                expected,
+                missing,
+            } => write!(
+                f,
+                "We have not received all frames. We received {} of {} pages, missing pages: {}",
+                received,
+                expected,
+                format_page_ranges(missing)
+            ),
+            HdmiError::NotEnoughSymbols {
+                received,
+                recovered_blocks,
+                source_blocks,
+            } => write!(
+                f,
+                "We have not received enough fountain frames. We recovered {} of {} source blocks from {} frames",
+                recovered_blocks, source_blocks, received
+            ),
+            HdmiError::HashMismatch { expected, actual } => write!(
+                f,
+                "SHA-256 hash mismatch: the Start frame declares {} but the reassembled file hashes to {}",
+                expected, actual
+            ),
+            HdmiError::Decompress { codec, source } => write!(
+                f,
+                "Unable to decompress the reassembled {} stream: {}",
+                codec, source
+            ),
+            HdmiError::DuplicateContainerPath { path } => {
+                write!(f, "Two inputs would be sent under the same path: {}", path)
+            }
+            HdmiError::InvalidContainer { reason } => {
+                write!(f, "The transfer is not a valid file container: {}", reason)
+            }
+            HdmiError::UnsafeContainerPath { path } => write!(
+                f,
+                "Refusing to extract {:?}: container paths must stay inside the output directory",
+                path
+            ),
+            HdmiError::ContainerHashMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "SHA-256 hash mismatch for {}: the manifest declares {} but the file hashes to {}",
+                path, expected, actual
+            ),
+            HdmiError::NoVideoBackend { operation } => {
+                write!(f, "{operation} requires the opencv-backend feature")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HdmiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HdmiError::ReadFile { source, .. }
+            | HdmiError::WriteFile { source, .. }
+            | HdmiError::Decompress { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_error_messages_carry_their_context() {
+        let missing = HdmiError::MissingPages {
+            received: 5,
+            expected: 9,
+            missing: vec![2, 3, 4, 8],
+        };
+        assert_eq!(
+            missing.to_string(),
+            "We have not received all frames. We received 5 of 9 pages, missing pages: 2-4,8"
+        );
+        assert_eq!(
+            HdmiError::InvalidLevels { levels: 3 }.to_string(),
+            "--levels must be a power of two between 2 and 256 (got 3)"
+        );
+        let too_small = HdmiError::FrameTooSmall {
+            width: 16,
+            height: 16,
+            size: 1,
+            fec: 0,
+        };
+        assert!(too_small.to_string().contains("16x16"));
+        assert!(!too_small.to_string().contains("--fec"));
+        let mismatch = HdmiError::HashMismatch {
+            expected: "ab".to_string(),
+            actual: "cd".to_string(),
+        };
+        assert!(mismatch.to_string().contains("hash mismatch"));
+        assert!(mismatch
+            .to_string()
+            .ends_with("declares ab but the reassembled file hashes to cd"));
+        let entry = HdmiError::ContainerHashMismatch {
+            path: "docs/a.txt".to_string(),
+            expected: "ab".to_string(),
+            actual: "cd".to_string(),
+        };
+        assert!(entry.to_string().contains("for docs/a.txt"));
+    }
+
+    #[test]
+    fn test_file_errors_expose_their_source() {
+        let error = HdmiError::ReadFile {
+            path: "input.bin".to_string(),
+            source: io::Error::new(io::ErrorKind::NotFound, "gone"),
+        };
+        assert!(error
+            .to_string()
+            .starts_with("Unable to read file: input.bin"));
+        assert_eq!(error.source().unwrap().to_string(), "gone");
+        assert!(HdmiError::MissingStart.source().is_none());
+    }
+}
@@ -1,25 +1,30 @@
-use opencv::videoio::VideoCapture;
-
 use std::fs;
-
-use crate::bitlogics::{bits_per_channel, get_bit_from_rgb, mutate_byte, value_to_symbol};
+use std::path::Path;
+
+use crate::bitlogics::{bits_per_channel, get_bit_from_rgb, mutate_byte, LevelThresholds};
+use crate::compression::Compression;
+use crate::container::{read_container, unpack_container};
+use crate::error::HdmiError;
+use crate::filemetadata::{ExtractedFile, FileMetadata};
+use crate::fountaincode::FountainDecoder;
 use crate::injectionextraction::{
-    content_cell_xy, frame_capacity, map_to_size, marker_centers_px, HEADER_BITS,
+    calibration_ramp, content_cell_xy, content_cols, frame_capacity, ramp_patch_xy, HEADER_BITS,
 };
-use crate::instructionlogics::{FrameHeader, FrameType};
+use crate::instructionlogics::{
+    file_sha256, format_session, sha256_hex, EncodingConfig, FrameHeader, FrameType,
+    ENCODING_CONFIG_BYTES,
+};
+use crate::meshcorrection::correct_mesh;
 use crate::options::AlgoFrame;
+use crate::pagereport::{missing_report_path, MissingPagesReport};
+use crate::reedsolomon::{fec_data_capacity, fec_decode};
+use crate::softcombining::{CellSamples, SoftCombiner};
+use crate::transferstate::{state_file_path, TransferState};
 use crate::videoframe::VideoFrame;
-use opencv::core::{Mat, Point, Point2f, Scalar, Vec4i, Vector, BORDER_CONSTANT};
-use opencv::imgproc::{
-    contour_area, cvt_color_def, find_contours_with_hierarchy, get_affine_transform_slice, moments,
-    threshold, warp_affine, CHAIN_APPROX_SIMPLE, COLOR_BGR2GRAY, INTER_LINEAR, RETR_TREE,
-    THRESH_BINARY_INV,
-};
-use opencv::prelude::*;
-use opencv::videoio::CAP_ANY;
 
 use crate::options::ExtractOptions;
 use indicatif::ProgressBar;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::iter::Iterator;
 
@@ -33,553 +38,998 @@ struct FrameBytesInfo {
     /// True when the recomputed CRC matches the header CRC. Only frames with a
     /// valid CRC are trusted.
     pub crc_valid: bool,
+    /// Sampled payload cells of a data or fountain frame whose CRC failed, to
+    /// combine with other copies of it.
+    pub samples: Option<CellSamples>,
 }
 
-pub fn video_to_frames(extract_options: &ExtractOptions) -> Vec<VideoFrame> {
-    let mut video = VideoCapture::from_file(&extract_options.video_file_path, CAP_ANY)
-        .expect("Could not open video path");
-    let mut all_frames = Vec::new();
-    loop {
-        let mut frame = Mat::default();
-        video
-            .read(&mut frame)
-            .expect("Reading frame shouldn't crash");
-
-        if frame.cols() == 0 {
-            break;
-        }
-
-        // Re-align the captured frame to canonical pixels using the calibration
-        // markers. Frames where the markers cannot be located are skipped; in a
-        // looped HDMI stream they will be captured cleanly on another pass.
-        if let Some(registered) = register_frame(
-            &frame,
-            extract_options.width,
-            extract_options.height,
-            extract_options.size,
-        ) {
-            all_frames.push(registered);
-        }
-    }
+/// Decode a collection of (already aligned) frames back into the original bytes.
+/// Thin wrapper over [`Reassembler`] that errors when the file is incomplete.
+///
+/// Each frame is decoded and its CRC checked; frames that fail the CRC (torn,
+/// garbled or transition frames) are dropped. The Start frame supplies the total
+/// byte count; Data frames are de-duplicated and ordered by their page number.
+/// Fountain frames are de-duplicated by seed and handed to the LT decoder, which
+/// rebuilds the file from any large enough subset of them.
+///
+/// The algo and geometry come from the Start frame; the options only need to
+/// carry them when the Start frame cannot be found by probing.
+pub fn frames_to_data(
+    extract_options: &ExtractOptions,
+    frames: Vec<VideoFrame>,
+) -> Result<Vec<u8>, HdmiError> {
+    reassemble_frames(extract_options, frames)?.finish()
+}
 
-    all_frames
+/// Like [`frames_to_data`], with what the Metadata frame says about the file
+/// when the frames include one. A Metadata frame announced by the Start frame
+/// but missing from the frames is [`HdmiError::MissingMetadata`].
+pub fn frames_to_file(
+    extract_options: &ExtractOptions,
+    frames: Vec<VideoFrame>,
+) -> Result<ExtractedFile, HdmiError> {
+    reassemble_frames(extract_options, frames)?.finish_file()
 }
 
-/// Locate the three finder patterns in a (possibly offset/scaled/compressed)
-/// captured frame and affine-warp it back to canonical `width` x `height`
-/// pixels so the cell grid lines up with what the encoder wrote. Returns `None`
-/// when the markers cannot be found.
-pub fn register_frame(image: &Mat, width: u16, height: u16, size: u8) -> Option<VideoFrame> {
-    let w = image.cols();
-    let h = image.rows();
-    if w == 0 || h == 0 {
-        return None;
-    }
+/// Push every frame into a new [`Reassembler`].
+fn reassemble_frames(
+    extract_options: &ExtractOptions,
+    frames: Vec<VideoFrame>,
+) -> Result<Reassembler, HdmiError> {
+    let (config, session) =
+        find_encoding_config(extract_options, &frames).ok_or(HdmiError::MissingStart)?;
+    let mut reassembler = Reassembler::new(extract_options);
+    reassembler.adopt_config(config, session);
 
-    let mut gray = Mat::default();
-    // OpenCV 4.11+ added a trailing `hint` arg to cvtColor; `cvt_color_def`
-    // keeps the pre-4.11 behavior (dst_cn = 0, default algorithm hint).
-    cvt_color_def(image, &mut gray, COLOR_BGR2GRAY).ok()?;
-    let mut thresh = Mat::default();
-    // Dark finder rings become foreground; the white quiet-zone border drops out.
-    threshold(&gray, &mut thresh, 128.0, 255.0, THRESH_BINARY_INV).ok()?;
+    let total_video_frame = frames.len() as u64;
+    let pb = ProgressBar::new(total_video_frame);
+    if extract_options.show_progress {
+        eprintln!("Initial Frames count: {}", total_video_frame);
+    }
 
-    let markers = detect_markers(&thresh, w, h)?;
-    let src = [
-        Point2f::new(markers[0].0, markers[0].1),
-        Point2f::new(markers[1].0, markers[1].1),
-        Point2f::new(markers[2].0, markers[2].1),
-    ];
-    let dst_px = marker_centers_px(width, height, size);
-    let dst = [
-        Point2f::new(dst_px[0].0, dst_px[0].1),
-        Point2f::new(dst_px[1].0, dst_px[1].1),
-        Point2f::new(dst_px[2].0, dst_px[2].1),
-    ];
+    for frame in frames.iter() {
+        reassembler.push(frame);
+        if extract_options.show_progress {
+            pb.inc(1);
+        }
+    }
 
-    let transform = get_affine_transform_slice(&src, &dst).ok()?;
-    let mut warped = Mat::default();
-    warp_affine(
-        image,
-        &mut warped,
-        &transform,
-        map_to_size(width, height),
-        INTER_LINEAR,
-        BORDER_CONSTANT,
-        Scalar::default(),
-    )
-    .ok()?;
-
-    VideoFrame::from(warped, size).ok()
+    if extract_options.show_progress {
+        pb.finish_with_message("done");
+        eprintln!(
+            "Relevant (unique, valid) data frames: {}",
+            reassembler.received_pages()
+        );
+        eprintln!(
+            "Pages recovered by combining copies: {}",
+            reassembler.combined_pages()
+        );
+    }
+    Ok(reassembler)
 }
 
-/// Centroid and (absolute) enclosed area of contour `idx`, or `None` when it is
-/// degenerate.
-fn contour_centroid_area(contours: &Vector<Vector<Point>>, idx: usize) -> Option<(f32, f32, f64)> {
-    let contour = contours.get(idx).ok()?;
-    let area = contour_area(&contour, false).unwrap_or(0.0).abs();
-    if area < f64::EPSILON {
-        return None;
-    }
-    let m = moments(&contour, false).ok()?;
-    if m.m00.abs() < f64::EPSILON {
-        return None;
-    }
-    Some(((m.m10 / m.m00) as f32, (m.m01 / m.m00) as f32, area))
+/// What [`Reassembler::push`] did with a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    /// The first valid Start frame, announcing the total byte count.
+    Start { total_bytes: u64 },
+    /// A data page not received before.
+    NewPage { page: u64 },
+    /// A fountain symbol not received before.
+    NewSymbol { seed: u64 },
+    /// The first valid Metadata frame, naming the file.
+    Metadata,
+    /// A valid frame whose page, symbol or Start was already received: its
+    /// type and header value (page, seed or total byte count).
+    Duplicate { frame_type: FrameType, value: u64 },
+    /// A frame of another transfer session than the one being decoded.
+    OtherSession { session: u32 },
+    /// The header was read but the CRC does not match: a torn or garbled frame.
+    CrcFail,
+    /// No header could be read (not one of our frames, a misaligned frame, or
+    /// a frame seen before the Start frame revealed the geometry).
+    NoHeader,
 }
 
-/// Find the centre of the best finder pattern in each of the three expected
-/// corners (top-left, top-right, bottom-left). Returns the three centres in
-/// TL/TR/BL order.
+/// Push-based reassembly of the transferred file from registered frames, for
+/// callers that receive frames from their own source.
 ///
-/// A finder pattern is a QR-style triple of concentric squares: a black outer
-/// ring, a white middle ring, and a black centre. After `THRESH_BINARY_INV`
-/// (black -> foreground) that yields a very specific contour-tree signature:
-/// an outer foreground contour whose child is a hole (the white ring) whose
-/// child is an inner foreground contour (the centre), with all three sharing a
-/// centre and an outer/inner area ratio near (7/3)^2 ~= 5.4.
-///
-/// The previous heuristic ("largest contour with >= 2 ancestors in the quadrant")
-/// was not specific: dense payload content (black BW cells / dark RGB cells)
-/// forms larger nested blobs in the corner quadrants and was selected instead of
-/// the real markers, producing a misaligned warp that corrupted every frame -
-/// even unperturbed ones. Validating the concentric triple makes detection
-/// scale-invariant and robust to arbitrary payload content.
-fn detect_markers(thresh: &Mat, w: i32, h: i32) -> Option<[(f32, f32); 3]> {
-    let mut contours: Vector<Vector<Point>> = Vector::new();
-    let mut hierarchy: Vector<Vec4i> = Vector::new();
-    find_contours_with_hierarchy(
-        thresh,
-        &mut contours,
-        &mut hierarchy,
-        RETR_TREE,
-        CHAIN_APPROX_SIMPLE,
-        Point::new(0, 0),
-    )
-    .ok()?;
-
-    let mut candidates: Vec<((f32, f32), f64)> = Vec::new();
-    for i in 0..contours.len() {
-        // hierarchy entry is [next, prev, first_child, parent].
-        let node = hierarchy.get(i).ok()?;
-        let child = node[2];
-        if child < 0 {
-            continue; // no middle-ring hole -> not a finder
-        }
-        let hole = hierarchy.get(child as usize).ok()?;
-        let grand = hole[2];
-        if grand < 0 {
-            continue; // no inner centre -> not a finder
-        }
-
-        let (ox, oy, outer_area) = match contour_centroid_area(&contours, i) {
-            Some(v) => v,
-            None => continue,
-        };
-        let (gx, gy, inner_area) = match contour_centroid_area(&contours, grand as usize) {
-            Some(v) => v,
-            None => continue,
-        };
+/// Each pushed frame is decoded and can be dropped right away: only the
+/// verified page bytes (or fountain symbols) are kept, so memory follows the
+/// file size rather than the capture length. The geometry and algo come from
+/// the Start frame; until it is pushed, frames that are not a Start frame
+/// cannot be read. The reassembler locks onto the transfer session of that
+/// Start frame (or the one given in the options) and ignores the frames of any
+/// other session, so a capture holding the tail of an older transfer does not
+/// mix its pages in. Without a session in the options, it switches to another
+/// session once that one's Start frames outnumber those of the locked one:
+/// a capture that begins with the last loop of an older transfer then still
+/// decodes the transfer looping after it.
+pub struct Reassembler {
+    options: ExtractOptions,
+    config: Option<EncodingConfig>,
+    /// Total byte count announced by the Start frame.
+    total_bytes: Option<u64>,
+    pages: HashMap<u64, Vec<u8>>,
+    /// Payload length of a data page, known once one has been received.
+    page_len: Option<usize>,
+    /// Number of pages present from page 0 without a gap, and their bytes.
+    contiguous_pages: u64,
+    contiguous_bytes: u64,
+    /// Fountain symbols received before the Start frame sized the decoder.
+    early_symbols: HashMap<u64, Vec<u8>>,
+    fountain: Option<FountainDecoder>,
+    /// Saved state to merge once the Start frame confirms the same transfer.
+    resumed: Option<TransferState>,
+    /// Copies of the frames that failed their CRC, for soft-combining.
+    combiner: SoftCombiner,
+    /// Pages and symbols recovered by soft-combining.
+    combined: u64,
+    /// What the Metadata frame says about the file, once received.
+    metadata: Option<FileMetadata>,
+    /// Transfer session being decoded, once locked.
+    session: Option<u32>,
+    /// Other sessions seen, reported once each.
+    other_sessions: Vec<u32>,
+    /// Valid Start frames seen per session.
+    start_frames: HashMap<u32, u64>,
+}
 
-        // Reject sub-pixel noise; finders are several cells across.
-        if outer_area < 16.0 || inner_area < f64::EPSILON {
-            continue;
+impl Reassembler {
+    pub fn new(extract_options: &ExtractOptions) -> Reassembler {
+        Reassembler {
+            options: extract_options.clone(),
+            config: None,
+            total_bytes: None,
+            pages: HashMap::new(),
+            page_len: None,
+            contiguous_pages: 0,
+            contiguous_bytes: 0,
+            early_symbols: HashMap::new(),
+            fountain: None,
+            resumed: None,
+            combiner: SoftCombiner::new(),
+            combined: 0,
+            metadata: None,
+            session: extract_options.session,
+            other_sessions: Vec::new(),
+            start_frames: HashMap::new(),
         }
+    }
 
-        // Concentric: the inner centre must sit on top of the outer centre,
-        // tolerant to within ~30% of the outer square's side (sqrt of its area).
-        let outer_side = (outer_area as f32).sqrt();
-        let dx = ox - gx;
-        let dy = oy - gy;
-        if (dx * dx + dy * dy).sqrt() > 0.3 * outer_side {
-            continue;
+    /// Resume from the pages (and metadata record) saved by an earlier,
+    /// incomplete extraction. They are merged once the Start frame shows this
+    /// is the same transfer, and ignored otherwise.
+    pub fn resume(&mut self, state: TransferState) {
+        self.resumed = Some(state);
+        if let (Some(total_bytes), Some(config)) = (self.total_bytes, self.config) {
+            self.merge_resumed(total_bytes, &config);
         }
+    }
 
-        // Outer/inner area ratio: ideal (7/3)^2 ~= 5.44. A generous window keeps
-        // it tolerant to warp/blur while still rejecting unrelated nestings.
-        let ratio = outer_area / inner_area;
-        if !(2.5..=12.0).contains(&ratio) {
-            continue;
+    fn merge_resumed(&mut self, total_bytes: u64, config: &EncodingConfig) {
+        let Some(state) = self.resumed.take() else {
+            return;
+        };
+        if !state.is_same_transfer(total_bytes, config) {
+            if self.options.show_progress {
+                eprintln!("Ignoring the saved state: it belongs to another transfer");
+            }
+            // Kept in case the reassembler switches to another session.
+            self.resumed = Some(state);
+            return;
         }
+        if self.metadata.is_none() {
+            self.metadata = state.metadata;
+        }
+        let mut merged = 0;
+        for (page, payload) in state.pages {
+            if self.insert_page(page, payload) {
+                merged += 1;
+            }
+        }
+        if self.options.show_progress {
+            eprintln!("Resumed {} pages from the saved state", merged);
+        }
+    }
 
-        candidates.push(((ox, oy), outer_area));
+    /// The verified pages received so far, and the Metadata frame's record, to
+    /// save and resume from. `None` until the Start frame is received. Fountain
+    /// symbols are not included.
+    pub fn state(&self) -> Option<TransferState> {
+        let mut pages: Vec<(u64, Vec<u8>)> = self
+            .pages
+            .iter()
+            .map(|(page, payload)| (*page, payload.clone()))
+            .collect();
+        pages.sort_unstable_by_key(|(page, _)| *page);
+        Some(TransferState {
+            total_bytes: self.total_bytes?,
+            config: self.config?,
+            metadata: self.metadata.clone(),
+            pages,
+        })
     }
 
-    let wf = w as f32;
-    let hf = h as f32;
-    let tl = best_in_region(&candidates, |cx, cy| cx < wf * 0.4 && cy < hf * 0.4)?;
-    let tr = best_in_region(&candidates, |cx, cy| cx > wf * 0.6 && cy < hf * 0.4)?;
-    let bl = best_in_region(&candidates, |cx, cy| cx < wf * 0.4 && cy > hf * 0.6)?;
-    Some([tl, tr, bl])
-}
+    /// Store a page not received before. Returns false for a duplicate.
+    fn insert_page(&mut self, page: u64, payload: Vec<u8>) -> bool {
+        match self.pages.entry(page) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(e) => {
+                self.page_len.get_or_insert(payload.len());
+                e.insert(payload);
+                while let Some(page) = self.pages.get(&self.contiguous_pages) {
+                    self.contiguous_bytes += page.len() as u64;
+                    self.contiguous_pages += 1;
+                }
+                true
+            }
+        }
+    }
 
-/// Pick the largest-area candidate whose centre satisfies `pred`.
-fn best_in_region(
-    candidates: &[((f32, f32), f64)],
-    pred: impl Fn(f32, f32) -> bool,
-) -> Option<(f32, f32)> {
-    candidates
-        .iter()
-        .filter(|((cx, cy), _)| pred(*cx, *cy))
-        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
-        .map(|(c, _)| *c)
-}
+    /// Decode frames with `config` from now on, from the Start frame of
+    /// `session` unless a session is already locked.
+    pub(crate) fn adopt_config(&mut self, config: EncodingConfig, session: u32) {
+        self.session.get_or_insert(session);
+        if self.options.show_progress {
+            if let Some(algo) = self.options.algo.filter(|&algo| algo != config.algo) {
+                eprintln!(
+                    "Ignoring --algo {}: the Start frame declares {}",
+                    algo, config.algo
+                );
+            }
+        }
+        self.config = Some(config);
+    }
 
-/// Decode a collection of (already aligned) frames back into the original bytes.
-///
-/// Each frame is decoded and its CRC checked; frames that fail the CRC (torn,
-/// garbled or transition frames) are dropped. The Start frame supplies the total
-/// byte count; Data frames are de-duplicated and ordered by their page number.
-pub fn frames_to_data(extract_options: &ExtractOptions, frames: Vec<VideoFrame>) -> Vec<u8> {
-    let mut pages: HashMap<u64, Vec<u8>> = HashMap::new();
-    let mut total_bytes: Option<u64> = None;
-    let mut relevant_frame_count = 0u64;
+    /// Decode `frame` and keep its payload when the CRC checks out and it is
+    /// new. A data or fountain frame failing its CRC is averaged with the other
+    /// copies of it seen so far, which can still recover the page when every
+    /// copy has a few bad cells. Until the encoding configuration is known,
+    /// frames are probed for the Start frame and anything else is dropped.
+    pub fn push(&mut self, frame: &VideoFrame) -> PushOutcome {
+        let config = match self.config {
+            Some(config) => config,
+            None => match find_encoding_config(&self.options, std::slice::from_ref(frame)) {
+                Some((config, session)) => {
+                    self.adopt_config(config, session);
+                    config
+                }
+                None => return PushOutcome::NoHeader,
+            },
+        };
 
-    let total_video_frame = frames.len() as u64;
-    let pb = ProgressBar::new(total_video_frame);
-    if extract_options.show_progress {
-        println!("Initial Frames count: {}", total_video_frame);
+        let frame_data = decode_frame(frame, &config);
+        let header = match frame_data.header {
+            Some(h) => h,
+            None => return PushOutcome::NoHeader,
+        };
+        // Only trust frames whose CRC checks out, which also covers the session.
+        if frame_data.crc_valid {
+            return self.accept_in_session(header, frame_data.payload, &config);
+        }
+        let Some(samples) = frame_data.samples.filter(|_| !self.is_received(header)) else {
+            return PushOutcome::CrcFail;
+        };
+        let Some(combined) = self.combiner.add(header, &samples) else {
+            return PushOutcome::CrcFail;
+        };
+        let combined = data_frame_info(header, decide_bytes(&combined, &config));
+        if !combined.crc_valid {
+            return PushOutcome::CrcFail;
+        }
+        self.combiner.remove(&header);
+        let outcome = self.accept_in_session(header, combined.payload, &config);
+        if !matches!(outcome, PushOutcome::OtherSession { .. }) {
+            self.combined += 1;
+        }
+        outcome
     }
 
-    for frame in frames.iter() {
-        let frame_data = match extract_options.algo {
-            AlgoFrame::RGB => frame_to_data_method_rgb(frame, extract_options),
-            AlgoFrame::BW => frame_to_data_method_bw(frame, extract_options),
-            AlgoFrame::Quantized(levels) => {
-                frame_to_data_method_quantized(frame, extract_options, levels)
-            }
-            AlgoFrame::Brightness(levels) => {
-                frame_to_data_method_brightness(frame, extract_options, levels)
+    /// Keep the verified `payload` of a frame with `header` when it belongs to
+    /// the session being decoded.
+    fn accept_in_session(
+        &mut self,
+        header: FrameHeader,
+        payload: Vec<u8>,
+        config: &EncodingConfig,
+    ) -> PushOutcome {
+        if header.frame_type == FrameType::Start {
+            *self.start_frames.entry(header.session).or_default() += 1;
+        }
+        match self.session.filter(|&locked| locked != header.session) {
+            Some(locked) if self.outnumbers(header, locked) => {
+                self.switch_session(locked, header.session);
+                self.accept(header, payload, config)
             }
+            Some(locked) => self.other_session(locked, header.session),
+            None => self.accept(header, payload, config),
+        }
+    }
+
+    /// Whether the Start frame `header` tips its session over the `locked` one,
+    /// when the options do not ask for a session.
+    fn outnumbers(&self, header: FrameHeader, locked: u32) -> bool {
+        let count = |session| self.start_frames.get(&session).copied().unwrap_or(0);
+        self.options.session.is_none()
+            && header.frame_type == FrameType::Start
+            && count(header.session) > count(locked)
+    }
+
+    /// Drop what was decoded of the `locked` session and decode `session`
+    /// instead.
+    fn switch_session(&mut self, locked: u32, session: u32) {
+        if self.options.show_progress {
+            eprintln!(
+                "Switching to session {}: it shows more Start frames than session {}",
+                format_session(session),
+                format_session(locked)
+            );
+        }
+        let mut other_sessions = std::mem::take(&mut self.other_sessions);
+        other_sessions.retain(|&other| other != session);
+        other_sessions.push(locked);
+        *self = Reassembler {
+            resumed: self.resumed.take(),
+            session: Some(session),
+            other_sessions,
+            start_frames: std::mem::take(&mut self.start_frames),
+            ..Reassembler::new(&self.options)
         };
+    }
 
-        if extract_options.show_progress {
-            pb.inc(1);
+    /// Ignore a frame of `session` while decoding `locked`, telling about it the
+    /// first time.
+    fn other_session(&mut self, locked: u32, session: u32) -> PushOutcome {
+        if !self.other_sessions.contains(&session) {
+            self.other_sessions.push(session);
+            if self.options.show_progress {
+                eprintln!(
+                    "Ignoring the frames of session {}: decoding session {} (pick another with --session)",
+                    format_session(session),
+                    format_session(locked)
+                );
+            }
         }
+        PushOutcome::OtherSession { session }
+    }
 
-        // Only trust frames whose CRC checks out.
-        if !frame_data.crc_valid {
-            continue;
+    /// Whether the page or fountain symbol of a data or fountain frame header
+    /// was already received.
+    fn is_received(&self, header: FrameHeader) -> bool {
+        match header.frame_type {
+            FrameType::Start => self.total_bytes.is_some(),
+            FrameType::Metadata => self.metadata.is_some(),
+            FrameType::Data => self.pages.contains_key(&header.value),
+            FrameType::Fountain => {
+                self.early_symbols.contains_key(&header.value)
+                    || self
+                        .fountain
+                        .as_ref()
+                        .is_some_and(|decoder| decoder.has_symbol(header.value))
+            }
         }
-        let header = match frame_data.header {
-            Some(h) => h,
-            None => continue,
-        };
+    }
 
+    /// Keep the verified `payload` of a frame with `header` when it is new.
+    fn accept(
+        &mut self,
+        header: FrameHeader,
+        payload: Vec<u8>,
+        config: &EncodingConfig,
+    ) -> PushOutcome {
+        self.combiner.forget(header.frame_type, header.value);
+        let duplicate = PushOutcome::Duplicate {
+            frame_type: header.frame_type,
+            value: header.value,
+        };
         match header.frame_type {
             FrameType::Start => {
-                total_bytes = Some(header.value);
-                if extract_options.show_progress {
-                    println!("Start frame found with data size of {}", header.value);
+                if self.total_bytes.is_some() {
+                    return duplicate;
+                }
+                if self.options.show_progress {
+                    eprintln!(
+                        "Start frame found with data size of {} (session {})",
+                        header.value,
+                        format_session(header.session)
+                    );
+                }
+                // The configuration in use may have been given before this
+                // frame; the file hash only comes with it.
+                let config = EncodingConfig::from_bytes(&payload).unwrap_or(*config);
+                self.config = Some(config);
+                self.total_bytes = Some(header.value);
+                self.merge_resumed(header.value, &config);
+                for (seed, symbol) in std::mem::take(&mut self.early_symbols) {
+                    self.feed_symbol(header.value, seed, symbol);
+                }
+                PushOutcome::Start {
+                    total_bytes: header.value,
+                }
+            }
+            FrameType::Metadata => {
+                if self.metadata.is_some() {
+                    return duplicate;
                 }
+                // A record this decoder does not understand is ignored.
+                let Some(metadata) = payload
+                    .get(..header.value as usize)
+                    .and_then(FileMetadata::from_bytes)
+                else {
+                    return PushOutcome::NoHeader;
+                };
+                if self.options.show_progress {
+                    eprintln!(
+                        "Metadata frame found: {} ({} bytes, {})",
+                        metadata.name, metadata.size, metadata.mime
+                    );
+                }
+                self.metadata = Some(metadata);
+                PushOutcome::Metadata
             }
             FrameType::Data => {
-                if let std::collections::hash_map::Entry::Vacant(e) = pages.entry(header.value) {
-                    e.insert(frame_data.payload);
-                    relevant_frame_count += 1;
+                if self.insert_page(header.value, payload) {
+                    PushOutcome::NewPage { page: header.value }
+                } else {
+                    duplicate
+                }
+            }
+            FrameType::Fountain => {
+                let seed = header.value;
+                let new = match self.total_bytes {
+                    Some(expected) => self.feed_symbol(expected, seed, payload),
+                    None => match self.early_symbols.entry(seed) {
+                        Entry::Occupied(_) => false,
+                        Entry::Vacant(e) => {
+                            e.insert(payload);
+                            true
+                        }
+                    },
+                };
+                if new {
+                    PushOutcome::NewSymbol { seed }
+                } else {
+                    duplicate
                 }
             }
         }
     }
 
-    if extract_options.show_progress {
-        pb.finish_with_message("done");
-        println!(
-            "Relevant (unique, valid) data frames: {}",
-            relevant_frame_count
-        );
+    /// Hand a fountain symbol to the LT decoder, creating it on the first one:
+    /// every symbol is one source block long, so the block length (and therefore
+    /// the number of source blocks for `expected` bytes) follows from it.
+    /// Returns true when the symbol was new.
+    fn feed_symbol(&mut self, expected: u64, seed: u64, symbol: Vec<u8>) -> bool {
+        let block_len = symbol.len();
+        let decoder = self.fountain.get_or_insert_with(|| {
+            FountainDecoder::new((expected as usize).div_ceil(block_len.max(1)), block_len)
+        });
+        let before = decoder.received_symbols();
+        decoder.add_symbol(seed, symbol);
+        decoder.received_symbols() > before
     }
 
-    match total_bytes {
-        Some(expected) => {
-            // Merge the pages in order, starting at page 0.
-            let mut byte_data = Vec::new();
-            let mut page_index = 0u64;
-            while let Some(payload) = pages.get(&page_index) {
-                byte_data.extend(payload);
-                page_index += 1;
-            }
+    /// Number of pages (or fountain symbols) that no single copy got right,
+    /// recovered by averaging several copies.
+    pub fn combined_pages(&self) -> u64 {
+        self.combined
+    }
 
-            if (byte_data.len() as u64) < expected {
-                panic!(
-                    "We have not received all frames. We assembled {} pages for a total of {} bytes and expected {} bytes",
-                    page_index,
-                    byte_data.len(),
-                    expected
-                );
-            }
+    /// What the Metadata frame says about the file, once received.
+    pub fn metadata(&self) -> Option<&FileMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Transfer session being decoded, once locked.
+    pub fn session(&self) -> Option<u32> {
+        self.session
+    }
+
+    /// Sessions whose frames were ignored, in the order they were first seen.
+    pub fn other_sessions(&self) -> &[u32] {
+        &self.other_sessions
+    }
+
+    /// Total byte count announced by the Start frame, once received.
+    pub fn total_bytes(&self) -> Option<u64> {
+        self.total_bytes
+    }
 
-            // Drop the NULL padding from the last frame.
-            byte_data.into_iter().take(expected as usize).collect()
+    /// Number of data pages (or, in fountain mode, source blocks) the file
+    /// needs. `None` until the Start frame is received. Before any data page
+    /// arrives the page size follows from the Start frame's configuration.
+    pub fn expected_pages(&self) -> Option<u64> {
+        let total_bytes = self.total_bytes?;
+        if let Some(decoder) = &self.fountain {
+            return Some(decoder.source_blocks() as u64);
         }
-        None => {
-            panic!("Instruction not found while extracting data from video");
+        let page_len = match (self.page_len, self.config) {
+            (Some(page_len), _) => page_len,
+            (None, Some(config)) => fec_data_capacity(raw_bytes_per_frame(&config), config.fec),
+            (None, None) => return None,
+        };
+        Some(total_bytes.div_ceil(page_len.max(1) as u64))
+    }
+
+    /// Number of distinct data pages (or fountain symbols) received.
+    pub fn received_pages(&self) -> u64 {
+        match &self.fountain {
+            Some(decoder) => decoder.received_symbols() as u64,
+            None => (self.pages.len() + self.early_symbols.len()) as u64,
         }
     }
-}
 
-/// Read the per-frame header from the first `HEADER_BITS` content cells. The
-/// header is always written black/white regardless of the payload algorithm.
-fn read_header(source: &VideoFrame, width: u16, size: u8) -> Option<FrameHeader> {
-    let bits: Vec<bool> = (0..HEADER_BITS)
-        .map(|i| {
-            let (x, y) = content_cell_xy(i, width, size);
-            let rgb = get_pixel(source, x as i32, y as i32, size);
-            get_bit_from_rgb(&rgb)
+    /// Data pages still missing, in order. Empty until the page count is known
+    /// and in fountain mode, where any symbols can stand in for any page.
+    pub fn missing_pages(&self) -> Vec<u64> {
+        if self.fountain.is_some() {
+            return Vec::new();
+        }
+        match self.expected_pages() {
+            Some(expected) => (self.contiguous_pages..expected)
+                .filter(|page| !self.pages.contains_key(page))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The pages still missing, for a repair video. `None` until the Start
+    /// frame is received and in fountain mode.
+    pub fn missing_report(&self) -> Option<MissingPagesReport> {
+        if self.fountain.is_some() {
+            return None;
+        }
+        Some(MissingPagesReport {
+            session: self.session?,
+            total_bytes: self.total_bytes?,
+            expected_pages: self.expected_pages()?,
+            received_pages: self.pages.len() as u64,
+            missing: self.missing_pages(),
         })
-        .collect();
-    FrameHeader::from_bits(&bits)
-}
+    }
 
-/// Decode a frame whose payload was encoded with RGB (3 bytes per content cell).
-fn frame_to_data_method_rgb(source: &VideoFrame, options: &ExtractOptions) -> FrameBytesInfo {
-    let width = options.width;
-    let height = options.height;
-    let size = options.size;
+    /// True once the Start frame and every page have been received, or the
+    /// fountain decoder has recovered every source block, and the Metadata
+    /// frame too when the Start frame announces one.
+    pub fn is_complete(&self) -> bool {
+        let pages = match (self.total_bytes, &self.fountain) {
+            (None, _) => false,
+            (Some(_), Some(decoder)) => decoder.is_complete(),
+            (Some(expected), None) => self.contiguous_bytes >= expected,
+        };
+        pages && !self.is_missing_metadata()
+    }
 
-    let header = match read_header(source, width, size) {
-        Some(h) => h,
-        None => {
-            return FrameBytesInfo {
-                header: None,
-                payload: Vec::new(),
-                crc_valid: false,
-            }
+    /// True when the Start frame announces a Metadata frame not received yet.
+    pub fn is_missing_metadata(&self) -> bool {
+        self.metadata.is_none() && self.config.is_some_and(|config| config.metadata)
+    }
+
+    /// The reassembled bytes, decompressed when the Start frame names a codec,
+    /// once they hash to the SHA-256 it declares, or what went wrong
+    /// ([`HdmiError::MissingStart`], [`HdmiError::MissingPages`],
+    /// [`HdmiError::NotEnoughSymbols`], [`HdmiError::Decompress`] or
+    /// [`HdmiError::HashMismatch`]).
+    pub fn finish(self) -> Result<Vec<u8>, HdmiError> {
+        self.verified_bytes()
+    }
+
+    /// Like [`Reassembler::finish`], with what the Metadata frame says about
+    /// the file when one was received. [`HdmiError::MissingMetadata`] when the
+    /// Start frame announces one that was not received: the file name, and
+    /// whether the bytes are a container to unpack, are unknown.
+    pub fn finish_file(mut self) -> Result<ExtractedFile, HdmiError> {
+        let missing_metadata = self.is_missing_metadata();
+        let metadata = self.metadata.take();
+        let data = self.verified_bytes()?;
+        if missing_metadata {
+            return Err(HdmiError::MissingMetadata);
         }
-    };
+        Ok(ExtractedFile { data, metadata })
+    }
 
-    match header.frame_type {
-        FrameType::Start => {
-            let crc_valid = header.verify(&[]);
-            FrameBytesInfo {
-                header: Some(header),
-                payload: Vec::new(),
-                crc_valid,
-            }
+    /// The reassembled bytes, decompressed and checked against the SHA-256 of
+    /// the Start frame.
+    fn verified_bytes(self) -> Result<Vec<u8>, HdmiError> {
+        let expected = self.total_bytes.ok_or(HdmiError::MissingStart)?;
+        let config = self.config.ok_or(HdmiError::MissingStart)?;
+        let sha256 = config.sha256;
+        let show_progress = self.options.show_progress;
+        let byte_data = config
+            .compression
+            .decompress(self.into_bytes(expected)?, config.file_size)?;
+        if show_progress && config.compression != Compression::None {
+            eprintln!(
+                "Decompressed {} bytes of {} into {} bytes",
+                expected,
+                config.compression,
+                byte_data.len()
+            );
         }
-        FrameType::Data => {
-            let capacity = frame_capacity(width, height, size);
-            let mut payload = Vec::with_capacity(capacity * 3);
-            for cell in 0..capacity {
-                let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
-                let rgb = get_pixel(source, x as i32, y as i32, size);
-                payload.push(rgb[0]);
-                payload.push(rgb[1]);
-                payload.push(rgb[2]);
-            }
-            let crc_valid = header.verify(&payload);
-            FrameBytesInfo {
-                header: Some(header),
-                payload,
-                crc_valid,
-            }
+
+        let actual = file_sha256(&byte_data);
+        if actual != sha256 {
+            return Err(HdmiError::HashMismatch {
+                expected: sha256_hex(&sha256),
+                actual: sha256_hex(&actual),
+            });
         }
+        if show_progress {
+            eprintln!("SHA-256 verified: {}", sha256_hex(&actual));
+        }
+        Ok(byte_data)
+    }
+
+    /// The `expected` bytes of the file, from the fountain decoder or the
+    /// pages in order.
+    fn into_bytes(self, expected: u64) -> Result<Vec<u8>, HdmiError> {
+        if let Some(decoder) = self.fountain {
+            let received = decoder.received_symbols() as u64;
+            let recovered_blocks = decoder.recovered_blocks() as u64;
+            let source_blocks = decoder.source_blocks() as u64;
+            return match decoder.into_data() {
+                Some(mut byte_data) => {
+                    // Drop the NULL padding from the last block.
+                    byte_data.truncate(expected as usize);
+                    Ok(byte_data)
+                }
+                None => Err(HdmiError::NotEnoughSymbols {
+                    received,
+                    recovered_blocks,
+                    source_blocks,
+                }),
+            };
+        }
+
+        if self.contiguous_bytes < expected {
+            let missing = self.missing_pages();
+            return Err(HdmiError::MissingPages {
+                received: self.pages.len() as u64,
+                expected: self.expected_pages().unwrap_or_default(),
+                missing,
+            });
+        }
+
+        // Merge the pages in order, starting at page 0, and drop the NULL
+        // padding from the last frame.
+        let mut pages = self.pages;
+        let mut byte_data = Vec::with_capacity(self.contiguous_bytes as usize);
+        for page in 0..self.contiguous_pages {
+            byte_data.extend(pages.remove(&page).unwrap_or_default());
+        }
+        byte_data.truncate(expected as usize);
+        Ok(byte_data)
     }
 }
 
-/// Decode a frame whose payload was encoded with black/white (1 bit per content
-/// cell, 8 cells per byte, most-significant bit first).
-fn frame_to_data_method_bw(source: &VideoFrame, options: &ExtractOptions) -> FrameBytesInfo {
-    let width = options.width;
-    let height = options.height;
-    let size = options.size;
+/// Raw payload bytes the cells of one frame hold for the algo of `config`,
+/// before any FEC parity is taken out.
+fn raw_bytes_per_frame(config: &EncodingConfig) -> usize {
+    let capacity = frame_capacity(config.width, config.height, config.size, config.lattice);
+    match config.algo {
+        AlgoFrame::RGB => capacity * 3,
+        AlgoFrame::BW => capacity / 8,
+        AlgoFrame::Quantized(levels) => capacity * 3 * bits_per_channel(levels) as usize / 8,
+        AlgoFrame::Brightness(levels) => capacity * bits_per_channel(levels) as usize / 8,
+    }
+}
 
-    let header = match read_header(source, width, size) {
+/// Decode one registered frame with the payload algorithm of `config`, after
+/// correcting its grid from the alignment lattice when the stream has one. The
+/// cell samples of a data, fountain or metadata frame failing its CRC are kept
+/// for [`SoftCombiner`].
+fn decode_frame(frame: &VideoFrame, config: &EncodingConfig) -> FrameBytesInfo {
+    let corrected;
+    let frame = if config.lattice {
+        corrected = correct_mesh(frame, config.width, config.height, config.size);
+        &corrected
+    } else {
+        frame
+    };
+    let header = match read_header(frame, config.width, config.size) {
         Some(h) => h,
         None => {
             return FrameBytesInfo {
                 header: None,
                 payload: Vec::new(),
                 crc_valid: false,
+                samples: None,
             }
         }
     };
-
     match header.frame_type {
-        FrameType::Start => {
-            let crc_valid = header.verify(&[]);
-            FrameBytesInfo {
-                header: Some(header),
-                payload: Vec::new(),
-                crc_valid,
+        FrameType::Start => start_frame_info(frame, header, config.width, config.size),
+        FrameType::Data | FrameType::Fountain | FrameType::Metadata => {
+            let samples = sample_cells(frame, config);
+            let mut info = data_frame_info(header, decide_bytes(&samples, config));
+            if !info.crc_valid {
+                info.samples = Some(samples);
             }
+            info
         }
-        FrameType::Data => {
-            let capacity = frame_capacity(width, height, size);
-            let bytes_per_frame = capacity / 8;
-            let mut payload = Vec::with_capacity(bytes_per_frame);
+    }
+}
+
+/// Read the per-frame header from the first `HEADER_BITS` content cells. The
+/// header is always written black/white regardless of the payload algorithm,
+/// and above the alignment lattice, so it reads the same with or without it.
+/// Returns `None` when `width` leaves no content column at this cell size.
+fn read_header(source: &VideoFrame, width: u16, size: u8) -> Option<FrameHeader> {
+    if content_cols(width, size) == 0 {
+        return None;
+    }
+    let bits: Vec<bool> = (0..HEADER_BITS)
+        .map(|i| {
+            let (x, y) = content_cell_xy(i, width, source.height(), size, false);
+            let rgb = get_pixel(source, x as i32, y as i32, size);
+            get_bit_from_rgb(&rgb)
+        })
+        .collect();
+    FrameHeader::from_bits(&bits)
+}
+
+/// Read `count` bytes stored black/white (8 cells per byte, most-significant
+/// bit first) starting at content cell `first_cell`, within the first
+/// `START_CELLS` cells, which the alignment lattice never takes.
+fn read_bw_bytes(
+    source: &VideoFrame,
+    width: u16,
+    size: u8,
+    first_cell: usize,
+    count: usize,
+) -> Vec<u8> {
+    (0..count)
+        .map(|byte| {
             let mut data: u8 = 0;
-            let mut bit_index: u8 = 7;
-            for cell in 0..(bytes_per_frame * 8) {
-                let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
+            for bit_index in (0..8u8).rev() {
+                let cell = first_cell + byte * 8 + (7 - bit_index) as usize;
+                let (x, y) = content_cell_xy(cell, width, source.height(), size, false);
                 let rgb = get_pixel(source, x as i32, y as i32, size);
-                let bit_value = get_bit_from_rgb(&rgb);
-                mutate_byte(&mut data, bit_value, bit_index);
-                if bit_index == 0 {
-                    payload.push(data);
-                    data = 0;
-                    bit_index = 7;
-                } else {
-                    bit_index -= 1;
-                }
+                mutate_byte(&mut data, get_bit_from_rgb(&rgb), bit_index);
             }
-            let crc_valid = header.verify(&payload);
-            FrameBytesInfo {
-                header: Some(header),
-                payload,
-                crc_valid,
-            }
-        }
-    }
+            data
+        })
+        .collect()
 }
 
-/// Decode a frame whose payload was encoded with quantized colour: each channel
-/// of each content cell is rounded to its nearest of `levels` symbols, yielding
-/// `log2(levels)` bits per channel. The bit stream is read R, then G, then B per
-/// cell, most-significant bit first, matching the injection order.
-fn frame_to_data_method_quantized(
+/// Read the encoding configuration that follows the header of a Start frame.
+/// The CRC covers it, and a configuration this decoder does not understand
+/// (e.g. another format version) makes the frame invalid.
+fn start_frame_info(
     source: &VideoFrame,
-    options: &ExtractOptions,
-    levels: u32,
+    header: FrameHeader,
+    width: u16,
+    size: u8,
 ) -> FrameBytesInfo {
-    let width = options.width;
-    let height = options.height;
-    let size = options.size;
+    let payload = read_bw_bytes(source, width, size, HEADER_BITS, ENCODING_CONFIG_BYTES);
+    let crc_valid = header.verify(&payload) && EncodingConfig::from_bytes(&payload).is_some();
+    FrameBytesInfo {
+        header: Some(header),
+        payload,
+        crc_valid,
+        samples: None,
+    }
+}
 
-    let header = match read_header(source, width, size) {
-        Some(h) => h,
-        None => {
-            return FrameBytesInfo {
-                header: None,
-                payload: Vec::new(),
-                crc_valid: false,
-            }
-        }
-    };
+/// The encoding configuration and the session of `source` when it is a valid
+/// Start frame laid out with the given width and cell size, of the session
+/// `options` asks for if any.
+pub(crate) fn start_config_at(
+    options: &ExtractOptions,
+    source: &VideoFrame,
+    width: u16,
+    size: u8,
+) -> Option<(EncodingConfig, u32)> {
+    let header = read_header(source, width, size)?;
+    if header.frame_type != FrameType::Start
+        || options
+            .session
+            .is_some_and(|session| session != header.session)
+    {
+        return None;
+    }
+    let info = start_frame_info(source, header, width, size);
+    if !info.crc_valid {
+        return None;
+    }
+    Some((EncodingConfig::from_bytes(&info.payload)?, header.session))
+}
 
-    match header.frame_type {
-        FrameType::Start => {
-            let crc_valid = header.verify(&[]);
-            FrameBytesInfo {
-                header: Some(header),
-                payload: Vec::new(),
-                crc_valid,
-            }
-        }
-        FrameType::Data => {
-            let bits_chan = bits_per_channel(levels) as usize;
-            let capacity = frame_capacity(width, height, size);
-            let bytes_per_frame = capacity * 3 * bits_chan / 8;
-            let needed_bits = bytes_per_frame * 8;
+/// Largest cell size tried when probing for the Start frame without `--size`.
+const MAX_PROBE_SIZE: u8 = 16;
 
-            let mut bits: Vec<bool> = Vec::with_capacity(needed_bits + 24);
-            'cells: for cell in 0..capacity {
-                let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
-                let rgb = get_pixel(source, x as i32, y as i32, size);
-                for &value in &rgb {
-                    let symbol = value_to_symbol(value, levels);
-                    for k in (0..bits_chan).rev() {
-                        bits.push((symbol >> k) & 1 == 1);
-                    }
-                    if bits.len() >= needed_bits {
-                        break 'cells;
-                    }
-                }
-            }
+/// Geometries `(width, height, size)` to try when looking for the Start frame
+/// in a frame of `frame_width` x `frame_height` pixels: the given options where
+/// set, otherwise the frame's own dimensions and every cell size dividing them.
+pub(crate) fn candidate_geometries(
+    options: &ExtractOptions,
+    frame_width: u16,
+    frame_height: u16,
+) -> Vec<(u16, u16, u8)> {
+    let width = options.width.unwrap_or(frame_width);
+    let height = options.height.unwrap_or(frame_height);
+    let sizes: Vec<u8> = match options.size {
+        Some(size) => vec![size],
+        None => (1..=MAX_PROBE_SIZE)
+            .filter(|&size| width.is_multiple_of(size as u16) && height.is_multiple_of(size as u16))
+            .collect(),
+    };
+    sizes
+        .into_iter()
+        .map(|size| (width, height, size))
+        .collect()
+}
 
-            let mut payload = Vec::with_capacity(bytes_per_frame);
-            for byte_index in 0..bytes_per_frame {
-                let mut data: u8 = 0;
-                for k in 0..8 {
-                    let bit = bits.get(byte_index * 8 + k).copied().unwrap_or(false);
-                    data = (data << 1) | bit as u8;
-                }
-                payload.push(data);
-            }
+/// Find the first valid Start frame (of the session `options` asks for, if
+/// any) among registered `frames` and return the configuration it carries and
+/// its session.
+fn find_encoding_config(
+    options: &ExtractOptions,
+    frames: &[VideoFrame],
+) -> Option<(EncodingConfig, u32)> {
+    frames.iter().find_map(|frame| {
+        candidate_geometries(options, frame.width(), frame.height())
+            .into_iter()
+            .find_map(|(width, _, size)| start_config_at(options, frame, width, size))
+    })
+}
 
+/// Turn the raw bytes read from the payload cells of a data frame into the page
+/// payload: apply the Reed-Solomon correction announced by the header (if any)
+/// and check the CRC over the corrected data bytes. A frame with more errors
+/// than the parity can repair is reported with an invalid CRC.
+fn data_frame_info(header: FrameHeader, raw: Vec<u8>) -> FrameBytesInfo {
+    let data_len = fec_data_capacity(raw.len(), header.fec);
+    match fec_decode(&raw, header.fec, data_len) {
+        Some(payload) => {
             let crc_valid = header.verify(&payload);
             FrameBytesInfo {
                 header: Some(header),
                 payload,
                 crc_valid,
+                samples: None,
             }
         }
+        None => FrameBytesInfo {
+            header: Some(header),
+            payload: raw,
+            crc_valid: false,
+            samples: None,
+        },
     }
 }
 
-/// Decode a frame whose payload was encoded with brightness (luma): each content
-/// cell is a single grey shade, so its three (averaged) channels are collapsed to
-/// one value and rounded to the nearest of `levels` symbols, yielding
-/// `log2(levels)` bits per cell, most-significant bit first.
-fn frame_to_data_method_brightness(
-    source: &VideoFrame,
-    options: &ExtractOptions,
-    levels: u32,
-) -> FrameBytesInfo {
-    let width = options.width;
-    let height = options.height;
-    let size = options.size;
-
-    let header = match read_header(source, width, size) {
-        Some(h) => h,
-        None => {
-            return FrameBytesInfo {
-                header: None,
-                payload: Vec::new(),
-                crc_valid: false,
-            }
-        }
+/// Sample every payload cell of a data or fountain frame, and the patches of
+/// its calibration ramp for the algos that measure one.
+fn sample_cells(source: &VideoFrame, config: &EncodingConfig) -> CellSamples {
+    let (width, height, size, lattice) = (config.width, config.height, config.size, config.lattice);
+    let sample = |x: u16, y: u16| {
+        let rgb = get_pixel(source, x as i32, y as i32, size);
+        [rgb[0], rgb[1], rgb[2]]
+    };
+    let cells = (0..frame_capacity(width, height, size, lattice))
+        .map(|cell| {
+            let (x, y) = content_cell_xy(HEADER_BITS + cell, width, height, size, lattice);
+            sample(x, y)
+        })
+        .collect();
+    let patches = match config.algo {
+        AlgoFrame::Quantized(levels) => 3 * levels as usize,
+        AlgoFrame::Brightness(levels) => levels as usize,
+        AlgoFrame::RGB | AlgoFrame::BW => 0,
     };
+    let ramp = if calibration_ramp(config.algo, width, size).is_empty() {
+        Vec::new()
+    } else {
+        (0..patches)
+            .map(|patch| {
+                let (x, y) = ramp_patch_xy(patch, size);
+                sample(x, y)
+            })
+            .collect()
+    };
+    CellSamples { cells, ramp }
+}
 
-    match header.frame_type {
-        FrameType::Start => {
-            let crc_valid = header.verify(&[]);
-            FrameBytesInfo {
-                header: Some(header),
-                payload: Vec::new(),
-                crc_valid,
-            }
+/// Decide the symbol of every sampled cell with the payload algorithm of
+/// `config` and pack the bits into the raw payload bytes, parity included.
+///
+/// RGB cells carry 3 bytes each. Black/white cells carry 1 bit each, 8 cells
+/// per byte. Quantized cells map each channel to its nearest of `levels`
+/// symbols, using the per-channel thresholds measured from the calibration
+/// ramp, read R, then G, then B. Brightness cells collapse to one grey value
+/// mapped the same way against the grey ramp. Bits are most-significant first,
+/// matching the injection order.
+fn decide_bytes(samples: &CellSamples, config: &EncodingConfig) -> Vec<u8> {
+    let capacity = samples.cells.len();
+    match config.algo {
+        AlgoFrame::RGB => samples.cells.iter().flatten().copied().collect(),
+        AlgoFrame::BW => {
+            let bits = samples
+                .cells
+                .iter()
+                .map(|rgb| get_bit_from_rgb(&rgb.to_vec()));
+            pack_bits(bits, capacity / 8)
+        }
+        AlgoFrame::Quantized(levels) => {
+            let bits_chan = bits_per_channel(levels) as usize;
+            let thresholds = level_thresholds(&samples.ramp, AlgoFrame::Quantized(levels));
+            let bits = samples.cells.iter().flat_map(|rgb| {
+                rgb.iter()
+                    .zip(&thresholds)
+                    .flat_map(|(&value, thresholds)| {
+                        symbol_bits(thresholds.symbol(value), bits_chan)
+                    })
+                    .collect::<Vec<_>>()
+            });
+            pack_bits(bits, capacity * 3 * bits_chan / 8)
         }
-        FrameType::Data => {
+        AlgoFrame::Brightness(levels) => {
             let bits_cell = bits_per_channel(levels) as usize;
-            let capacity = frame_capacity(width, height, size);
-            let bytes_per_frame = capacity * bits_cell / 8;
-            let needed_bits = bytes_per_frame * 8;
+            let thresholds = level_thresholds(&samples.ramp, AlgoFrame::Brightness(levels));
+            let bits = samples
+                .cells
+                .iter()
+                .flat_map(|rgb| symbol_bits(thresholds[0].symbol(gray_of(rgb)), bits_cell));
+            pack_bits(bits, capacity * bits_cell / 8)
+        }
+    }
+}
 
-            let mut bits: Vec<bool> = Vec::with_capacity(needed_bits + 8);
-            'cells: for cell in 0..capacity {
-                let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
-                let rgb = get_pixel(source, x as i32, y as i32, size);
-                // Collapse to luma: the channels are nominally equal, so an
-                // average rejects per-channel chroma noise.
-                let gray = ((rgb[0] as u32 + rgb[1] as u32 + rgb[2] as u32) / 3) as u8;
-                let symbol = value_to_symbol(gray, levels);
-                for k in (0..bits_cell).rev() {
-                    bits.push((symbol >> k) & 1 == 1);
-                    if bits.len() >= needed_bits {
-                        break 'cells;
-                    }
-                }
-            }
+/// The `bits` low bits of `symbol`, most-significant first.
+fn symbol_bits(symbol: u32, bits: usize) -> impl Iterator<Item = bool> {
+    (0..bits).rev().map(move |k| (symbol >> k) & 1 == 1)
+}
 
-            let mut payload = Vec::with_capacity(bytes_per_frame);
-            for byte_index in 0..bytes_per_frame {
-                let mut data: u8 = 0;
-                for k in 0..8 {
-                    let bit = bits.get(byte_index * 8 + k).copied().unwrap_or(false);
-                    data = (data << 1) | bit as u8;
-                }
-                payload.push(data);
-            }
+/// Pack a bit stream into `bytes` bytes, most-significant bit first. Missing
+/// bits read as zero and extra ones are dropped.
+fn pack_bits(bits: impl Iterator<Item = bool>, bytes: usize) -> Vec<u8> {
+    let mut bits = bits.take(bytes * 8);
+    (0..bytes)
+        .map(|_| {
+            (0..8).fold(0u8, |data, _| {
+                (data << 1) | bits.next().unwrap_or(false) as u8
+            })
+        })
+        .collect()
+}
 
-            let crc_valid = header.verify(&payload);
-            FrameBytesInfo {
-                header: Some(header),
-                payload,
-                crc_valid,
+/// Collapse a sampled cell to luma: the channels of a grey cell are nominally
+/// equal, so an average rejects per-channel chroma noise.
+fn gray_of(rgb: &[u8]) -> u8 {
+    ((rgb[0] as u32 + rgb[1] as u32 + rgb[2] as u32) / 3) as u8
+}
+
+/// Derive the decision thresholds for `algo` from the sampled patches of the
+/// calibration ramp: one set per channel (red, green, blue) for `Quantized`, a
+/// single grey set for `Brightness`. A channel whose ramp is missing or
+/// unreadable falls back to evenly spaced thresholds.
+fn level_thresholds(ramp: &[[u8; 3]], algo: AlgoFrame) -> Vec<LevelThresholds> {
+    let (levels, channels) = match algo {
+        AlgoFrame::Quantized(levels) => (levels, 3),
+        AlgoFrame::Brightness(levels) => (levels, 1),
+        AlgoFrame::RGB | AlgoFrame::BW => return Vec::new(),
+    };
+    (0..channels)
+        .map(|channel| {
+            if ramp.is_empty() {
+                return LevelThresholds::even(levels);
             }
-        }
-    }
+            let measured: Vec<f32> = (0..levels as usize)
+                .map(|symbol| {
+                    let rgb = &ramp[channel * levels as usize + symbol];
+                    if channels == 1 {
+                        gray_of(rgb) as f32
+                    } else {
+                        rgb[channel] as f32
+                    }
+                })
+                .collect();
+            LevelThresholds::from_measured(&measured)
+                .unwrap_or_else(|| LevelThresholds::even(levels))
+        })
+        .collect()
 }
 
 /// Extract a pixel value that might be spread on many sibling pixel to reduce innacuracy
@@ -590,8 +1040,8 @@ fn get_pixel(frame: &VideoFrame, x: i32, y: i32, size: u8) -> Vec<u8> {
     let mut g_list: Vec<u8> = Vec::new();
     let mut b_list: Vec<u8> = Vec::new();
 
-    let max_x = frame.image.cols() - 1;
-    let max_y = frame.image.rows() - 1;
+    let max_x = i32::from(frame.width()) - 1;
+    let max_y = i32::from(frame.height()) - 1;
 
     // Sample the center of the cell rather than its top-left corner. The corner
     // sits on the seam between cells, exactly where a registration warp's
@@ -609,13 +1059,10 @@ fn get_pixel(frame: &VideoFrame, x: i32, y: i32, size: u8) -> Vec<u8> {
             // registration warp) never reads out of bounds.
             let sample_y = (y + i).clamp(0, max_y);
             let sample_x = (x + j).clamp(0, max_x);
-            let bgr = frame
-                .image
-                .at_2d::<opencv::core::Vec3b>(sample_y, sample_x)
-                .unwrap();
-            r_list.push(bgr[2]);
-            g_list.push(bgr[1]);
-            b_list.push(bgr[0]);
+            let [r, g, b] = frame.buffer.pixel(sample_x as u16, sample_y as u16);
+            r_list.push(r);
+            g_list.push(g);
+            b_list.push(b);
         }
     }
 
@@ -633,54 +1080,122 @@ fn get_pixel(frame: &VideoFrame, x: i32, y: i32, size: u8) -> Vec<u8> {
 /// Move all the data from gathered from the movie file into
 /// a file that should be the original file.
 ///
+/// When the output path is a directory, the file is written into it under the
+/// name carried by the Metadata frame, with its modification time and
+/// permission bits restored.
+///
+/// A multi-file container, as announced by the Metadata frame, is unpacked
+/// instead: its tree is rebuilt under the output path, created as a directory,
+/// once every file matches its manifest. A plain file is written as it is,
+/// whatever its first bytes.
+///
 /// # Example
 /// if we injected a .zip file, we expect the file to be written to be also a .zip
 ///
-pub fn data_to_files(extract_options: &ExtractOptions, whole_movie_data: Vec<u8>) {
-    fs::write(
-        extract_options.extracted_file_path.clone(),
-        whole_movie_data,
-    )
-    .expect("Writing file fail");
+pub fn data_to_files(
+    extract_options: &ExtractOptions,
+    file: ExtractedFile,
+) -> Result<(), HdmiError> {
+    if file.is_container() {
+        let files = read_container(&file.data)?;
+        let dir = Path::new(&extract_options.extracted_file_path);
+        fs::create_dir_all(dir).map_err(|source| HdmiError::WriteFile {
+            path: extract_options.extracted_file_path.clone(),
+            source,
+        })?;
+        unpack_container(&files, dir)?;
+        if extract_options.show_progress {
+            eprintln!("Extracted {} files into {:?}", files.len(), dir);
+        }
+        remove_transfer_sidecars(extract_options);
+        return Ok(());
+    }
+    let into_directory = Path::new(&extract_options.extracted_file_path).is_dir();
+    let path = file.output_path(&extract_options.extracted_file_path);
+    fs::write(&path, &file.data).map_err(|source| HdmiError::WriteFile {
+        path: path.to_string_lossy().to_string(),
+        source,
+    })?;
+    if into_directory {
+        if let Some(metadata) = &file.metadata {
+            metadata.restore(&path)?;
+        }
+        if extract_options.show_progress {
+            eprintln!("Extracted file written to {:?}", path);
+        }
+    }
+    remove_transfer_sidecars(extract_options);
+    Ok(())
+}
+
+/// Remove the saved state and the missing pages report of the transfer
+/// extracted to `extract_options.extracted_file_path`, once the file is written:
+/// until then, a failed write can still be retried from them.
+fn remove_transfer_sidecars(extract_options: &ExtractOptions) {
+    let extracted_file_path = &extract_options.extracted_file_path;
+    let sidecars = [
+        state_file_path(extracted_file_path),
+        missing_report_path(extracted_file_path),
+    ];
+    for path in sidecars.into_iter().flatten() {
+        if let Err(err) = fs::remove_file(&path) {
+            if extract_options.show_progress && err.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("Unable to remove {:?}: {}", path, err);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod extractionlogics_tests {
     use super::*;
-    use crate::injectionlogics::{create_starting_frame, data_to_frames};
+    use crate::filemetadata::{mime_hint, FileMetadata};
+    use crate::injectionlogics::{
+        create_metadata_frame, create_starting_frame, data_to_frames, start_frame,
+        transfer_to_frames,
+    };
+    use crate::instructionlogics::SHA256_BYTES;
     use crate::options::InjectOptions;
 
     fn inject_opts(algo: AlgoFrame) -> InjectOptions {
         InjectOptions {
-            file_path: String::new(),
-            output_video_file: String::new(),
-            fps: 30,
             width: 64,
             height: 64,
-            size: 1,
             algo,
-            show_progress: false,
+            ..Default::default()
         }
     }
 
     fn extract_opts(algo: AlgoFrame) -> ExtractOptions {
         ExtractOptions {
-            video_file_path: String::new(),
-            extracted_file_path: String::new(),
-            fps: 30,
+            width: Some(64),
+            height: Some(64),
+            size: Some(1),
+            algo: Some(algo),
+            ..Default::default()
+        }
+    }
+
+    fn encoding_config(algo: AlgoFrame) -> EncodingConfig {
+        EncodingConfig {
+            algo,
+            size: 1,
             width: 64,
             height: 64,
-            size: 1,
-            algo,
-            show_progress: false,
+            lattice: false,
+            metadata: false,
+            compression: Compression::None,
+            fec: 0,
+            file_size: 0,
+            sha256: [0; SHA256_BYTES],
         }
     }
 
     fn build_frames(data: &[u8], algo: AlgoFrame) -> Vec<VideoFrame> {
         let io = inject_opts(algo);
-        let start = create_starting_frame(data.len() as u64, &io);
+        let start = create_starting_frame(data, &io).unwrap();
         let mut frames = vec![start];
-        frames.extend(data_to_frames(&io, data.to_vec()));
+        frames.extend(data_to_frames(&io, data.to_vec()).unwrap());
         frames
     }
 
@@ -688,7 +1203,7 @@ mod extractionlogics_tests {
     fn test_round_trip_bw_in_memory() {
         let data: Vec<u8> = (0..600u32).map(|i| (i % 251) as u8).collect();
         let frames = build_frames(&data, AlgoFrame::BW);
-        let result = frames_to_data(&extract_opts(AlgoFrame::BW), frames);
+        let result = frames_to_data(&extract_opts(AlgoFrame::BW), frames).unwrap();
         assert_eq!(result, data);
     }
 
@@ -696,7 +1211,7 @@ mod extractionlogics_tests {
     fn test_round_trip_rgb_in_memory() {
         let data: Vec<u8> = (0..600u32).map(|i| (i % 253) as u8).collect();
         let frames = build_frames(&data, AlgoFrame::RGB);
-        let result = frames_to_data(&extract_opts(AlgoFrame::RGB), frames);
+        let result = frames_to_data(&extract_opts(AlgoFrame::RGB), frames).unwrap();
         assert_eq!(result, data);
     }
 
@@ -707,7 +1222,7 @@ mod extractionlogics_tests {
             let data: Vec<u8> = (0..600u32).map(|i| (i % 251) as u8).collect();
             let algo = AlgoFrame::Quantized(levels);
             let frames = build_frames(&data, algo);
-            let result = frames_to_data(&extract_opts(algo), frames);
+            let result = frames_to_data(&extract_opts(algo), frames).unwrap();
             assert_eq!(result, data, "levels={levels}");
         }
     }
@@ -718,7 +1233,7 @@ mod extractionlogics_tests {
             let data: Vec<u8> = (0..600u32).map(|i| (i % 251) as u8).collect();
             let algo = AlgoFrame::Brightness(levels);
             let frames = build_frames(&data, algo);
-            let result = frames_to_data(&extract_opts(algo), frames);
+            let result = frames_to_data(&extract_opts(algo), frames).unwrap();
             assert_eq!(result, data, "brightness levels={levels}");
         }
     }
@@ -729,23 +1244,23 @@ mod extractionlogics_tests {
         let levels = 4u32;
         let algo = AlgoFrame::Quantized(levels);
         let io = inject_opts(algo);
-        let mut data_frames = data_to_frames(&io, data.clone());
+        let mut data_frames = data_to_frames(&io, data.clone()).unwrap();
         assert!(!data_frames.is_empty());
-        let opts = extract_opts(algo);
+        let config = encoding_config(algo);
 
-        let info = frame_to_data_method_quantized(&data_frames[0], &opts, levels);
+        let info = decode_frame(&data_frames[0], &config);
         assert!(info.crc_valid);
         assert_eq!(&info.payload[..data.len()], &data[..]);
 
         // Push the first content cell to the opposite end of the level range.
-        let (x, y) = content_cell_xy(HEADER_BITS, io.width, io.size);
+        let (x, y) = content_cell_xy(HEADER_BITS, io.width, io.height, io.size, false);
         let original = data_frames[0].read_coordinate_color(x, y);
         if original.r > 127 {
             data_frames[0].write(0, 0, 0, x, y, io.size);
         } else {
             data_frames[0].write(255, 255, 255, x, y, io.size);
         }
-        let corrupted = frame_to_data_method_quantized(&data_frames[0], &opts, levels);
+        let corrupted = decode_frame(&data_frames[0], &config);
         assert!(!corrupted.crc_valid);
     }
 
@@ -753,33 +1268,225 @@ mod extractionlogics_tests {
     fn test_frame_crc_valid_then_corrupt_bw() {
         let data: Vec<u8> = (0..100u32).map(|i| (i % 200) as u8 + 1).collect();
         let io = inject_opts(AlgoFrame::BW);
-        let mut data_frames = data_to_frames(&io, data.clone());
+        let mut data_frames = data_to_frames(&io, data.clone()).unwrap();
         assert!(!data_frames.is_empty());
-        let opts = extract_opts(AlgoFrame::BW);
+        let config = encoding_config(AlgoFrame::BW);
 
         // The clean data frame has a valid CRC and the first bytes match the input.
-        let info = frame_to_data_method_bw(&data_frames[0], &opts);
+        let info = decode_frame(&data_frames[0], &config);
         assert!(info.crc_valid);
         assert_eq!(&info.payload[..data.len()], &data[..]);
 
         // Flip the first payload cell -> CRC must now fail.
-        let (x, y) = content_cell_xy(HEADER_BITS, io.width, io.size);
+        let (x, y) = content_cell_xy(HEADER_BITS, io.width, io.height, io.size, false);
         let original = data_frames[0].read_coordinate_color(x, y);
         if original.r > 127 {
             data_frames[0].write(0, 0, 0, x, y, io.size);
         } else {
             data_frames[0].write(255, 255, 255, x, y, io.size);
         }
-        let corrupted = frame_to_data_method_bw(&data_frames[0], &opts);
+        let corrupted = decode_frame(&data_frames[0], &config);
         assert!(!corrupted.crc_valid);
     }
 
+    #[test]
+    fn test_copies_failing_their_crc_are_combined() {
+        let data: Vec<u8> = (0..100u32).map(|i| (i % 200) as u8 + 1).collect();
+        let io = inject_opts(AlgoFrame::BW);
+        let start = create_starting_frame(&data, &io).unwrap();
+        let page = data_to_frames(&io, data.clone()).unwrap().remove(0);
+
+        // Three captures of the page, each with other cells flipped: none
+        // passes its CRC, but every cell is right in most of them.
+        let copies: Vec<VideoFrame> = [[0usize, 9], [40, 77], [200, 301]]
+            .iter()
+            .map(|cells| {
+                let mut copy = page.clone();
+                for &cell in cells {
+                    let (x, y) =
+                        content_cell_xy(HEADER_BITS + cell, io.width, io.height, io.size, false);
+                    let v = if copy.read_coordinate_color(x, y).r > 127 {
+                        0
+                    } else {
+                        255
+                    };
+                    copy.write(v, v, v, x, y, io.size);
+                }
+                assert!(!decode_frame(&copy, &encoding_config(AlgoFrame::BW)).crc_valid);
+                copy
+            })
+            .collect();
+
+        let mut reassembler = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        reassembler.push(&start);
+        let outcomes: Vec<PushOutcome> = copies.iter().map(|copy| reassembler.push(copy)).collect();
+        assert_eq!(outcomes[0], PushOutcome::CrcFail);
+        assert!(outcomes.contains(&PushOutcome::NewPage { page: 0 }));
+        assert_eq!(reassembler.combined_pages(), 1);
+        // Copies of a page already received are not kept.
+        assert_eq!(reassembler.push(&copies[0]), PushOutcome::CrcFail);
+        assert_eq!(reassembler.finish().unwrap(), data);
+    }
+
+    #[test]
+    fn test_fec_corrects_flipped_cells_bw() {
+        let data: Vec<u8> = (0..100u32).map(|i| (i % 200) as u8 + 1).collect();
+        let mut io = inject_opts(AlgoFrame::BW);
+        io.fec = 16;
+        let mut data_frames = data_to_frames(&io, data.clone()).unwrap();
+        let config = encoding_config(AlgoFrame::BW);
+
+        // Flip one cell in five different bytes: 5 byte errors, 8 correctable.
+        for byte in [0usize, 5, 10, 60, 120] {
+            let (x, y) =
+                content_cell_xy(HEADER_BITS + byte * 8, io.width, io.height, io.size, false);
+            let original = data_frames[0].read_coordinate_color(x, y);
+            if original.r > 127 {
+                data_frames[0].write(0, 0, 0, x, y, io.size);
+            } else {
+                data_frames[0].write(255, 255, 255, x, y, io.size);
+            }
+        }
+        let info = decode_frame(&data_frames[0], &config);
+        assert_eq!(info.header.unwrap().fec, 16);
+        assert!(info.crc_valid);
+        assert_eq!(&info.payload[..data.len()], &data[..]);
+    }
+
+    #[test]
+    fn test_round_trip_quantized_with_fec_in_memory() {
+        let data: Vec<u8> = (0..900u32).map(|i| (i % 251) as u8).collect();
+        let algo = AlgoFrame::Quantized(8);
+        let mut io = inject_opts(algo);
+        io.fec = 32;
+        let mut frames = vec![create_starting_frame(&data, &io).unwrap()];
+        frames.extend(data_to_frames(&io, data.clone()).unwrap());
+        let result = frames_to_data(&extract_opts(algo), frames).unwrap();
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_fountain_round_trip_survives_dropped_frames() {
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        let mut io = inject_opts(AlgoFrame::BW);
+        io.fountain = Some(100);
+        let data_frames = data_to_frames(&io, data.clone()).unwrap();
+
+        // Lose every third symbol frame; the rest still rebuild the file.
+        let mut frames = vec![create_starting_frame(&data, &io).unwrap()];
+        frames.extend(
+            data_frames
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| i % 3 != 0)
+                .map(|(_, f)| f),
+        );
+        let result = frames_to_data(&extract_opts(AlgoFrame::BW), frames).unwrap();
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_fountain_errors_with_too_few_frames() {
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        let mut io = inject_opts(AlgoFrame::BW);
+        io.fountain = Some(100);
+        let data_frames = data_to_frames(&io, data.clone()).unwrap();
+
+        let mut frames = vec![create_starting_frame(&data, &io).unwrap()];
+        frames.extend(data_frames.into_iter().take(4));
+        let result = frames_to_data(&extract_opts(AlgoFrame::BW), frames);
+        assert!(
+            matches!(result, Err(HdmiError::NotEnoughSymbols { .. })),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[test]
+    fn test_calibration_ramp_decodes_limited_range_capture() {
+        let data: Vec<u8> = (0..400u32).map(|i| (i * 7 % 256) as u8).collect();
+        for algo in [AlgoFrame::Quantized(8), AlgoFrame::Brightness(16)] {
+            // Wide enough for the whole ramp to fit in the top border strip.
+            let mut io = inject_opts(algo);
+            io.width = 256;
+            let mut eo = extract_opts(algo);
+            eo.width = Some(256);
+            let mut frames = vec![create_starting_frame(&data, &io).unwrap()];
+            frames.extend(data_to_frames(&io, data.clone()).unwrap());
+
+            // Squeeze every frame into limited range (16..235) like many capture
+            // cards do; evenly spaced thresholds misread the outer levels.
+            for frame in frames.iter_mut() {
+                for value in frame.buffer.rgb_mut() {
+                    *value = (f32::from(*value) * 219.0 / 255.0 + 16.0).round() as u8;
+                }
+            }
+            assert_eq!(frames_to_data(&eo, frames).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_frames_to_data_adopts_start_frame_configuration() {
+        let data: Vec<u8> = (0..700u32).map(|i| (i * 13 % 256) as u8).collect();
+        let algo = AlgoFrame::Brightness(4);
+        let mut io = inject_opts(algo);
+        io.width = 128;
+        io.height = 96;
+        io.size = 2;
+        let mut frames = vec![create_starting_frame(&data, &io).unwrap()];
+        frames.extend(data_to_frames(&io, data.clone()).unwrap());
+
+        // No geometry or algo given: everything is read from the Start frame.
+        let mut eo = extract_opts(algo);
+        eo.width = None;
+        eo.height = None;
+        eo.size = None;
+        eo.algo = None;
+        assert_eq!(frames_to_data(&eo, frames.clone()).unwrap(), data);
+
+        // A mismatching algo flag is overridden by the Start frame.
+        eo.algo = Some(AlgoFrame::RGB);
+        assert_eq!(frames_to_data(&eo, frames).unwrap(), data);
+    }
+
+    #[test]
+    fn test_find_encoding_config_probes_cell_size() {
+        let mut io = inject_opts(AlgoFrame::Quantized(4));
+        io.size = 4;
+        io.width = 256;
+        io.height = 128;
+        io.session = 0x1234;
+        let frames = vec![create_starting_frame(&[0; 10], &io).unwrap()];
+        let mut eo = extract_opts(AlgoFrame::BW);
+        eo.width = None;
+        eo.height = None;
+        eo.size = None;
+        let (config, session) =
+            find_encoding_config(&eo, &frames).expect("Start frame should be found");
+        assert_eq!(session, 0x1234);
+        assert_eq!(
+            config,
+            EncodingConfig {
+                algo: AlgoFrame::Quantized(4),
+                size: 4,
+                width: 256,
+                height: 128,
+                lattice: false,
+                metadata: false,
+                compression: Compression::None,
+                fec: 0,
+                file_size: 10,
+                sha256: file_sha256(&[0; 10]),
+            }
+        );
+    }
+
     #[test]
     fn test_frames_to_data_ignores_duplicates_and_order() {
         let data: Vec<u8> = (0..600u32).map(|i| (i % 249) as u8).collect();
         let io = inject_opts(AlgoFrame::BW);
-        let start = create_starting_frame(data.len() as u64, &io);
-        let data_frames = data_to_frames(&io, data.clone());
+        let start = create_starting_frame(&data, &io).unwrap();
+        let data_frames = data_to_frames(&io, data.clone()).unwrap();
         assert!(data_frames.len() >= 2);
 
         // Reorder and duplicate frames; CRC + page numbers must still reassemble.
@@ -791,16 +1498,529 @@ mod extractionlogics_tests {
         }
         frames.push(data_frames[0].clone());
 
-        let result = frames_to_data(&extract_opts(AlgoFrame::BW), frames);
+        let result = frames_to_data(&extract_opts(AlgoFrame::BW), frames).unwrap();
         assert_eq!(result, data);
     }
 
     #[test]
-    #[should_panic(expected = "Instruction not found")]
-    fn test_frames_to_data_panics_without_start() {
+    fn test_frames_to_data_rejects_pages_of_another_file() {
+        // Same size and encoding, so every page fits, but another file.
+        let data: Vec<u8> = (0..600u32).map(|i| (i % 249) as u8).collect();
+        let other = vec![0u8; data.len()];
+        let io = inject_opts(AlgoFrame::BW);
+        let mut frames = vec![create_starting_frame(&other, &io).unwrap()];
+        frames.extend(data_to_frames(&io, data.clone()).unwrap());
+
+        let result = frames_to_data(&extract_opts(AlgoFrame::BW), frames);
+        match result {
+            Err(HdmiError::HashMismatch { expected, actual }) => {
+                assert_eq!(expected, sha256_hex(&file_sha256(&other)));
+                assert_eq!(actual, sha256_hex(&file_sha256(&data)));
+            }
+            other => panic!("expected a hash mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_frames_to_file_returns_the_metadata_frame() {
+        let data: Vec<u8> = (0..600u32).map(|i| (i % 241) as u8).collect();
+        let io = inject_opts(AlgoFrame::BW);
+        let metadata = FileMetadata {
+            name: "report.csv".to_string(),
+            size: data.len() as u64,
+            modified_secs: 1_700_000_000,
+            modified_nanos: 250,
+            mode: 0o640,
+            mime: mime_hint("report.csv").to_string(),
+        };
+        let data_frames = data_to_frames(&io, data.clone()).unwrap();
+        let metadata_frame = create_metadata_frame(&metadata, &io).unwrap();
+
+        // Seen before the Start frame and again later: kept once.
+        let mut frames = vec![metadata_frame.clone()];
+        frames.push(create_starting_frame(&data, &io).unwrap());
+        frames.extend(data_frames);
+        frames.push(metadata_frame);
+
+        let file = frames_to_file(&extract_opts(AlgoFrame::BW), frames).unwrap();
+        assert_eq!(file.data, data);
+        assert_eq!(file.metadata, Some(metadata));
+    }
+
+    #[test]
+    fn test_frames_to_file_without_metadata_frame() {
+        let data: Vec<u8> = (0..50u32).map(|i| i as u8).collect();
+        let io = inject_opts(AlgoFrame::BW);
+        let mut frames = vec![create_starting_frame(&data, &io).unwrap()];
+        frames.extend(data_to_frames(&io, data.clone()).unwrap());
+
+        let file = frames_to_file(&extract_opts(AlgoFrame::BW), frames).unwrap();
+        assert_eq!(file.data, data);
+        assert_eq!(file.metadata, None);
+    }
+
+    #[test]
+    fn test_frames_to_data_keeps_to_one_session() {
+        // Yesterday's and today's transfers: same size and encoding, so every
+        // page of one fits the other.
+        let today: Vec<u8> = (0..600u32).map(|i| (i % 249) as u8).collect();
+        let yesterday = vec![0u8; today.len()];
+        let mut old_io = inject_opts(AlgoFrame::BW);
+        old_io.session = 1;
+        let mut new_io = inject_opts(AlgoFrame::BW);
+        new_io.session = 2;
+
+        // The capture starts with the tail of yesterday's loop.
+        let mut frames = data_to_frames(&old_io, yesterday.clone()).unwrap();
+        frames.push(create_starting_frame(&today, &new_io).unwrap());
+        frames.extend(data_to_frames(&new_io, today.clone()).unwrap());
+        frames.push(create_starting_frame(&yesterday, &old_io).unwrap());
+
+        let mut eo = extract_opts(AlgoFrame::BW);
+        assert_eq!(frames_to_data(&eo, frames.clone()).unwrap(), today);
+        eo.session = Some(1);
+        assert_eq!(frames_to_data(&eo, frames.clone()).unwrap(), yesterday);
+        eo.session = Some(3);
+        assert!(matches!(
+            frames_to_data(&eo, frames),
+            Err(HdmiError::MissingStart)
+        ));
+    }
+
+    #[test]
+    fn test_frames_to_data_switches_to_the_session_with_more_start_frames() {
+        let today: Vec<u8> = (0..600u32).map(|i| (i % 249) as u8).collect();
+        let yesterday = vec![0u8; today.len()];
+        let mut old_io = inject_opts(AlgoFrame::BW);
+        old_io.session = 1;
+        let mut new_io = inject_opts(AlgoFrame::BW);
+        new_io.session = 2;
+
+        // The capture starts with yesterday's Start frame and a few of its
+        // pages, then today's transfer loops twice.
+        let mut frames = vec![create_starting_frame(&yesterday, &old_io).unwrap()];
+        frames.extend(
+            data_to_frames(&old_io, yesterday.clone())
+                .unwrap()
+                .into_iter()
+                .take(2),
+        );
+        for _ in 0..2 {
+            frames.push(create_starting_frame(&today, &new_io).unwrap());
+            frames.extend(data_to_frames(&new_io, today.clone()).unwrap());
+        }
+
+        let mut eo = extract_opts(AlgoFrame::BW);
+        assert_eq!(frames_to_data(&eo, frames.clone()).unwrap(), today);
+        // A session given in the options is kept.
+        eo.session = Some(1);
+        assert!(frames_to_data(&eo, frames).is_err());
+    }
+
+    #[test]
+    fn test_reassembler_reports_frames_of_other_sessions() {
+        let data: Vec<u8> = (0..600u32).map(|i| (i % 249) as u8).collect();
+        let mut io = inject_opts(AlgoFrame::BW);
+        io.session = 5;
+        let mut other = inject_opts(AlgoFrame::BW);
+        other.session = 6;
+
+        let mut reassembler = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        assert!(matches!(
+            reassembler.push(&create_starting_frame(&data, &io).unwrap()),
+            PushOutcome::Start { .. }
+        ));
+        assert_eq!(reassembler.session(), Some(5));
+        let stale = &data_to_frames(&other, data.clone()).unwrap()[0];
+        assert_eq!(
+            reassembler.push(stale),
+            PushOutcome::OtherSession { session: 6 }
+        );
+        assert_eq!(reassembler.other_sessions(), &[6]);
+        assert_eq!(reassembler.received_pages(), 0);
+    }
+
+    #[test]
+    fn test_reassembler_reports_a_misread_session_as_corrupt() {
+        let data: Vec<u8> = (0..600u32).map(|i| (i % 249) as u8).collect();
+        let mut io = inject_opts(AlgoFrame::BW);
+        io.session = 5;
+        let mut reassembler = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        reassembler.push(&create_starting_frame(&data, &io).unwrap());
+        assert_eq!(reassembler.session(), Some(5));
+
+        // Flip the last bit of the session field: the header now reads session 4.
+        let mut frame = data_to_frames(&io, data.clone()).unwrap().remove(0);
+        let (x, y) = content_cell_xy(151, io.width, io.height, io.size, false);
+        let original = frame.read_coordinate_color(x, y);
+        if original.r > 127 {
+            frame.write(0, 0, 0, x, y, io.size);
+        } else {
+            frame.write(255, 255, 255, x, y, io.size);
+        }
+        assert_eq!(reassembler.push(&frame), PushOutcome::CrcFail);
+        assert!(reassembler.other_sessions().is_empty());
+        assert_eq!(reassembler.received_pages(), 0);
+    }
+
+    #[test]
+    fn test_frames_to_data_decompresses_with_the_codec_of_the_start_frame() {
+        let data: Vec<u8> = (0..3000u32)
+            .flat_map(|i| format!("{},{}\n", i % 13, i % 5).into_bytes())
+            .collect();
+        for codec in [Compression::Deflate, Compression::Zstd] {
+            let mut io = inject_opts(AlgoFrame::BW);
+            io.compression = codec;
+            let frames = transfer_to_frames(&io, data.clone()).unwrap();
+            let raw_frames = data_to_frames(&inject_opts(AlgoFrame::BW), data.clone()).unwrap();
+            assert!(frames.len() * 3 < raw_frames.len(), "{codec}");
+            let eo = extract_opts(AlgoFrame::BW);
+            assert_eq!(frames_to_data(&eo, frames).unwrap(), data, "{codec}");
+        }
+    }
+
+    #[test]
+    fn test_frames_to_data_errors_when_the_pages_do_not_decompress() {
+        let data = vec![7u8; 500];
+        let mut io = inject_opts(AlgoFrame::BW);
+        io.compression = Compression::Zstd;
+        let start = create_starting_frame(&data, &io).unwrap();
+        // Pages of the right length, but not a zstd stream.
+        let mut frames = vec![start];
+        let length = Compression::Zstd.compress(&data).len();
+        let garbage = vec![0xFF; length];
+        frames.extend(data_to_frames(&inject_opts(AlgoFrame::BW), garbage).unwrap());
+        match frames_to_data(&extract_opts(AlgoFrame::BW), frames) {
+            Err(HdmiError::Decompress { codec, .. }) => assert_eq!(codec, Compression::Zstd),
+            other => panic!("expected Decompress, got {:?}", other.map(|d| d.len())),
+        }
+    }
+
+    #[test]
+    fn test_frames_to_data_errors_without_start() {
         let data: Vec<u8> = (0..50u32).map(|i| i as u8).collect();
         let io = inject_opts(AlgoFrame::BW);
-        let frames = data_to_frames(&io, data);
-        let _ = frames_to_data(&extract_opts(AlgoFrame::BW), frames);
+        let frames = data_to_frames(&io, data).unwrap();
+        let result = frames_to_data(&extract_opts(AlgoFrame::BW), frames);
+        assert!(
+            matches!(result, Err(HdmiError::MissingStart)),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[test]
+    fn test_reassembler_counts_fec_pages_from_the_start_frame() {
+        let data: Vec<u8> = (0..600u32).map(|i| (i % 247) as u8).collect();
+        let mut io = inject_opts(AlgoFrame::BW);
+        io.fec = 64;
+        let pages = data_to_frames(&io, data.clone()).unwrap().len() as u64;
+        assert!(
+            pages
+                > data_to_frames(&inject_opts(AlgoFrame::BW), data.clone())
+                    .unwrap()
+                    .len() as u64
+        );
+
+        // No data page yet: the page size comes from the Start frame alone.
+        let mut reassembler = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        reassembler.push(&create_starting_frame(&data, &io).unwrap());
+        assert_eq!(reassembler.expected_pages(), Some(pages));
+        let report = reassembler.missing_report().unwrap();
+        assert_eq!(report.expected_pages, pages);
+        assert_eq!(report.missing, (0..pages).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_reassembler_reports_push_outcomes_and_progress() {
+        let data: Vec<u8> = (0..600u32).map(|i| (i % 247) as u8).collect();
+        let io = inject_opts(AlgoFrame::BW);
+        let data_frames = data_to_frames(&io, data.clone()).unwrap();
+        assert!(data_frames.len() >= 3);
+        let last = data_frames.len() as u64 - 1;
+
+        let mut reassembler = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        assert_eq!(reassembler.expected_pages(), None);
+        assert_eq!(
+            reassembler.push(&create_starting_frame(&data, &io).unwrap()),
+            PushOutcome::Start { total_bytes: 600 }
+        );
+        assert_eq!(
+            reassembler.push(&create_starting_frame(&data, &io).unwrap()),
+            PushOutcome::Duplicate {
+                frame_type: FrameType::Start,
+                value: 600
+            }
+        );
+        assert_eq!(reassembler.expected_pages(), Some(last + 1));
+        assert_eq!(reassembler.missing_pages(), (0..=last).collect::<Vec<_>>());
+
+        // Pages arrive out of order; the file is complete with the last one.
+        for (page, frame) in data_frames.iter().enumerate().rev() {
+            assert!(!reassembler.is_complete());
+            assert_eq!(
+                reassembler.push(frame),
+                PushOutcome::NewPage { page: page as u64 }
+            );
+        }
+        assert!(reassembler.is_complete());
+        assert_eq!(
+            reassembler.push(&data_frames[1]),
+            PushOutcome::Duplicate {
+                frame_type: FrameType::Data,
+                value: 1
+            }
+        );
+        assert_eq!(reassembler.received_pages(), last + 1);
+        assert!(reassembler.missing_pages().is_empty());
+        assert_eq!(reassembler.finish().unwrap(), data);
+    }
+
+    #[test]
+    fn test_reassembler_reports_crc_failures_and_foreign_frames() {
+        let io = inject_opts(AlgoFrame::BW);
+        let mut frame = data_to_frames(&io, vec![7u8; 100]).unwrap().remove(0);
+        let mut reassembler = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        reassembler.push(&create_starting_frame(&[7u8; 100], &io).unwrap());
+
+        // Flip a payload cell: the header still reads but the CRC fails.
+        let (x, y) = content_cell_xy(HEADER_BITS + 3, 64, 64, 1, false);
+        let c = frame.read_coordinate_color(x, y);
+        frame.write(255 - c.r, 255 - c.g, 255 - c.b, x, y, 1);
+        assert_eq!(reassembler.push(&frame), PushOutcome::CrcFail);
+
+        let blank = VideoFrame::new(64, 64);
+        assert_eq!(reassembler.push(&blank), PushOutcome::NoHeader);
+    }
+
+    #[test]
+    fn test_reassembler_finish_reports_missing_pages() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 247) as u8).collect();
+        let io = inject_opts(AlgoFrame::BW);
+        let data_frames = data_to_frames(&io, data.clone()).unwrap();
+        assert!(data_frames.len() >= 4);
+
+        let mut reassembler = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        reassembler.push(&create_starting_frame(&data, &io).unwrap());
+        reassembler.push(&data_frames[0]);
+        reassembler.push(&data_frames[3]);
+        let expected = data_frames.len() as u64;
+        let missing: Vec<u64> = (1..expected).filter(|&page| page != 3).collect();
+        assert_eq!(reassembler.missing_pages(), missing);
+        match reassembler.finish() {
+            Err(HdmiError::MissingPages {
+                received,
+                expected: declared,
+                missing: listed,
+            }) => {
+                assert_eq!((received, declared), (2, expected));
+                assert_eq!(listed, missing);
+            }
+            other => panic!("expected missing pages, got {:?}", other.err()),
+        }
+
+        assert!(matches!(
+            Reassembler::new(&extract_opts(AlgoFrame::BW)).finish(),
+            Err(HdmiError::MissingStart)
+        ));
+    }
+
+    #[test]
+    fn test_reassembler_finds_configuration_from_pushed_start_frame() {
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 241) as u8).collect();
+        let io = inject_opts(AlgoFrame::Quantized(4));
+        let mut frames = data_to_frames(&io, data.clone()).unwrap();
+        assert!(frames.len() >= 2);
+
+        // Without a configuration, data frames seen before the Start frame
+        // cannot be read; the ones after it are kept.
+        let options = ExtractOptions {
+            algo: None,
+            ..extract_opts(AlgoFrame::BW)
+        };
+        let mut reassembler = Reassembler::new(&options);
+        assert_eq!(reassembler.push(&frames.remove(0)), PushOutcome::NoHeader);
+        reassembler.push(&create_starting_frame(&data, &io).unwrap());
+        for frame in &frames {
+            reassembler.push(frame);
+        }
+        assert_eq!(reassembler.missing_pages(), vec![0]);
+
+        let first_page = data_to_frames(&io, data.clone()).unwrap().remove(0);
+        assert_eq!(
+            reassembler.push(&first_page),
+            PushOutcome::NewPage { page: 0 }
+        );
+        assert!(reassembler.is_complete());
+        assert_eq!(reassembler.finish().unwrap(), data);
+    }
+
+    #[test]
+    fn test_reassembler_completes_fountain_when_symbols_arrive_before_start() {
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        let mut io = inject_opts(AlgoFrame::BW);
+        io.fountain = Some(100);
+        let data_frames = data_to_frames(&io, data.clone()).unwrap();
+
+        let mut reassembler = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        reassembler.adopt_config(encoding_config(AlgoFrame::BW), 0);
+        let (before, after) = data_frames.split_at(5);
+        for frame in before {
+            assert!(matches!(
+                reassembler.push(frame),
+                PushOutcome::NewSymbol { .. }
+            ));
+        }
+        reassembler.push(&create_starting_frame(&data, &io).unwrap());
+        assert_eq!(reassembler.received_pages(), 5);
+        for frame in after {
+            reassembler.push(frame);
+        }
+        assert_eq!(reassembler.finish().unwrap(), data);
+    }
+
+    #[test]
+    fn test_reassembler_waits_for_an_announced_metadata_frame() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 233) as u8).collect();
+        let io = inject_opts(AlgoFrame::BW);
+        let start = start_frame(
+            data.len() as u64,
+            data.len() as u64,
+            file_sha256(&data),
+            true,
+            &io,
+        )
+        .unwrap();
+        let metadata = FileMetadata {
+            name: "photos".to_string(),
+            size: data.len() as u64,
+            modified_secs: 1_700_000_000,
+            modified_nanos: 0,
+            mode: 0,
+            mime: "application/x-hdmift-container".to_string(),
+        };
+        let metadata_frame = create_metadata_frame(&metadata, &io).unwrap();
+        let data_frames = data_to_frames(&io, data.clone()).unwrap();
+
+        // Every page but no Metadata frame: not complete, and not a file yet.
+        let mut reassembler = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        reassembler.push(&start);
+        for frame in &data_frames {
+            reassembler.push(frame);
+        }
+        assert!(reassembler.is_missing_metadata());
+        assert!(!reassembler.is_complete());
+        assert!(matches!(
+            reassembler.finish_file(),
+            Err(HdmiError::MissingMetadata)
+        ));
+
+        // The record is saved with the pages and restored on resume.
+        let mut first = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        first.push(&start);
+        assert_eq!(first.push(&metadata_frame), PushOutcome::Metadata);
+        first.push(&data_frames[0]);
+        let state = first.state().unwrap();
+        assert_eq!(state.metadata.as_ref(), Some(&metadata));
+
+        let mut second = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        second.resume(state);
+        second.push(&start);
+        for frame in &data_frames[1..] {
+            second.push(frame);
+        }
+        assert!(second.is_complete());
+        let file = second.finish_file().unwrap();
+        assert_eq!(file.data, data);
+        assert_eq!(file.metadata, Some(metadata));
+    }
+
+    #[test]
+    fn test_reassembler_resumes_from_saved_pages_of_the_same_transfer() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 239) as u8).collect();
+        let io = inject_opts(AlgoFrame::BW);
+        let start = create_starting_frame(&data, &io).unwrap();
+        let data_frames = data_to_frames(&io, data.clone()).unwrap();
+        assert!(data_frames.len() >= 4);
+
+        // First capture: the Start frame and the first two pages.
+        let mut first = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        first.push(&start);
+        first.push(&data_frames[0]);
+        first.push(&data_frames[1]);
+        assert!(!first.is_complete());
+        let state = first.state().unwrap();
+        assert_eq!(state.pages.len(), 2);
+
+        // Another transfer's state is ignored.
+        let mut other = state.clone();
+        other.total_bytes += 1;
+        let mut second = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        second.resume(other);
+        second.push(&start);
+        assert_eq!(second.received_pages(), 0);
+
+        // Second capture: the rest, merged into the saved pages.
+        let mut second = Reassembler::new(&extract_opts(AlgoFrame::BW));
+        second.resume(state);
+        second.push(&start);
+        assert_eq!(second.received_pages(), 2);
+        for frame in &data_frames[2..] {
+            second.push(frame);
+        }
+        assert!(second.is_complete());
+        assert_eq!(
+            second.push(&data_frames[0]),
+            PushOutcome::Duplicate {
+                frame_type: FrameType::Data,
+                value: 0
+            }
+        );
+        assert_eq!(second.finish().unwrap(), data);
+    }
+
+    #[test]
+    fn test_only_announced_containers_are_unpacked() {
+        use crate::container::{CONTAINER_MAGIC, CONTAINER_MIME};
+
+        let dir = std::env::temp_dir().join(format!("hdmift_announced_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("out.bin");
+        let options = ExtractOptions {
+            extracted_file_path: output.to_string_lossy().to_string(),
+            ..extract_opts(AlgoFrame::BW)
+        };
+        let metadata = |mime: &str| FileMetadata {
+            name: "out.bin".to_string(),
+            size: 0,
+            modified_secs: 0,
+            modified_nanos: 0,
+            mode: 0,
+            mime: mime.to_string(),
+        };
+
+        // A plain file that happens to start with the magic is kept as it is.
+        let mut data = CONTAINER_MAGIC.to_vec();
+        data.extend_from_slice(b"not a manifest");
+        let plain = ExtractedFile {
+            data: data.clone(),
+            metadata: Some(metadata("application/octet-stream")),
+        };
+        data_to_files(&options, plain).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), data);
+        fs::remove_file(&output).unwrap();
+
+        // Bytes announced as a container must be one.
+        let announced = ExtractedFile {
+            data: b"plain bytes".to_vec(),
+            metadata: Some(metadata(CONTAINER_MIME)),
+        };
+        assert!(matches!(
+            data_to_files(&options, announced),
+            Err(HdmiError::InvalidContainer { .. })
+        ));
+        assert!(!output.exists());
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,398 @@
+//! What a transfer knows about the file besides its bytes.
+//!
+//! The name, modification time and permissions of the injected file never
+//! crossed the link, so `extract` wrote whatever `-o` named. Right after the
+//! Start frame, `inject` now renders a `Metadata` frame whose payload is this
+//! record, painted and protected (CRC, `--fec`) like a data page. When `-o` is a
+//! directory, `extract` writes the file into it under its original name and
+//! restores its modification time and permission bits.
+//!
+//! Record layout (integers big-endian), the header value being its length:
+//!
+//! ```text
+//!   byte  0         record version (METADATA_VERSION)
+//!   bytes 1..9      file size in bytes
+//!   bytes 9..17     modification time, seconds since the Unix epoch (signed)
+//!   bytes 17..21    nanoseconds of the modification time
+//!   bytes 21..25    Unix mode bits (0 when the sender has none)
+//!   byte  25        name length N
+//!   bytes 26..26+N  file name (UTF-8, no directory)
+//!   byte  26+N      MIME hint length M
+//!   then M bytes    MIME hint (ASCII)
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::container::CONTAINER_MIME;
+use crate::error::HdmiError;
+
+/// Version of the metadata record.
+const METADATA_VERSION: u8 = 1;
+
+/// Bytes of the record before the file name.
+const FIXED_BYTES: usize = 26;
+
+/// File name used in an output directory when the transfer carried no
+/// metadata frame.
+pub const DEFAULT_FILE_NAME: &str = "mydata.txt";
+
+/// Only the permission bits are restored: never set-user-ID, set-group-ID or
+/// sticky bits that came over the link.
+const PERMISSION_BITS: u32 = 0o777;
+
+/// MIME hints by lowercase file extension. Anything else is
+/// `application/octet-stream`.
+const MIME_HINTS: &[(&str, &str)] = &[
+    ("7z", "application/x-7z-compressed"),
+    ("csv", "text/csv"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("json", "application/json"),
+    ("md", "text/markdown"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("tar", "application/x-tar"),
+    ("txt", "text/plain"),
+    ("xml", "application/xml"),
+    ("zip", "application/zip"),
+    ("zst", "application/zstd"),
+];
+
+/// Name, size, modification time, permissions and MIME hint of the transferred
+/// file, carried by its `Metadata` frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// File name, without any directory.
+    pub name: String,
+    pub size: u64,
+    /// Modification time, as seconds and nanoseconds since the Unix epoch
+    /// (negative seconds before it).
+    pub modified_secs: i64,
+    pub modified_nanos: u32,
+    /// Unix mode bits of the file, 0 when unknown.
+    pub mode: u32,
+    /// MIME type guessed from the file extension, see [`mime_hint`].
+    pub mime: String,
+}
+
+impl FileMetadata {
+    /// Read the metadata of the file at `path`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<FileMetadata, HdmiError> {
+        let path = path.as_ref();
+        let read_error = |source| HdmiError::ReadFile {
+            path: path.to_string_lossy().to_string(),
+            source,
+        };
+        let metadata = fs::metadata(path).map_err(read_error)?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (modified_secs, modified_nanos) = metadata.modified().map_or((0, 0), unix_time);
+        Ok(FileMetadata {
+            mime: mime_hint(&name).to_string(),
+            name,
+            size: metadata.len(),
+            modified_secs,
+            modified_nanos,
+            mode: file_mode(&metadata),
+        })
+    }
+
+    /// The modification time, or `None` when it cannot be represented.
+    pub fn modified(&self) -> Option<SystemTime> {
+        let nanos = Duration::from_nanos(u64::from(self.modified_nanos));
+        if self.modified_secs >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_secs(self.modified_secs as u64) + nanos)
+        } else {
+            UNIX_EPOCH
+                .checked_sub(Duration::from_secs(self.modified_secs.unsigned_abs()))?
+                .checked_add(nanos)
+        }
+    }
+
+    /// Serialize to the record carried by the `Metadata` frame. The name and
+    /// MIME hint are cut (on a character boundary) to 255 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let name = truncate_utf8(&self.name, u8::MAX as usize);
+        let mime = truncate_utf8(&self.mime, u8::MAX as usize);
+        let mut bytes = Vec::with_capacity(FIXED_BYTES + name.len() + 1 + mime.len());
+        bytes.push(METADATA_VERSION);
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&self.modified_secs.to_be_bytes());
+        bytes.extend_from_slice(&self.modified_nanos.to_be_bytes());
+        bytes.extend_from_slice(&self.mode.to_be_bytes());
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.push(mime.len() as u8);
+        bytes.extend_from_slice(mime.as_bytes());
+        bytes
+    }
+
+    /// Parse a record. Returns `None` for another record version, a truncated
+    /// record or a name that is not UTF-8.
+    pub fn from_bytes(bytes: &[u8]) -> Option<FileMetadata> {
+        if bytes.len() < FIXED_BYTES || bytes[0] != METADATA_VERSION {
+            return None;
+        }
+        let name_len = bytes[25] as usize;
+        let name = bytes.get(FIXED_BYTES..FIXED_BYTES + name_len)?;
+        let mime_at = FIXED_BYTES + name_len;
+        let mime_len = *bytes.get(mime_at)? as usize;
+        let mime = bytes.get(mime_at + 1..mime_at + 1 + mime_len)?;
+        let modified_nanos = u32::from_be_bytes(bytes[17..21].try_into().ok()?);
+        if modified_nanos >= 1_000_000_000 {
+            return None;
+        }
+        Some(FileMetadata {
+            name: String::from_utf8(name.to_vec()).ok()?,
+            size: u64::from_be_bytes(bytes[1..9].try_into().ok()?),
+            modified_secs: i64::from_be_bytes(bytes[9..17].try_into().ok()?),
+            modified_nanos,
+            mode: u32::from_be_bytes(bytes[21..25].try_into().ok()?),
+            mime: String::from_utf8(mime.to_vec()).ok()?,
+        })
+    }
+
+    /// The name to write the file under: the last component of the carried
+    /// name, so a name such as `../../.bashrc` cannot leave the output
+    /// directory. `None` when nothing usable is left.
+    pub fn safe_name(&self) -> Option<&str> {
+        let name = self.name.rsplit(['/', '\\']).next()?;
+        match name {
+            "" | "." | ".." => None,
+            name if name.contains('\0') => None,
+            name => Some(name),
+        }
+    }
+
+    /// Give the file at `path` the carried modification time and permission
+    /// bits.
+    pub fn restore(&self, path: &Path) -> Result<(), HdmiError> {
+        let write_error = |source| HdmiError::WriteFile {
+            path: path.to_string_lossy().to_string(),
+            source,
+        };
+        if let Some(modified) = self.modified() {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_modified(modified))
+                .map_err(write_error)?;
+        }
+        set_mode(path, self.mode).map_err(write_error)
+    }
+}
+
+/// The bytes of a transfer and, when it carried a `Metadata` frame, what is
+/// known about the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedFile {
+    pub data: Vec<u8>,
+    pub metadata: Option<FileMetadata>,
+}
+
+impl ExtractedFile {
+    /// Where to write the file for the `-o` path `output`: `output` itself, or
+    /// when it is a directory, the carried file name inside it
+    /// ([`DEFAULT_FILE_NAME`] without metadata).
+    pub fn output_path(&self, output: &str) -> PathBuf {
+        let output = Path::new(output);
+        if !output.is_dir() {
+            return output.to_path_buf();
+        }
+        let name = self
+            .metadata
+            .as_ref()
+            .and_then(FileMetadata::safe_name)
+            .unwrap_or(DEFAULT_FILE_NAME);
+        output.join(name)
+    }
+
+    /// Whether the Metadata frame announced a multi-file container
+    /// ([`CONTAINER_MIME`]).
+    pub fn is_container(&self) -> bool {
+        self.metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.mime == CONTAINER_MIME)
+    }
+}
+
+/// MIME hint for a file name, from its extension.
+pub fn mime_hint(name: &str) -> &'static str {
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension)) => extension.to_ascii_lowercase(),
+        None => return "application/octet-stream",
+    };
+    MIME_HINTS
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map_or("application/octet-stream", |(_, mime)| mime)
+}
+
+/// Seconds (negative before the epoch) and nanoseconds of `time` since the
+/// Unix epoch, as carried by the record.
+pub(crate) fn unix_time(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (after.as_secs() as i64, after.subsec_nanos()),
+        Err(before) => {
+            let before = before.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+/// The longest prefix of `text` of at most `max` bytes that ends on a
+/// character boundary.
+fn truncate_utf8(text: &str, max: usize) -> &str {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> u32 {
+    0
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if mode == 0 {
+        return Ok(());
+    }
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & PERMISSION_BITS))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod filemetadata_tests {
+    use super::*;
+
+    fn sample() -> FileMetadata {
+        FileMetadata {
+            name: "report.pdf".to_string(),
+            size: 1234,
+            modified_secs: 1_700_000_000,
+            modified_nanos: 250_000_000,
+            mode: 0o100640,
+            mime: "application/pdf".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let metadata = sample();
+        assert_eq!(
+            FileMetadata::from_bytes(&metadata.to_bytes()),
+            Some(metadata)
+        );
+        let before_epoch = FileMetadata {
+            name: "été.txt".to_string(),
+            modified_secs: -5,
+            ..sample()
+        };
+        assert_eq!(
+            FileMetadata::from_bytes(&before_epoch.to_bytes()),
+            Some(before_epoch)
+        );
+    }
+
+    #[test]
+    fn test_truncated_or_foreign_records_are_rejected() {
+        let bytes = sample().to_bytes();
+        assert!(FileMetadata::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(FileMetadata::from_bytes(&bytes[..10]).is_none());
+        let mut other_version = bytes.clone();
+        other_version[0] = METADATA_VERSION + 1;
+        assert!(FileMetadata::from_bytes(&other_version).is_none());
+    }
+
+    #[test]
+    fn test_long_names_are_cut_on_a_character_boundary() {
+        let metadata = FileMetadata {
+            name: "é".repeat(200),
+            ..sample()
+        };
+        let parsed = FileMetadata::from_bytes(&metadata.to_bytes()).unwrap();
+        assert_eq!(parsed.name, "é".repeat(127));
+    }
+
+    #[test]
+    fn test_names_cannot_leave_the_output_directory() {
+        let named = |name: &str| FileMetadata {
+            name: name.to_string(),
+            ..sample()
+        };
+        assert_eq!(named("report.pdf").safe_name(), Some("report.pdf"));
+        assert_eq!(named("../../.bashrc").safe_name(), Some(".bashrc"));
+        assert_eq!(named("C:\\Users\\me\\a.zip").safe_name(), Some("a.zip"));
+        assert_eq!(named("..").safe_name(), None);
+        assert_eq!(named("dir/").safe_name(), None);
+    }
+
+    #[test]
+    fn test_mime_hint_follows_the_extension() {
+        assert_eq!(mime_hint("backup.ZIP"), "application/zip");
+        assert_eq!(mime_hint("notes.txt"), "text/plain");
+        assert_eq!(mime_hint("README"), "application/octet-stream");
+        assert_eq!(mime_hint("data.bin"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_file_is_restored_in_an_output_directory() {
+        let dir = std::env::temp_dir().join(format!("hdmift_metadata_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = ExtractedFile {
+            data: vec![1, 2, 3],
+            metadata: Some(sample()),
+        };
+        let path = file.output_path(&dir.to_string_lossy());
+        assert_eq!(path, dir.join("report.pdf"));
+        fs::write(&path, &file.data).unwrap();
+        sample().restore(&path).unwrap();
+        let restored = FileMetadata::from_path(&path).unwrap();
+        assert_eq!(
+            (restored.modified_secs, restored.modified_nanos),
+            (1_700_000_000, 250_000_000)
+        );
+        #[cfg(unix)]
+        assert_eq!(restored.mode & PERMISSION_BITS, 0o640);
+
+        // Without metadata, or for a file path, the output is used as is.
+        let bare = ExtractedFile {
+            data: vec![],
+            metadata: None,
+        };
+        assert_eq!(
+            bare.output_path(&dir.to_string_lossy()),
+            dir.join(DEFAULT_FILE_NAME)
+        );
+        assert_eq!(
+            file.output_path(&path.to_string_lossy()),
+            path.to_path_buf()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
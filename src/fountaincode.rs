@@ -0,0 +1,429 @@
+// ---------------------------------------------------------------------------
+// Fountain (LT) erasure coding across frames.
+//
+// The file is cut into `k` source blocks of `block_len` bytes (one frame's
+// payload each). The encoder emits an unbounded stream of symbols identified by
+// a seed, which is stored in the frame header value field. Each symbol is the
+// XOR of `d` distinct source blocks, where `d` follows the robust soliton
+// distribution and both `d` and the blocks are derived from the seed with a
+// deterministic PRNG, so every symbol is equally useful to the decoder.
+//
+// The decoder rebuilds the file from any sufficiently large subset of symbols
+// (a few percent more than `k`) by peeling: a symbol that covers a single
+// unknown block reveals it, and every revealed block is XORed out of the
+// symbols still waiting on it. When peeling stalls, the remaining unknowns are
+// solved by Gaussian elimination over the pending symbols.
+// ---------------------------------------------------------------------------
+
+use std::collections::{HashMap, HashSet};
+
+/// Robust soliton tuning: `c` scales the spike, `delta` bounds the decoding
+/// failure probability. These are the commonly used textbook values.
+const SOLITON_C: f64 = 0.03;
+const SOLITON_DELTA: f64 = 0.5;
+
+/// Deterministic splitmix64 generator so encoder and decoder derive the same
+/// degree and block choice from a seed on any platform.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        SplitMix64(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    /// Uniform in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+    /// Uniform in `0..bound`.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// Cumulative robust soliton distribution over degrees `1..=k`:
+/// `cdf[d - 1]` is the probability of drawing a degree `<= d`.
+fn robust_soliton_cdf(k: usize) -> Vec<f64> {
+    if k <= 1 {
+        return vec![1.0];
+    }
+    let kf = k as f64;
+    let r = SOLITON_C * (kf / SOLITON_DELTA).ln() * kf.sqrt();
+    let spike = ((kf / r).floor() as usize).clamp(1, k);
+    let mut weights = vec![0.0f64; k];
+    for (i, weight) in weights.iter_mut().enumerate() {
+        let d = i + 1;
+        let rho = if d == 1 {
+            1.0 / kf
+        } else {
+            1.0 / (d as f64 * (d as f64 - 1.0))
+        };
+        let tau = if d < spike {
+            r / (d as f64 * kf)
+        } else if d == spike {
+            r * (r / SOLITON_DELTA).ln().max(0.0) / kf
+        } else {
+            0.0
+        };
+        *weight = rho + tau;
+    }
+    let total: f64 = weights.iter().sum();
+    let mut acc = 0.0;
+    weights
+        .iter()
+        .map(|w| {
+            acc += w / total;
+            acc
+        })
+        .collect()
+}
+
+/// Source block indices combined into symbol `seed` for a file of `k` blocks.
+fn symbol_indices(seed: u64, k: usize, cdf: &[f64]) -> Vec<usize> {
+    let mut rng = SplitMix64::new(seed);
+    let draw = rng.next_f64();
+    let degree = cdf.partition_point(|&p| p < draw).min(k - 1) + 1;
+    // Floyd's algorithm: `degree` distinct indices in O(degree).
+    let mut chosen: HashSet<usize> = HashSet::with_capacity(degree);
+    for upper in (k - degree)..k {
+        let candidate = rng.below(upper + 1);
+        if !chosen.insert(candidate) {
+            chosen.insert(upper);
+        }
+    }
+    let mut indices: Vec<usize> = chosen.into_iter().collect();
+    indices.sort_unstable();
+    indices
+}
+
+fn xor_into(target: &mut [u8], source: &[u8]) {
+    for (t, s) in target.iter_mut().zip(source) {
+        *t ^= s;
+    }
+}
+
+/// Number of symbols to emit for `k` source blocks with `overhead` percent of
+/// extra repair symbols (e.g. 50 emits `1.5 * k` symbols).
+pub fn fountain_symbol_count(k: usize, overhead: u16) -> usize {
+    k + (k * overhead as usize).div_ceil(100)
+}
+
+/// Unbounded stream of `(seed, symbol)` pairs for `data`, starting at seed 0.
+pub struct FountainEncoder {
+    data: Vec<u8>,
+    block_len: usize,
+    k: usize,
+    cdf: Vec<f64>,
+    next_seed: u64,
+}
+
+impl FountainEncoder {
+    /// Cut `data` into blocks of `block_len` bytes; the last block is padded
+    /// with zeros (dropped at extraction time using the Start frame byte count).
+    pub fn new(data: &[u8], block_len: usize) -> FountainEncoder {
+        let k = data.len().div_ceil(block_len);
+        let mut padded = data.to_vec();
+        padded.resize(k * block_len, 0);
+        FountainEncoder {
+            data: padded,
+            block_len,
+            k,
+            cdf: robust_soliton_cdf(k),
+            next_seed: 0,
+        }
+    }
+
+    /// Number of source blocks.
+    pub fn source_blocks(&self) -> usize {
+        self.k
+    }
+
+    fn block(&self, index: usize) -> &[u8] {
+        &self.data[index * self.block_len..(index + 1) * self.block_len]
+    }
+}
+
+impl Iterator for FountainEncoder {
+    type Item = (u64, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.k == 0 {
+            return None;
+        }
+        let seed = self.next_seed;
+        self.next_seed += 1;
+        let mut symbol = vec![0u8; self.block_len];
+        for index in symbol_indices(seed, self.k, &self.cdf) {
+            xor_into(&mut symbol, self.block(index));
+        }
+        Some((seed, symbol))
+    }
+}
+
+/// Peeling decoder: feed it symbols in any order, with duplicates, until
+/// [`FountainDecoder::is_complete`] reports every source block recovered.
+pub struct FountainDecoder {
+    k: usize,
+    block_len: usize,
+    cdf: Vec<f64>,
+    blocks: Vec<Option<Vec<u8>>>,
+    recovered: usize,
+    /// Symbols still covering two or more unknown blocks.
+    pending: Vec<Option<(Vec<usize>, Vec<u8>)>>,
+    /// For each source block, the pending symbols that reference it.
+    waiting: Vec<Vec<usize>>,
+    seen: HashSet<u64>,
+}
+
+impl FountainDecoder {
+    pub fn new(k: usize, block_len: usize) -> FountainDecoder {
+        FountainDecoder {
+            k,
+            block_len,
+            cdf: robust_soliton_cdf(k),
+            blocks: vec![None; k],
+            recovered: 0,
+            pending: Vec::new(),
+            waiting: vec![Vec::new(); k],
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Whether the symbol produced for `seed` was already added.
+    pub fn has_symbol(&self, seed: u64) -> bool {
+        self.seen.contains(&seed)
+    }
+
+    /// Add the symbol produced for `seed`. Duplicates and symbols of the wrong
+    /// length are ignored.
+    pub fn add_symbol(&mut self, seed: u64, mut symbol: Vec<u8>) {
+        if self.is_complete() || symbol.len() != self.block_len || !self.seen.insert(seed) {
+            return;
+        }
+        let mut indices = symbol_indices(seed, self.k, &self.cdf);
+        indices.retain(|&index| match &self.blocks[index] {
+            Some(block) => {
+                xor_into(&mut symbol, block);
+                false
+            }
+            None => true,
+        });
+        match indices.len() {
+            0 => {}
+            1 => self.recover(indices[0], symbol),
+            _ => {
+                let id = self.pending.len();
+                for &index in &indices {
+                    self.waiting[index].push(id);
+                }
+                self.pending.push(Some((indices, symbol)));
+            }
+        }
+    }
+
+    /// Store a revealed block and peel it out of every symbol waiting on it,
+    /// cascading whenever that reveals another block.
+    fn recover(&mut self, index: usize, block: Vec<u8>) {
+        let mut queue = vec![(index, block)];
+        while let Some((index, block)) = queue.pop() {
+            if self.blocks[index].is_some() {
+                continue;
+            }
+            for id in std::mem::take(&mut self.waiting[index]) {
+                let Some((indices, symbol)) = &mut self.pending[id] else {
+                    continue;
+                };
+                xor_into(symbol, &block);
+                indices.retain(|&i| i != index);
+                if indices.len() == 1 {
+                    if let Some((indices, symbol)) = self.pending[id].take() {
+                        queue.push((indices[0], symbol));
+                    }
+                }
+            }
+            self.blocks[index] = Some(block);
+            self.recovered += 1;
+        }
+    }
+
+    /// Number of source blocks the file was cut into.
+    pub fn source_blocks(&self) -> usize {
+        self.k
+    }
+
+    /// Number of source blocks recovered so far.
+    pub fn recovered_blocks(&self) -> usize {
+        self.recovered
+    }
+
+    /// Number of distinct symbols received so far.
+    pub fn received_symbols(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.recovered == self.k
+    }
+
+    /// Finish a stalled peel: solve the blocks still unknown by Gaussian
+    /// elimination over GF(2) using the pending symbols. Returns true when every
+    /// block is now recovered. This costs `O(unknown^2 * block_len)`, so it is
+    /// meant to be called once enough symbols have arrived, not per symbol.
+    pub fn try_complete(&mut self) -> bool {
+        if self.is_complete() {
+            return true;
+        }
+        let unknown: Vec<usize> = (0..self.k).filter(|&i| self.blocks[i].is_none()).collect();
+        let pending: Vec<&(Vec<usize>, Vec<u8>)> = self.pending.iter().flatten().collect();
+        if pending.len() < unknown.len() {
+            return false;
+        }
+        let column: HashMap<usize, usize> =
+            unknown.iter().enumerate().map(|(c, &i)| (i, c)).collect();
+        let words = unknown.len().div_ceil(64);
+        let mut rows: Vec<(Vec<u64>, Vec<u8>)> = pending
+            .into_iter()
+            .map(|(indices, symbol)| {
+                let mut bits = vec![0u64; words];
+                for index in indices {
+                    let c = column[index];
+                    bits[c / 64] |= 1 << (c % 64);
+                }
+                (bits, symbol.clone())
+            })
+            .collect();
+
+        for col in 0..unknown.len() {
+            let has_col = |bits: &[u64]| bits[col / 64] & (1 << (col % 64)) != 0;
+            let Some(found) = (col..rows.len()).find(|&r| has_col(&rows[r].0)) else {
+                return false; // rank deficient: more symbols are needed
+            };
+            rows.swap(col, found);
+            let (pivot_bits, pivot_symbol) = rows[col].clone();
+            for (r, (bits, symbol)) in rows.iter_mut().enumerate() {
+                if r != col && has_col(bits) {
+                    for (b, p) in bits.iter_mut().zip(&pivot_bits) {
+                        *b ^= p;
+                    }
+                    xor_into(symbol, &pivot_symbol);
+                }
+            }
+        }
+
+        // Fully reduced: row `c` now holds exactly the block of column `c`.
+        for (c, (_, symbol)) in rows.into_iter().take(unknown.len()).enumerate() {
+            self.blocks[unknown[c]] = Some(symbol);
+        }
+        self.recovered = self.k;
+        self.pending.clear();
+        self.waiting.iter_mut().for_each(Vec::clear);
+        true
+    }
+
+    /// The concatenated source blocks, or `None` while some are still missing
+    /// even after [`FountainDecoder::try_complete`].
+    pub fn into_data(mut self) -> Option<Vec<u8>> {
+        if !self.try_complete() {
+            return None;
+        }
+        let mut data = Vec::with_capacity(self.k * self.block_len);
+        for block in self.blocks.into_iter().flatten() {
+            data.extend(block);
+        }
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod fountaincode_tests {
+    use super::*;
+
+    fn sample_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_robust_soliton_cdf_ends_at_one() {
+        for k in [1usize, 2, 10, 1000] {
+            let cdf = robust_soliton_cdf(k);
+            assert_eq!(cdf.len(), k);
+            assert!((cdf[k - 1] - 1.0).abs() < 1e-9, "k={k}");
+        }
+    }
+
+    #[test]
+    fn test_symbol_indices_are_distinct_and_in_range() {
+        let k = 50;
+        let cdf = robust_soliton_cdf(k);
+        for seed in 0..500u64 {
+            let indices = symbol_indices(seed, k, &cdf);
+            assert!(!indices.is_empty());
+            assert!(indices.windows(2).all(|w| w[0] < w[1]));
+            assert!(indices.iter().all(|&i| i < k));
+            // Encoder and decoder must agree on every symbol's blocks.
+            assert_eq!(indices, symbol_indices(seed, k, &cdf));
+        }
+    }
+
+    #[test]
+    fn test_fountain_symbol_count() {
+        assert_eq!(fountain_symbol_count(10, 0), 10);
+        assert_eq!(fountain_symbol_count(10, 50), 15);
+        assert_eq!(fountain_symbol_count(3, 10), 4);
+    }
+
+    #[test]
+    fn test_decode_pads_last_block() {
+        let data = sample_data(1000);
+        let encoder = FountainEncoder::new(&data, 64);
+        let k = encoder.source_blocks();
+        assert_eq!(k, 16);
+        let mut decoder = FountainDecoder::new(k, 64);
+        for (seed, symbol) in encoder.take(fountain_symbol_count(k, 100)) {
+            decoder.add_symbol(seed, symbol);
+        }
+        let decoded = decoder.into_data().unwrap();
+        assert_eq!(decoded.len(), 1024);
+        assert_eq!(&decoded[..data.len()], &data[..]);
+    }
+
+    #[test]
+    fn test_decode_from_any_large_subset() {
+        let data = sample_data(200 * 40);
+        let encoder = FountainEncoder::new(&data, 40);
+        let k = encoder.source_blocks();
+        let symbols: Vec<(u64, Vec<u8>)> = encoder.take(fountain_symbol_count(k, 100)).collect();
+
+        // Drop every third symbol and feed the rest backwards.
+        let mut decoder = FountainDecoder::new(k, 40);
+        for (seed, symbol) in symbols.into_iter().rev() {
+            if seed % 3 != 0 {
+                decoder.add_symbol(seed, symbol);
+            }
+        }
+        assert!(decoder.is_complete());
+        assert_eq!(decoder.into_data().unwrap(), data);
+    }
+
+    #[test]
+    fn test_decoder_is_incomplete_with_too_few_symbols() {
+        let data = sample_data(100 * 16);
+        let encoder = FountainEncoder::new(&data, 16);
+        let k = encoder.source_blocks();
+        let mut decoder = FountainDecoder::new(k, 16);
+        for (seed, symbol) in encoder.skip(1).take(k - 10) {
+            decoder.add_symbol(seed, symbol.clone());
+            decoder.add_symbol(seed, symbol); // duplicates carry no information
+        }
+        assert_eq!(decoder.received_symbols(), k - 10);
+        assert!(!decoder.is_complete());
+        assert!(decoder.recovered_blocks() < k);
+        assert!(decoder.into_data().is_none());
+    }
+}
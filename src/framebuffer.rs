@@ -0,0 +1,175 @@
+//! Plain in-memory RGB image backing every [`crate::VideoFrame`].
+//!
+//! Pixels are stored row-major, three bytes per pixel in R, G, B order, with no
+//! padding between rows. Drawing and sampling cells only needs this buffer, so
+//! frames can be encoded and decoded without OpenCV; OpenCV is only used to
+//! read and write video containers and to register captured frames.
+
+use crate::error::HdmiError;
+
+/// Bytes per pixel (R, G, B).
+pub const RGB_CHANNELS: usize = 3;
+
+/// A `width` x `height` RGB image held in a `Vec<u8>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    width: u16,
+    height: u16,
+    pixels: Vec<u8>,
+}
+
+impl FrameBuffer {
+    /// A black image.
+    pub fn new(width: u16, height: u16) -> FrameBuffer {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![0; rgb_len(width, height)],
+        }
+    }
+
+    /// Wrap `pixels` (row-major RGB, `width * height * 3` bytes).
+    pub fn from_rgb(width: u16, height: u16, pixels: Vec<u8>) -> Result<FrameBuffer, HdmiError> {
+        if pixels.len() != rgb_len(width, height) {
+            return Err(HdmiError::InvalidImage {
+                width: u32::from(width),
+                height: u32::from(height),
+                reason: format!(
+                    "expected {} RGB bytes, got {}",
+                    rgb_len(width, height),
+                    pixels.len()
+                ),
+            });
+        }
+        Ok(FrameBuffer {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Row-major RGB bytes.
+    pub fn rgb(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn rgb_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+
+    pub fn into_rgb(self) -> Vec<u8> {
+        self.pixels
+    }
+
+    /// The `[r, g, b]` of the pixel at `(x, y)`. Panics outside the image.
+    pub fn pixel(&self, x: u16, y: u16) -> [u8; 3] {
+        let offset = self.offset(x, y);
+        [
+            self.pixels[offset],
+            self.pixels[offset + 1],
+            self.pixels[offset + 2],
+        ]
+    }
+
+    /// Set the pixel at `(x, y)`. Panics outside the image.
+    pub fn set_pixel(&mut self, x: u16, y: u16, rgb: [u8; 3]) {
+        let offset = self.offset(x, y);
+        self.pixels[offset..offset + RGB_CHANNELS].copy_from_slice(&rgb);
+    }
+
+    /// Fill the `width` x `height` rectangle whose top-left pixel is `(x, y)`.
+    /// The part of the rectangle outside the image is ignored.
+    pub fn fill_rect(&mut self, x: u16, y: u16, width: u16, height: u16, rgb: [u8; 3]) {
+        let left = usize::from(x).min(usize::from(self.width));
+        let right = usize::from(self.width).min(usize::from(x) + usize::from(width));
+        let bottom = usize::from(self.height).min(usize::from(y) + usize::from(height));
+        if left >= right || y >= self.height {
+            return;
+        }
+        let stride = usize::from(self.width) * RGB_CHANNELS;
+        for row in usize::from(y)..bottom {
+            let line = &mut self.pixels[row * stride..(row + 1) * stride];
+            for pixel in
+                line[left * RGB_CHANNELS..right * RGB_CHANNELS].chunks_exact_mut(RGB_CHANNELS)
+            {
+                pixel.copy_from_slice(&rgb);
+            }
+        }
+    }
+
+    fn offset(&self, x: u16, y: u16) -> usize {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({}, {}) is outside the {}x{} image",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        (usize::from(y) * usize::from(self.width) + usize::from(x)) * RGB_CHANNELS
+    }
+}
+
+/// Number of bytes of a `width` x `height` RGB image.
+fn rgb_len(width: u16, height: u16) -> usize {
+    usize::from(width) * usize::from(height) * RGB_CHANNELS
+}
+
+#[cfg(test)]
+mod framebuffer_tests {
+    use super::*;
+
+    #[test]
+    fn test_new_is_black() {
+        let buffer = FrameBuffer::new(4, 3);
+        assert_eq!((buffer.width(), buffer.height()), (4, 3));
+        assert_eq!(buffer.rgb().len(), 4 * 3 * 3);
+        assert!(buffer.rgb().iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn test_pixels_are_row_major_rgb() {
+        let mut buffer = FrameBuffer::new(4, 3);
+        buffer.set_pixel(1, 2, [10, 20, 30]);
+        assert_eq!(buffer.pixel(1, 2), [10, 20, 30]);
+        let offset = (2 * 4 + 1) * 3;
+        assert_eq!(&buffer.rgb()[offset..offset + 3], &[10, 20, 30]);
+    }
+
+    #[test]
+    fn test_fill_rect_is_clipped_to_the_image() {
+        let mut buffer = FrameBuffer::new(4, 3);
+        buffer.fill_rect(2, 1, 5, 5, [255, 0, 0]);
+        assert_eq!(buffer.pixel(1, 1), [0, 0, 0]);
+        assert_eq!(buffer.pixel(2, 1), [255, 0, 0]);
+        assert_eq!(buffer.pixel(3, 2), [255, 0, 0]);
+        assert_eq!(buffer.pixel(3, 0), [0, 0, 0]);
+
+        // Rectangles starting past the right or bottom edge draw nothing.
+        let before = buffer.clone().into_rgb();
+        buffer.fill_rect(4, 0, 3, 3, [0, 255, 0]);
+        buffer.fill_rect(9, 1, 1, 1, [0, 255, 0]);
+        buffer.fill_rect(0, 3, 4, 2, [0, 255, 0]);
+        buffer.fill_rect(0, 0, 0, 3, [0, 255, 0]);
+        assert_eq!(buffer.into_rgb(), before);
+    }
+
+    #[test]
+    fn test_from_rgb_checks_the_length() {
+        let buffer = FrameBuffer::from_rgb(2, 1, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(buffer.pixel(1, 0), [4, 5, 6]);
+        assert_eq!(buffer.clone().into_rgb(), vec![1, 2, 3, 4, 5, 6]);
+        assert!(matches!(
+            FrameBuffer::from_rgb(2, 2, vec![0; 6]),
+            Err(HdmiError::InvalidImage { .. })
+        ));
+    }
+}
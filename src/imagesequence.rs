@@ -0,0 +1,62 @@
+//! Reading a directory of images (see [`crate::pngsequence`] for the layout)
+//! with OpenCV: each image is registered like a captured video frame, so
+//! photos of the screen decode as well as the frames written by `inject`.
+
+use opencv::core::Mat;
+use opencv::imgcodecs::{imread, IMREAD_COLOR};
+use opencv::prelude::*;
+use std::path::PathBuf;
+
+use crate::error::HdmiError;
+use crate::filemetadata::ExtractedFile;
+use crate::options::ExtractOptions;
+use crate::pngsequence::image_sequence_files;
+use crate::videocapture::{source_to_file, source_to_frames, FrameSource};
+use crate::videoframe::VideoFrame;
+
+/// The images of a directory, read one at a time.
+pub struct ImageSequence {
+    files: std::vec::IntoIter<PathBuf>,
+}
+
+impl FrameSource for ImageSequence {
+    fn next_image(&mut self) -> Option<Mat> {
+        // An unreadable image is skipped, like a torn frame of a capture.
+        self.files.by_ref().find_map(|path| {
+            let image = imread(&path.to_string_lossy(), IMREAD_COLOR).ok()?;
+            (image.cols() > 0).then_some(image)
+        })
+    }
+}
+
+/// Open the directory of images at `dir`. With a
+/// [`MANIFEST_FILE`](crate::MANIFEST_FILE) only the frames it lists are read,
+/// in frame order; without one every PNG, JPEG, BMP or TIFF file is read in
+/// file name order.
+pub fn open_image_sequence(dir: &str) -> Result<ImageSequence, HdmiError> {
+    Ok(ImageSequence {
+        files: image_sequence_files(dir)?.into_iter(),
+    })
+}
+
+/// Read and register every image of the directory at
+/// `extract_options.video_file_path`. The sibling of `video_to_frames`.
+pub fn images_to_frames(extract_options: &ExtractOptions) -> Result<Vec<VideoFrame>, HdmiError> {
+    let source = open_image_sequence(&extract_options.video_file_path)?;
+    Ok(source_to_frames(source, extract_options))
+}
+
+/// Decode the directory of images at `extract_options.video_file_path` with
+/// `source_to_data`. The sibling of `video_to_data`.
+pub fn images_to_data(extract_options: &ExtractOptions) -> Result<Vec<u8>, HdmiError> {
+    images_to_file(extract_options).map(|file| file.data)
+}
+
+/// Like [`images_to_data`], with what the Metadata frame says about the file.
+/// The sibling of `video_to_file`.
+pub fn images_to_file(extract_options: &ExtractOptions) -> Result<ExtractedFile, HdmiError> {
+    source_to_file(
+        open_image_sequence(&extract_options.video_file_path)?,
+        extract_options,
+    )
+}
@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+
+use crate::capturereassembly::{reassemble_capture, CanonicalFrames};
+use crate::error::HdmiError;
+use crate::filemetadata::ExtractedFile;
+use crate::options::ExtractOptions;
+use crate::pngsequence::{image_sequence_files, read_png};
+use crate::videoframe::VideoFrame;
+
+/// True for a `.png` file name, the only format read without OpenCV.
+fn is_png_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+/// The PNG files of the directory at `extract_options.video_file_path`, in
+/// frame order. Decoding JPEG, BMP or TIFF photos requires OpenCV.
+fn png_files(extract_options: &ExtractOptions) -> Result<Vec<PathBuf>, HdmiError> {
+    let files = image_sequence_files(&extract_options.video_file_path)?;
+    if !files.iter().all(|path| is_png_file(path)) {
+        return Err(HdmiError::NoVideoBackend {
+            operation: "reading JPEG, BMP or TIFF images",
+        });
+    }
+    Ok(files)
+}
+
+/// Read every PNG frame of the directory at `extract_options.video_file_path`.
+///
+/// Without OpenCV the frames are not registered: this reads the frames written
+/// by `inject` (`file_to_images`), not photos of the screen. Enable the default
+/// `opencv-backend` feature to register captured images. An unreadable image
+/// is skipped.
+pub fn images_to_frames(extract_options: &ExtractOptions) -> Result<Vec<VideoFrame>, HdmiError> {
+    Ok(png_files(extract_options)?
+        .iter()
+        .filter_map(|path| read_png(path).ok())
+        .collect())
+}
+
+/// Decode the PNG frames of the directory at
+/// `extract_options.video_file_path`, as written by `inject`. See
+/// [`images_to_frames`].
+pub fn images_to_data(extract_options: &ExtractOptions) -> Result<Vec<u8>, HdmiError> {
+    images_to_file(extract_options).map(|file| file.data)
+}
+
+/// Like [`images_to_data`], with what the Metadata frame says about the file.
+/// The frames are read one at a time and dropped once decoded; the saved state,
+/// the missing pages and the capture timeline are kept like `video_to_file`
+/// does with OpenCV.
+pub fn images_to_file(extract_options: &ExtractOptions) -> Result<ExtractedFile, HdmiError> {
+    let frames = png_files(extract_options)?
+        .into_iter()
+        .filter_map(|path| read_png(&path).ok());
+    reassemble_capture(CanonicalFrames(frames), extract_options)
+}
+
+#[cfg(test)]
+mod imagesequence_stub_tests {
+    use super::*;
+    use crate::options::{AlgoFrame, InjectOptions};
+    use crate::pngsequence::file_to_images;
+    use std::fs;
+
+    #[test]
+    fn test_png_frames_round_trip_without_opencv() {
+        let dir = std::env::temp_dir().join(format!("hdmift_stub_images_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.bin");
+        let data: Vec<u8> = (0..2000u32).map(|i| (i % 239) as u8).collect();
+        fs::write(&input, &data).unwrap();
+        let frames_dir = dir.join("frames").to_string_lossy().to_string();
+        file_to_images(&InjectOptions {
+            file_path: input.to_string_lossy().to_string(),
+            output_video_file: frames_dir.clone(),
+            width: 96,
+            height: 96,
+            size: 2,
+            algo: AlgoFrame::BW,
+            fec: 8,
+            session: 3,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let extract_options = ExtractOptions {
+            video_file_path: frames_dir,
+            ..Default::default()
+        };
+        let file = images_to_file(&extract_options).unwrap();
+        assert_eq!(file.data, data);
+        assert_eq!(file.metadata.unwrap().name, "input.bin");
+        assert!(images_to_frames(&extract_options).unwrap().len() > 2);
+
+        // Photos need registration, hence OpenCV.
+        let photos = dir.join("photos");
+        fs::create_dir_all(&photos).unwrap();
+        fs::write(photos.join("shot.jpg"), b"jpeg").unwrap();
+        let photos = ExtractOptions {
+            video_file_path: photos.to_string_lossy().to_string(),
+            ..extract_options
+        };
+        assert!(matches!(
+            images_to_file(&photos),
+            Err(HdmiError::NoVideoBackend { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
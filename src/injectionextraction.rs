@@ -1,3 +1,7 @@
+use crate::bitlogics::symbol_to_value;
+use crate::instructionlogics::ENCODING_CONFIG_BYTES;
+use crate::options::AlgoFrame;
+
 pub const NULL_CHAR: u8 = 0u8;
 
 #[cfg(feature = "opencv-backend")]
@@ -23,8 +27,9 @@ pub fn map_to_size(width: u16, height: u16) -> Size {
 //
 // Every frame is laid out as:
 //   * an outer calibration ring `BORDER_CELLS` thick that holds three QR-style
-//     finder patterns (top-left, top-right, bottom-left) used to re-align a
-//     captured frame back to canonical pixels (see extractionlogics).
+//     finder patterns (top-left, top-right, bottom-left) and a smaller
+//     alignment marker (bottom-right) used to re-align a captured frame back to
+//     canonical pixels (see videocapture).
 //   * an inner content rectangle that holds, in row-major cell order, a fixed
 //     `HEADER_BITS` header followed by the payload.
 //
@@ -45,11 +50,26 @@ pub const BORDER_CELLS: usize = 9;
 /// A finder pattern is `MARKER_CELLS` x `MARKER_CELLS` cells (QR-style 7x7).
 pub const MARKER_CELLS: usize = 7;
 
+/// The bottom-right alignment marker is `ALIGNMENT_CELLS` x `ALIGNMENT_CELLS`
+/// cells (QR-style 5x5: black ring, white ring, one black centre cell). Its
+/// outer/inner area ratio of 25 tells it apart from the finders' ~5.4.
+pub const ALIGNMENT_CELLS: usize = 5;
+
+/// True when cell (`row`, `col`) of a `cells` x `cells` concentric-square
+/// pattern (a finder or an alignment marker) is black: the outer ring and the
+/// centre square two cells in.
+pub fn concentric_cell_is_dark(row: usize, col: usize, cells: usize) -> bool {
+    let last = cells - 1;
+    let outer_ring = row == 0 || row == last || col == 0 || col == last;
+    let centre = (2..=cells - 3).contains(&row) && (2..=cells - 3).contains(&col);
+    outer_ring || centre
+}
+
 /// Quiet zone, in cells, between the absolute frame edge and a finder pattern.
 pub const QUIET_CELLS: usize = 1;
 
 /// Number of black/white cells reserved for the per-frame header.
-pub const HEADER_BITS: usize = 128;
+pub const HEADER_BITS: usize = 160;
 
 /// Identifies our frame format. A mismatch means the frame is not ours (or is a
 /// different/older format) and must be rejected.
@@ -75,20 +95,133 @@ pub fn content_rows(height: u16, size: u8) -> usize {
     cells_high(height, size).saturating_sub(2 * BORDER_CELLS)
 }
 
+/// Spacing, in cells, between the alignment patterns of the optional lattice
+/// inside the content rectangle. QR codes repeat theirs every 16 to 28 modules.
+pub const LATTICE_SPACING: usize = 24;
+
+/// Offset, in cells, of the first lattice pattern from the left edge of the
+/// content rectangle and from the top of the lattice rows, which centres the
+/// patterns between the edges like they are between each other.
+const LATTICE_OFFSET: usize = (LATTICE_SPACING - ALIGNMENT_CELLS) / 2;
+
+/// Content cells a Start frame fills before any payload: the header and the
+/// encoding configuration with the file hash. The lattice starts below the rows holding them, so
+/// they are found at the same place whether or not a stream uses the lattice,
+/// before the extractor knows whether it does.
+pub const START_CELLS: usize = HEADER_BITS + ENCODING_CONFIG_BYTES * 8;
+
+/// Number of lattice patterns that fit along a `length`-cell span.
+fn lattice_count(length: usize) -> usize {
+    match length.checked_sub(LATTICE_OFFSET + ALIGNMENT_CELLS) {
+        Some(rest) => rest / LATTICE_SPACING + 1,
+        None => 0,
+    }
+}
+
+/// First content row of the lattice: the row after the header and the Start
+/// frame configuration, in a content rectangle `cols` cells wide.
+fn lattice_top_row(cols: usize) -> usize {
+    START_CELLS.div_ceil(cols) + LATTICE_OFFSET
+}
+
+/// Number of lattice patterns `(across, down)` in the content rectangle;
+/// `(0, 0)` without the lattice or when not a single pattern fits.
+pub fn lattice_shape(width: u16, height: u16, size: u8, lattice: bool) -> (usize, usize) {
+    let cols = content_cols(width, size);
+    if !lattice || cols == 0 {
+        return (0, 0);
+    }
+    let rows = content_rows(height, size);
+    let across = lattice_count(cols);
+    let down = lattice_count((rows + LATTICE_OFFSET).saturating_sub(lattice_top_row(cols)));
+    if across == 0 || down == 0 {
+        return (0, 0);
+    }
+    (across, down)
+}
+
+/// Content (column, row) of the top-left cell of every lattice pattern, row by
+/// row. Empty without the lattice.
+pub fn lattice_cells(width: u16, height: u16, size: u8, lattice: bool) -> Vec<(usize, usize)> {
+    let (across, down) = lattice_shape(width, height, size, lattice);
+    let top = lattice_top_row(content_cols(width, size));
+    (0..down)
+        .flat_map(|row| {
+            (0..across).map(move |col| {
+                (
+                    LATTICE_OFFSET + col * LATTICE_SPACING,
+                    top + row * LATTICE_SPACING,
+                )
+            })
+        })
+        .collect()
+}
+
+/// Number of lattice cells before position `pos` along one axis, where `count`
+/// patterns repeat every `LATTICE_SPACING` cells from `start`.
+fn reserved_before(pos: usize, start: usize, count: usize) -> usize {
+    let Some(rel) = pos.checked_sub(start) else {
+        return 0;
+    };
+    let full = rel / LATTICE_SPACING;
+    if full >= count {
+        return count * ALIGNMENT_CELLS;
+    }
+    full * ALIGNMENT_CELLS + (rel % LATTICE_SPACING).min(ALIGNMENT_CELLS)
+}
+
 /// Number of payload cells available in a single frame, after reserving the
-/// header. Returns 0 if the frame is too small to hold even the header.
-pub fn frame_capacity(width: u16, height: u16, size: u8) -> usize {
+/// header and, with `lattice`, the alignment patterns. Returns 0 if the frame
+/// is too small to hold even the header.
+pub fn frame_capacity(width: u16, height: u16, size: u8, lattice: bool) -> usize {
+    let (across, down) = lattice_shape(width, height, size, lattice);
     let content = content_cols(width, size) * content_rows(height, size);
-    content.saturating_sub(HEADER_BITS)
+    content.saturating_sub(HEADER_BITS + across * down * ALIGNMENT_CELLS * ALIGNMENT_CELLS)
 }
 
 /// Pixel coordinate (top-left) of the content cell at linear index `index`.
 /// Index 0 is the first header cell; index `HEADER_BITS` is the first payload
-/// cell. Cells are laid out row-major inside the content rectangle.
-pub fn content_cell_xy(index: usize, width: u16, size: u8) -> (u16, u16) {
+/// cell. Cells are laid out row-major inside the content rectangle, skipping
+/// the cells of the alignment patterns with `lattice`.
+pub fn content_cell_xy(
+    index: usize,
+    width: u16,
+    height: u16,
+    size: u8,
+    lattice: bool,
+) -> (u16, u16) {
     let cols = content_cols(width, size);
-    let cy = index / cols;
-    let cx = index % cols;
+    let (across, down) = lattice_shape(width, height, size, lattice);
+    let (cx, cy) = if across == 0 {
+        (index % cols, index / cols)
+    } else {
+        // Rows crossing a pattern have `band` fewer free cells. Find the last
+        // row starting at or before `index`: no row holds fewer than
+        // `cols - band` cells, which bounds the search.
+        let top = lattice_top_row(cols);
+        let band = across * ALIGNMENT_CELLS;
+        let cells_before = |row: usize| row * cols - band * reserved_before(row, top, down);
+        let (mut row, mut past) = (0, index / (cols - band) + 1);
+        while past - row > 1 {
+            let mid = (row + past) / 2;
+            if cells_before(mid) <= index {
+                row = mid;
+            } else {
+                past = mid;
+            }
+        }
+        let free = index - cells_before(row);
+        let in_band = reserved_before(row + 1, top, down) > reserved_before(row, top, down);
+        let col = match free.checked_sub(LATTICE_OFFSET) {
+            Some(rest) if in_band => {
+                let gap = LATTICE_SPACING - ALIGNMENT_CELLS;
+                let pattern = (rest / gap).min(across - 1);
+                LATTICE_OFFSET + pattern * LATTICE_SPACING + ALIGNMENT_CELLS + rest - pattern * gap
+            }
+            _ => free,
+        };
+        (col, row)
+    };
     let x = (BORDER_CELLS + cx) * size as usize;
     let y = (BORDER_CELLS + cy) * size as usize;
     (x as u16, y as u16)
@@ -96,11 +229,13 @@ pub fn content_cell_xy(index: usize, width: u16, size: u8) -> (u16, u16) {
 
 /// Cell offset (column or row) of the centre of a finder pattern measured from
 /// the corresponding frame edge.
-const MARKER_CENTER_CELLS: f32 = QUIET_CELLS as f32 + MARKER_CELLS as f32 / 2.0;
+#[cfg(feature = "opencv-backend")]
+pub const MARKER_CENTER_CELLS: f32 = QUIET_CELLS as f32 + MARKER_CELLS as f32 / 2.0;
 
 /// Canonical pixel centres of the three finder patterns, in the order
 /// `[top-left, top-right, bottom-left]`. These are the destination points used
 /// to compute the affine transform that re-aligns a captured frame.
+#[cfg(feature = "opencv-backend")]
 pub fn marker_centers_px(width: u16, height: u16, size: u8) -> [(f32, f32); 3] {
     let s = size as f32;
     let off = MARKER_CENTER_CELLS * s;
@@ -110,6 +245,15 @@ pub fn marker_centers_px(width: u16, height: u16, size: u8) -> [(f32, f32); 3] {
     [tl, tr, bl]
 }
 
+/// Canonical pixel centre of the bottom-right alignment marker. Together with
+/// [`marker_centers_px`] it gives the four destination points of the
+/// perspective transform used when the marker is found in a capture.
+#[cfg(feature = "opencv-backend")]
+pub fn alignment_center_px(width: u16, height: u16, size: u8) -> (f32, f32) {
+    let off = MARKER_CENTER_CELLS * size as f32;
+    (width as f32 - off, height as f32 - off)
+}
+
 /// Cell (column, row) top-left positions of the three finder patterns, in the
 /// order `[top-left, top-right, bottom-left]`. Used by the encoder to draw them.
 pub fn marker_cell_origins(width: u16, height: u16, size: u8) -> [(usize, usize); 3] {
@@ -121,15 +265,93 @@ pub fn marker_cell_origins(width: u16, height: u16, size: u8) -> [(usize, usize)
     [tl, tr, bl]
 }
 
+/// Cell (column, row) top-left position of the alignment marker. It sits in the
+/// bottom-right corner of the ring, centred where a fourth finder would be, so
+/// it keeps the same quiet moat around it.
+pub fn alignment_cell_origin(width: u16, height: u16, size: u8) -> (usize, usize) {
+    let inset = QUIET_CELLS + MARKER_CELLS - (MARKER_CELLS - ALIGNMENT_CELLS) / 2;
+    (
+        cells_wide(width, size) - inset,
+        cells_high(height, size) - inset,
+    )
+}
+
+/// Row (and column) of the two QR-style timing patterns: alternating black and
+/// white cells running between the finder patterns, along their last row (top
+/// strip) and last column (left strip). They let the decoder count cells, and so
+/// recover the frame geometry, from a capture of unknown scale.
+pub const TIMING_CELL: usize = QUIET_CELLS + MARKER_CELLS - 1;
+
+/// True when the timing pattern cell at `index` cells from the start of the
+/// content rectangle is black. The pattern starts and alternates with black.
+pub fn timing_cell_is_dark(index: usize) -> bool {
+    index.is_multiple_of(2)
+}
+
+/// Width, in cells, of one patch of the colour calibration ramp. Patches are
+/// several cells wide so the decoder samples the middle cell, away from the blur
+/// and chroma bleed at the patch edges.
+pub const RAMP_PATCH_CELLS: usize = 3;
+
+/// Row, in cells from the top edge, holding the sampled centre of the ramp. The
+/// ramp is three rows tall and sits in the top border strip between the
+/// top-left and top-right finder patterns, level with their centres, so it is
+/// surrounded by the white quiet zone on every side.
+pub const RAMP_ROW: usize = QUIET_CELLS + MARKER_CELLS / 2;
+
+/// Colours of the calibration ramp patches drawn for `algo`, in order. For
+/// `Quantized` that is every level of the red, then green, then blue channel
+/// (the other channels black); for `Brightness` every grey level. Empty for
+/// `RGB`/`BW`, which decode without level thresholds, and when the ramp does not
+/// fit in the top border strip of a `width`-pixel frame.
+pub fn calibration_ramp(algo: AlgoFrame, width: u16, size: u8) -> Vec<[u8; 3]> {
+    let ramp: Vec<[u8; 3]> = match algo {
+        AlgoFrame::RGB | AlgoFrame::BW => Vec::new(),
+        AlgoFrame::Quantized(levels) => (0..3)
+            .flat_map(|channel| {
+                (0..levels).map(move |symbol| {
+                    let mut rgb = [0u8; 3];
+                    rgb[channel] = symbol_to_value(symbol, levels);
+                    rgb
+                })
+            })
+            .collect(),
+        AlgoFrame::Brightness(levels) => (0..levels)
+            .map(|symbol| [symbol_to_value(symbol, levels); 3])
+            .collect(),
+    };
+    if ramp.len() * RAMP_PATCH_CELLS > content_cols(width, size) {
+        return Vec::new();
+    }
+    ramp
+}
+
+/// Cell column of the left edge of ramp patch `index`. The ramp starts at the
+/// same column as the content rectangle.
+pub fn ramp_patch_col(index: usize) -> usize {
+    BORDER_CELLS + index * RAMP_PATCH_CELLS
+}
+
+/// Pixel coordinate (top-left) of the centre cell of ramp patch `index`, the
+/// cell the decoder samples.
+pub fn ramp_patch_xy(index: usize, size: u8) -> (u16, u16) {
+    let cx = ramp_patch_col(index) + RAMP_PATCH_CELLS / 2;
+    let x = cx * size as usize;
+    let y = RAMP_ROW * size as usize;
+    (x as u16, y as u16)
+}
+
 #[cfg(test)]
 mod injectionextraction_tests {
     use super::*;
+    #[cfg(feature = "opencv-backend")]
     #[test]
     fn test_calculate_actual_size_1() {
         let result = map_to_size(100, 50);
         assert_eq!(result.width, 100);
         assert_eq!(result.height, 50);
     }
+    #[cfg(feature = "opencv-backend")]
     #[test]
     fn test_calculate_actual_size_2() {
         let result = map_to_size(1000, 500);
@@ -141,22 +363,141 @@ mod injectionextraction_tests {
     fn test_frame_capacity_excludes_border_and_header() {
         // 64 cells wide/high, border removes 2*BORDER_CELLS each side.
         let content = 64 - 2 * BORDER_CELLS;
-        let cap = frame_capacity(64, 64, 1);
+        let cap = frame_capacity(64, 64, 1, false);
         assert_eq!(cap, content * content - HEADER_BITS);
     }
 
     #[test]
     fn test_content_cell_xy_is_inside_content_region() {
-        let (x, y) = content_cell_xy(0, 64, 1);
+        let (x, y) = content_cell_xy(0, 64, 64, 1, false);
         assert_eq!(x as usize, BORDER_CELLS);
         assert_eq!(y as usize, BORDER_CELLS);
     }
 
     #[test]
+    fn test_lattice_sits_below_the_start_cells() {
+        // 110 x 78 content cells: the header and configuration fill 6 rows.
+        assert_eq!(lattice_shape(256, 192, 2, true), (5, 3));
+        assert_eq!(lattice_shape(256, 192, 2, false), (0, 0));
+        let cells = lattice_cells(256, 192, 2, true);
+        assert_eq!(cells.len(), 15);
+        assert_eq!(cells[0], (LATTICE_OFFSET, 6 + LATTICE_OFFSET));
+        assert_eq!(
+            cells[6],
+            (
+                LATTICE_OFFSET + LATTICE_SPACING,
+                6 + LATTICE_OFFSET + LATTICE_SPACING
+            )
+        );
+        assert!(cells
+            .iter()
+            .all(|&(col, row)| col + ALIGNMENT_CELLS <= 110 && row + ALIGNMENT_CELLS <= 78));
+        for index in 0..START_CELLS {
+            assert_eq!(
+                content_cell_xy(index, 256, 192, 2, true),
+                content_cell_xy(index, 256, 192, 2, false)
+            );
+        }
+    }
+
+    #[test]
+    fn test_content_cells_skip_the_lattice() {
+        let (width, height, size) = (256, 192, 2);
+        let capacity = frame_capacity(width, height, size, true);
+        assert_eq!(
+            capacity,
+            frame_capacity(width, height, size, false) - 15 * ALIGNMENT_CELLS * ALIGNMENT_CELLS
+        );
+
+        let mut reserved = std::collections::HashSet::new();
+        for (col, row) in lattice_cells(width, height, size, true) {
+            for r in 0..ALIGNMENT_CELLS {
+                for c in 0..ALIGNMENT_CELLS {
+                    reserved.insert((col + c, row + r));
+                }
+            }
+        }
+        // Every header and payload cell is a free cell, in row-major order.
+        let mut previous = None;
+        for index in 0..HEADER_BITS + capacity {
+            let (x, y) = content_cell_xy(index, width, height, size, true);
+            let cell = (x as usize / 2 - BORDER_CELLS, y as usize / 2 - BORDER_CELLS);
+            assert!(!reserved.contains(&cell), "cell {index} at {cell:?}");
+            assert!(cell.0 < 110 && cell.1 < 78);
+            if let Some((px, py)) = previous {
+                assert!(cell.1 > py || (cell.1 == py && cell.0 > px), "cell {index}");
+            }
+            previous = Some(cell);
+        }
+        // And so all of them: the last one is the last free cell.
+        assert_eq!(previous, Some((109, 77)));
+    }
+
+    #[test]
+    #[cfg(feature = "opencv-backend")]
     fn test_marker_centers_are_symmetric() {
         let [tl, tr, bl] = marker_centers_px(100, 80, 1);
         assert_eq!(tl, (4.5, 4.5));
         assert_eq!(tr, (95.5, 4.5));
         assert_eq!(bl, (4.5, 75.5));
+        assert_eq!(alignment_center_px(100, 80, 1), (95.5, 75.5));
+    }
+
+    #[test]
+    fn test_alignment_marker_sits_in_the_bottom_right_ring() {
+        let (ox, oy) = alignment_cell_origin(100, 80, 1);
+        assert_eq!((ox, oy), (93, 73));
+        // Centred on the same cell as a fourth finder would be...
+        let [tl, _, _] = marker_cell_origins(100, 80, 1);
+        assert_eq!(
+            ox + ALIGNMENT_CELLS / 2,
+            100 - 1 - (tl.0 + MARKER_CELLS / 2)
+        );
+        // ...with a quiet cell outside it and a quiet moat before the content.
+        assert!(ox + ALIGNMENT_CELLS < 100 - QUIET_CELLS);
+        assert!(ox > 100 - BORDER_CELLS);
+    }
+
+    #[test]
+    fn test_timing_patterns_clear_the_ramp_and_the_content() {
+        assert_eq!(TIMING_CELL, 7);
+        assert!(RAMP_ROW + 1 < TIMING_CELL);
+        assert!(TIMING_CELL < BORDER_CELLS - QUIET_CELLS);
+        assert!(timing_cell_is_dark(0));
+        assert!(!timing_cell_is_dark(1));
+    }
+
+    #[test]
+    fn test_calibration_ramp_covers_every_level_in_use() {
+        let ramp = calibration_ramp(AlgoFrame::Quantized(4), 640, 1);
+        assert_eq!(ramp.len(), 12);
+        assert_eq!(ramp[0], [0, 0, 0]);
+        assert_eq!(ramp[3], [255, 0, 0]);
+        assert_eq!(ramp[5], [0, 85, 0]);
+        assert_eq!(ramp[11], [0, 0, 255]);
+
+        let ramp = calibration_ramp(AlgoFrame::Brightness(8), 640, 1);
+        assert_eq!(ramp.len(), 8);
+        assert!(ramp.iter().all(|rgb| rgb[0] == rgb[1] && rgb[1] == rgb[2]));
+
+        assert!(calibration_ramp(AlgoFrame::RGB, 640, 1).is_empty());
+        assert!(calibration_ramp(AlgoFrame::BW, 640, 1).is_empty());
+    }
+
+    #[test]
+    fn test_calibration_ramp_is_dropped_when_it_does_not_fit() {
+        // 64 px wide: 46 content columns, room for 15 patches only.
+        assert!(calibration_ramp(AlgoFrame::Quantized(8), 64, 1).is_empty());
+        assert_eq!(calibration_ramp(AlgoFrame::Brightness(8), 64, 1).len(), 8);
+    }
+
+    #[test]
+    fn test_ramp_patches_sit_in_top_border_between_finders() {
+        let (x, y) = ramp_patch_xy(0, 2);
+        assert_eq!(x as usize, (BORDER_CELLS + 1) * 2);
+        assert_eq!(y as usize, RAMP_ROW * 2);
+        assert!(RAMP_ROW + 1 < BORDER_CELLS - QUIET_CELLS);
+        let last = content_cols(640, 1) / RAMP_PATCH_CELLS - 1;
+        assert!(ramp_patch_col(last) + RAMP_PATCH_CELLS <= BORDER_CELLS + content_cols(640, 1));
     }
 }
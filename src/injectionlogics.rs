@@ -1,40 +1,82 @@
-use opencv::{
-    core::Size,
-    videoio::{VideoWriter, VideoWriterTrait, VideoWriterTraitConst},
-};
 use std::fs;
+use std::io::{self, BufReader, Read};
 
 use crate::{
     bitlogics::{bits_per_channel, get_bit_at, get_rgb_for_bit, symbol_to_value},
+    compression::Compression,
+    container::ContainerInput,
+    error::HdmiError,
+    filemetadata::FileMetadata,
+    fountaincode::{fountain_symbol_count, FountainEncoder},
     injectionextraction::{
         cells_high, cells_wide, content_cell_xy, frame_capacity, HEADER_BITS, NULL_CHAR,
     },
-    instructionlogics::{FrameHeader, FrameType},
+    instructionlogics::{
+        file_sha256, format_session, sha256_hex, EncodingConfig, FrameHeader, FrameType,
+        ENCODING_CONFIG_BYTES, SHA256_BYTES,
+    },
     options::{AlgoFrame, InjectOptions},
+    pagereport::format_page_ranges,
+    reedsolomon::{fec_data_capacity, fec_encode},
     videoframe::VideoFrame,
 };
 
-pub fn file_to_data(options: &InjectOptions) -> Vec<u8> {
-    fs::read(&options.file_path).unwrap_or_else(|err| {
-        panic!(
-            "Unable to read file: {} with error: {}",
-            &options.file_path, err
-        )
-    })
-}
 use indicatif::ProgressBar;
+use sha2::{Digest, Sha256};
 
 /// Create a starting frame to indicate that we are starting the transmission of the data.
 ///
 /// Needed because the source will play the video with all the data in a loop. The consumer
 /// reads the stream until it finds this Start frame, which carries the total number of data
-/// bytes to expect (`total_data_size`).
+/// bytes to expect and the SHA-256 of the whole file (`data`), checked once it is reassembled.
+/// With `inject_options.compression`, the byte count is that of the compressed `data`, as
+/// framed by [`data_to_frames`]; [`transfer_to_frames`] builds both while compressing once.
 ///
 /// The frame is filled red as a human visual cue, then the calibration ring (used to
-/// re-align a captured frame) and the CRC-protected header are drawn on top.
-pub fn create_starting_frame(total_data_size: u64, inject_options: &InjectOptions) -> VideoFrame {
+/// re-align a captured frame), the CRC-protected header and the [`EncodingConfig`]
+/// (black/white, right after the header) are drawn on top.
+///
+/// The frame does not announce a Metadata frame: one built with
+/// [`create_metadata_frame`] is still read, but extraction can call the
+/// transfer complete before it arrives.
+///
+/// Returns [`HdmiError::FrameTooSmall`] when the frame cannot hold the header and
+/// the configuration.
+pub fn create_starting_frame(
+    data: &[u8],
+    inject_options: &InjectOptions,
+) -> Result<VideoFrame, HdmiError> {
+    let total_data_size = match inject_options.compression {
+        Compression::None => data.len() as u64,
+        codec => codec.compressed_len(data),
+    };
+    start_frame(
+        total_data_size,
+        data.len() as u64,
+        file_sha256(data),
+        false,
+        inject_options,
+    )
+}
+
+/// The Start frame of a file of `file_size` bytes hashing to `sha256`, framed
+/// as `total_data_size` bytes, see [`create_starting_frame`]. With `metadata`,
+/// it announces a Metadata frame.
+pub(crate) fn start_frame(
+    total_data_size: u64,
+    file_size: u64,
+    sha256: [u8; SHA256_BYTES],
+    metadata: bool,
+    inject_options: &InjectOptions,
+) -> Result<VideoFrame, HdmiError> {
     let size = inject_options.size;
-    let mut frame = VideoFrame::new(inject_options.width, inject_options.height);
+    let (width, height) = (inject_options.width, inject_options.height);
+    // Check the capacity before drawing anything: the calibration ring of a
+    // frame smaller than the ring itself has no room for its markers.
+    if frame_capacity(width, height, size, inject_options.lattice) < ENCODING_CONFIG_BYTES * 8 {
+        return Err(frame_too_small(inject_options, 0));
+    }
+    let mut frame = VideoFrame::new(width, height);
     // Fill only the full cells of the grid. Iterating raw width/height with
     // `step_by(size)` would start a cell at the last partial column/row when the
     // dimension is not a multiple of `size` (e.g. width 1280, size 3 -> x = 1278),
@@ -49,83 +91,281 @@ pub fn create_starting_frame(total_data_size: u64, inject_options: &InjectOption
             frame.write(255, 0, 0, x, y, size); // full red visual cue
         }
     }
-    frame.write_calibration(size);
-    // The Start frame has no payload; its CRC covers only the type and value.
-    let header = FrameHeader::new(FrameType::Start, total_data_size, &[]);
+    frame.write_calibration(size, inject_options.algo);
+    if inject_options.lattice {
+        frame.write_lattice(size);
+    }
+    // The Start frame payload is the encoding configuration, so extraction can
+    // adopt it instead of relying on matching flags.
+    let config = EncodingConfig {
+        algo: inject_options.algo,
+        size,
+        width: inject_options.width,
+        height: inject_options.height,
+        lattice: inject_options.lattice,
+        metadata,
+        compression: inject_options.compression,
+        fec: inject_options.fec,
+        file_size,
+        sha256,
+    }
+    .to_bytes();
+    let header = FrameHeader::new(
+        FrameType::Start,
+        total_data_size,
+        0,
+        inject_options.session,
+        &config,
+    );
     frame.write_header(&header, size);
-    frame
+    let mut cell = HEADER_BITS;
+    for byte in config {
+        for bit_pos in (0u8..8).rev() {
+            let (r, g, b) = get_rgb_for_bit(get_bit_at(byte, bit_pos));
+            let (x, y) = content_cell_xy(cell, width, height, size, inject_options.lattice);
+            frame.write(r, g, b, x, y, size);
+            cell += 1;
+        }
+    }
+    Ok(frame)
 }
 
-pub fn data_to_frames(inject_options: &InjectOptions, data: Vec<u8>) -> Vec<VideoFrame> {
-    match inject_options.algo {
-        AlgoFrame::RGB => data_to_frames_method_rgb(inject_options, data),
-        AlgoFrame::BW => data_to_frames_method_bw(inject_options, data),
-        AlgoFrame::Quantized(levels) => {
-            data_to_frames_method_quantized(inject_options, data, levels)
-        }
-        AlgoFrame::Brightness(levels) => {
-            data_to_frames_method_brightness(inject_options, data, levels)
-        }
+/// The error returned when a frame of `inject_options` cannot carry a payload
+/// with `fec` parity bytes per block.
+fn frame_too_small(inject_options: &InjectOptions, fec: u8) -> HdmiError {
+    HdmiError::FrameTooSmall {
+        width: inject_options.width,
+        height: inject_options.height,
+        size: inject_options.size,
+        fec,
+    }
+}
+
+/// Encode `data` into frames held in memory, compressed first with
+/// `inject_options.compression`. Convenience wrapper over the streaming encoder
+/// used by [`file_to_video`](crate::file_to_video); prefer that one for large
+/// files.
+pub fn data_to_frames(
+    inject_options: &InjectOptions,
+    data: Vec<u8>,
+) -> Result<Vec<VideoFrame>, HdmiError> {
+    let mut frames = Vec::new();
+    push_data_frames(
+        inject_options,
+        &compressed(inject_options, data),
+        &mut frames,
+    )?;
+    Ok(frames)
+}
+
+/// The Start frame of `data` followed by its data frames, held in memory:
+/// [`create_starting_frame`] and [`data_to_frames`] in one call, which
+/// compresses `data` once for both.
+pub fn transfer_to_frames(
+    inject_options: &InjectOptions,
+    data: Vec<u8>,
+) -> Result<Vec<VideoFrame>, HdmiError> {
+    let file_size = data.len() as u64;
+    let sha256 = file_sha256(&data);
+    let framed = compressed(inject_options, data);
+    let mut frames = vec![start_frame(
+        framed.len() as u64,
+        file_size,
+        sha256,
+        false,
+        inject_options,
+    )?];
+    push_data_frames(inject_options, &framed, &mut frames)?;
+    Ok(frames)
+}
+
+/// `data` compressed with `inject_options.compression`.
+fn compressed(inject_options: &InjectOptions, data: Vec<u8>) -> Vec<u8> {
+    match inject_options.compression {
+        Compression::None => data,
+        codec => codec.compress(&data),
     }
 }
 
-/// Slice `data` into the payload for page `page`, padded with NULL_CHAR up to
+/// Encode the `framed` bytes into data frames appended to `frames`.
+fn push_data_frames(
+    inject_options: &InjectOptions,
+    framed: &[u8],
+    frames: &mut Vec<VideoFrame>,
+) -> Result<(), HdmiError> {
+    let writer = payload_writer(inject_options)?;
+    encode_frames(
+        inject_options,
+        &writer,
+        framed,
+        framed.len() as u64,
+        &mut |frame| {
+            frames.push(frame);
+            Ok(())
+        },
+    )
+}
+
+/// Read the payload of the next page from `source`, padded with NULL_CHAR up to
 /// `bytes_per_frame` so every frame carries a fixed-size payload (the trailing
 /// padding of the last frame is dropped at extraction time using the Start
 /// frame's total byte count).
-fn page_payload(data: &[u8], page: usize, bytes_per_frame: usize) -> Vec<u8> {
-    let start = page * bytes_per_frame;
-    let end = std::cmp::min(start + bytes_per_frame, data.len());
-    let mut payload = if start < data.len() {
-        data[start..end].to_vec()
-    } else {
-        Vec::new()
-    };
+fn read_page(source: &mut impl Read, bytes_per_frame: usize) -> std::io::Result<Vec<u8>> {
+    let mut payload = Vec::with_capacity(bytes_per_frame);
+    source
+        .take(bytes_per_frame as u64)
+        .read_to_end(&mut payload)?;
     payload.resize(bytes_per_frame, NULL_CHAR);
-    payload
+    Ok(payload)
 }
 
-/// Move data into many frames using RGB: each content cell holds 3 bytes (R, G, B).
-fn data_to_frames_method_rgb(inject_options: &InjectOptions, data: Vec<u8>) -> Vec<VideoFrame> {
-    let size = inject_options.size;
-    let width = inject_options.width;
-    let height = inject_options.height;
+/// Build the bytes painted into the payload cells of a data frame: `payload`
+/// followed by its Reed-Solomon parity (when `fec > 0`), padded with NULL_CHAR
+/// up to the raw `bytes_per_frame` the cells can hold.
+fn frame_bytes(payload: &[u8], fec: u8, bytes_per_frame: usize) -> Vec<u8> {
+    let mut bytes = fec_encode(payload, fec);
+    bytes.resize(bytes_per_frame, NULL_CHAR);
+    bytes
+}
 
-    let capacity = frame_capacity(width, height, size);
-    if capacity == 0 {
-        panic!(
-            "Frame is too small to hold the header and any payload. Increase width/height (content cells must exceed {} header cells).",
-            HEADER_BITS
-        );
+/// A frame payload to render: `(frame type, header value, data bytes)`.
+type Payload = (FrameType, u64, Vec<u8>);
+
+/// Payloads in emission order, read lazily from the source.
+type Payloads<'a> = Box<dyn Iterator<Item = std::io::Result<Payload>> + 'a>;
+
+/// Payloads to render, in emission order: one `Data` frame per page (value =
+/// page number), read from `source` as it is consumed, or in fountain mode a
+/// stream of `Fountain` symbols (value = seed) long enough to carry the requested
+/// overhead. Also returns how many frames the stream yields.
+///
+/// When `inject_options.pages` is set, only those pages are yielded (a repair
+/// video); the pages before them are still read, and reading stops after the
+/// last one.
+///
+/// Pages are read one at a time, but every fountain symbol mixes blocks from
+/// the whole stream: fountain mode reads the `total_bytes` framed bytes into
+/// memory first, so the (compressed) file must fit in memory.
+fn frame_payloads<'a>(
+    inject_options: &InjectOptions,
+    mut source: impl Read + 'a,
+    total_bytes: u64,
+    data_per_frame: usize,
+) -> std::io::Result<(usize, Payloads<'a>)> {
+    let pages = (total_bytes as usize).div_ceil(data_per_frame);
+    Ok(match inject_options.fountain {
+        None => {
+            let wanted = inject_options.pages.clone();
+            let (count, end) = match &wanted {
+                Some(wanted) => (
+                    wanted.len(),
+                    wanted.last().map_or(0, |&last| last as usize + 1),
+                ),
+                None => (pages, pages),
+            };
+            let payloads = (0..end).filter_map(move |page| {
+                let payload = match read_page(&mut source, data_per_frame) {
+                    Ok(payload) => payload,
+                    Err(err) => return Some(Err(err)),
+                };
+                let page = page as u64;
+                let selected = wanted
+                    .as_ref()
+                    .is_none_or(|wanted| wanted.binary_search(&page).is_ok());
+                selected.then_some(Ok((FrameType::Data, page, payload)))
+            });
+            (count, Box::new(payloads))
+        }
+        Some(overhead) => {
+            let mut data = Vec::with_capacity(total_bytes as usize);
+            source.take(total_bytes).read_to_end(&mut data)?;
+            let encoder = FountainEncoder::new(&data, data_per_frame);
+            let total = fountain_symbol_count(encoder.source_blocks(), overhead);
+            let symbols = encoder
+                .take(total)
+                .map(|(seed, symbol)| Ok((FrameType::Fountain, seed, symbol)));
+            (total, Box::new(symbols))
+        }
+    })
+}
+
+/// Paints the payload bytes into the payload cells of a frame.
+type PaintFn = Box<dyn Fn(&mut VideoFrame, &[u8])>;
+
+/// How payload bytes are laid out in a frame for the configured algo.
+pub(crate) struct PayloadWriter {
+    /// Raw (FEC-encoded) bytes the payload cells of one frame hold.
+    bytes_per_frame: usize,
+    /// Human-readable name of the layout, for progress output.
+    label: String,
+    /// Paints `bytes_per_frame` bytes into the payload cells of a frame.
+    paint: PaintFn,
+}
+
+/// Payload layout for the algo of `inject_options`, or
+/// [`HdmiError::FrameTooSmall`] when the frame cannot carry any payload.
+pub(crate) fn payload_writer(inject_options: &InjectOptions) -> Result<PayloadWriter, HdmiError> {
+    match inject_options.algo {
+        AlgoFrame::RGB => payload_writer_rgb(inject_options),
+        AlgoFrame::BW => payload_writer_bw(inject_options),
+        AlgoFrame::Quantized(levels) => payload_writer_quantized(inject_options, levels),
+        AlgoFrame::Brightness(levels) => payload_writer_brightness(inject_options, levels),
     }
-    let bytes_per_frame = capacity * 3; // 3 bytes per cell
+}
 
-    let total_data = data.len();
-    let total_frames = total_data.div_ceil(bytes_per_frame);
+/// Render one frame per payload read from `source` (`total_bytes` long):
+/// calibration ring, header (with the FEC strength) and the payload cells,
+/// painted by `writer`. Each frame is handed to `emit` as soon as it is
+/// rendered, so only one frame is alive at a time.
+pub(crate) fn encode_frames(
+    inject_options: &InjectOptions,
+    writer: &PayloadWriter,
+    source: impl Read,
+    total_bytes: u64,
+    emit: &mut dyn FnMut(VideoFrame) -> Result<(), HdmiError>,
+) -> Result<(), HdmiError> {
+    let fec = inject_options.fec;
+    let data_per_frame = fec_data_capacity(writer.bytes_per_frame, fec);
+    let read_error = |source| HdmiError::ReadFile {
+        path: inject_options.file_path.clone(),
+        source,
+    };
+    if let Some(&last) = inject_options.pages.as_ref().and_then(|pages| pages.last()) {
+        let pages = total_bytes.div_ceil(data_per_frame as u64);
+        if last >= pages {
+            return Err(HdmiError::PageOutOfRange { page: last, pages });
+        }
+    }
+    let (total_frames, payloads) =
+        frame_payloads(inject_options, source, total_bytes, data_per_frame).map_err(read_error)?;
 
     let pb = ProgressBar::new(total_frames as u64);
     if inject_options.show_progress {
-        println!(
-            "Inserting {} bytes into {} frames (RGB)",
-            total_data, total_frames
+        eprintln!(
+            "Inserting {} bytes into {} frames ({})",
+            total_bytes, total_frames, writer.label
         );
-    }
-
-    let mut frames: Vec<VideoFrame> = Vec::with_capacity(total_frames);
-    for page in 0..total_frames {
-        let payload = page_payload(&data, page, bytes_per_frame);
-        let mut frame = VideoFrame::new(width, height);
-        frame.write_calibration(size);
-        let header = FrameHeader::new(FrameType::Data, page as u64, &payload);
-        frame.write_header(&header, size);
-
-        for cell in 0..capacity {
-            let bi = cell * 3;
-            let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
-            frame.write(payload[bi], payload[bi + 1], payload[bi + 2], x, y, size);
+        if let Some(pages) = &inject_options.pages {
+            eprintln!("Repair video: only pages {}", format_page_ranges(pages));
+        }
+        if let Some(overhead) = inject_options.fountain {
+            eprintln!(
+                "Fountain mode: {} source blocks, {}% repair overhead",
+                (total_bytes as usize).div_ceil(data_per_frame),
+                overhead
+            );
         }
+    }
 
-        frames.push(frame);
+    for payload in payloads {
+        let (frame_type, value, payload) = payload.map_err(read_error)?;
+        emit(payload_frame(
+            inject_options,
+            writer,
+            frame_type,
+            value,
+            &payload,
+        ))?;
         if inject_options.show_progress {
             pb.inc(1);
         }
@@ -133,354 +373,496 @@ fn data_to_frames_method_rgb(inject_options: &InjectOptions, data: Vec<u8>) -> V
     if inject_options.show_progress {
         pb.finish_with_message("done");
     }
-    frames
+    Ok(())
 }
 
-/// Move data into many frames using black and white: each content cell holds one
-/// bit (black = 0, white = 1), so 8 cells hold one byte. More robust over a lossy
-/// HDMI capture than RGB.
-fn data_to_frames_method_bw(inject_options: &InjectOptions, data: Vec<u8>) -> Vec<VideoFrame> {
-    let size = inject_options.size;
-    let width = inject_options.width;
-    let height = inject_options.height;
+/// Render one frame carrying `payload`: calibration ring, header (with the FEC
+/// strength and the session ID) and the payload cells, painted by `writer`.
+fn payload_frame(
+    inject_options: &InjectOptions,
+    writer: &PayloadWriter,
+    frame_type: FrameType,
+    value: u64,
+    payload: &[u8],
+) -> VideoFrame {
+    let (size, fec) = (inject_options.size, inject_options.fec);
+    let mut frame = VideoFrame::new(inject_options.width, inject_options.height);
+    frame.write_calibration(size, inject_options.algo);
+    if inject_options.lattice {
+        frame.write_lattice(size);
+    }
+    let header = FrameHeader::new(frame_type, value, fec, inject_options.session, payload);
+    frame.write_header(&header, size);
+    (writer.paint)(
+        &mut frame,
+        &frame_bytes(payload, fec, writer.bytes_per_frame),
+    );
+    frame
+}
 
-    let capacity = frame_capacity(width, height, size);
-    if capacity < 8 {
-        panic!(
-            "Frame is too small to hold the header and at least one byte of payload. Increase width/height (need more than {} header cells plus 8).",
-            HEADER_BITS
-        );
+/// Create the frame carrying the name, size, modification time and permissions
+/// of the file, sent right after the Start frame so extraction can restore it
+/// under its original name. It is laid out, and protected, like a data page.
+///
+/// Returns [`HdmiError::FrameTooSmall`] when the page payload cannot hold the
+/// metadata record.
+pub fn create_metadata_frame(
+    metadata: &FileMetadata,
+    inject_options: &InjectOptions,
+) -> Result<VideoFrame, HdmiError> {
+    metadata_frame(metadata, inject_options, &payload_writer(inject_options)?)
+}
+
+fn metadata_frame(
+    metadata: &FileMetadata,
+    inject_options: &InjectOptions,
+    writer: &PayloadWriter,
+) -> Result<VideoFrame, HdmiError> {
+    let fec = inject_options.fec;
+    let record = metadata.to_bytes();
+    let data_per_frame = fec_data_capacity(writer.bytes_per_frame, fec);
+    if record.len() > data_per_frame {
+        return Err(frame_too_small(inject_options, fec));
     }
-    let bytes_per_frame = capacity / 8; // 8 cells per byte
+    // Padded like a page, so the CRC covers the same bytes extraction reads.
+    let mut payload = record.clone();
+    payload.resize(data_per_frame, NULL_CHAR);
+    Ok(payload_frame(
+        inject_options,
+        writer,
+        FrameType::Metadata,
+        record.len() as u64,
+        &payload,
+    ))
+}
 
-    let total_data = data.len();
-    let total_frames = total_data.div_ceil(bytes_per_frame);
+/// Destination that rendered frames are written to one at a time: a video file
+/// or an image sequence.
+pub(crate) trait FrameSink: Sized {
+    fn write(&mut self, frame: &VideoFrame) -> Result<(), HdmiError>;
 
-    let pb = ProgressBar::new(total_frames as u64);
-    if inject_options.show_progress {
-        println!(
-            "Inserting {} bytes into {} frames (BW)",
-            total_data, total_frames
+    /// Flush and close the destination.
+    fn finish(self) -> Result<(), HdmiError>;
+}
+
+/// Stream the file at `options.file_path` into the sink built by `create`, see
+/// `file_to_video`. The sink is only created once the input file and the
+/// geometry are known to be usable.
+///
+/// A directory or several inputs (see [`InjectOptions::sends_container`]) are
+/// streamed as one multi-file container.
+pub(crate) fn file_to_sink<S: FrameSink>(
+    options: &InjectOptions,
+    create: impl FnOnce(&InjectOptions) -> Result<S, HdmiError>,
+) -> Result<(), HdmiError> {
+    if options.sends_container() {
+        let container = ContainerInput::collect(&options.input_paths())?;
+        if options.show_progress {
+            eprintln!(
+                "Container: {} files, {} bytes",
+                container.entries().len(),
+                container.total_bytes()
+            );
+        }
+        return input_to_sink(
+            options,
+            &container.metadata(),
+            || Ok(container.reader()),
+            create,
         );
     }
+    let metadata = FileMetadata::from_path(&options.file_path)?;
+    input_to_sink(
+        options,
+        &metadata,
+        || fs::File::open(&options.file_path),
+        create,
+    )
+}
 
-    let mut frames: Vec<VideoFrame> = Vec::with_capacity(total_frames);
-    for page in 0..total_frames {
-        let payload = page_payload(&data, page, bytes_per_frame);
-        let mut frame = VideoFrame::new(width, height);
-        frame.write_calibration(size);
-        let header = FrameHeader::new(FrameType::Data, page as u64, &payload);
-        frame.write_header(&header, size);
-
-        let mut cell = 0;
-        for byte in &payload {
-            for bit_pos in (0u8..8).rev() {
-                // Most-significant bit first.
-                let bit = get_bit_at(*byte, bit_pos);
-                let (r, g, b) = get_rgb_for_bit(bit);
-                let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
-                frame.write(r, g, b, x, y, size);
-                cell += 1;
-            }
+/// Stream the bytes read from `open` into the sink built by `create`, after
+/// the Start frame and the Metadata frame describing them. With
+/// `options.compression` the frames carry the compressed stream instead.
+///
+/// The input is read twice: a first pass hashes it and counts the bytes to
+/// frame, then the frames are rendered from a second read. Only fountain mode
+/// holds it in memory (see [`InjectOptions::fountain`]).
+fn input_to_sink<S: FrameSink, R: Read>(
+    options: &InjectOptions,
+    metadata: &FileMetadata,
+    open: impl Fn() -> io::Result<R>,
+    create: impl FnOnce(&InjectOptions) -> Result<S, HdmiError>,
+) -> Result<(), HdmiError> {
+    let read_error = |source| HdmiError::ReadFile {
+        path: options.file_path.clone(),
+        source,
+    };
+    // The Start frame carries the size and hash of the whole input and the
+    // length of what is framed: with a codec, the first pass compresses the
+    // input only to count the bytes, streamed again below rather than held in
+    // memory.
+    let mut input = HashingReader::new(open().map_err(read_error)?);
+    let framed_bytes = options
+        .compression
+        .reader(BufReader::new(&mut input))
+        .and_then(|mut reader| io::copy(&mut reader, &mut io::sink()))
+        .map_err(read_error)?;
+    let (total_bytes, sha256) = input.finalize();
+    if options.show_progress {
+        eprintln!("File SHA-256: {}", sha256_hex(&sha256));
+        eprintln!("Session: {}", format_session(options.session));
+        if options.compression != Compression::None {
+            eprintln!(
+                "Compression: {}, {} -> {} bytes ({:.1}x)",
+                options.compression,
+                total_bytes,
+                framed_bytes,
+                total_bytes as f64 / framed_bytes.max(1) as f64
+            );
         }
-        // Fill any leftover content cells (capacity not a multiple of 8) with black.
-        while cell < capacity {
-            let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
-            frame.write(0, 0, 0, x, y, size);
-            cell += 1;
+    }
+
+    // Validate the geometry before creating the output.
+    let writer = payload_writer(options)?;
+    let starting_frame = start_frame(framed_bytes, total_bytes, sha256, true, options)?;
+    let metadata_frame = metadata_frame(metadata, options, &writer)?;
+
+    let mut sink = create(options)?;
+    sink.write(&starting_frame)?;
+    sink.write(&metadata_frame)?;
+    encode_frames(
+        options,
+        &writer,
+        options
+            .compression
+            .reader(BufReader::new(open().map_err(read_error)?))
+            .map_err(read_error)?,
+        framed_bytes,
+        &mut |frame| sink.write(&frame),
+    )?;
+    sink.finish()
+}
+
+/// Reader counting and hashing (SHA-256) the bytes read through it.
+struct HashingReader<R> {
+    inner: R,
+    bytes: u64,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            bytes: 0,
+            hasher: Sha256::new(),
         }
+    }
 
-        frames.push(frame);
-        if inject_options.show_progress {
+    /// The number and the hash of the bytes read so far.
+    fn finalize(self) -> (u64, [u8; SHA256_BYTES]) {
+        (self.bytes, self.hasher.finalize().into())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes += read as u64;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Write `frames` held in memory to `sink`.
+pub(crate) fn frames_to_sink(
+    options: &InjectOptions,
+    frames: Vec<VideoFrame>,
+    mut sink: impl FrameSink,
+) -> Result<(), HdmiError> {
+    let total_frames = frames.len() as u64;
+    if options.show_progress {
+        eprintln!("Frames to {}", options.output_video_file);
+    }
+    let pb = ProgressBar::new(total_frames);
+    for frame in frames {
+        sink.write(&frame)?;
+        if options.show_progress {
             pb.inc(1);
         }
     }
-    if inject_options.show_progress {
+    sink.finish()?;
+    if options.show_progress {
         pb.finish_with_message("done");
     }
-    frames
+    Ok(())
 }
 
-/// Move data into many frames using quantized colour: each channel of each cell
+/// Payload layout using RGB: each content cell holds 3 bytes (R, G, B).
+fn payload_writer_rgb(inject_options: &InjectOptions) -> Result<PayloadWriter, HdmiError> {
+    let size = inject_options.size;
+    let width = inject_options.width;
+    let height = inject_options.height;
+    let lattice = inject_options.lattice;
+
+    let capacity = frame_capacity(width, height, size, lattice);
+    if capacity == 0 {
+        return Err(frame_too_small(inject_options, 0));
+    }
+    let bytes_per_frame = capacity * 3; // 3 bytes per cell
+    if fec_data_capacity(bytes_per_frame, inject_options.fec) == 0 {
+        return Err(frame_too_small(inject_options, inject_options.fec));
+    }
+
+    Ok(PayloadWriter {
+        bytes_per_frame,
+        label: "RGB".to_string(),
+        paint: Box::new(move |frame, payload| {
+            for cell in 0..capacity {
+                let bi = cell * 3;
+                let (x, y) = content_cell_xy(HEADER_BITS + cell, width, height, size, lattice);
+                frame.write(payload[bi], payload[bi + 1], payload[bi + 2], x, y, size);
+            }
+        }),
+    })
+}
+
+/// Payload layout using black and white: each content cell holds one
+/// bit (black = 0, white = 1), so 8 cells hold one byte. More robust over a lossy
+/// HDMI capture than RGB.
+fn payload_writer_bw(inject_options: &InjectOptions) -> Result<PayloadWriter, HdmiError> {
+    let size = inject_options.size;
+    let width = inject_options.width;
+    let height = inject_options.height;
+    let lattice = inject_options.lattice;
+
+    let capacity = frame_capacity(width, height, size, lattice);
+    if capacity < 8 {
+        return Err(frame_too_small(inject_options, 0));
+    }
+    let bytes_per_frame = capacity / 8; // 8 cells per byte
+    if fec_data_capacity(bytes_per_frame, inject_options.fec) == 0 {
+        return Err(frame_too_small(inject_options, inject_options.fec));
+    }
+
+    Ok(PayloadWriter {
+        bytes_per_frame,
+        label: "BW".to_string(),
+        paint: Box::new(move |frame, payload| {
+            let mut cell = 0;
+            for byte in payload {
+                for bit_pos in (0u8..8).rev() {
+                    // Most-significant bit first.
+                    let bit = get_bit_at(*byte, bit_pos);
+                    let (r, g, b) = get_rgb_for_bit(bit);
+                    let (x, y) = content_cell_xy(HEADER_BITS + cell, width, height, size, lattice);
+                    frame.write(r, g, b, x, y, size);
+                    cell += 1;
+                }
+            }
+            // Fill any leftover content cells (capacity not a multiple of 8) with black.
+            while cell < capacity {
+                let (x, y) = content_cell_xy(HEADER_BITS + cell, width, height, size, lattice);
+                frame.write(0, 0, 0, x, y, size);
+                cell += 1;
+            }
+        }),
+    })
+}
+
+/// Payload layout using quantized colour: each channel of each cell
 /// carries one of `levels` evenly spaced symbols, i.e. `log2(levels)` bits per
 /// channel and `3*log2(levels)` bits per cell. This sits between BW (1 bit/cell,
 /// most robust) and RGB (24 bits/cell, least robust): picking a small `levels`
 /// keeps the colours far apart (resilient) while still packing several bits per
 /// cell (denser than BW).
-fn data_to_frames_method_quantized(
+fn payload_writer_quantized(
     inject_options: &InjectOptions,
-    data: Vec<u8>,
     levels: u32,
-) -> Vec<VideoFrame> {
+) -> Result<PayloadWriter, HdmiError> {
     let size = inject_options.size;
     let width = inject_options.width;
     let height = inject_options.height;
+    let lattice = inject_options.lattice;
 
     let bits_chan = bits_per_channel(levels) as usize;
-    let capacity = frame_capacity(width, height, size);
+    let capacity = frame_capacity(width, height, size, lattice);
     // Each cell holds 3 * bits_chan payload bits; we only fill whole bytes.
     let bytes_per_frame = capacity * 3 * bits_chan / 8;
-    if bytes_per_frame == 0 {
-        panic!(
-            "Frame is too small to hold the header and at least one byte of payload at {levels} levels/channel. Increase width/height or levels."
-        );
-    }
-
-    let total_data = data.len();
-    let total_frames = total_data.div_ceil(bytes_per_frame);
-
-    let pb = ProgressBar::new(total_frames as u64);
-    if inject_options.show_progress {
-        println!(
-            "Inserting {} bytes into {} frames (Quantized, {} levels/channel)",
-            total_data, total_frames, levels
-        );
+    if fec_data_capacity(bytes_per_frame, inject_options.fec) == 0 {
+        return Err(frame_too_small(inject_options, 0));
     }
 
-    let mut frames: Vec<VideoFrame> = Vec::with_capacity(total_frames);
-    for page in 0..total_frames {
-        let payload = page_payload(&data, page, bytes_per_frame);
-        let mut frame = VideoFrame::new(width, height);
-        frame.write_calibration(size);
-        let header = FrameHeader::new(FrameType::Data, page as u64, &payload);
-        frame.write_header(&header, size);
-
-        // Walk the payload as a most-significant-bit-first bit stream, pulling
-        // `bits_chan` bits per channel (R, then G, then B) for each cell.
-        let total_bits = payload.len() * 8;
-        let mut bit_index = 0usize;
-        for cell in 0..capacity {
-            let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
-            let mut channel = [0u8; 3];
-            for slot in channel.iter_mut() {
-                let mut symbol = 0u32;
-                for _ in 0..bits_chan {
-                    let bit = if bit_index < total_bits {
-                        (payload[bit_index / 8] >> (7 - (bit_index % 8))) & 1
-                    } else {
-                        0
-                    };
-                    symbol = (symbol << 1) | bit as u32;
-                    bit_index += 1;
+    let label = format!("Quantized, {} levels/channel", levels);
+    Ok(PayloadWriter {
+        bytes_per_frame,
+        label,
+        paint: Box::new(move |frame, payload| {
+            // Walk the payload as a most-significant-bit-first bit stream, pulling
+            // `bits_chan` bits per channel (R, then G, then B) for each cell.
+            let total_bits = payload.len() * 8;
+            let mut bit_index = 0usize;
+            for cell in 0..capacity {
+                let (x, y) = content_cell_xy(HEADER_BITS + cell, width, height, size, lattice);
+                let mut channel = [0u8; 3];
+                for slot in channel.iter_mut() {
+                    let mut symbol = 0u32;
+                    for _ in 0..bits_chan {
+                        let bit = if bit_index < total_bits {
+                            (payload[bit_index / 8] >> (7 - (bit_index % 8))) & 1
+                        } else {
+                            0
+                        };
+                        symbol = (symbol << 1) | bit as u32;
+                        bit_index += 1;
+                    }
+                    *slot = symbol_to_value(symbol, levels);
                 }
-                *slot = symbol_to_value(symbol, levels);
+                frame.write(channel[0], channel[1], channel[2], x, y, size);
             }
-            frame.write(channel[0], channel[1], channel[2], x, y, size);
-        }
-
-        frames.push(frame);
-        if inject_options.show_progress {
-            pb.inc(1);
-        }
-    }
-    if inject_options.show_progress {
-        pb.finish_with_message("done");
-    }
-    frames
+        }),
+    })
 }
 
-/// Move data into many frames using brightness (luma): each content cell is a
+/// Payload layout using brightness (luma): each content cell is a
 /// single grey shade (R = G = B) chosen from `levels` evenly spaced values, so a
 /// cell carries `log2(levels)` bits. Capture cards keep luminance at full
 /// resolution but subsample colour, so data hidden in brightness survives
 /// compression much better than the same number of levels spread across the
 /// colour channels.
-fn data_to_frames_method_brightness(
+fn payload_writer_brightness(
     inject_options: &InjectOptions,
-    data: Vec<u8>,
     levels: u32,
-) -> Vec<VideoFrame> {
+) -> Result<PayloadWriter, HdmiError> {
     let size = inject_options.size;
     let width = inject_options.width;
     let height = inject_options.height;
+    let lattice = inject_options.lattice;
 
     let bits_cell = bits_per_channel(levels) as usize; // one symbol per cell
-    let capacity = frame_capacity(width, height, size);
+    let capacity = frame_capacity(width, height, size, lattice);
     let bytes_per_frame = capacity * bits_cell / 8;
-    if bytes_per_frame == 0 {
-        panic!(
-            "Frame is too small to hold the header and at least one byte of payload at {levels} brightness levels. Increase width/height or levels."
-        );
+    if fec_data_capacity(bytes_per_frame, inject_options.fec) == 0 {
+        return Err(frame_too_small(inject_options, 0));
     }
 
-    let total_data = data.len();
-    let total_frames = total_data.div_ceil(bytes_per_frame);
-
-    let pb = ProgressBar::new(total_frames as u64);
-    if inject_options.show_progress {
-        println!(
-            "Inserting {} bytes into {} frames (Brightness, {} levels)",
-            total_data, total_frames, levels
-        );
-    }
-
-    let mut frames: Vec<VideoFrame> = Vec::with_capacity(total_frames);
-    for page in 0..total_frames {
-        let payload = page_payload(&data, page, bytes_per_frame);
-        let mut frame = VideoFrame::new(width, height);
-        frame.write_calibration(size);
-        let header = FrameHeader::new(FrameType::Data, page as u64, &payload);
-        frame.write_header(&header, size);
-
-        let total_bits = payload.len() * 8;
-        let mut bit_index = 0usize;
-        for cell in 0..capacity {
-            let (x, y) = content_cell_xy(HEADER_BITS + cell, width, size);
-            let mut symbol = 0u32;
-            for _ in 0..bits_cell {
-                let bit = if bit_index < total_bits {
-                    (payload[bit_index / 8] >> (7 - (bit_index % 8))) & 1
-                } else {
-                    0
-                };
-                symbol = (symbol << 1) | bit as u32;
-                bit_index += 1;
+    let label = format!("Brightness, {} levels", levels);
+    Ok(PayloadWriter {
+        bytes_per_frame,
+        label,
+        paint: Box::new(move |frame, payload| {
+            let total_bits = payload.len() * 8;
+            let mut bit_index = 0usize;
+            for cell in 0..capacity {
+                let (x, y) = content_cell_xy(HEADER_BITS + cell, width, height, size, lattice);
+                let mut symbol = 0u32;
+                for _ in 0..bits_cell {
+                    let bit = if bit_index < total_bits {
+                        (payload[bit_index / 8] >> (7 - (bit_index % 8))) & 1
+                    } else {
+                        0
+                    };
+                    symbol = (symbol << 1) | bit as u32;
+                    bit_index += 1;
+                }
+                let value = symbol_to_value(symbol, levels);
+                frame.write(value, value, value, x, y, size);
             }
-            let value = symbol_to_value(symbol, levels);
-            frame.write(value, value, value, x, y, size);
-        }
-
-        frames.push(frame);
-        if inject_options.show_progress {
-            pb.inc(1);
-        }
-    }
-    if inject_options.show_progress {
-        pb.finish_with_message("done");
-    }
-    frames
-}
-
-pub fn frames_to_video(options: InjectOptions, frames: Vec<VideoFrame>) -> Result<(), String> {
-    let frame_size = Size {
-        height: options.height as i32,
-        width: options.width as i32,
-    };
-
-    // Make sure the destination folder exists, otherwise the video writer
-    // silently fails to open the file.
-    if let Some(parent) = std::path::Path::new(&options.output_video_file).parent() {
-        if !parent.as_os_str().is_empty() {
-            fs::create_dir_all(parent).map_err(|err| {
-                format!("Unable to create output directory {:?}: {}", parent, err)
-            })?;
-        }
-    }
-
-    // Fourcc is a code for video codecs. The data is encoded directly into pixel
-    // values, so the codec MUST be lossless otherwise the recovered bytes differ
-    // from the injected ones. FFV1 is a lossless codec; it requires a container
-    // such as .mkv or .avi (a lossy .mp4 would corrupt the data).
-    // See list of codec here: https://learn.fotoware.com/On-Premises/Getting_started/Metadata_in_the_FotoWare_system/04_Operators_to_search_in_specific_fields/FourCC_codes
-    // Careful, codec and file extension must match.
-    let fourcc = VideoWriter::fourcc('F', 'F', 'V', '1')
-        .map_err(|error| format!("Unable to build the fourcc code: {:?}", error))?;
-
-    let total_frames = frames.len() as u64;
-    if options.show_progress {
-        println!("Frames to video");
-    }
-    let pb = ProgressBar::new(total_frames);
-
-    let mut video = VideoWriter::new(
-        options.output_video_file.as_str(),
-        fourcc,
-        options.fps.into(),
-        frame_size,
-        true,
-    )
-    .map_err(|error_video| format!("Error with video writer: {:?}", error_video))?;
-
-    if !video
-        .is_opened()
-        .map_err(|error| format!("Error checking the video writer state: {:?}", error))?
-    {
-        return Err(format!(
-            "Unable to open the video file for writing: {}",
-            options.output_video_file
-        ));
-    }
-
-    for frame in frames {
-        let image = frame.image;
-        video
-            .write(&image)
-            .map_err(|error| format!("A frame could not be written: {:?}", error))?;
-        if options.show_progress {
-            pb.inc(1);
-        }
-    }
-
-    video
-        .release()
-        .map_err(|error_release| format!("Error saving the video: {:?}", error_release))?;
-
-    if options.show_progress {
-        pb.finish_with_message("done");
-        println!("Video saved:{}", options.output_video_file.as_str());
-    }
-    Ok(())
+        }),
+    })
 }
 
 #[cfg(test)]
 mod injectionlogics_tests {
     use super::*;
     use crate::injectionextraction::frame_capacity;
+    use crate::instructionlogics::ENCODING_CONFIG_BYTES;
     use crate::options::AlgoFrame;
 
     fn opts(algo: AlgoFrame, width: u16, height: u16, size: u8) -> InjectOptions {
         InjectOptions {
-            file_path: String::new(),
-            output_video_file: String::new(),
-            fps: 30,
             width,
             height,
             size,
             algo,
-            show_progress: false,
+            ..Default::default()
         }
     }
 
     fn read_header_bits(frame: &VideoFrame, width: u16, size: u8) -> Vec<bool> {
         (0..HEADER_BITS)
             .map(|i| {
-                let (x, y) = content_cell_xy(i, width, size);
+                let (x, y) = content_cell_xy(i, width, frame.height(), size, false);
                 let c = frame.read_coordinate_color(x, y);
                 (c.r as u32 + c.g as u32 + c.b as u32) >= 382 // white => bit set
             })
             .collect()
     }
 
+    /// Hands out its bytes a few at a time and records how many were read.
+    struct TrickleReader {
+        data: Vec<u8>,
+        read: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl Read for TrickleReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let start = self.read.get();
+            let n = buf.len().min(7).min(self.data.len() - start);
+            buf[..n].copy_from_slice(&self.data[start..start + n]);
+            self.read.set(start + n);
+            Ok(n)
+        }
+    }
+
     #[test]
-    #[should_panic(expected = "Unable to read file")]
-    fn test_file_to_data_panics_for_missing_file() {
+    fn test_encode_frames_streams_one_page_at_a_time() {
         let options = opts(AlgoFrame::BW, 64, 64, 1);
-        let missing_path = std::env::temp_dir().join(format!(
-            "hdmift_missing_{}_{}.bin",
-            std::process::id(),
-            "file_to_data"
-        ));
-        let mut options = options;
-        options.file_path = missing_path.to_string_lossy().to_string();
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let writer = payload_writer(&options).unwrap();
+        let read = std::rc::Rc::new(std::cell::Cell::new(0));
+        let source = TrickleReader {
+            data: data.clone(),
+            read: read.clone(),
+        };
+
+        let mut streamed = Vec::new();
+        encode_frames(&options, &writer, source, data.len() as u64, &mut |frame| {
+            // Only the page being emitted has been read so far.
+            let pages = streamed.len() + 1;
+            assert!(read.get() <= pages * writer.bytes_per_frame);
+            streamed.push(frame);
+            Ok(())
+        })
+        .unwrap();
 
-        let _ = file_to_data(&options);
+        let frames = data_to_frames(&options, data.clone()).unwrap();
+        assert_eq!(streamed.len(), frames.len());
+        assert_eq!(read.get(), data.len());
+        for (a, b) in streamed.iter().zip(&frames) {
+            assert_eq!(
+                read_header_bits(a, 64, 1),
+                read_header_bits(b, 64, 1),
+                "streamed frames must match the in-memory ones"
+            );
+        }
     }
 
     #[test]
     fn test_data_to_frames_rgb_frame_count() {
         let options = opts(AlgoFrame::RGB, 64, 64, 1);
-        let bytes_per_frame = frame_capacity(64, 64, 1) * 3;
+        let bytes_per_frame = frame_capacity(64, 64, 1, false) * 3;
         let data = vec![7u8; bytes_per_frame * 2 + 5];
-        let frames = data_to_frames_method_rgb(&options, data);
+        let frames = data_to_frames(&options, data).unwrap();
         assert_eq!(frames.len(), 3);
     }
 
     #[test]
     fn test_data_to_frames_bw_frame_count() {
         let options = opts(AlgoFrame::BW, 64, 64, 1);
-        let bytes_per_frame = frame_capacity(64, 64, 1) / 8;
+        let bytes_per_frame = frame_capacity(64, 64, 1, false) / 8;
         let data = vec![9u8; bytes_per_frame + 1];
-        let frames = data_to_frames_method_bw(&options, data);
+        let frames = data_to_frames(&options, data).unwrap();
         assert_eq!(frames.len(), 2);
     }
 
@@ -488,12 +870,12 @@ mod injectionlogics_tests {
     fn test_data_to_frames_quantized_frame_count_and_header() {
         let levels = 4;
         let options = opts(AlgoFrame::Quantized(levels), 64, 64, 1);
-        let bytes_per_frame = frame_capacity(64, 64, 1) * 3 * 2 / 8;
+        let bytes_per_frame = frame_capacity(64, 64, 1, false) * 3 * 2 / 8;
         let data: Vec<u8> = (0..(bytes_per_frame + 7))
             .map(|i| (i % 251) as u8)
             .collect();
 
-        let frames = data_to_frames_method_quantized(&options, data, levels);
+        let frames = data_to_frames(&options, data).unwrap();
 
         assert_eq!(frames.len(), 2);
         for (page, frame) in frames.iter().enumerate() {
@@ -507,12 +889,12 @@ mod injectionlogics_tests {
     fn test_data_to_frames_brightness_frame_count_and_header() {
         let levels = 16;
         let options = opts(AlgoFrame::Brightness(levels), 64, 64, 1);
-        let bytes_per_frame = frame_capacity(64, 64, 1) * 4 / 8;
+        let bytes_per_frame = frame_capacity(64, 64, 1, false) * 4 / 8;
         let data: Vec<u8> = (0..(bytes_per_frame * 2 + 1))
             .map(|i| (i % 251) as u8)
             .collect();
 
-        let frames = data_to_frames_method_brightness(&options, data, levels);
+        let frames = data_to_frames(&options, data).unwrap();
 
         assert_eq!(frames.len(), 3);
         for (page, frame) in frames.iter().enumerate() {
@@ -523,38 +905,102 @@ mod injectionlogics_tests {
     }
 
     #[test]
-    #[should_panic]
+    fn test_data_to_frames_fec_reserves_parity_and_sets_header() {
+        let mut options = opts(AlgoFrame::BW, 64, 64, 1);
+        let bytes_per_frame = frame_capacity(64, 64, 1, false) / 8;
+        let data = vec![5u8; bytes_per_frame];
+        assert_eq!(data_to_frames(&options, data.clone()).unwrap().len(), 1);
+
+        // The same bytes no longer fit a single frame once parity is reserved.
+        options.fec = 16;
+        let frames = data_to_frames(&options, data).unwrap();
+        assert_eq!(frames.len(), 2);
+        let header = FrameHeader::from_bits(&read_header_bits(&frames[0], 64, 1)).unwrap();
+        assert_eq!(header.fec, 16);
+    }
+
+    fn assert_frame_too_small(options: &InjectOptions) {
+        let result = data_to_frames(options, vec![1, 2, 3, 4]);
+        assert!(
+            matches!(result, Err(HdmiError::FrameTooSmall { .. })),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[test]
     fn test_data_to_frames_rgb_frame_too_small() {
         // 24x24 content is only 8x8 = 64 cells, less than the header -> capacity 0.
-        let options = opts(AlgoFrame::RGB, 24, 24, 1);
-        data_to_frames_method_rgb(&options, vec![1, 2, 3, 4]);
+        assert_frame_too_small(&opts(AlgoFrame::RGB, 24, 24, 1));
     }
 
     #[test]
-    #[should_panic(expected = "Frame is too small to hold the header and at least one byte of payload")]
     fn test_data_to_frames_bw_frame_too_small() {
-        let options = opts(AlgoFrame::BW, 25, 24, 1);
-        data_to_frames_method_bw(&options, vec![1]);
+        assert_frame_too_small(&opts(AlgoFrame::BW, 25, 24, 1));
     }
 
     #[test]
-    #[should_panic(expected = "Frame is too small to hold the header and at least one byte of payload at 2 levels/channel")]
     fn test_data_to_frames_quantized_frame_too_small() {
-        let options = opts(AlgoFrame::Quantized(2), 25, 24, 1);
-        data_to_frames_method_quantized(&options, vec![1], 2);
+        assert_frame_too_small(&opts(AlgoFrame::Quantized(2), 25, 24, 1));
     }
 
     #[test]
-    #[should_panic(expected = "Frame is too small to hold the header and at least one byte of payload at 2 brightness levels")]
     fn test_data_to_frames_brightness_frame_too_small() {
-        let options = opts(AlgoFrame::Brightness(2), 31, 24, 1);
-        data_to_frames_method_brightness(&options, vec![1], 2);
+        assert_frame_too_small(&opts(AlgoFrame::Brightness(2), 31, 24, 1));
+    }
+
+    #[test]
+    fn test_data_to_frames_fec_too_strong_for_the_frame() {
+        // 40x40 BW carries (22 * 22 - 128) / 8 = 44 bytes, all taken by 64 parity bytes.
+        let mut options = opts(AlgoFrame::BW, 40, 40, 1);
+        options.fec = 64;
+        let result = data_to_frames(&options, vec![1]);
+        assert!(
+            matches!(result, Err(HdmiError::FrameTooSmall { fec: 64, .. })),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[test]
+    fn test_frames_smaller_than_the_ring_are_rejected() {
+        // Narrower than the two sides of the calibration ring: nothing may be drawn.
+        let metadata = FileMetadata {
+            name: "a.txt".to_string(),
+            size: 1,
+            modified_secs: 0,
+            modified_nanos: 0,
+            mode: 0,
+            mime: "text/plain".to_string(),
+        };
+        let algos = [
+            AlgoFrame::RGB,
+            AlgoFrame::BW,
+            AlgoFrame::Quantized(4),
+            AlgoFrame::Brightness(4),
+        ];
+        for algo in algos {
+            for (width, height) in [(8, 8), (1, 1), (17, 64), (64, 18)] {
+                let options = opts(algo, width, height, 1);
+                let start = create_starting_frame(&[1], &options);
+                assert!(
+                    matches!(start, Err(HdmiError::FrameTooSmall { .. })),
+                    "{algo:?} {width}x{height}"
+                );
+                assert_frame_too_small(&options);
+                let metadata_frame = create_metadata_frame(&metadata, &options);
+                assert!(
+                    matches!(metadata_frame, Err(HdmiError::FrameTooSmall { .. })),
+                    "{algo:?} {width}x{height}"
+                );
+            }
+        }
     }
 
     #[test]
     fn test_create_starting_frame_is_mostly_red_with_white_border() {
         let io = opts(AlgoFrame::RGB, 64, 64, 1);
-        let frame = create_starting_frame(123, &io);
+        let frame = create_starting_frame(&[1; 123], &io).unwrap();
         // A pixel deep inside the content rectangle is red (payload area).
         let c = frame.read_coordinate_color(40, 40);
         assert_eq!((c.r, c.g, c.b), (255, 0, 0));
@@ -566,20 +1012,44 @@ mod injectionlogics_tests {
     #[test]
     fn test_create_starting_frame_header_is_start() {
         let io = opts(AlgoFrame::BW, 64, 64, 1);
-        let total = 4242u64;
-        let frame = create_starting_frame(total, &io);
+        let data = vec![9u8; 4242];
+        let frame = create_starting_frame(&data, &io).unwrap();
         let header = FrameHeader::from_bits(&read_header_bits(&frame, 64, 1)).unwrap();
         assert_eq!(header.frame_type, FrameType::Start);
-        assert_eq!(header.value, total);
-        assert!(header.verify(&[]));
+        assert_eq!(header.value, 4242);
+        // The payload is the encoding configuration and the file hash, written
+        // right after the header.
+        let config = EncodingConfig {
+            algo: AlgoFrame::BW,
+            size: 1,
+            width: 64,
+            height: 64,
+            lattice: false,
+            metadata: false,
+            compression: Compression::None,
+            fec: 0,
+            file_size: 4242,
+            sha256: file_sha256(&data),
+        };
+        assert!(header.verify(&config.to_bytes()));
+        let bytes: Vec<u8> = (0..ENCODING_CONFIG_BYTES)
+            .map(|byte| {
+                (0..8).fold(0u8, |acc, bit| {
+                    let (x, y) = content_cell_xy(HEADER_BITS + byte * 8 + bit, 64, 64, 1, false);
+                    let white = frame.read_coordinate_color(x, y).r > 127;
+                    (acc << 1) | white as u8
+                })
+            })
+            .collect();
+        assert_eq!(EncodingConfig::from_bytes(&bytes), Some(config));
     }
 
     #[test]
     fn test_data_frame_headers_have_sequential_pages() {
         let io = opts(AlgoFrame::BW, 64, 64, 1);
-        let bytes_per_frame = frame_capacity(64, 64, 1) / 8;
+        let bytes_per_frame = frame_capacity(64, 64, 1, false) / 8;
         let data = vec![3u8; bytes_per_frame * 3];
-        let frames = data_to_frames_method_bw(&io, data);
+        let frames = data_to_frames(&io, data).unwrap();
         assert_eq!(frames.len(), 3);
         for (page, frame) in frames.iter().enumerate() {
             let header = FrameHeader::from_bits(&read_header_bits(frame, 64, 1)).unwrap();
@@ -587,4 +1057,41 @@ mod injectionlogics_tests {
             assert_eq!(header.value, page as u64);
         }
     }
+
+    #[test]
+    fn test_encode_frames_repair_video_holds_only_the_listed_pages() {
+        let mut options = opts(AlgoFrame::BW, 64, 64, 1);
+        let bytes_per_frame = frame_capacity(64, 64, 1, false) / 8;
+        let data: Vec<u8> = (0..bytes_per_frame * 5).map(|i| (i % 251) as u8).collect();
+        let all_frames = data_to_frames(&options, data.clone()).unwrap();
+        assert_eq!(all_frames.len(), 5);
+
+        options.pages = Some(vec![1, 3]);
+        let frames = data_to_frames(&options, data.clone()).unwrap();
+        assert_eq!(frames.len(), 2);
+        for (frame, page) in frames.iter().zip([1usize, 3]) {
+            let header = FrameHeader::from_bits(&read_header_bits(frame, 64, 1)).unwrap();
+            assert_eq!(header.frame_type, FrameType::Data);
+            assert_eq!(header.value, page as u64);
+            assert_eq!(
+                read_header_bits(frame, 64, 1),
+                read_header_bits(&all_frames[page], 64, 1)
+            );
+        }
+
+        options.pages = Some(vec![2, 5]);
+        let writer = payload_writer(&options).unwrap();
+        let error = encode_frames(
+            &options,
+            &writer,
+            data.as_slice(),
+            data.len() as u64,
+            &mut |_| Ok(()),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(error, HdmiError::PageOutOfRange { page: 5, pages: 5 }),
+            "{error}"
+        );
+    }
 }
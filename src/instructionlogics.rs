@@ -1,5 +1,12 @@
-use crate::bitlogics::{get_bit_at, get_bit_at64, get_byte_from_bits};
+use crate::bitlogics::{bits_per_channel, get_bit_at, get_bit_at64, get_byte_from_bits};
+use crate::compression::Compression;
 use crate::injectionextraction::{FORMAT_MAGIC, HEADER_BITS};
+use crate::options::AlgoFrame;
+use crate::reedsolomon::MAX_FEC_PARITY;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Information to pass from the injection to the extraction.
 /// The way to move information from one to the other is to
@@ -75,12 +82,20 @@ impl Instruction {
 }
 
 /// The role of a frame in the stream.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FrameType {
-    /// The red marker frame. Its value carries the total number of data bytes.
+    /// The red marker frame. Its value carries the total number of data bytes
+    /// and its payload the [`EncodingConfig`].
     Start,
     /// A data frame. Its value carries the page (frame) number.
     Data,
+    /// A fountain-coded symbol. Its value carries the seed that selects which
+    /// source blocks were XOR-ed into the payload.
+    Fountain,
+    /// The name, size, modification time and permissions of the file (a
+    /// `FileMetadata` record), sent after the Start frame. Its value carries
+    /// the length of the record.
+    Metadata,
 }
 
 impl FrameType {
@@ -88,12 +103,16 @@ impl FrameType {
         match self {
             FrameType::Start => 0,
             FrameType::Data => 1,
+            FrameType::Fountain => 2,
+            FrameType::Metadata => 3,
         }
     }
     fn from_byte(b: u8) -> Option<FrameType> {
         match b {
             0 => Some(FrameType::Start),
             1 => Some(FrameType::Data),
+            2 => Some(FrameType::Fountain),
+            3 => Some(FrameType::Metadata),
             _ => None,
         }
     }
@@ -104,38 +123,67 @@ impl FrameType {
 ///
 /// ```text
 ///   bits   0..8   format magic (FORMAT_MAGIC)
-///   bits   8..16  frame type (0 = Start, 1 = Data)
-///   bits  16..80  value (Start = total byte count, Data = page number)
-///   bits  80..112 CRC32 over [type byte, value big-endian, payload bytes]
-///   bits 112..128 reserved (zero)
+///   bits   8..16  frame type (0 = Start, 1 = Data, 2 = Fountain, 3 = Metadata)
+///   bits  16..80  value (Start = total byte count, Data = page number,
+///                 Fountain = symbol seed, Metadata = record length)
+///   bits  80..112 CRC32 over [type byte, value big-endian, FEC byte,
+///                 session big-endian, payload bytes]
+///   bits 112..120 Reed-Solomon parity bytes per payload block (0 = no FEC)
+///   bits 120..152 transfer session ID
+///   bits 152..160 reserved (zero)
 /// ```
 ///
 /// The CRC lets the extractor reject torn or garbled frames before they are
 /// trusted, and the explicit type removes the need to guess the start frame
-/// from its colour.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// from its colour. The session ID, drawn anew by every `inject` run, keeps the
+/// pages of two transfers apart when a capture holds frames of both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FrameHeader {
     pub frame_type: FrameType,
     pub value: u64,
     pub crc: u32,
+    /// Number of Reed-Solomon parity bytes appended to every 255-byte block of
+    /// the payload. The CRC always covers the (corrected) data bytes.
+    pub fec: u8,
+    /// Transfer session the frame belongs to, see [`new_session_id`].
+    pub session: u32,
 }
 
 impl FrameHeader {
-    /// CRC32 over the type byte, the value (big-endian) and the payload bytes.
-    pub fn compute_crc(frame_type: FrameType, value: u64, payload: &[u8]) -> u32 {
+    /// CRC32 over the type byte, the value (big-endian), the FEC byte, the
+    /// session (big-endian) and the payload bytes.
+    pub fn compute_crc(
+        frame_type: FrameType,
+        value: u64,
+        fec: u8,
+        session: u32,
+        payload: &[u8],
+    ) -> u32 {
         let mut hasher = crc32fast::Hasher::new();
         hasher.update(&[frame_type.to_byte()]);
         hasher.update(&value.to_be_bytes());
+        hasher.update(&[fec]);
+        hasher.update(&session.to_be_bytes());
         hasher.update(payload);
         hasher.finalize()
     }
 
-    /// Build a header, computing the CRC over the given payload.
-    pub fn new(frame_type: FrameType, value: u64, payload: &[u8]) -> FrameHeader {
+    /// Build the header of a frame of transfer `session` whose payload is
+    /// protected by `fec` Reed-Solomon parity bytes per block, computing the CRC
+    /// over the given payload.
+    pub fn new(
+        frame_type: FrameType,
+        value: u64,
+        fec: u8,
+        session: u32,
+        payload: &[u8],
+    ) -> FrameHeader {
         FrameHeader {
             frame_type,
             value,
-            crc: FrameHeader::compute_crc(frame_type, value, payload),
+            crc: FrameHeader::compute_crc(frame_type, value, fec, session, payload),
+            fec,
+            session,
         }
     }
 
@@ -153,6 +201,11 @@ impl FrameHeader {
             bits[idx] = get_bit_at64(self.crc as u64, (31 - i) as u8);
             idx += 1;
         }
+        push_byte_bits(&mut bits, &mut idx, self.fec);
+        for i in 0..32 {
+            bits[idx] = get_bit_at64(self.session as u64, (31 - i) as u8);
+            idx += 1;
+        }
         bits
     }
 
@@ -180,18 +233,211 @@ impl FrameHeader {
             crc = (crc << 1) | (bits[idx] as u32);
             idx += 1;
         }
+        let fec = read_byte_bits(bits, &mut idx);
+        let mut session: u32 = 0;
+        for _ in 0..32 {
+            session = (session << 1) | (bits[idx] as u32);
+            idx += 1;
+        }
         Some(FrameHeader {
             frame_type,
             value,
             crc,
+            fec,
+            session,
         })
     }
 
     /// True when the stored CRC matches the CRC recomputed over this header's
-    /// type and value plus the supplied payload bytes.
+    /// fields plus the supplied payload bytes.
     pub fn verify(&self, payload: &[u8]) -> bool {
-        self.crc == FrameHeader::compute_crc(self.frame_type, self.value, payload)
+        self.crc
+            == FrameHeader::compute_crc(
+                self.frame_type,
+                self.value,
+                self.fec,
+                self.session,
+                payload,
+            )
+    }
+}
+
+/// Version of the frame format, stored in every Start frame so an extractor
+/// rejects a stream it does not understand instead of misreading it.
+pub const FORMAT_VERSION: u8 = 9;
+
+/// Size, in bytes, of a SHA-256 digest.
+pub const SHA256_BYTES: usize = 32;
+
+/// Size, in bytes, of a serialized [`EncodingConfig`].
+pub const ENCODING_CONFIG_BYTES: usize = 19 + SHA256_BYTES;
+
+/// Layout flag: the content rectangle holds the alignment lattice.
+const LAYOUT_LATTICE: u8 = 1;
+
+/// Layout flag: a Metadata frame describing the file is sent with the pages.
+const LAYOUT_METADATA: u8 = 2;
+
+/// Encoding configuration carried by the Start frame, right after its header,
+/// so extraction does not need the inject-time flags. Serialized as:
+///
+/// ```text
+///   byte  0     format version (FORMAT_VERSION)
+///   byte  1     algo (0 = RGB, 1 = BW, 2 = Quantized, 3 = Brightness)
+///   byte  2     log2(levels) for Quantized/Brightness, 0 otherwise
+///   byte  3     cell size in pixels
+///   bytes 4..6  canonical width (big-endian)
+///   bytes 6..8  canonical height (big-endian)
+///   byte  8     layout flags (bit 0 = alignment lattice, bit 1 = Metadata
+///               frame sent)
+///   byte  9     compression codec (0 = none, 1 = deflate, 2 = zstd)
+///   byte  10    Reed-Solomon parity bytes per payload block (0 = no FEC)
+///   bytes 11..19 size of the transferred file, before compression
+///               (big-endian)
+///   bytes 19..51 SHA-256 of the transferred file, before compression
+/// ```
+///
+/// The bytes are the Start frame's payload, so its header CRC covers them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodingConfig {
+    pub algo: AlgoFrame,
+    pub size: u8,
+    pub width: u16,
+    pub height: u16,
+    /// Whether the content rectangle holds the alignment lattice (see
+    /// `injectionextraction::lattice_cells`).
+    pub lattice: bool,
+    /// Whether a Metadata frame is sent with the pages, so extraction waits
+    /// for it before calling the transfer complete.
+    pub metadata: bool,
+    /// Codec the file was compressed with before framing: the pages carry the
+    /// compressed bytes, decompressed once reassembled.
+    pub compression: Compression,
+    /// Reed-Solomon parity bytes per payload block of the data pages, so the
+    /// page size is known before the first page arrives.
+    pub fec: u8,
+    /// Size of the whole transferred file before compression, which bounds
+    /// what the reassembled pages may decompress into.
+    pub file_size: u64,
+    /// SHA-256 of the whole transferred file, checked once it is reassembled
+    /// and decompressed.
+    pub sha256: [u8; SHA256_BYTES],
+}
+
+impl EncodingConfig {
+    pub fn to_bytes(self) -> [u8; ENCODING_CONFIG_BYTES] {
+        let (algo_id, levels_log2) = match self.algo {
+            AlgoFrame::RGB => (0, 0),
+            AlgoFrame::BW => (1, 0),
+            AlgoFrame::Quantized(levels) => (2, bits_per_channel(levels) as u8),
+            AlgoFrame::Brightness(levels) => (3, bits_per_channel(levels) as u8),
+        };
+        let width = self.width.to_be_bytes();
+        let height = self.height.to_be_bytes();
+        let mut bytes = [0; ENCODING_CONFIG_BYTES];
+        bytes[..11].copy_from_slice(&[
+            FORMAT_VERSION,
+            algo_id,
+            levels_log2,
+            self.size,
+            width[0],
+            width[1],
+            height[0],
+            height[1],
+            self.layout_flags(),
+            self.compression.id(),
+            self.fec,
+        ]);
+        bytes[11..19].copy_from_slice(&self.file_size.to_be_bytes());
+        bytes[19..].copy_from_slice(&self.sha256);
+        bytes
+    }
+
+    fn layout_flags(self) -> u8 {
+        let lattice = if self.lattice { LAYOUT_LATTICE } else { 0 };
+        let metadata = if self.metadata { LAYOUT_METADATA } else { 0 };
+        lattice | metadata
+    }
+
+    /// Parse a serialized configuration. Returns `None` for a different format
+    /// version, an unknown algo or codec, or a value no encoder would write.
+    pub fn from_bytes(bytes: &[u8]) -> Option<EncodingConfig> {
+        if bytes.len() < ENCODING_CONFIG_BYTES || bytes[0] != FORMAT_VERSION {
+            return None;
+        }
+        let levels = || match bytes[2] {
+            1..=8 => Some(1u32 << bytes[2]),
+            _ => None,
+        };
+        let algo = match bytes[1] {
+            0 => AlgoFrame::RGB,
+            1 => AlgoFrame::BW,
+            2 => AlgoFrame::Quantized(levels()?),
+            3 => AlgoFrame::Brightness(levels()?),
+            _ => return None,
+        };
+        let size = bytes[3];
+        let width = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let height = u16::from_be_bytes([bytes[6], bytes[7]]);
+        if size == 0
+            || width == 0
+            || height == 0
+            || bytes[8] & !(LAYOUT_LATTICE | LAYOUT_METADATA) != 0
+            || bytes[10] > MAX_FEC_PARITY
+        {
+            return None;
+        }
+        Some(EncodingConfig {
+            algo,
+            size,
+            width,
+            height,
+            lattice: bytes[8] & LAYOUT_LATTICE != 0,
+            metadata: bytes[8] & LAYOUT_METADATA != 0,
+            compression: Compression::from_id(bytes[9])?,
+            fec: bytes[10],
+            file_size: u64::from_be_bytes(bytes[11..19].try_into().ok()?),
+            sha256: bytes[19..ENCODING_CONFIG_BYTES].try_into().ok()?,
+        })
+    }
+}
+
+/// A random transfer session ID for a new `inject` run, carried by every frame
+/// header of the run.
+pub fn new_session_id() -> u32 {
+    // Every `RandomState` is seeded from the OS randomness; the clock and the
+    // process ID are mixed in for good measure.
+    let mut hasher = RandomState::new().build_hasher();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    hasher.write_u128(now);
+    hasher.write_u32(std::process::id());
+    hasher.finish() as u32
+}
+
+/// A session ID as written and read by `--session`: 8 hex digits.
+pub fn format_session(session: u32) -> String {
+    format!("{session:08x}")
+}
+
+/// Parse a `--session` value: up to 8 hex digits, with or without `0x`.
+pub fn parse_session(session: &str) -> Option<u32> {
+    let digits = session.trim().trim_start_matches("0x");
+    if digits.is_empty() || digits.len() > 8 {
+        return None;
     }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/// SHA-256 of a whole file, as carried by its Start frame.
+pub fn file_sha256(data: &[u8]) -> [u8; SHA256_BYTES] {
+    Sha256::digest(data).into()
+}
+
+/// Lowercase hexadecimal form of a digest, as printed by `sha256sum`.
+pub fn sha256_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
 }
 
 fn push_byte_bits(bits: &mut [bool], idx: &mut usize, byte: u8) {
@@ -338,7 +584,7 @@ mod injectionlogics_tests {
     #[test]
     fn test_frame_header_round_trip_data() {
         let payload = vec![1u8, 2, 3, 250, 0, 7];
-        let header = FrameHeader::new(FrameType::Data, 42, &payload);
+        let header = FrameHeader::new(FrameType::Data, 42, 0, 0, &payload);
         let bits = header.to_bits();
         assert_eq!(bits.len(), HEADER_BITS);
         let parsed = FrameHeader::from_bits(&bits).expect("magic should match");
@@ -350,17 +596,55 @@ mod injectionlogics_tests {
 
     #[test]
     fn test_frame_header_round_trip_start() {
-        let header = FrameHeader::new(FrameType::Start, 123456, &[]);
+        let header = FrameHeader::new(FrameType::Start, 123456, 0, 0, &[]);
         let parsed = FrameHeader::from_bits(&header.to_bits()).unwrap();
         assert_eq!(parsed.frame_type, FrameType::Start);
         assert_eq!(parsed.value, 123456);
         assert!(parsed.verify(&[]));
     }
 
+    #[test]
+    fn test_frame_header_round_trip_fec() {
+        let payload = vec![9u8; 12];
+        let header = FrameHeader::new(FrameType::Data, 3, 32, 0, &payload);
+        let parsed = FrameHeader::from_bits(&header.to_bits()).unwrap();
+        assert_eq!(parsed.fec, 32);
+        assert_eq!(parsed, header);
+        assert!(parsed.verify(&payload));
+    }
+
+    #[test]
+    fn test_frame_header_round_trip_session() {
+        let header = FrameHeader::new(FrameType::Data, 3, 8, 0xdead_beef, &[1, 2]);
+        let parsed = FrameHeader::from_bits(&header.to_bits()).unwrap();
+        assert_eq!(parsed.session, 0xdead_beef);
+        assert_eq!(parsed, header);
+        assert!(parsed.verify(&[1, 2]));
+        // The FEC byte and the session are covered by the CRC.
+        assert!(!FrameHeader { fec: 9, ..parsed }.verify(&[1, 2]));
+        assert!(!FrameHeader {
+            session: 0xdead_beee,
+            ..parsed
+        }
+        .verify(&[1, 2]));
+    }
+
+    #[test]
+    fn test_session_ids_are_parsed_as_hex() {
+        assert_eq!(parse_session("deadbeef"), Some(0xdead_beef));
+        assert_eq!(parse_session("0x00ff"), Some(0xff));
+        assert_eq!(parse_session(&format_session(7)), Some(7));
+        assert_eq!(format_session(7), "00000007");
+        assert_eq!(parse_session(""), None);
+        assert_eq!(parse_session("123456789"), None);
+        assert_eq!(parse_session("xyz"), None);
+        assert_ne!(new_session_id(), new_session_id());
+    }
+
     #[test]
     fn test_frame_header_crc_detects_payload_corruption() {
         let payload = vec![10u8, 20, 30];
-        let header = FrameHeader::new(FrameType::Data, 1, &payload);
+        let header = FrameHeader::new(FrameType::Data, 1, 0, 0, &payload);
         let parsed = FrameHeader::from_bits(&header.to_bits()).unwrap();
         // A single flipped payload byte must fail verification.
         let corrupted = vec![10u8, 20, 31];
@@ -374,6 +658,84 @@ mod injectionlogics_tests {
         assert!(FrameHeader::from_bits(&bits).is_none());
     }
 
+    #[test]
+    fn test_encoding_config_round_trip() {
+        for (algo_index, algo) in [
+            AlgoFrame::RGB,
+            AlgoFrame::BW,
+            AlgoFrame::Quantized(8),
+            AlgoFrame::Brightness(256),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let config = EncodingConfig {
+                algo,
+                size: 4,
+                width: 3840,
+                height: 2160,
+                lattice: algo == AlgoFrame::BW,
+                metadata: algo_index % 2 == 0,
+                compression: Compression::ALL[algo_index % Compression::ALL.len()],
+                fec: 32 * algo_index as u8,
+                file_size: 1 << (20 + algo_index),
+                sha256: file_sha256(b"config"),
+            };
+            let bytes = config.to_bytes();
+            assert_eq!(bytes[0], FORMAT_VERSION);
+            assert_eq!(EncodingConfig::from_bytes(&bytes), Some(config));
+        }
+    }
+
+    #[test]
+    fn test_encoding_config_rejects_other_version_and_unknown_algo() {
+        let config = EncodingConfig {
+            algo: AlgoFrame::Quantized(4),
+            size: 2,
+            width: 1920,
+            height: 1080,
+            lattice: false,
+            metadata: false,
+            compression: Compression::None,
+            fec: 0,
+            file_size: 0,
+            sha256: [0; SHA256_BYTES],
+        };
+        let mut bytes = config.to_bytes();
+        bytes[0] = FORMAT_VERSION + 1;
+        assert!(EncodingConfig::from_bytes(&bytes).is_none());
+
+        // More parity than any encoder writes.
+        let mut bytes = config.to_bytes();
+        bytes[10] = MAX_FEC_PARITY + 1;
+        assert!(EncodingConfig::from_bytes(&bytes).is_none());
+
+        let mut bytes = config.to_bytes();
+        bytes[1] = 9;
+        assert!(EncodingConfig::from_bytes(&bytes).is_none());
+
+        let mut bytes = config.to_bytes();
+        bytes[2] = 0;
+        assert!(EncodingConfig::from_bytes(&bytes).is_none());
+
+        let mut bytes = config.to_bytes();
+        bytes[8] = 0x80;
+        assert!(EncodingConfig::from_bytes(&bytes).is_none());
+
+        let mut bytes = config.to_bytes();
+        bytes[9] = 7;
+        assert!(EncodingConfig::from_bytes(&bytes).is_none());
+        assert!(EncodingConfig::from_bytes(&bytes[..4]).is_none());
+    }
+
+    #[test]
+    fn test_file_sha256_matches_sha256sum() {
+        assert_eq!(
+            sha256_hex(&file_sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
     #[test]
     fn test_frame_header_short_bits_are_rejected() {
         let bits = vec![true; HEADER_BITS - 1];
@@ -382,14 +744,13 @@ mod injectionlogics_tests {
 
     #[test]
     fn test_frame_header_unknown_type_is_rejected() {
-        let header = FrameHeader::new(FrameType::Data, 99, &[1, 2, 3]);
+        let header = FrameHeader::new(FrameType::Data, 99, 0, 0, &[1, 2, 3]);
         let mut bits = header.to_bits();
         // Header layout is magic byte followed by frame type byte. Set the type
-        // byte to 2, which is intentionally not assigned.
+        // byte to 0xFF, which is intentionally not assigned.
         for bit in &mut bits[8..16] {
-            *bit = false;
+            *bit = true;
         }
-        bits[14] = true;
 
         assert!(FrameHeader::from_bits(&bits).is_none());
     }
@@ -16,17 +16,18 @@ The goals are:
 ```no_run
 use hdmifiletransporter::execute_with_video_options;
 use hdmifiletransporter::options::{VideoOptions, InjectOptions, AlgoFrame};
+use hdmifiletransporter::Compression;
 
 let options = VideoOptions::InjectInVideo({
     InjectOptions {
         file_path: "/your/file/here.zip".to_string(),
         output_video_file: "/your/video.mkv".to_string(),
-        fps: 30,
         width: 1920,
         height: 1080,
-        size: 1,
         algo: AlgoFrame::RGB,
-        show_progress: false
+        compression: Compression::None,
+        // CLI defaults for the rest: 30 fps, cells of 1 pixel, no FEC.
+        ..Default::default()
     }
 });
 let _ = execute_with_video_options(options);
@@ -36,18 +37,15 @@ let _ = execute_with_video_options(options);
 
 ```no_run
 use hdmifiletransporter::execute_with_video_options;
-use hdmifiletransporter::options::{VideoOptions, ExtractOptions, AlgoFrame};
+use hdmifiletransporter::options::{VideoOptions, ExtractOptions, Binarization};
 
 let options = VideoOptions::ExtractFromVideo({
     ExtractOptions {
         video_file_path:"/your/video.mkv".to_string(),
         extracted_file_path: "/your/file/here.zip".to_string(),
-        fps: 30,
-        width: 1920,
-        height: 1080,
-        size: 1,
-        algo: AlgoFrame::RGB,
-        show_progress: false
+        // Geometry and algo are read from the video's Start frame.
+        binarization: Binarization::Fixed,
+        ..Default::default()
     }
 });
 let _ = execute_with_video_options(options);
@@ -55,67 +53,131 @@ let _ = execute_with_video_options(options);
 */
 
 mod bitlogics;
-#[cfg(feature = "opencv-backend")]
+mod capturereassembly;
+mod compression;
+mod container;
+mod error;
 mod extractionlogics;
+mod filemetadata;
+mod fountaincode;
+mod framebuffer;
+#[cfg(feature = "opencv-backend")]
+mod imagesequence;
 #[cfg(not(feature = "opencv-backend"))]
-mod extractionlogics_stub;
+mod imagesequence_stub;
 mod injectionextraction;
-#[cfg(feature = "opencv-backend")]
 mod injectionlogics;
-#[cfg(not(feature = "opencv-backend"))]
-mod injectionlogics_stub;
 mod instructionlogics;
+mod meshcorrection;
 pub mod options;
+mod pagereport;
+mod pngsequence;
+mod rawpipe;
+mod reedsolomon;
+mod softcombining;
+mod timeline;
+mod transferstate;
 #[cfg(feature = "opencv-backend")]
-mod videoframe;
+mod videocapture;
 #[cfg(not(feature = "opencv-backend"))]
-mod videoframe_stub;
-
-#[cfg(feature = "opencv-backend")]
-use extractionlogics::data_to_files;
-#[cfg(not(feature = "opencv-backend"))]
-use extractionlogics_stub::data_to_files;
+mod videocapture_stub;
+mod videoframe;
 #[cfg(feature = "opencv-backend")]
-use injectionlogics::file_to_data;
+mod videowriter;
 #[cfg(not(feature = "opencv-backend"))]
-use injectionlogics_stub::file_to_data;
+mod videowriter_stub;
 
 // Re-export for external access (main.rs)
+pub use crate::compression::Compression;
+pub use crate::container::{
+    read_container, unpack_container, ContainerEntry, ContainerFile, CONTAINER_MAGIC,
+    CONTAINER_MIME,
+};
+pub use crate::error::HdmiError;
+pub use crate::extractionlogics::{
+    data_to_files, frames_to_data, frames_to_file, PushOutcome, Reassembler,
+};
+pub use crate::filemetadata::{mime_hint, ExtractedFile, FileMetadata, DEFAULT_FILE_NAME};
+pub use crate::fountaincode::fountain_symbol_count;
+pub use crate::framebuffer::FrameBuffer;
 #[cfg(feature = "opencv-backend")]
-pub use crate::extractionlogics::{frames_to_data, register_frame, video_to_frames};
+pub use crate::imagesequence::{
+    images_to_data, images_to_file, images_to_frames, open_image_sequence, ImageSequence,
+};
 #[cfg(not(feature = "opencv-backend"))]
-pub use crate::extractionlogics_stub::{frames_to_data, video_to_frames};
+pub use crate::imagesequence_stub::{images_to_data, images_to_file, images_to_frames};
 pub use crate::injectionextraction::{content_cell_xy, frame_capacity, HEADER_BITS};
+pub use crate::injectionlogics::{
+    create_metadata_frame, create_starting_frame, data_to_frames, transfer_to_frames,
+};
+pub use crate::instructionlogics::{
+    format_session, new_session_id, parse_session, EncodingConfig, FrameHeader, FrameType,
+    Instruction,
+};
+pub use crate::options::{
+    extract_options, Binarization, CliData, ExtractOptions, InjectOptions, PixelFormat,
+    RawVideoFormat, VideoOptions,
+};
+pub use crate::pagereport::{
+    format_page_ranges, missing_report_path, parse_page_ranges, read_page_list, read_page_report,
+    MissingPagesReport, MAX_LISTED_PAGES,
+};
+pub use crate::pngsequence::{file_to_images, frames_to_images, MANIFEST_FILE};
+pub use crate::rawpipe::{file_to_raw, frames_to_raw, RawFrameReader};
+pub use crate::reedsolomon::fec_data_capacity;
+pub use crate::timeline::{
+    timeline_report_path, CaptureTimeline, FrameOutcome, LoopCoverage, PageGap,
+};
+pub use crate::transferstate::{state_file_path, TransferState};
 #[cfg(feature = "opencv-backend")]
-pub use crate::injectionlogics::{create_starting_frame, data_to_frames, frames_to_video};
+pub use crate::videocapture::{
+    estimate_geometry, estimate_geometry_with, open_capture, raw_to_data, raw_to_file,
+    register_frame, register_frame_with, source_to_data, source_to_file, video_to_data,
+    video_to_file, video_to_frames, FrameSource,
+};
 #[cfg(not(feature = "opencv-backend"))]
-pub use crate::injectionlogics_stub::{create_starting_frame, data_to_frames, frames_to_video};
-pub use crate::instructionlogics::{FrameHeader, FrameType, Instruction};
-pub use crate::options::{extract_options, CliData, ExtractOptions, InjectOptions, VideoOptions};
-#[cfg(feature = "opencv-backend")]
+pub use crate::videocapture_stub::{
+    raw_to_data, raw_to_file, video_to_data, video_to_file, video_to_frames,
+};
 pub use crate::videoframe::VideoFrame;
+#[cfg(feature = "opencv-backend")]
+pub use crate::videowriter::{file_to_video, frames_to_video};
 #[cfg(not(feature = "opencv-backend"))]
-pub use crate::videoframe_stub::VideoFrame;
+pub use crate::videowriter_stub::{file_to_video, frames_to_video};
 
 /// Execute video logics
 /// Two executions possible: inject a file into a video or extract it.
 ///
-/// Returns an error describing the failure (for example if the video could not
-/// be written) so the caller can react instead of silently continuing.
-pub fn execute_with_video_options(options: VideoOptions) -> Result<(), String> {
+/// A directory instead of a video file (see
+/// [`InjectOptions::writes_image_sequence`] and
+/// [`ExtractOptions::reads_image_sequence`]) is written or read as numbered
+/// PNG frames.
+/// With `raw_output` or `raw_input` set, raw frames are written to stdout or
+/// read from stdin instead.
+///
+/// Returns an [`HdmiError`] describing the failure (for example if the video
+/// could not be written or pages are missing) so the caller can react instead
+/// of silently continuing.
+pub fn execute_with_video_options(options: VideoOptions) -> Result<(), HdmiError> {
     match options {
         VideoOptions::InjectInVideo(n) => {
-            let data = file_to_data(&n);
-            let starting_frame = create_starting_frame(data.len() as u64, &n);
-            let frames = data_to_frames(&n, data);
-            let mut merged_frames = vec![starting_frame];
-            merged_frames.extend(frames);
-            frames_to_video(n, merged_frames)?;
+            if let Some(pixel_format) = n.raw_output {
+                file_to_raw(&n, std::io::stdout().lock(), pixel_format)?;
+            } else if n.writes_image_sequence() {
+                file_to_images(&n)?;
+            } else {
+                file_to_video(&n)?;
+            }
         }
         VideoOptions::ExtractFromVideo(n) => {
-            let frames = video_to_frames(&n);
-            let data = frames_to_data(&n, frames);
-            data_to_files(&n, data);
+            let file = if let Some(format) = n.raw_input {
+                raw_to_file(std::io::stdin().lock(), format, &n)?
+            } else if n.reads_image_sequence() {
+                images_to_file(&n)?
+            } else {
+                video_to_file(&n)?
+            };
+            data_to_files(&n, file)?;
         }
     }
     Ok(())
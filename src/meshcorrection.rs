@@ -0,0 +1,277 @@
+//! Piecewise (mesh) correction of a registered frame from its alignment
+//! lattice.
+//!
+//! Registration maps the corner markers back onto their canonical pixels with a
+//! single affine or perspective transform, which cannot undo barrel/pincushion
+//! distortion or a scaler that is not linear across the frame: the grid still
+//! bends in the middle, by more than a cell when cells are small. When the
+//! stream carries the lattice (see `injectionextraction::lattice_cells`), each
+//! alignment pattern is looked for around its canonical place, and the offsets
+//! found are interpolated over the content rectangle to re-sample the frame
+//! before its cells are read.
+
+use crate::framebuffer::FrameBuffer;
+use crate::injectionextraction::{
+    concentric_cell_is_dark, content_cols, content_rows, lattice_cells, lattice_shape,
+    ALIGNMENT_CELLS, BORDER_CELLS, LATTICE_SPACING,
+};
+use crate::videoframe::VideoFrame;
+
+/// How far, in cells, an alignment pattern is looked for from its canonical
+/// place along each axis.
+const SEARCH_CELLS: i32 = 2;
+
+/// Smallest difference between the mean grey of the light and the dark cells
+/// of an alignment pattern for a match to be trusted.
+const MIN_CONTRAST: i32 = 96;
+
+/// Offsets are extrapolated past the outer patterns by at most this many
+/// lattice spacings (the content edges are closer than that).
+const MAX_EXTRAPOLATION: f32 = 1.0;
+
+/// Mean grey of the `size` x `size` cell whose top-left pixel is `(x, y)`,
+/// clamped inside the frame.
+fn cell_grey(buffer: &FrameBuffer, x: i32, y: i32, size: i32) -> i32 {
+    let max_x = i32::from(buffer.width()) - 1;
+    let max_y = i32::from(buffer.height()) - 1;
+    let mut total = 0;
+    for dy in 0..size {
+        for dx in 0..size {
+            let sample_x = (x + dx).clamp(0, max_x) as u16;
+            let sample_y = (y + dy).clamp(0, max_y) as u16;
+            let [r, g, b] = buffer.pixel(sample_x, sample_y);
+            total += i32::from(r) + i32::from(g) + i32::from(b);
+        }
+    }
+    total / (3 * size * size)
+}
+
+/// How well the pixels at `(x, y)` look like an alignment pattern: the mean
+/// grey of its light cells minus that of its dark cells.
+fn pattern_contrast(buffer: &FrameBuffer, x: i32, y: i32, size: i32) -> i32 {
+    let (mut dark, mut dark_cells, mut light, mut light_cells) = (0, 0, 0, 0);
+    for row in 0..ALIGNMENT_CELLS {
+        for col in 0..ALIGNMENT_CELLS {
+            let grey = cell_grey(buffer, x + col as i32 * size, y + row as i32 * size, size);
+            if concentric_cell_is_dark(row, col, ALIGNMENT_CELLS) {
+                dark += grey;
+                dark_cells += 1;
+            } else {
+                light += grey;
+                light_cells += 1;
+            }
+        }
+    }
+    light / light_cells - dark / dark_cells
+}
+
+/// Offset, in pixels, from `(x, y)` (the canonical top-left pixel of an
+/// alignment pattern) to where the pattern is in `buffer`, or `None` when no
+/// offset within `SEARCH_CELLS` cells shows a clear pattern.
+///
+/// Every offset within a cell of the best match that matches nearly as well is
+/// averaged, so the result lands in the middle of the plateau a cell several
+/// pixels wide produces instead of on its edge.
+fn locate_pattern(buffer: &FrameBuffer, x: i32, y: i32, size: i32) -> Option<(f32, f32)> {
+    let radius = SEARCH_CELLS * size;
+    let mut scores = Vec::with_capacity(((2 * radius + 1) * (2 * radius + 1)) as usize);
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            scores.push((dx, dy, pattern_contrast(buffer, x + dx, y + dy, size)));
+        }
+    }
+    let &(best_x, best_y, best) = scores.iter().max_by_key(|(_, _, contrast)| *contrast)?;
+    if best < MIN_CONTRAST {
+        return None;
+    }
+    let plateau: Vec<(i32, i32)> = scores
+        .into_iter()
+        .filter(|&(dx, dy, contrast)| {
+            contrast * 10 >= best * 9 && (dx - best_x).abs() < size && (dy - best_y).abs() < size
+        })
+        .map(|(dx, dy, _)| (dx, dy))
+        .collect();
+    let count = plateau.len() as f32;
+    let sum_x: i32 = plateau.iter().map(|(dx, _)| dx).sum();
+    let sum_y: i32 = plateau.iter().map(|(_, dy)| dy).sum();
+    Some((sum_x as f32 / count, sum_y as f32 / count))
+}
+
+/// Give every pattern that could not be located the offset of the nearest
+/// located one (in lattice steps). `None` when none was located.
+fn fill_missing(offsets: &[Option<(f32, f32)>], across: usize) -> Option<Vec<(f32, f32)>> {
+    let located: Vec<(usize, (f32, f32))> = offsets
+        .iter()
+        .enumerate()
+        .filter_map(|(index, offset)| offset.map(|offset| (index, offset)))
+        .collect();
+    if located.is_empty() {
+        return None;
+    }
+    let distance =
+        |a: usize, b: usize| (a % across).abs_diff(b % across) + (a / across).abs_diff(b / across);
+    Some(
+        offsets
+            .iter()
+            .enumerate()
+            .map(|(index, offset)| {
+                offset.unwrap_or_else(|| {
+                    located
+                        .iter()
+                        .min_by_key(|(other, _)| distance(index, *other))
+                        .map(|(_, offset)| *offset)
+                        .unwrap_or_default()
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Lower pattern index and weight of the next one along an axis of `count`
+/// patterns, for position `t` in lattice spacings from the first pattern. Past
+/// the outer patterns the weight leaves `0..=1`, extrapolating linearly.
+fn interpolation_step(t: f32, count: usize) -> (usize, f32) {
+    if count < 2 {
+        return (0, 0.0);
+    }
+    let t = t.clamp(-MAX_EXTRAPOLATION, (count - 1) as f32 + MAX_EXTRAPOLATION);
+    let lower = (t.floor().max(0.0) as usize).min(count - 2);
+    (lower, t - lower as f32)
+}
+
+/// Re-sample the content rectangle of a registered frame of the given canonical
+/// geometry so its alignment lattice lands back on its canonical cells.
+/// Offsets between patterns are interpolated bilinearly. The frame comes back
+/// unchanged when it has no lattice or not one pattern can be located.
+pub(crate) fn correct_mesh(frame: &VideoFrame, width: u16, height: u16, size: u8) -> VideoFrame {
+    let (across, down) = lattice_shape(width, height, size, true);
+    if across == 0 || frame.width() != width || frame.height() != height {
+        return frame.clone();
+    }
+    let s = i32::from(size);
+    let border = BORDER_CELLS as i32 * s;
+    let cells = lattice_cells(width, height, size, true);
+    let offsets: Vec<Option<(f32, f32)>> = cells
+        .iter()
+        .map(|&(col, row)| {
+            let x = border + col as i32 * s;
+            let y = border + row as i32 * s;
+            locate_pattern(&frame.buffer, x, y, s)
+        })
+        .collect();
+    let Some(offsets) = fill_missing(&offsets, across) else {
+        return frame.clone();
+    };
+
+    // Canonical pixel centre of the first pattern, and the pixel spacing.
+    let (first_col, first_row) = cells[0];
+    let centre = |cell: usize| {
+        ((BORDER_CELLS + cell) as f32 + ALIGNMENT_CELLS as f32 / 2.0) * f32::from(size)
+    };
+    let (origin_x, origin_y) = (centre(first_col), centre(first_row));
+    let pitch = (LATTICE_SPACING * usize::from(size)) as f32;
+    let offset = |col: usize, row: usize| offsets[row * across + col];
+
+    let mut corrected = frame.clone();
+    let left = BORDER_CELLS * usize::from(size);
+    let right = left + content_cols(width, size) * usize::from(size);
+    let bottom = left + content_rows(height, size) * usize::from(size);
+    let (max_x, max_y) = (f32::from(width) - 1.0, f32::from(height) - 1.0);
+    for y in left..bottom {
+        let (row, v) = interpolation_step((y as f32 + 0.5 - origin_y) / pitch, down);
+        let next_row = (row + 1).min(down - 1);
+        for x in left..right {
+            let (col, u) = interpolation_step((x as f32 + 0.5 - origin_x) / pitch, across);
+            let next_col = (col + 1).min(across - 1);
+            let blend = |pick: fn((f32, f32)) -> f32| {
+                let top = pick(offset(col, row)) * (1.0 - u) + pick(offset(next_col, row)) * u;
+                let bottom =
+                    pick(offset(col, next_row)) * (1.0 - u) + pick(offset(next_col, next_row)) * u;
+                top * (1.0 - v) + bottom * v
+            };
+            let source_x = (x as f32 + blend(|o| o.0)).round().clamp(0.0, max_x);
+            let source_y = (y as f32 + blend(|o| o.1)).round().clamp(0.0, max_y);
+            let rgb = frame.buffer.pixel(source_x as u16, source_y as u16);
+            corrected.buffer.set_pixel(x as u16, y as u16, rgb);
+        }
+    }
+    corrected
+}
+
+#[cfg(test)]
+mod meshcorrection_tests {
+    use super::*;
+    use crate::options::AlgoFrame;
+
+    /// A frame of random-looking cells with the lattice drawn over them.
+    fn lattice_frame(width: u16, height: u16, size: u8) -> VideoFrame {
+        let mut frame = VideoFrame::new(width, height);
+        let mut state = 12345u32;
+        for cy in 0..height / u16::from(size) {
+            for cx in 0..width / u16::from(size) {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                let v = if state >> 16 & 1 == 1 { 255 } else { 0 };
+                frame.write(v, v, v, cx * u16::from(size), cy * u16::from(size), size);
+            }
+        }
+        frame.write_calibration(size, AlgoFrame::BW);
+        frame.write_lattice(size);
+        frame
+    }
+
+    /// `frame` with every pixel moved right by `dx` and down by `dy`.
+    fn shifted(frame: &VideoFrame, dx: i32, dy: i32) -> VideoFrame {
+        let mut moved = frame.clone();
+        for y in 0..i32::from(frame.height()) {
+            for x in 0..i32::from(frame.width()) {
+                let source_x = (x - dx).clamp(0, i32::from(frame.width()) - 1) as u16;
+                let source_y = (y - dy).clamp(0, i32::from(frame.height()) - 1) as u16;
+                let rgb = frame.buffer.pixel(source_x, source_y);
+                moved.buffer.set_pixel(x as u16, y as u16, rgb);
+            }
+        }
+        moved
+    }
+
+    #[test]
+    fn test_patterns_are_located_where_they_were_drawn() {
+        let frame = lattice_frame(256, 256, 2);
+        let moved = shifted(&frame, 3, -2);
+        let border = BORDER_CELLS as i32 * 2;
+        for (col, row) in lattice_cells(256, 256, 2, true) {
+            let (x, y) = (border + col as i32 * 2, border + row as i32 * 2);
+            assert_eq!(locate_pattern(&frame.buffer, x, y, 2), Some((0.0, 0.0)));
+            assert_eq!(locate_pattern(&moved.buffer, x, y, 2), Some((3.0, -2.0)));
+        }
+    }
+
+    #[test]
+    fn test_a_shifted_content_area_is_moved_back() {
+        let frame = lattice_frame(256, 256, 2);
+        let corrected = correct_mesh(&shifted(&frame, 2, 3), 256, 256, 2);
+        // Away from the edges the shift left nothing uncovered to restore.
+        let inner = (BORDER_CELLS * 2 + 8) as u16..(256 - BORDER_CELLS * 2 - 8) as u16;
+        for y in inner.clone() {
+            for x in inner.clone() {
+                assert_eq!(corrected.buffer.pixel(x, y), frame.buffer.pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_frames_without_a_lattice_are_left_alone() {
+        let mut frame = VideoFrame::new(64, 64);
+        frame.write_calibration(1, AlgoFrame::BW);
+        assert_eq!(correct_mesh(&frame, 64, 64, 1), frame);
+        // Too small for a single pattern below the header rows.
+        assert_eq!(lattice_shape(64, 30, 1, true), (0, 0));
+    }
+
+    #[test]
+    fn test_missing_patterns_borrow_the_nearest_offset() {
+        let (a, b) = ((1.0, 0.0), (0.0, 2.0));
+        let offsets = [Some(a), None, None, None, None, Some(b)];
+        assert_eq!(fill_missing(&offsets, 3), Some(vec![a, a, b, a, b, b]));
+        assert_eq!(fill_missing(&[None, None], 2), None);
+    }
+}
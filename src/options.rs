@@ -1,6 +1,12 @@
 use clap::builder::TypedValueParser;
 use clap::Parser;
 
+use crate::compression::Compression;
+use crate::error::HdmiError;
+use crate::instructionlogics::{new_session_id, parse_session};
+use crate::pagereport::read_page_report;
+use crate::reedsolomon::MAX_FEC_PARITY;
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum AppMode {
     Inject,
@@ -76,6 +82,118 @@ impl std::str::FromStr for AlgoFrame {
     }
 }
 
+/// Byte order of the pixels of raw frames piped through stdin or stdout, named
+/// as ffmpeg's `-pix_fmt` values.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PixelFormat {
+    Rgb24,
+    /// OpenCV's and most capture pipelines' native order.
+    Bgr24,
+}
+
+impl std::fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Rgb24 => "rgb24",
+            Self::Bgr24 => "bgr24",
+        };
+        s.fmt(f)
+    }
+}
+
+impl std::str::FromStr for PixelFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb24" => Ok(Self::Rgb24),
+            "bgr24" => Ok(Self::Bgr24),
+            _ => Err(format!("Unknown pixel format: {s}")),
+        }
+    }
+}
+
+/// How a captured frame is turned black and white to look for its markers.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Binarization {
+    /// Everything darker than mid-grey (128) is dark.
+    Fixed,
+    /// One threshold per frame, chosen by Otsu's method from its histogram.
+    /// Survives dim or limited-range captures.
+    Otsu,
+    /// A threshold per pixel: the mean of its neighbourhood. Survives uneven
+    /// backlighting and vignetting.
+    AdaptiveMean,
+    /// As `AdaptiveMean`, with a gaussian-weighted neighbourhood.
+    AdaptiveGaussian,
+}
+
+impl Binarization {
+    /// Every strategy, in the order they are retried.
+    pub const ALL: [Binarization; 4] = [
+        Self::Fixed,
+        Self::Otsu,
+        Self::AdaptiveMean,
+        Self::AdaptiveGaussian,
+    ];
+
+    /// This strategy, then the others in [`Binarization::ALL`] order: what to
+    /// try in turn until the markers of a frame are found.
+    pub fn fallbacks(self) -> impl Iterator<Item = Binarization> {
+        std::iter::once(self).chain(Self::ALL.into_iter().filter(move |other| *other != self))
+    }
+}
+
+impl std::fmt::Display for Binarization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Fixed => "fixed",
+            Self::Otsu => "otsu",
+            Self::AdaptiveMean => "adaptive-mean",
+            Self::AdaptiveGaussian => "adaptive-gaussian",
+        };
+        s.fmt(f)
+    }
+}
+
+impl std::str::FromStr for Binarization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Self::Fixed),
+            "otsu" => Ok(Self::Otsu),
+            "adaptive-mean" => Ok(Self::AdaptiveMean),
+            "adaptive-gaussian" => Ok(Self::AdaptiveGaussian),
+            _ => Err(format!("Unknown binarization: {s}")),
+        }
+    }
+}
+
+/// Layout of the raw frames read from stdin: every frame is exactly
+/// `width * height * 3` bytes, with no header between frames.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RawVideoFormat {
+    pub pixel_format: PixelFormat,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Input or output path that stands for stdin or stdout (raw frames).
+pub const PIPE_PATH: &str = "-";
+
+/// Parse a `WIDTHxHEIGHT` frame size such as `1920x1080`.
+fn parse_raw_size(size: &str) -> Result<(u16, u16), HdmiError> {
+    let invalid = || HdmiError::InvalidRawSize {
+        size: size.to_string(),
+    };
+    let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (width.trim().parse::<u16>(), height.trim().parse::<u16>()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
 /// Default number of levels per channel when `--algo quantized` is selected
 /// without an explicit `--levels`. Four levels = 2 bits/channel = 6 bits/cell,
 /// a good density/robustness compromise for typical HDMI links.
@@ -84,22 +202,47 @@ pub const DEFAULT_QUANTIZED_LEVELS: u32 = 4;
 /// Resolve the level count and validate the algo selection. For the level-based
 /// algos (`quantized`, `brightness`) the levels come from `--levels` (falling
 /// back to the default) and must be a power of two in `2..=256`.
-fn resolve_algo(algo: AlgoFrame, levels: Option<u32>) -> AlgoFrame {
+fn resolve_algo(algo: AlgoFrame, levels: Option<u32>) -> Result<AlgoFrame, HdmiError> {
     let validate = |levels: u32| {
         if !levels.is_power_of_two() || !(2..=256).contains(&levels) {
-            panic!("--levels must be a power of two between 2 and 256 (got {levels})");
+            return Err(HdmiError::InvalidLevels { levels });
         }
-        levels
+        Ok(levels)
     };
-    match algo {
+    Ok(match algo {
         AlgoFrame::Quantized(_) => {
-            AlgoFrame::Quantized(validate(levels.unwrap_or(DEFAULT_QUANTIZED_LEVELS)))
+            AlgoFrame::Quantized(validate(levels.unwrap_or(DEFAULT_QUANTIZED_LEVELS))?)
         }
         AlgoFrame::Brightness(_) => {
-            AlgoFrame::Brightness(validate(levels.unwrap_or(DEFAULT_QUANTIZED_LEVELS)))
+            AlgoFrame::Brightness(validate(levels.unwrap_or(DEFAULT_QUANTIZED_LEVELS))?)
         }
         other => other,
+    })
+}
+
+/// Validate the Reed-Solomon strength: the number of parity bytes reserved in
+/// every 255-byte payload block, `0` disabling forward error correction.
+fn resolve_fec(fec: Option<u8>) -> Result<u8, HdmiError> {
+    let fec = fec.unwrap_or(0);
+    if fec > MAX_FEC_PARITY {
+        return Err(HdmiError::InvalidFec {
+            fec,
+            max: MAX_FEC_PARITY,
+        });
     }
+    Ok(fec)
+}
+
+/// Check that a frame dimension is a whole number of `size`-pixel cells.
+fn check_aligned(dimension: &'static str, pixels: u16, size: u8) -> Result<(), HdmiError> {
+    if u32::from(pixels).checked_rem(u32::from(size)) != Some(0) {
+        return Err(HdmiError::UnalignedSize {
+            dimension,
+            pixels: u32::from(pixels),
+            size,
+        });
+    }
+    Ok(())
 }
 
 /// CLI arguments
@@ -112,9 +255,11 @@ fn resolve_algo(algo: AlgoFrame, levels: Option<u32>) -> AlgoFrame {
 #[clap(name = "from_str")]
 #[command(author, version, about, long_about = None)] // Read from `Cargo.toml`
 pub struct CliData {
-    /// The source of the file to inject the video
-    #[arg(short = 'i', long)]
-    input_file_path: Option<String>,
+    /// The source of the file to inject the video. Several paths, or a
+    /// directory, are injected as one multi-file container that `extract`
+    /// unpacks into the `-o` directory. Extract reads a single input.
+    #[arg(short = 'i', long, num_args = 1..)]
+    input_file_path: Vec<String>,
 
     #[arg(short = 'f', long)]
     pub fps: Option<u8>,
@@ -132,9 +277,12 @@ pub struct CliData {
     #[arg(short = 's', long)]
     pub size: Option<u8>,
 
+    /// Frame height in pixels. Optional when extracting: the Start frame
+    /// carries it (as well as width, size and algo).
     #[arg(short = 'g', long)]
     pub height: Option<u16>,
 
+    /// Frame width in pixels. Optional when extracting.
     #[arg(short = 'w', long)]
     pub width: Option<u16>,
 
@@ -161,14 +309,83 @@ pub struct CliData {
     #[arg(short = 'l', long)]
     pub levels: Option<u32>,
 
+    /// Reed-Solomon parity bytes per 255-byte payload block (0..=128). Each
+    /// block corrects up to `fec / 2` wrong bytes, so a frame with a few misread
+    /// cells is repaired instead of dropped. Only used when injecting: the value
+    /// is stored in every frame header. Default 0 (no correction).
+    #[arg(short = 'e', long)]
+    pub fec: Option<u8>,
+
+    /// Switch injection to fountain (LT) coding across frames and emit this
+    /// many percent of extra repair frames. Any large enough subset of the
+    /// frames rebuilds the file, so dropped or duplicated captures no longer
+    /// matter; 25..50 is typical. Holds the (compressed) file in memory while
+    /// encoding. Omitted = classic one-page-per-frame layout.
+    #[arg(long)]
+    pub fountain: Option<u16>,
+
+    /// Inject only these data pages, after the Start frame, to render a short
+    /// repair video for an extraction that missed them. Either the
+    /// `<output>.missing.json` report written by `extract`, whose session the
+    /// repair video then carries unless `--session` is given, or a range string
+    /// such as `3,7-9`. Not available with `--fountain`.
+    #[arg(long)]
+    pub pages: Option<String>,
+
+    /// Inject only: compress the file before framing it, `none`, `deflate` or
+    /// `zstd`. Text, logs and CSVs often shrink 5-10x, and the video with
+    /// them. The codec is recorded in the Start frame, so extraction
+    /// decompresses without a flag. Default `none`.
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(["none", "deflate", "zstd"])
+    .map(|s| s.parse::<Compression>().unwrap()),)]
+    pub compress: Option<Compression>,
+
+    /// Inject only: reserve a lattice of small alignment patterns inside the
+    /// content area, every 24 cells. The extractor locates them to correct
+    /// lens or scaler distortion that bends the cell grid between the corner
+    /// markers, which otherwise limits the usable `--size`. Costs about 4% of
+    /// the payload. Default false.
+    #[arg(long)]
+    pub lattice: Option<bool>,
+
+    /// Pixel layout of the raw frames read from stdin (`-i -` when extracting)
+    /// or written to stdout (`-o -` when injecting), as ffmpeg's `-pix_fmt`:
+    /// `bgr24` or `rgb24`. Default `bgr24`.
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(["rgb24", "bgr24"])
+    .map(|s| s.parse::<PixelFormat>().unwrap()),)]
+    pub pix_fmt: Option<PixelFormat>,
+
+    /// Extract only: size of the raw frames read from stdin (`-i -`), as
+    /// `WIDTHxHEIGHT`. Defaults to `--width` x `--height`.
+    #[arg(long)]
+    pub raw_size: Option<String>,
+
+    /// Extract only: how captured frames are thresholded to find their
+    /// markers: `fixed` (mid-grey), `otsu` (per frame), `adaptive-mean` or
+    /// `adaptive-gaussian` (per neighbourhood). When a frame shows fewer than
+    /// three finder patterns the other strategies are tried in turn. Default
+    /// `fixed`.
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(["fixed", "otsu", "adaptive-mean", "adaptive-gaussian"])
+    .map(|s| s.parse::<Binarization>().unwrap()),)]
+    pub binarize: Option<Binarization>,
+
+    /// Transfer session ID, as 8 hex digits. Inject: the ID every frame
+    /// carries, for example to render a repair video for the same session;
+    /// default the session of the `--pages` report, or a new random one.
+    /// Extract: the session to decode, ignoring frames of other transfers;
+    /// default the session with the most Start frames (the first one found
+    /// until another outnumbers it).
+    #[arg(long)]
+    pub session: Option<String>,
+
     #[arg(short = 'p', long)]
     pub show_progress: Option<bool>,
 }
 
 /// Extract from the command line (CLI) argument the option.
 /// Depending of the mode, the function returns
-/// the proper formed structure or panic telling what argument
-/// is missing
+/// the proper formed structure or an error telling what argument
+/// is missing or invalid
 ///
 /// # Arguments
 /// args - The command line argument that may contain inject or extract information
@@ -176,56 +393,113 @@ pub struct CliData {
 /// # Returns
 /// Return a well formed structure for the task asked or return a failure with the missing
 /// fields
-pub fn extract_options(args: CliData) -> Result<VideoOptions, String> {
+pub fn extract_options(args: CliData) -> Result<VideoOptions, HdmiError> {
+    let session = args
+        .session
+        .as_deref()
+        .map(|session| {
+            parse_session(session).ok_or_else(|| HdmiError::InvalidSession {
+                session: session.to_string(),
+            })
+        })
+        .transpose()?;
     Ok(match args.mode {
         Some(i) => match i {
             AppMode::Inject => {
-                let file_path = args
-                    .input_file_path
-                    .unwrap_or_else(|| panic!("Missing input file"));
-                println!("Input file: {}", file_path);
+                let mut inputs = args.input_file_path.into_iter();
+                let file_path = inputs.next().ok_or(HdmiError::MissingInputFile)?;
+                let extra_file_paths: Vec<String> = inputs.collect();
+                let output_video_file = args
+                    .output_video_path
+                    .unwrap_or_else(|| "video.mkv".to_string());
+                // Raw frames go to stdout, so nothing else may be printed there.
+                let raw_output = (output_video_file == PIPE_PATH)
+                    .then(|| args.pix_fmt.unwrap_or(PixelFormat::Bgr24));
+                if raw_output.is_none() && args.show_progress.unwrap_or(false) {
+                    for path in std::iter::once(&file_path).chain(&extra_file_paths) {
+                        eprintln!("Input file: {}", path);
+                    }
+                }
                 let size = args.size.unwrap_or(1);
                 let height = args.height.unwrap_or(2160);
                 let width = args.width.unwrap_or(3840);
-                if i32::from(height) % i32::from(size) != 0 {
-                    panic!("Height and size are not a divided round number");
-                }
-                if i32::from(width) % i32::from(size) != 0 {
-                    panic!("Width and size are not a divided round number");
+                check_aligned("height", height, size)?;
+                check_aligned("width", width, size)?;
+                // A report written by `extract` also names the session to repair.
+                let (pages, report_session) = match args.pages {
+                    Some(pages) => {
+                        let (pages, session) = read_page_report(&pages)?;
+                        (Some(pages), session)
+                    }
+                    None => (None, None),
+                };
+                if pages.is_some() && args.fountain.is_some() {
+                    return Err(HdmiError::PagesWithFountain);
                 }
                 VideoOptions::InjectInVideo({
                     InjectOptions {
                         file_path,
-                        output_video_file: args
-                            .output_video_path
-                            .unwrap_or_else(|| "video.mkv".to_string()),
-                        size: args.size.unwrap_or(1),
+                        extra_file_paths,
+                        output_video_file,
+                        size,
                         fps: args.fps.unwrap_or(30),
-                        height: args.height.unwrap_or(2160),
-                        width: args.width.unwrap_or(3840),
-                        algo: resolve_algo(args.algo.unwrap_or(AlgoFrame::RGB), args.levels),
-                        show_progress: args.show_progress.unwrap_or(false),
+                        height,
+                        width,
+                        algo: resolve_algo(args.algo.unwrap_or(AlgoFrame::RGB), args.levels)?,
+                        fec: resolve_fec(args.fec)?,
+                        fountain: args.fountain,
+                        pages,
+                        compression: args.compress.unwrap_or_default(),
+                        session: session.or(report_session).unwrap_or_else(new_session_id),
+                        lattice: args.lattice.unwrap_or(false),
+                        raw_output,
+                        show_progress: raw_output.is_none() && args.show_progress.unwrap_or(false),
                     }
                 })
             }
             AppMode::Extract => VideoOptions::ExtractFromVideo({
+                let mut inputs = args.input_file_path;
+                if inputs.len() > 1 {
+                    return Err(HdmiError::SeveralExtractInputs {
+                        count: inputs.len(),
+                    });
+                }
+                let video_file_path = inputs.pop().unwrap_or_else(|| "video.mkv".to_string());
+                let raw_input = if video_file_path == PIPE_PATH {
+                    let (width, height) = match (&args.raw_size, args.width, args.height) {
+                        (Some(size), _, _) => parse_raw_size(size)?,
+                        (None, Some(width), Some(height)) => (width, height),
+                        _ => return Err(HdmiError::MissingRawSize),
+                    };
+                    Some(RawVideoFormat {
+                        pixel_format: args.pix_fmt.unwrap_or(PixelFormat::Bgr24),
+                        width,
+                        height,
+                    })
+                } else {
+                    None
+                };
                 ExtractOptions {
-                    video_file_path: args
-                        .input_file_path
-                        .unwrap_or_else(|| "video.mkv".to_string()),
+                    video_file_path,
                     extracted_file_path: args
                         .output_video_path
                         .unwrap_or_else(|| "mydata.txt".to_string()),
-                    size: args.size.unwrap_or(1),
+                    size: args.size,
                     fps: args.fps.unwrap_or(30),
-                    height: args.height.unwrap_or(2160),
-                    width: args.width.unwrap_or(3840),
-                    algo: resolve_algo(args.algo.unwrap_or(AlgoFrame::RGB), args.levels),
+                    height: args.height,
+                    width: args.width,
+                    algo: args
+                        .algo
+                        .map(|algo| resolve_algo(algo, args.levels))
+                        .transpose()?,
+                    binarization: args.binarize.unwrap_or(Binarization::Fixed),
+                    session,
+                    raw_input,
                     show_progress: args.show_progress.unwrap_or(false),
                 }
             }),
         },
-        None => panic!("Mode is required (use -m inject or -m extract)"),
+        None => return Err(HdmiError::MissingMode),
     })
 }
 
@@ -233,27 +507,149 @@ pub fn extract_options(args: CliData) -> Result<VideoOptions, String> {
 #[derive(Clone)]
 pub struct InjectOptions {
     pub file_path: String,
+    /// Further files or directories sent with `file_path`, see
+    /// [`InjectOptions::sends_container`].
+    pub extra_file_paths: Vec<String>,
     pub output_video_file: String,
     pub fps: u8,
     pub width: u16,
     pub height: u16,
     pub size: u8,
     pub algo: AlgoFrame,
+    /// Reed-Solomon parity bytes per 255-byte payload block (0 = no FEC).
+    pub fec: u8,
+    /// Fountain-code repair overhead in percent, or `None` to write one page
+    /// per frame. Every symbol mixes blocks from the whole file, so fountain
+    /// mode holds the (compressed) file in memory while encoding; the paged
+    /// layout streams it.
+    pub fountain: Option<u16>,
+    /// Data pages to write after the Start frame (sorted), or `None` for all of
+    /// them. Set to render a repair video.
+    pub pages: Option<Vec<u64>>,
+    /// Codec the file is compressed with before it is framed, recorded in the
+    /// Start frame.
+    pub compression: Compression,
+    /// Transfer session ID carried by every frame header. The CLI draws a new
+    /// one per run ([`new_session_id`]).
+    pub session: u32,
+    /// Reserve the alignment lattice inside the content rectangle.
+    pub lattice: bool,
+    /// Write raw frames in this pixel format to stdout instead of a video
+    /// file (`-o -`).
+    pub raw_output: Option<PixelFormat>,
     pub show_progress: bool,
 }
 
+/// The CLI defaults (RGB cells of 1 pixel in 3840x2160 frames at 30 fps, no
+/// FEC, compression or lattice), with empty paths and session 0.
+impl Default for InjectOptions {
+    fn default() -> Self {
+        InjectOptions {
+            file_path: String::new(),
+            extra_file_paths: Vec::new(),
+            output_video_file: String::new(),
+            fps: 30,
+            width: 3840,
+            height: 2160,
+            size: 1,
+            algo: AlgoFrame::RGB,
+            fec: 0,
+            fountain: None,
+            pages: None,
+            compression: Compression::None,
+            session: 0,
+            lattice: false,
+            raw_output: None,
+            show_progress: false,
+        }
+    }
+}
+
+impl InjectOptions {
+    /// `file_path`, then `extra_file_paths`.
+    pub fn input_paths(&self) -> Vec<&str> {
+        std::iter::once(&self.file_path)
+            .chain(&self.extra_file_paths)
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Whether the inputs are sent as a multi-file container rather than as
+    /// one plain file: there are several of them, or `file_path` is a
+    /// directory.
+    pub fn sends_container(&self) -> bool {
+        !self.extra_file_paths.is_empty() || std::path::Path::new(&self.file_path).is_dir()
+    }
+
+    /// Whether `output_video_file` names a directory of PNG frames rather than
+    /// a video file: an existing directory, or a path ending with a separator
+    /// or without an extension (a video file needs one to pick its container).
+    /// `-` is stdout, never a directory.
+    pub fn writes_image_sequence(&self) -> bool {
+        let path = std::path::Path::new(&self.output_video_file);
+        if self.output_video_file == PIPE_PATH {
+            return false;
+        }
+        path.is_dir()
+            || self.output_video_file.ends_with(std::path::is_separator)
+            || path.extension().is_none()
+    }
+}
+
+/// Options for the extraction of the file from a video. The geometry
+/// and algo are optional: the Start frame carries the values used at inject
+/// time and they win. Given values only help locate the Start frame (and skip
+/// probing for it).
 #[derive(Clone)]
 pub struct ExtractOptions {
     pub video_file_path: String,
     pub extracted_file_path: String,
     pub fps: u8,
-    pub width: u16,
-    pub height: u16,
-    pub size: u8,
-    pub algo: AlgoFrame,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    pub size: Option<u8>,
+    pub algo: Option<AlgoFrame>,
+    /// Thresholding tried first to find the markers of a captured frame.
+    pub binarization: Binarization,
+    /// Only decode the frames of this transfer session. `None` locks onto the
+    /// session of the first Start frame found, and switches to another session
+    /// once its Start frames outnumber those of the locked one.
+    pub session: Option<u32>,
+    /// Read raw frames of this layout from stdin instead of a video file
+    /// (`-i -`).
+    pub raw_input: Option<RawVideoFormat>,
     pub show_progress: bool,
 }
 
+/// The CLI defaults (geometry and algo read from the Start frame, fixed
+/// binarization, the session with the most Start frames), with empty paths: no
+/// state, missing pages or timeline is written next to the output.
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            video_file_path: String::new(),
+            extracted_file_path: String::new(),
+            fps: 30,
+            width: None,
+            height: None,
+            size: None,
+            algo: None,
+            binarization: Binarization::Fixed,
+            session: None,
+            raw_input: None,
+            show_progress: false,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// Whether `video_file_path` is a directory of images rather than a video
+    /// file or a capture device.
+    pub fn reads_image_sequence(&self) -> bool {
+        std::path::Path::new(&self.video_file_path).is_dir()
+    }
+}
+
 #[derive(Clone)]
 pub enum VideoOptions {
     InjectInVideo(InjectOptions),
@@ -302,49 +698,84 @@ mod options_tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_extract_options_no_mode() {
-        let _ = extract_options(CliData {
+        let result = extract_options(CliData {
             fps: None,
             height: None,
-            input_file_path: Some("inputfile.txt".to_string()),
+            input_file_path: vec!["inputfile.txt".to_string()],
             mode: None,
             output_video_path: None,
             size: None,
             width: None,
             algo: None,
             levels: None,
+            fec: None,
+            fountain: None,
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
             show_progress: None,
         });
+        assert!(
+            matches!(result, Err(HdmiError::MissingMode)),
+            "{:?}",
+            result.err()
+        );
     }
     #[test]
-    #[should_panic]
     fn test_extract_options_inject_no_input_file_path() {
-        let _ = extract_options(CliData {
+        let result = extract_options(CliData {
             fps: None,
             height: None,
-            input_file_path: None,
+            input_file_path: Vec::new(),
             mode: Some(AppMode::Inject),
             output_video_path: None,
             size: None,
             width: None,
             algo: None,
             levels: None,
+            fec: None,
+            fountain: None,
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
             show_progress: None,
         });
+        assert!(
+            matches!(result, Err(HdmiError::MissingInputFile)),
+            "{:?}",
+            result.err()
+        );
     }
     #[test]
     fn test_extract_options_inject_default() {
         let options = extract_options(CliData {
             fps: None,
             height: None,
-            input_file_path: Some("inputfile.txt".to_string()),
+            input_file_path: vec!["inputfile.txt".to_string()],
             mode: Some(AppMode::Inject),
             output_video_path: None,
             size: None,
             width: None,
             algo: None,
             levels: None,
+            fec: None,
+            fountain: None,
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
             show_progress: None,
         });
         let unwrapped_options = options.unwrap();
@@ -355,24 +786,173 @@ mod options_tests {
             assert_eq!(op.size, 1);
             assert_eq!(op.output_video_file, "video.mkv");
             assert_eq!(op.algo, AlgoFrame::RGB);
+            assert_eq!(op.fec, 0);
+            assert_eq!(op.fountain, None);
+            assert_eq!(op.pages, None);
             assert_eq!(op.show_progress, false);
         } else {
             assert!(true, "Failed to unwrapped inject options");
         }
     }
 
+    #[test]
+    fn test_extract_options_inject_repair_pages() {
+        let options = extract_options(CliData {
+            fps: None,
+            height: None,
+            input_file_path: vec!["inputfile.txt".to_string()],
+            mode: Some(AppMode::Inject),
+            output_video_path: None,
+            size: None,
+            width: None,
+            algo: None,
+            levels: None,
+            fec: None,
+            fountain: None,
+            pages: Some("7-9,3".to_string()),
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
+            show_progress: None,
+        });
+        if let InjectInVideo(op) = options.unwrap() {
+            assert_eq!(op.pages, Some(vec![3, 7, 8, 9]));
+        } else {
+            panic!("Expected inject options");
+        }
+    }
+
+    #[test]
+    fn test_extract_options_inject_repair_adopts_the_report_session() {
+        let path = std::env::temp_dir().join(format!(
+            "hdmift_options_report_{}.missing.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, "{\"session\": \"2f4c91a7\", \"missing\": [3, 5]}").unwrap();
+        let cli = |session: Option<&str>| CliData {
+            fps: None,
+            height: None,
+            input_file_path: vec!["inputfile.txt".to_string()],
+            mode: Some(AppMode::Inject),
+            output_video_path: None,
+            size: None,
+            width: None,
+            algo: None,
+            levels: None,
+            fec: None,
+            fountain: None,
+            pages: Some(path.to_string_lossy().to_string()),
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: session.map(str::to_string),
+            show_progress: None,
+        };
+        match extract_options(cli(None)).unwrap() {
+            InjectInVideo(op) => {
+                assert_eq!(op.pages, Some(vec![3, 5]));
+                assert_eq!(op.session, 0x2f4c_91a7);
+            }
+            _ => panic!("Expected inject options"),
+        }
+        // An explicit --session wins over the report.
+        match extract_options(cli(Some("00000007"))).unwrap() {
+            InjectInVideo(op) => assert_eq!(op.session, 7),
+            _ => panic!("Expected inject options"),
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_extract_options_inject_compression() {
+        let cli = |compress| CliData {
+            fps: None,
+            height: None,
+            input_file_path: vec!["inputfile.txt".to_string()],
+            mode: Some(AppMode::Inject),
+            output_video_path: None,
+            size: None,
+            width: None,
+            algo: None,
+            levels: None,
+            fec: None,
+            fountain: None,
+            pages: None,
+            compress,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
+            show_progress: None,
+        };
+        for (compress, expected) in [
+            (None, Compression::None),
+            (Some(Compression::Zstd), Compression::Zstd),
+        ] {
+            match extract_options(cli(compress)) {
+                Ok(InjectInVideo(op)) => assert_eq!(op.compression, expected),
+                _ => panic!("Expected inject options"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_options_inject_pages_with_fountain() {
+        let result = extract_options(CliData {
+            fps: None,
+            height: None,
+            input_file_path: vec!["inputfile.txt".to_string()],
+            mode: Some(AppMode::Inject),
+            output_video_path: None,
+            size: None,
+            width: None,
+            algo: None,
+            levels: None,
+            fec: None,
+            fountain: Some(40),
+            pages: Some("3".to_string()),
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
+            show_progress: None,
+        });
+        assert!(
+            matches!(result, Err(HdmiError::PagesWithFountain)),
+            "{:?}",
+            result.err()
+        );
+    }
+
     #[test]
     fn test_extract_options_inject_quantized_custom_levels() {
         let options = extract_options(CliData {
             fps: Some(24),
             height: Some(720),
-            input_file_path: Some("inputfile.txt".to_string()),
+            input_file_path: vec!["inputfile.txt".to_string()],
             mode: Some(AppMode::Inject),
             output_video_path: Some("out.mkv".to_string()),
             size: Some(2),
             width: Some(1280),
             algo: Some(AlgoFrame::Quantized(DEFAULT_QUANTIZED_LEVELS)),
             levels: Some(16),
+            fec: Some(32),
+            fountain: Some(40),
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
             show_progress: Some(true),
         });
 
@@ -384,6 +964,8 @@ mod options_tests {
             assert_eq!(op.size, 2);
             assert_eq!(op.output_video_file, "out.mkv");
             assert_eq!(op.algo, AlgoFrame::Quantized(16));
+            assert_eq!(op.fec, 32);
+            assert_eq!(op.fountain, Some(40));
             assert_eq!(op.show_progress, true);
         } else {
             panic!("Expected inject options");
@@ -395,25 +977,37 @@ mod options_tests {
         let options = extract_options(CliData {
             fps: Some(60),
             height: Some(1080),
-            input_file_path: Some("input.mkv".to_string()),
+            input_file_path: vec!["input.mkv".to_string()],
             mode: Some(AppMode::Extract),
             output_video_path: Some("payload.bin".to_string()),
             size: Some(4),
             width: Some(1920),
             algo: Some(AlgoFrame::Brightness(DEFAULT_QUANTIZED_LEVELS)),
             levels: None,
+            fec: None,
+            fountain: None,
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
             show_progress: Some(true),
         });
 
         let unwrapped_options = options.unwrap();
         if let ExtractFromVideo(op) = unwrapped_options {
             assert_eq!(op.fps, 60);
-            assert_eq!(op.height, 1080);
-            assert_eq!(op.width, 1920);
-            assert_eq!(op.size, 4);
+            assert_eq!(op.height, Some(1080));
+            assert_eq!(op.width, Some(1920));
+            assert_eq!(op.size, Some(4));
             assert_eq!(op.extracted_file_path, "payload.bin");
             assert_eq!(op.video_file_path, "input.mkv");
-            assert_eq!(op.algo, AlgoFrame::Brightness(DEFAULT_QUANTIZED_LEVELS));
+            assert_eq!(
+                op.algo,
+                Some(AlgoFrame::Brightness(DEFAULT_QUANTIZED_LEVELS))
+            );
             assert_eq!(op.show_progress, true);
         } else {
             panic!("Expected extract options");
@@ -421,54 +1015,137 @@ mod options_tests {
     }
 
     #[test]
-    #[should_panic(expected = "--levels must be a power of two between 2 and 256")]
     fn test_extract_options_rejects_invalid_levels() {
-        let _ = extract_options(CliData {
+        let result = extract_options(CliData {
             fps: None,
             height: None,
-            input_file_path: Some("inputfile.txt".to_string()),
+            input_file_path: vec!["inputfile.txt".to_string()],
             mode: Some(AppMode::Inject),
             output_video_path: None,
             size: None,
             width: None,
             algo: Some(AlgoFrame::Quantized(DEFAULT_QUANTIZED_LEVELS)),
             levels: Some(3),
+            fec: None,
+            fountain: None,
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
             show_progress: None,
         });
+        assert!(
+            matches!(result, Err(HdmiError::InvalidLevels { levels: 3 })),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[test]
+    fn test_extract_options_rejects_too_much_fec() {
+        let result = extract_options(CliData {
+            fps: None,
+            height: None,
+            input_file_path: vec!["inputfile.txt".to_string()],
+            mode: Some(AppMode::Inject),
+            output_video_path: None,
+            size: None,
+            width: None,
+            algo: Some(AlgoFrame::Brightness(DEFAULT_QUANTIZED_LEVELS)),
+            levels: Some(8),
+            fec: Some(200),
+            fountain: None,
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
+            show_progress: None,
+        });
+        assert!(
+            matches!(result, Err(HdmiError::InvalidFec { fec: 200, max: 128 })),
+            "{:?}",
+            result.err()
+        );
     }
 
     #[test]
-    #[should_panic(expected = "Height and size are not a divided round number")]
     fn test_extract_options_inject_rejects_unaligned_height() {
-        let _ = extract_options(CliData {
+        let result = extract_options(CliData {
             fps: None,
             height: Some(65),
-            input_file_path: Some("inputfile.txt".to_string()),
+            input_file_path: vec!["inputfile.txt".to_string()],
             mode: Some(AppMode::Inject),
             output_video_path: None,
             size: Some(2),
             width: Some(64),
             algo: None,
             levels: None,
+            fec: None,
+            fountain: None,
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
             show_progress: None,
         });
+        assert!(
+            matches!(
+                result,
+                Err(HdmiError::UnalignedSize {
+                    dimension: "height",
+                    pixels: 65,
+                    size: 2
+                })
+            ),
+            "{:?}",
+            result.err()
+        );
     }
 
     #[test]
-    #[should_panic(expected = "Width and size are not a divided round number")]
     fn test_extract_options_inject_rejects_unaligned_width() {
-        let _ = extract_options(CliData {
+        let result = extract_options(CliData {
             fps: None,
             height: Some(64),
-            input_file_path: Some("inputfile.txt".to_string()),
+            input_file_path: vec!["inputfile.txt".to_string()],
             mode: Some(AppMode::Inject),
             output_video_path: None,
             size: Some(2),
             width: Some(65),
             algo: None,
             levels: None,
+            fec: None,
+            fountain: None,
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
             show_progress: None,
         });
+        assert!(
+            matches!(
+                result,
+                Err(HdmiError::UnalignedSize {
+                    dimension: "width",
+                    pixels: 65,
+                    size: 2
+                })
+            ),
+            "{:?}",
+            result.err()
+        );
     }
 
     #[test]
@@ -476,27 +1153,233 @@ mod options_tests {
         let options = extract_options(CliData {
             fps: None,
             height: None,
-            input_file_path: None,
+            input_file_path: Vec::new(),
             mode: Some(AppMode::Extract),
             output_video_path: None,
             size: None,
             width: None,
             algo: None,
             levels: None,
+            fec: None,
+            fountain: None,
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
             show_progress: None,
         });
         let unwrapped_options = options.unwrap();
         if let ExtractFromVideo(op) = unwrapped_options {
             assert_eq!(op.fps, 30);
-            assert_eq!(op.height, 2160);
-            assert_eq!(op.width, 3840);
-            assert_eq!(op.size, 1);
+            assert_eq!(op.height, None);
+            assert_eq!(op.width, None);
+            assert_eq!(op.size, None);
             assert_eq!(op.extracted_file_path, "mydata.txt");
             assert_eq!(op.video_file_path, "video.mkv");
-            assert_eq!(op.algo, AlgoFrame::RGB);
+            assert_eq!(op.algo, None);
             assert_eq!(op.show_progress, false);
         } else {
             assert!(true, "Failed to unwrapped extract options");
         }
     }
+
+    #[test]
+    fn test_image_sequence_is_chosen_by_the_path() {
+        let inject = |output: &str| InjectOptions {
+            file_path: "input.zip".to_string(),
+            output_video_file: output.to_string(),
+            ..Default::default()
+        };
+        assert!(!inject("video.mkv").writes_image_sequence());
+        assert!(!inject("outputs/video.avi").writes_image_sequence());
+        assert!(inject("outputs/frames").writes_image_sequence());
+        assert!(inject("outputs/frames.d/").writes_image_sequence());
+        let temp = std::env::temp_dir();
+        assert!(inject(&temp.to_string_lossy()).writes_image_sequence());
+
+        let extract = |input: &str| ExtractOptions {
+            video_file_path: input.to_string(),
+            extracted_file_path: "output.zip".to_string(),
+            ..Default::default()
+        };
+        assert!(extract(&temp.to_string_lossy()).reads_image_sequence());
+        // Capture devices have no extension either.
+        assert!(!extract("0").reads_image_sequence());
+        assert!(!extract("/dev/video0").reads_image_sequence());
+        assert!(!extract("capture.mkv").reads_image_sequence());
+    }
+
+    fn cli(mode: AppMode, input: &str, output: &str) -> CliData {
+        CliData {
+            fps: None,
+            height: None,
+            input_file_path: vec![input.to_string()],
+            mode: Some(mode),
+            output_video_path: Some(output.to_string()),
+            size: None,
+            width: None,
+            algo: None,
+            levels: None,
+            fec: None,
+            fountain: None,
+            pages: None,
+            compress: None,
+            lattice: None,
+            pix_fmt: None,
+            raw_size: None,
+            binarize: None,
+            session: None,
+            show_progress: Some(true),
+        }
+    }
+
+    #[test]
+    fn test_several_inputs_are_injected_as_a_container() {
+        let mut args = cli(AppMode::Inject, "a.txt", "video.mkv");
+        args.input_file_path.push("photos".to_string());
+        match extract_options(args).unwrap() {
+            InjectInVideo(op) => {
+                assert_eq!(op.input_paths(), vec!["a.txt", "photos"]);
+                assert!(op.sends_container());
+            }
+            ExtractFromVideo(_) => panic!("expected inject options"),
+        }
+        match extract_options(cli(AppMode::Inject, "a.txt", "video.mkv")).unwrap() {
+            InjectInVideo(op) => assert!(!op.sends_container()),
+            ExtractFromVideo(_) => panic!("expected inject options"),
+        }
+        let dir = std::env::temp_dir().to_string_lossy().to_string();
+        match extract_options(cli(AppMode::Inject, &dir, "video.mkv")).unwrap() {
+            InjectInVideo(op) => assert!(op.sends_container(), "a directory is a container"),
+            ExtractFromVideo(_) => panic!("expected inject options"),
+        }
+
+        let mut args = cli(AppMode::Extract, "a.mkv", "out");
+        args.input_file_path.push("b.mkv".to_string());
+        assert!(matches!(
+            extract_options(args),
+            Err(HdmiError::SeveralExtractInputs { count: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_raw_output_goes_to_stdout_without_progress() {
+        let mut args = cli(AppMode::Inject, "inputfile.txt", "-");
+        args.pix_fmt = Some(PixelFormat::Rgb24);
+        match extract_options(args).unwrap() {
+            InjectInVideo(op) => {
+                assert_eq!(op.raw_output, Some(PixelFormat::Rgb24));
+                assert!(!op.show_progress);
+                assert!(!op.writes_image_sequence());
+            }
+            ExtractFromVideo(_) => panic!("expected inject options"),
+        }
+        match extract_options(cli(AppMode::Inject, "inputfile.txt", "out.mkv")).unwrap() {
+            InjectInVideo(op) => {
+                assert_eq!(op.raw_output, None);
+                assert!(op.show_progress);
+            }
+            ExtractFromVideo(_) => panic!("expected inject options"),
+        }
+    }
+
+    #[test]
+    fn test_binarization_falls_back_to_the_other_strategies() {
+        assert_eq!(
+            Binarization::AdaptiveMean.fallbacks().collect::<Vec<_>>(),
+            vec![
+                Binarization::AdaptiveMean,
+                Binarization::Fixed,
+                Binarization::Otsu,
+                Binarization::AdaptiveGaussian,
+            ]
+        );
+        assert_eq!(
+            Binarization::Fixed.fallbacks().collect::<Vec<_>>(),
+            Binarization::ALL
+        );
+        for binarization in Binarization::ALL {
+            assert_eq!(binarization.to_string().parse(), Ok(binarization));
+        }
+
+        let binarization = |args: CliData| match extract_options(args).unwrap() {
+            ExtractFromVideo(op) => op.binarization,
+            InjectInVideo(_) => panic!("expected extract options"),
+        };
+        assert_eq!(
+            binarization(cli(AppMode::Extract, "capture.mkv", "out.zip")),
+            Binarization::Fixed
+        );
+        let mut args = cli(AppMode::Extract, "capture.mkv", "out.zip");
+        args.binarize = Some(Binarization::Otsu);
+        assert_eq!(binarization(args), Binarization::Otsu);
+    }
+
+    #[test]
+    fn test_raw_input_needs_a_frame_size() {
+        let raw_input = |args: CliData| match extract_options(args) {
+            Ok(ExtractFromVideo(op)) => Ok(op.raw_input),
+            Ok(InjectInVideo(_)) => panic!("expected extract options"),
+            Err(err) => Err(err),
+        };
+
+        let mut args = cli(AppMode::Extract, "-", "out.zip");
+        args.raw_size = Some("1920x1080".to_string());
+        assert_eq!(
+            raw_input(args).unwrap(),
+            Some(RawVideoFormat {
+                pixel_format: PixelFormat::Bgr24,
+                width: 1920,
+                height: 1080,
+            })
+        );
+
+        // The frame geometry doubles as the raw size.
+        let mut args = cli(AppMode::Extract, "-", "out.zip");
+        args.width = Some(640);
+        args.height = Some(480);
+        args.pix_fmt = Some(PixelFormat::Rgb24);
+        assert_eq!(
+            raw_input(args).unwrap(),
+            Some(RawVideoFormat {
+                pixel_format: PixelFormat::Rgb24,
+                width: 640,
+                height: 480,
+            })
+        );
+
+        assert!(matches!(
+            raw_input(cli(AppMode::Extract, "-", "out.zip")),
+            Err(HdmiError::MissingRawSize)
+        ));
+        let mut args = cli(AppMode::Extract, "-", "out.zip");
+        args.raw_size = Some("1920by1080".to_string());
+        assert!(matches!(
+            raw_input(args),
+            Err(HdmiError::InvalidRawSize { .. })
+        ));
+        assert_eq!(
+            raw_input(cli(AppMode::Extract, "capture.mkv", "out.zip")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_raw_size() {
+        assert_eq!(parse_raw_size("1920x1080").unwrap(), (1920, 1080));
+        assert_eq!(parse_raw_size("64X48").unwrap(), (64, 48));
+        assert!(parse_raw_size("0x1080").is_err());
+        assert!(parse_raw_size("1920").is_err());
+        assert!(parse_raw_size("1920x70000").is_err());
+    }
+
+    #[test]
+    fn test_pixel_format_display_and_parse() {
+        assert_eq!(PixelFormat::Bgr24.to_string(), "bgr24");
+        assert_eq!("rgb24".parse::<PixelFormat>().unwrap(), PixelFormat::Rgb24);
+        assert!("yuv420p".parse::<PixelFormat>().is_err());
+    }
 }
@@ -0,0 +1,300 @@
+//! Machine-readable list of the data pages an extraction is still missing.
+//!
+//! When a capture ends before every page is in, `extract` writes the missing
+//! page numbers next to the extracted file (`<output>.missing.json`). `inject`
+//! takes that file, or the same list as a compact range string (`3,7-9`),
+//! through `--pages` and renders a repair video with the Start frame and only
+//! those pages, which the sender loops instead of the full transfer. Given a
+//! report, the repair video carries the session of the transfer it repairs, so
+//! `extract` takes its frames for the same transfer.
+//!
+//! The report is a small JSON object:
+//!
+//! ```text
+//! {
+//!   "session": "2f4c91a7",
+//!   "total_bytes": 120000,
+//!   "expected_pages": 40,
+//!   "received_pages": 37,
+//!   "missing": [3, 7, 8],
+//!   "ranges": "3,7-8"
+//! }
+//! ```
+//!
+//! Only the `session` and the `missing` array are read back.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::HdmiError;
+use crate::instructionlogics::{format_session, parse_session};
+
+/// Extension appended to the extracted file path to name the report.
+const REPORT_EXTENSION: &str = "missing.json";
+
+/// Most pages a list may name, ranges expanded: a repair video is meant to be
+/// short, and a range such as `0-18446744073709551615` must not be expanded
+/// into memory.
+pub const MAX_LISTED_PAGES: u64 = 1 << 20;
+
+/// Data pages of a transfer that have not been received yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingPagesReport {
+    /// Transfer session of the Start frame, see
+    /// [`new_session_id`](crate::new_session_id).
+    pub session: u32,
+    /// Total byte count declared by the Start frame.
+    pub total_bytes: u64,
+    pub expected_pages: u64,
+    pub received_pages: u64,
+    /// Missing page numbers, in order.
+    pub missing: Vec<u64>,
+}
+
+impl MissingPagesReport {
+    pub fn to_json(&self) -> String {
+        let missing: Vec<String> = self.missing.iter().map(|page| page.to_string()).collect();
+        format!(
+            "{{\n  \"session\": \"{}\",\n  \"total_bytes\": {},\n  \"expected_pages\": {},\n  \"received_pages\": {},\n  \"missing\": [{}],\n  \"ranges\": \"{}\"\n}}\n",
+            format_session(self.session),
+            self.total_bytes,
+            self.expected_pages,
+            self.received_pages,
+            missing.join(", "),
+            format_page_ranges(&self.missing)
+        )
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), HdmiError> {
+        fs::write(path, self.to_json()).map_err(|source| HdmiError::WriteFile {
+            path: path.to_string_lossy().to_string(),
+            source,
+        })
+    }
+}
+
+/// Report file of the transfer extracted to `extracted_file_path`, or `None`
+/// when no output path is set.
+pub fn missing_report_path(extracted_file_path: &str) -> Option<PathBuf> {
+    if extracted_file_path.is_empty() {
+        return None;
+    }
+    Some(PathBuf::from(format!(
+        "{}.{}",
+        extracted_file_path, REPORT_EXTENSION
+    )))
+}
+
+/// Compact list of page numbers, consecutive runs collapsed: `0-3,7,9-12`.
+/// `pages` must be sorted.
+pub fn format_page_ranges(pages: &[u64]) -> String {
+    let mut ranges: Vec<String> = Vec::new();
+    let mut index = 0;
+    while index < pages.len() {
+        let first = pages[index];
+        let mut last = first;
+        while index + 1 < pages.len() && pages[index + 1] == last + 1 {
+            index += 1;
+            last = pages[index];
+        }
+        if first == last {
+            ranges.push(first.to_string());
+        } else {
+            ranges.push(format!("{}-{}", first, last));
+        }
+        index += 1;
+    }
+    ranges.join(",")
+}
+
+/// Parse a range string as written by [`format_page_ranges`] (whitespace is
+/// ignored). Returns the pages sorted, without duplicates, or
+/// [`HdmiError::InvalidPageList`] when it names more than [`MAX_LISTED_PAGES`].
+pub fn parse_page_ranges(ranges: &str) -> Result<Vec<u64>, HdmiError> {
+    let invalid = |reason: String| HdmiError::InvalidPageList {
+        pages: ranges.to_string(),
+        reason,
+    };
+    let parse = |page: &str| {
+        page.trim()
+            .parse::<u64>()
+            .map_err(|_| invalid(format!("{:?} is not a page number", page.trim())))
+    };
+    let mut pages = Vec::new();
+    for range in ranges.split(',').filter(|range| !range.trim().is_empty()) {
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (parse(first)?, parse(last)?),
+            None => {
+                let page = parse(range)?;
+                (page, page)
+            }
+        };
+        if first > last {
+            return Err(invalid(format!(
+                "{:?} is not an ascending range",
+                range.trim()
+            )));
+        }
+        if last - first >= MAX_LISTED_PAGES - pages.len() as u64 {
+            return Err(invalid(format!(
+                "more than {} pages are listed",
+                MAX_LISTED_PAGES
+            )));
+        }
+        pages.extend(first..=last);
+    }
+    pages.sort_unstable();
+    pages.dedup();
+    Ok(pages)
+}
+
+/// Pages listed by `pages`: the path of a report written by `extract` (or of a
+/// text file holding a range string), or a range string itself.
+pub fn read_page_list(pages: &str) -> Result<Vec<u64>, HdmiError> {
+    read_page_report(pages).map(|(pages, _)| pages)
+}
+
+/// Like [`read_page_list`], with the session of the transfer when `pages` is a
+/// report written by `extract`.
+pub fn read_page_report(pages: &str) -> Result<(Vec<u64>, Option<u32>), HdmiError> {
+    let path = Path::new(pages);
+    if !path.is_file() {
+        return Ok((parse_page_ranges(pages)?, None));
+    }
+    let content = fs::read_to_string(path).map_err(|source| HdmiError::ReadFile {
+        path: pages.to_string(),
+        source,
+    })?;
+    if !content.trim_start().starts_with('{') {
+        return Ok((parse_page_ranges(&content)?, None));
+    }
+    let invalid = |reason: &str| HdmiError::InvalidPageList {
+        pages: pages.to_string(),
+        reason: reason.to_string(),
+    };
+    // The JSON report: read its `missing` array and its `session`.
+    let missing = json_field(&content, "missing")
+        .and_then(|rest| rest.strip_prefix('['))
+        .and_then(|rest| rest.split_once(']'))
+        .map(|(list, _)| list)
+        .ok_or_else(|| invalid("no \"missing\" page list in the report"))?;
+    let session = match json_field(&content, "session") {
+        Some(rest) => Some(
+            rest.strip_prefix('"')
+                .and_then(|rest| rest.split_once('"'))
+                .and_then(|(session, _)| parse_session(session))
+                .ok_or_else(|| invalid("the \"session\" of the report is not a session ID"))?,
+        ),
+        None => None,
+    };
+    Ok((parse_page_ranges(missing)?, session))
+}
+
+/// The text following `"key":` in the JSON `content`, leading whitespace
+/// trimmed.
+fn json_field<'a>(content: &'a str, key: &str) -> Option<&'a str> {
+    content
+        .split_once(&format!("\"{}\"", key))
+        .and_then(|(_, rest)| rest.trim_start().strip_prefix(':'))
+        .map(str::trim_start)
+}
+
+#[cfg(test)]
+mod pagereport_tests {
+    use super::*;
+
+    #[test]
+    fn test_format_page_ranges_collapses_runs() {
+        assert_eq!(format_page_ranges(&[]), "");
+        assert_eq!(format_page_ranges(&[4]), "4");
+        assert_eq!(
+            format_page_ranges(&[0, 1, 2, 3, 7, 9, 10, 11, 12]),
+            "0-3,7,9-12"
+        );
+    }
+
+    #[test]
+    fn test_parse_page_ranges() {
+        assert_eq!(parse_page_ranges("").unwrap(), Vec::<u64>::new());
+        assert_eq!(
+            parse_page_ranges("0-3,7, 9-12").unwrap(),
+            vec![0, 1, 2, 3, 7, 9, 10, 11, 12]
+        );
+        assert_eq!(parse_page_ranges("5,2,2-3").unwrap(), vec![2, 3, 5]);
+        assert!(matches!(
+            parse_page_ranges("4-2"),
+            Err(HdmiError::InvalidPageList { .. })
+        ));
+        assert!(parse_page_ranges("x").is_err());
+        // Huge ranges are refused instead of expanded.
+        for huge in ["0-18446744073709551615", "0-1048575,2000000"] {
+            assert!(matches!(
+                parse_page_ranges(huge),
+                Err(HdmiError::InvalidPageList { .. })
+            ));
+        }
+        assert_eq!(
+            parse_page_ranges("0-1048575").unwrap().len() as u64,
+            MAX_LISTED_PAGES
+        );
+        let pages = vec![1, 2, 3, 8, 20, 21];
+        assert_eq!(
+            parse_page_ranges(&format_page_ranges(&pages)).unwrap(),
+            pages
+        );
+    }
+
+    #[test]
+    fn test_report_round_trips_through_a_file() {
+        let dir = std::env::temp_dir().join(format!("hdmift_report_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = missing_report_path(&dir.join("out.bin").to_string_lossy()).unwrap();
+        assert!(path.to_string_lossy().ends_with("out.bin.missing.json"));
+
+        let report = MissingPagesReport {
+            session: 0x2f4c_91a7,
+            total_bytes: 120_000,
+            expected_pages: 40,
+            received_pages: 37,
+            missing: vec![3, 7, 8],
+        };
+        report.save(&path).unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("\"ranges\": \"3,7-8\""));
+        assert_eq!(
+            read_page_list(&path.to_string_lossy()).unwrap(),
+            vec![3, 7, 8]
+        );
+        assert_eq!(
+            read_page_report(&path.to_string_lossy()).unwrap(),
+            (vec![3, 7, 8], Some(0x2f4c_91a7))
+        );
+
+        let ranges = dir.join("ranges.txt");
+        fs::write(&ranges, "3,7-8\n").unwrap();
+        assert_eq!(
+            read_page_list(&ranges.to_string_lossy()).unwrap(),
+            vec![3, 7, 8]
+        );
+        assert_eq!(read_page_list("3,7-8").unwrap(), vec![3, 7, 8]);
+        assert_eq!(
+            read_page_report(&ranges.to_string_lossy()).unwrap(),
+            (vec![3, 7, 8], None)
+        );
+
+        // A report without a session (or with a garbled one).
+        fs::write(&path, "{\"missing\": [4]}").unwrap();
+        assert_eq!(
+            read_page_report(&path.to_string_lossy()).unwrap(),
+            (vec![4], None)
+        );
+        fs::write(&path, "{\"session\": \"xyz\", \"missing\": [4]}").unwrap();
+        assert!(matches!(
+            read_page_report(&path.to_string_lossy()),
+            Err(HdmiError::InvalidPageList { .. })
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(missing_report_path(""), None);
+    }
+}
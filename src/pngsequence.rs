@@ -0,0 +1,416 @@
+//! Frames stored as a directory of numbered lossless PNG images instead of a
+//! video file: to show them with an image viewer on a sender that cannot play
+//! videos, or to decode photos of the screen.
+//!
+//! ```text
+//! frames/
+//!   manifest.txt
+//!   frame_000000.png    Start frame
+//!   frame_000001.png
+//!   ...
+//! ```
+//!
+//! The manifest is a few `key=value` lines:
+//!
+//! ```text
+//! format=hdmifiletransporter-frames
+//! version=1
+//! width=1920
+//! height=1080
+//! fps=30
+//! frames=42
+//! ```
+//!
+//! A directory with a manifest is read in frame order, only the frames it
+//! lists. A directory without one (photos from a capture) is read in file name
+//! order, every PNG, JPEG, BMP or TIFF file.
+//!
+//! The PNG files are written and read in pure Rust, so every build renders
+//! image sequences. Registering each image like a captured video frame (and
+//! decoding JPEG, BMP or TIFF photos) needs OpenCV; without it, the PNG frames
+//! written by `inject` are decoded as they are.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::error::HdmiError;
+use crate::injectionlogics::{file_to_sink, frames_to_sink, FrameSink};
+use crate::options::InjectOptions;
+use crate::videoframe::VideoFrame;
+
+/// Name of the manifest inside the directory.
+pub const MANIFEST_FILE: &str = "manifest.txt";
+
+/// Value of the `format` key of the manifest.
+const MANIFEST_FORMAT: &str = "hdmifiletransporter-frames";
+
+/// Manifest layout version.
+const MANIFEST_VERSION: u32 = 1;
+
+/// File extensions read from a directory without a manifest.
+const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "bmp", "tif", "tiff"];
+
+/// What was written to an image-sequence directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ImageSequenceManifest {
+    width: u16,
+    height: u16,
+    fps: u8,
+    /// Number of frames, named `frame_000000.png` onwards.
+    frames: u64,
+}
+
+impl ImageSequenceManifest {
+    fn to_text(self) -> String {
+        format!(
+            "format={}\nversion={}\nwidth={}\nheight={}\nfps={}\nframes={}\n",
+            MANIFEST_FORMAT, MANIFEST_VERSION, self.width, self.height, self.fps, self.frames
+        )
+    }
+
+    /// Parse the manifest text. Unknown keys are ignored; a missing key, another
+    /// format or a newer version is an error.
+    fn from_text(text: &str) -> Result<ImageSequenceManifest, String> {
+        let value = |key: &str| {
+            text.lines()
+                .filter_map(|line| line.trim().split_once('='))
+                .find(|(name, _)| name.trim() == key)
+                .map(|(_, value)| value.trim())
+                .ok_or(format!("no {} entry", key))
+        };
+        fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("{} {:?} is not a valid number", key, value))
+        }
+        if value("format")? != MANIFEST_FORMAT {
+            return Err(format!("not a {} manifest", MANIFEST_FORMAT));
+        }
+        let version: u32 = number("version", value("version")?)?;
+        if version != MANIFEST_VERSION {
+            return Err(format!("unsupported version {}", version));
+        }
+        Ok(ImageSequenceManifest {
+            width: number("width", value("width")?)?,
+            height: number("height", value("height")?)?,
+            fps: number("fps", value("fps")?)?,
+            frames: number("frames", value("frames")?)?,
+        })
+    }
+
+    fn save(self, dir: &Path) -> Result<(), HdmiError> {
+        let path = dir.join(MANIFEST_FILE);
+        fs::write(&path, self.to_text()).map_err(|source| HdmiError::WriteFile {
+            path: path.to_string_lossy().to_string(),
+            source,
+        })
+    }
+
+    /// The manifest of `dir`, or `None` when it has none.
+    fn load(dir: &Path) -> Result<Option<ImageSequenceManifest>, HdmiError> {
+        let path = dir.join(MANIFEST_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(HdmiError::ReadFile {
+                    path: path.to_string_lossy().to_string(),
+                    source,
+                })
+            }
+        };
+        ImageSequenceManifest::from_text(&text)
+            .map(Some)
+            .map_err(|reason| HdmiError::VideoOpen {
+                path: path.to_string_lossy().to_string(),
+                reason,
+            })
+    }
+}
+
+/// File name of frame `index`. Zero-padded so the names sort in frame order.
+fn frame_file_name(index: u64) -> String {
+    format!("frame_{:06}.png", index)
+}
+
+fn is_image_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                IMAGE_EXTENSIONS
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(extension))
+            })
+}
+
+/// The images of the directory `dir`, in the order they are read (see the
+/// module documentation).
+pub(crate) fn image_sequence_files(dir: &str) -> Result<Vec<PathBuf>, HdmiError> {
+    let open_error = |reason: String| HdmiError::VideoOpen {
+        path: dir.to_string(),
+        reason,
+    };
+    let dir_path = Path::new(dir);
+    let files: Vec<PathBuf> = match ImageSequenceManifest::load(dir_path)? {
+        Some(manifest) => (0..manifest.frames)
+            .map(|index| dir_path.join(frame_file_name(index)))
+            .filter(|path| path.is_file())
+            .collect(),
+        None => {
+            let mut files: Vec<PathBuf> = fs::read_dir(dir_path)
+                .map_err(|err| open_error(err.to_string()))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| is_image_file(path))
+                .collect();
+            files.sort();
+            files
+        }
+    };
+    if files.is_empty() {
+        return Err(open_error("no images in the directory".to_string()));
+    }
+    Ok(files)
+}
+
+/// Write `frame` as an 8-bit RGB PNG file at `path`.
+fn write_png(path: &Path, frame: &VideoFrame) -> Result<(), HdmiError> {
+    let write_error = |reason: String| HdmiError::VideoWrite {
+        path: path.to_string_lossy().to_string(),
+        reason,
+    };
+    let file = File::create(path).map_err(|err| write_error(err.to_string()))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        frame.width().into(),
+        frame.height().into(),
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    // Frames are large and mostly flat: fast deflate keeps most of the gain.
+    encoder.set_compression(png::Compression::Fast);
+    encoder
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(frame.buffer.rgb())?;
+            writer.finish()
+        })
+        .map_err(|err| write_error(err.to_string()))
+}
+
+/// Read the PNG file at `path` into a frame. Grey levels and transparency are
+/// dropped to 8-bit RGB. OpenCV builds read images with OpenCV instead.
+#[cfg_attr(feature = "opencv-backend", allow(dead_code))]
+pub(crate) fn read_png(path: &Path) -> Result<VideoFrame, HdmiError> {
+    let read_error = |reason: String| HdmiError::VideoRead {
+        path: path.to_string_lossy().to_string(),
+        reason,
+    };
+    let file = File::open(path).map_err(|err| read_error(err.to_string()))?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|err| read_error(err.to_string()))?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut pixels)
+        .map_err(|err| read_error(err.to_string()))?;
+    pixels.truncate(info.buffer_size());
+    let rgb: Vec<u8> = match info.color_type {
+        png::ColorType::Rgb => pixels,
+        png::ColorType::Rgba => pixels
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&grey| [grey; 3]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|pixel| [pixel[0]; 3])
+            .collect(),
+        png::ColorType::Indexed => return Err(read_error("palette left unexpanded".to_string())),
+    };
+    let (Ok(width), Ok(height)) = (u16::try_from(info.width), u16::try_from(info.height)) else {
+        return Err(read_error("larger than 65535 pixels".to_string()));
+    };
+    VideoFrame::from_rgb(width, height, rgb)
+}
+
+/// Encode the file at `options.file_path` as PNG frames in the directory
+/// `options.output_video_file`. The sibling of `file_to_video`.
+pub fn file_to_images(options: &InjectOptions) -> Result<(), HdmiError> {
+    file_to_sink(options, ImageSequenceSink::create)?;
+    if options.show_progress {
+        eprintln!("Frames saved:{}", options.output_video_file.as_str());
+    }
+    Ok(())
+}
+
+/// Write `frames` as PNG frames in the directory `options.output_video_file`.
+/// The sibling of `frames_to_video`.
+pub fn frames_to_images(options: InjectOptions, frames: Vec<VideoFrame>) -> Result<(), HdmiError> {
+    frames_to_sink(&options, frames, ImageSequenceSink::create(&options)?)?;
+    if options.show_progress {
+        eprintln!("Frames saved:{}", options.output_video_file.as_str());
+    }
+    Ok(())
+}
+
+/// Directory that frames are written to as numbered PNG files.
+struct ImageSequenceSink {
+    dir: PathBuf,
+    manifest: ImageSequenceManifest,
+}
+
+impl ImageSequenceSink {
+    /// Create the directory `options.output_video_file` if needed. The manifest
+    /// of a previous run is removed, so an interrupted write is not mistaken
+    /// for a complete one.
+    fn create(options: &InjectOptions) -> Result<ImageSequenceSink, HdmiError> {
+        let open_error = |reason: String| HdmiError::VideoOpen {
+            path: options.output_video_file.clone(),
+            reason,
+        };
+        let dir = PathBuf::from(&options.output_video_file);
+        fs::create_dir_all(&dir)
+            .map_err(|err| open_error(format!("unable to create the directory: {}", err)))?;
+        match fs::remove_file(dir.join(MANIFEST_FILE)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                return Err(open_error(format!(
+                    "unable to remove the previous manifest: {}",
+                    err
+                )))
+            }
+            _ => {}
+        }
+        Ok(ImageSequenceSink {
+            dir,
+            manifest: ImageSequenceManifest {
+                width: options.width,
+                height: options.height,
+                fps: options.fps,
+                frames: 0,
+            },
+        })
+    }
+}
+
+impl FrameSink for ImageSequenceSink {
+    fn write(&mut self, frame: &VideoFrame) -> Result<(), HdmiError> {
+        write_png(&self.dir.join(frame_file_name(self.manifest.frames)), frame)?;
+        self.manifest.frames += 1;
+        Ok(())
+    }
+
+    /// Write the manifest once every frame is on disk.
+    fn finish(self) -> Result<(), HdmiError> {
+        self.manifest.save(&self.dir)
+    }
+}
+
+#[cfg(test)]
+mod pngsequence_tests {
+    use super::*;
+    use crate::injectionlogics::{create_starting_frame, data_to_frames};
+    use crate::options::AlgoFrame;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "hdmift_pngsequence_{}_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_manifest_round_trips() {
+        let manifest = ImageSequenceManifest {
+            width: 1920,
+            height: 1080,
+            fps: 30,
+            frames: 42,
+        };
+        assert_eq!(
+            ImageSequenceManifest::from_text(&manifest.to_text()),
+            Ok(manifest)
+        );
+
+        let dir = temp_dir("manifest");
+        assert_eq!(ImageSequenceManifest::load(&dir).unwrap(), None);
+        manifest.save(&dir).unwrap();
+        assert_eq!(ImageSequenceManifest::load(&dir).unwrap(), Some(manifest));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_manifest_rejects_other_formats() {
+        let text = ImageSequenceManifest {
+            width: 64,
+            height: 64,
+            fps: 30,
+            frames: 1,
+        }
+        .to_text();
+        assert!(ImageSequenceManifest::from_text(&text.replace("version=1", "version=2")).is_err());
+        assert!(ImageSequenceManifest::from_text(&text.replace("frames=1", "frames=x")).is_err());
+        assert!(ImageSequenceManifest::from_text(&text.replace("fps=30\n", "")).is_err());
+        assert!(ImageSequenceManifest::from_text("format=other\nversion=1\n").is_err());
+    }
+
+    #[test]
+    fn test_frame_names_sort_in_frame_order() {
+        assert_eq!(frame_file_name(0), "frame_000000.png");
+        let mut names: Vec<String> = [10, 2, 100, 1].into_iter().map(frame_file_name).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "frame_000001.png",
+                "frame_000002.png",
+                "frame_000010.png",
+                "frame_000100.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_sequence_files_errors_for_an_empty_directory() {
+        let dir = temp_dir("empty");
+        fs::write(dir.join("notes.txt"), b"not an image").unwrap();
+        let result = image_sequence_files(&dir.to_string_lossy());
+        assert!(
+            matches!(result, Err(HdmiError::VideoOpen { .. })),
+            "{:?}",
+            result.err()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_frames_round_trip_through_png_files() {
+        let dir = temp_dir("frames");
+        let options = InjectOptions {
+            output_video_file: dir.join("out").to_string_lossy().to_string(),
+            width: 64,
+            height: 64,
+            algo: AlgoFrame::Quantized(4),
+            ..Default::default()
+        };
+        let data: Vec<u8> = (0..200u32).map(|i| (i % 251) as u8).collect();
+        let mut frames = vec![create_starting_frame(&data, &options).unwrap()];
+        frames.extend(data_to_frames(&options, data).unwrap());
+        frames_to_images(options.clone(), frames.clone()).unwrap();
+
+        let files = image_sequence_files(&options.output_video_file).unwrap();
+        assert_eq!(files.len(), frames.len());
+        let read: Vec<VideoFrame> = files.iter().map(|path| read_png(path).unwrap()).collect();
+        assert_eq!(read, frames);
+        assert!(read_png(&dir.join("missing.png")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
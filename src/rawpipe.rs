@@ -0,0 +1,193 @@
+//! Raw frames piped through stdin and stdout, so ffmpeg (or any tool speaking
+//! `-f rawvideo`) can feed a capture straight into `extract`, or encode and play
+//! what `inject` renders, without an intermediate video file:
+//!
+//! ```text
+//! ffmpeg -f v4l2 -input_format mjpeg -video_size 1920x1080 -i /dev/video0 \
+//!     -f rawvideo -pix_fmt bgr24 - \
+//!   | hdmifiletransporter -m extract -i - --raw-size 1920x1080 -o transfer.zip
+//!
+//! hdmifiletransporter -m inject -i transfer.zip -o - --width 1920 --height 1080 --size 4 \
+//!   | ffmpeg -f rawvideo -pix_fmt bgr24 -video_size 1920x1080 -framerate 30 -i - -c:v ffv1 out.mkv
+//! ```
+//!
+//! A raw stream is frames of `width * height * 3` bytes back to back, in the
+//! declared pixel order, with no header or padding.
+
+use std::io::{Read, Write};
+
+use crate::error::HdmiError;
+use crate::framebuffer::{FrameBuffer, RGB_CHANNELS};
+use crate::injectionlogics::{file_to_sink, frames_to_sink, FrameSink};
+use crate::options::{InjectOptions, PixelFormat, RawVideoFormat, PIPE_PATH};
+use crate::videoframe::VideoFrame;
+
+/// Reads raw frames of a declared layout from a byte stream (usually stdin).
+pub struct RawFrameReader<R: Read> {
+    reader: R,
+    format: RawVideoFormat,
+}
+
+impl<R: Read> RawFrameReader<R> {
+    pub fn new(reader: R, format: RawVideoFormat) -> RawFrameReader<R> {
+        RawFrameReader { reader, format }
+    }
+
+    pub fn format(&self) -> RawVideoFormat {
+        self.format
+    }
+
+    /// The next frame, or `None` once the stream ends. A read error or a
+    /// truncated last frame ends the stream too, like the end of a capture.
+    pub fn next_frame(&mut self) -> Option<FrameBuffer> {
+        let len = usize::from(self.format.width) * usize::from(self.format.height) * RGB_CHANNELS;
+        let mut pixels = vec![0; len];
+        self.reader.read_exact(&mut pixels).ok()?;
+        if self.format.pixel_format == PixelFormat::Bgr24 {
+            swap_red_blue(&mut pixels);
+        }
+        FrameBuffer::from_rgb(self.format.width, self.format.height, pixels).ok()
+    }
+}
+
+/// Turn RGB pixels into BGR ones, or back.
+fn swap_red_blue(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(RGB_CHANNELS) {
+        pixel.swap(0, 2);
+    }
+}
+
+/// Byte stream that rendered frames are written to as raw pixels.
+struct RawFrameSink<W: Write> {
+    output: W,
+    pixel_format: PixelFormat,
+}
+
+impl<W: Write> RawFrameSink<W> {
+    fn write_error(error: std::io::Error) -> HdmiError {
+        HdmiError::VideoWrite {
+            path: PIPE_PATH.to_string(),
+            reason: error.to_string(),
+        }
+    }
+}
+
+impl<W: Write> FrameSink for RawFrameSink<W> {
+    fn write(&mut self, frame: &VideoFrame) -> Result<(), HdmiError> {
+        let result = match self.pixel_format {
+            PixelFormat::Rgb24 => self.output.write_all(frame.buffer.rgb()),
+            PixelFormat::Bgr24 => {
+                let mut pixels = frame.buffer.rgb().to_vec();
+                swap_red_blue(&mut pixels);
+                self.output.write_all(&pixels)
+            }
+        };
+        result.map_err(Self::write_error)
+    }
+
+    fn finish(mut self) -> Result<(), HdmiError> {
+        self.output.flush().map_err(Self::write_error)
+    }
+}
+
+/// Encode the file at `options.file_path` as raw frames written to `output`
+/// (usually stdout). The sibling of `file_to_video`; nothing but frames may be
+/// written to `output`, so keep `options.show_progress` off when it is stdout.
+pub fn file_to_raw(
+    options: &InjectOptions,
+    output: impl Write,
+    pixel_format: PixelFormat,
+) -> Result<(), HdmiError> {
+    file_to_sink(options, |_| {
+        Ok(RawFrameSink {
+            output,
+            pixel_format,
+        })
+    })
+}
+
+/// Write `frames` to `output` as raw frames. The sibling of `frames_to_video`.
+pub fn frames_to_raw(
+    options: &InjectOptions,
+    frames: Vec<VideoFrame>,
+    output: impl Write,
+    pixel_format: PixelFormat,
+) -> Result<(), HdmiError> {
+    frames_to_sink(
+        options,
+        frames,
+        RawFrameSink {
+            output,
+            pixel_format,
+        },
+    )
+}
+
+#[cfg(test)]
+mod rawpipe_tests {
+    use super::*;
+
+    fn format(pixel_format: PixelFormat) -> RawVideoFormat {
+        RawVideoFormat {
+            pixel_format,
+            width: 2,
+            height: 1,
+        }
+    }
+
+    fn options() -> InjectOptions {
+        InjectOptions {
+            output_video_file: PIPE_PATH.to_string(),
+            width: 2,
+            height: 1,
+            raw_output: Some(PixelFormat::Bgr24),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_reader_splits_the_stream_into_frames() {
+        let stream: Vec<u8> = (1..=12).collect();
+        let mut reader = RawFrameReader::new(stream.as_slice(), format(PixelFormat::Rgb24));
+        assert_eq!(reader.next_frame().unwrap().rgb(), &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(reader.next_frame().unwrap().rgb(), &[7, 8, 9, 10, 11, 12]);
+        assert!(reader.next_frame().is_none());
+    }
+
+    #[test]
+    fn test_reader_converts_bgr_and_drops_a_truncated_frame() {
+        let stream: Vec<u8> = vec![3, 2, 1, 6, 5, 4, 9, 9];
+        let mut reader = RawFrameReader::new(stream.as_slice(), format(PixelFormat::Bgr24));
+        let frame = reader.next_frame().unwrap();
+        assert_eq!(frame.pixel(0, 0), [1, 2, 3]);
+        assert_eq!(frame.pixel(1, 0), [4, 5, 6]);
+        assert!(reader.next_frame().is_none());
+    }
+
+    #[test]
+    fn test_frames_are_written_in_the_requested_order() {
+        let mut frame = VideoFrame::new(2, 1);
+        frame.buffer.set_pixel(0, 0, [1, 2, 3]);
+        frame.buffer.set_pixel(1, 0, [4, 5, 6]);
+
+        let mut bgr = Vec::new();
+        frames_to_raw(
+            &options(),
+            vec![frame.clone()],
+            &mut bgr,
+            PixelFormat::Bgr24,
+        )
+        .unwrap();
+        assert_eq!(bgr, vec![3, 2, 1, 6, 5, 4]);
+
+        let mut rgb = Vec::new();
+        frames_to_raw(
+            &options(),
+            vec![frame.clone(), frame],
+            &mut rgb,
+            PixelFormat::Rgb24,
+        )
+        .unwrap();
+        assert_eq!(rgb, vec![1, 2, 3, 4, 5, 6, 1, 2, 3, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,358 @@
+// ---------------------------------------------------------------------------
+// Reed-Solomon forward error correction over GF(2^8).
+//
+// A data frame's payload is split into blocks of at most `255 - nsym` data
+// bytes, each followed by `nsym` parity bytes (a shortened RS(255, 255 - nsym)
+// code). Each block corrects up to `nsym / 2` wrong bytes, so a frame with a
+// few misread cells is repaired instead of rejected by the CRC.
+//
+// Conventions: the generator polynomial has roots alpha^0 .. alpha^(nsym-1)
+// (first consecutive root 0) and a codeword is stored highest-degree
+// coefficient first, i.e. `codeword[0]` multiplies x^(n-1).
+// ---------------------------------------------------------------------------
+
+/// Length of a full Reed-Solomon codeword (data + parity) in GF(2^8).
+pub const RS_BLOCK_LEN: usize = 255;
+
+/// Largest number of parity bytes per block accepted by `--fec`. Above this the
+/// code spends more than half of every block on parity.
+pub const MAX_FEC_PARITY: u8 = 128;
+
+/// Primitive polynomial x^8 + x^4 + x^3 + x^2 + 1 (the usual QR-code field).
+const PRIMITIVE: u16 = 0x11d;
+
+/// `EXP[i] = alpha^i`, doubled in length so products never need a modulo.
+const EXP: [u8; 512] = build_exp_table();
+/// `LOG[alpha^i] = i`. `LOG[0]` is unused.
+const LOG: [u8; 256] = build_log_table();
+
+const fn build_exp_table() -> [u8; 512] {
+    let mut table = [0u8; 512];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        table[i] = x as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= PRIMITIVE;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        table[i] = table[i - 255];
+        i += 1;
+    }
+    table
+}
+
+const fn build_log_table() -> [u8; 256] {
+    let exp = build_exp_table();
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 255 {
+        table[exp[i] as usize] = i as u8;
+        i += 1;
+    }
+    table
+}
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    EXP[LOG[a as usize] as usize + LOG[b as usize] as usize]
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    EXP[(LOG[a as usize] as usize + 255 - LOG[b as usize] as usize) % 255]
+}
+
+/// alpha^power for any (possibly negative) power.
+fn gf_alpha_pow(power: i64) -> u8 {
+    EXP[power.rem_euclid(255) as usize]
+}
+
+/// Evaluate a polynomial stored lowest-degree coefficient first.
+fn poly_eval_low_first(poly: &[u8], x: u8) -> u8 {
+    poly.iter()
+        .rev()
+        .fold(0u8, |acc, &coef| gf_mul(acc, x) ^ coef)
+}
+
+/// Generator polynomial (highest-degree first) with roots alpha^0 .. alpha^(nsym-1).
+fn generator_poly(nsym: usize) -> Vec<u8> {
+    let mut generator = vec![1u8];
+    for i in 0..nsym {
+        let root = gf_alpha_pow(i as i64);
+        let mut next = vec![0u8; generator.len() + 1];
+        for (j, &coef) in generator.iter().enumerate() {
+            next[j] ^= coef;
+            next[j + 1] ^= gf_mul(coef, root);
+        }
+        generator = next;
+    }
+    generator
+}
+
+/// Encode a single block: returns `data` followed by `nsym` parity bytes.
+/// `data.len() + nsym` must not exceed `RS_BLOCK_LEN`.
+pub fn rs_encode(data: &[u8], nsym: usize) -> Vec<u8> {
+    assert!(
+        data.len() + nsym <= RS_BLOCK_LEN,
+        "A Reed-Solomon block holds at most {} bytes",
+        RS_BLOCK_LEN
+    );
+    let generator = generator_poly(nsym);
+    let mut out = data.to_vec();
+    out.resize(data.len() + nsym, 0);
+    // Polynomial long division of data * x^nsym by the (monic) generator; the
+    // remainder left in the tail is the parity.
+    for i in 0..data.len() {
+        let coef = out[i];
+        if coef != 0 {
+            for (j, &g) in generator.iter().enumerate().skip(1) {
+                out[i + j] ^= gf_mul(g, coef);
+            }
+        }
+    }
+    out[..data.len()].copy_from_slice(data);
+    out
+}
+
+/// Decode a single block produced by [`rs_encode`], correcting up to
+/// `nsym / 2` wrong bytes. Returns the corrected data bytes (parity stripped),
+/// or `None` when the block has more errors than the code can repair.
+pub fn rs_decode(codeword: &[u8], nsym: usize) -> Option<Vec<u8>> {
+    let n = codeword.len();
+    if n < nsym || n > RS_BLOCK_LEN {
+        return None;
+    }
+    let data_len = n - nsym;
+
+    // Syndromes S_j = R(alpha^j). All zero means no detectable error.
+    let syndromes: Vec<u8> = (0..nsym)
+        .map(|j| {
+            let x = gf_alpha_pow(j as i64);
+            codeword.iter().fold(0u8, |acc, &c| gf_mul(acc, x) ^ c)
+        })
+        .collect();
+    if syndromes.iter().all(|&s| s == 0) {
+        return Some(codeword[..data_len].to_vec());
+    }
+
+    // Berlekamp-Massey: error locator Lambda(x) (lowest-degree first).
+    let mut lambda = vec![1u8];
+    let mut prev = vec![1u8];
+    let mut errors = 0usize;
+    let mut shift = 1usize;
+    let mut prev_discrepancy = 1u8;
+    for step in 0..nsym {
+        let mut discrepancy = syndromes[step];
+        for i in 1..=errors.min(lambda.len() - 1) {
+            discrepancy ^= gf_mul(lambda[i], syndromes[step - i]);
+        }
+        if discrepancy == 0 {
+            shift += 1;
+            continue;
+        }
+        let scale = gf_div(discrepancy, prev_discrepancy);
+        let mut next = lambda.clone();
+        if next.len() < prev.len() + shift {
+            next.resize(prev.len() + shift, 0);
+        }
+        for (i, &coef) in prev.iter().enumerate() {
+            next[i + shift] ^= gf_mul(scale, coef);
+        }
+        if 2 * errors <= step {
+            prev = lambda;
+            errors = step + 1 - errors;
+            prev_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            shift += 1;
+        }
+        lambda = next;
+    }
+    while lambda.len() > 1 && lambda[lambda.len() - 1] == 0 {
+        lambda.pop();
+    }
+    let degree = lambda.len() - 1;
+    if degree != errors || 2 * errors > nsym {
+        return None;
+    }
+
+    // Chien search: an error at power e (index n-1-e) is a root alpha^-e.
+    let positions: Vec<usize> = (0..n)
+        .filter(|&power| poly_eval_low_first(&lambda, gf_alpha_pow(-(power as i64))) == 0)
+        .collect();
+    if positions.len() != errors {
+        return None;
+    }
+
+    // Forney: Omega(x) = S(x) * Lambda(x) mod x^nsym, and with the first root
+    // at alpha^0 the magnitude is X * Omega(X^-1) / Lambda'(X^-1).
+    let mut omega = vec![0u8; nsym];
+    for (i, &s) in syndromes.iter().enumerate() {
+        for (j, &l) in lambda.iter().enumerate() {
+            if i + j < nsym {
+                omega[i + j] ^= gf_mul(s, l);
+            }
+        }
+    }
+    // Formal derivative in characteristic 2 keeps only the odd terms.
+    let derivative: Vec<u8> = lambda
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, &coef)| if i % 2 == 1 { coef } else { 0 })
+        .collect();
+
+    let mut corrected = codeword.to_vec();
+    for power in positions {
+        let x = gf_alpha_pow(power as i64);
+        let x_inv = gf_alpha_pow(-(power as i64));
+        let denominator = poly_eval_low_first(&derivative, x_inv);
+        if denominator == 0 {
+            return None;
+        }
+        let magnitude = gf_mul(x, gf_div(poly_eval_low_first(&omega, x_inv), denominator));
+        corrected[n - 1 - power] ^= magnitude;
+    }
+    Some(corrected[..data_len].to_vec())
+}
+
+/// Number of data bytes that fit in `raw_bytes` once every block reserves
+/// `nsym` parity bytes. With `nsym = 0` FEC is disabled and all bytes are data.
+pub fn fec_data_capacity(raw_bytes: usize, nsym: u8) -> usize {
+    let nsym = nsym as usize;
+    if nsym == 0 {
+        return raw_bytes;
+    }
+    if nsym >= RS_BLOCK_LEN {
+        return 0;
+    }
+    let full_blocks = raw_bytes / RS_BLOCK_LEN;
+    let remainder = raw_bytes % RS_BLOCK_LEN;
+    full_blocks * (RS_BLOCK_LEN - nsym) + remainder.saturating_sub(nsym)
+}
+
+/// Split `data` into blocks of `255 - nsym` bytes and append the parity to each
+/// block. The last block may be shorter (shortened code).
+pub fn fec_encode(data: &[u8], nsym: u8) -> Vec<u8> {
+    let nsym = nsym as usize;
+    if nsym == 0 {
+        return data.to_vec();
+    }
+    let mut out = Vec::with_capacity(data.len() + data.len().div_ceil(RS_BLOCK_LEN - nsym) * nsym);
+    for block in data.chunks(RS_BLOCK_LEN - nsym) {
+        out.extend(rs_encode(block, nsym));
+    }
+    out
+}
+
+/// Reverse of [`fec_encode`]: decode the blocks of `raw` that hold `data_len`
+/// data bytes and return the corrected data. Returns `None` as soon as one block
+/// is beyond repair.
+pub fn fec_decode(raw: &[u8], nsym: u8, data_len: usize) -> Option<Vec<u8>> {
+    let nsym = nsym as usize;
+    if nsym == 0 {
+        return raw.get(..data_len).map(|d| d.to_vec());
+    }
+    if nsym >= RS_BLOCK_LEN {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data_len);
+    let mut offset = 0usize;
+    while out.len() < data_len {
+        let block_data = std::cmp::min(RS_BLOCK_LEN - nsym, data_len - out.len());
+        let block = raw.get(offset..offset + block_data + nsym)?;
+        out.extend(rs_decode(block, nsym)?);
+        offset += block_data + nsym;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod reedsolomon_tests {
+    use super::*;
+
+    #[test]
+    fn test_gf_tables_are_consistent() {
+        for a in 1..=255u16 {
+            let a = a as u8;
+            assert_eq!(gf_mul(a, gf_div(1, a)), 1, "a={a}");
+        }
+        assert_eq!(gf_mul(0, 7), 0);
+        assert_eq!(EXP[255], 1);
+    }
+
+    #[test]
+    fn test_rs_encode_is_systematic_with_zero_syndromes() {
+        let data: Vec<u8> = (0..40u8).collect();
+        let codeword = rs_encode(&data, 10);
+        assert_eq!(codeword.len(), 50);
+        assert_eq!(&codeword[..40], &data[..]);
+        assert_eq!(rs_decode(&codeword, 10).unwrap(), data);
+    }
+
+    #[test]
+    fn test_rs_decode_corrects_up_to_half_nsym_errors() {
+        let data: Vec<u8> = (0..200u32).map(|i| (i * 37 % 256) as u8).collect();
+        let nsym = 16;
+        let codeword = rs_encode(&data, nsym);
+        let mut damaged = codeword.clone();
+        for (k, pos) in [0usize, 13, 57, 99, 150, 199, 201, 215].iter().enumerate() {
+            damaged[*pos] ^= 0x5a ^ k as u8;
+        }
+        assert_eq!(rs_decode(&damaged, nsym).unwrap(), data);
+    }
+
+    #[test]
+    fn test_rs_decode_rejects_too_many_errors() {
+        let data: Vec<u8> = vec![42u8; 100];
+        let nsym = 4;
+        let mut damaged = rs_encode(&data, nsym);
+        for pos in [1usize, 5, 9, 20, 33] {
+            damaged[pos] ^= 0xff;
+        }
+        // Five errors with four parity bytes cannot be repaired; it must never
+        // silently return the original data either way.
+        assert_ne!(rs_decode(&damaged, nsym), Some(data));
+    }
+
+    #[test]
+    fn test_fec_data_capacity() {
+        assert_eq!(fec_data_capacity(600, 0), 600);
+        // Two full blocks (2 * 223) plus a 90-byte block holding 58 data bytes.
+        assert_eq!(fec_data_capacity(600, 32), 2 * 223 + 58);
+        assert_eq!(fec_data_capacity(20, 32), 0);
+        assert_eq!(fec_data_capacity(600, 255), 0);
+    }
+
+    #[test]
+    fn test_fec_round_trip_with_errors_in_every_block() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let nsym = 20u8;
+        let mut raw = fec_encode(&data, nsym);
+        assert!(raw.len() <= 1000 + 5 * 20);
+        for pos in (3..raw.len()).step_by(37) {
+            raw[pos] = raw[pos].wrapping_add(1);
+        }
+        assert_eq!(fec_decode(&raw, nsym, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_fec_encode_fits_data_capacity() {
+        for nsym in [0u8, 2, 16, 64, 128] {
+            let raw_len = 1234;
+            let data_len = fec_data_capacity(raw_len, nsym);
+            let data = vec![7u8; data_len];
+            let raw = fec_encode(&data, nsym);
+            assert!(raw.len() <= raw_len, "nsym={nsym}");
+            assert_eq!(fec_decode(&raw, nsym, data_len).unwrap(), data);
+        }
+    }
+}
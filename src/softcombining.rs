@@ -0,0 +1,280 @@
+//! Soft-combining of repeated captures of the same frame.
+//!
+//! A looped transfer shows every page many times, and a capture that gets a
+//! few cells of a page wrong usually gets different cells wrong than the next
+//! one. When no single copy of a page passes its CRC, the sampled cell values
+//! of the copies whose header still parsed are averaged: the noise of any one
+//! capture is outvoted by the others, and deciding the symbols again on the
+//! average recovers the page.
+
+use std::collections::HashMap;
+
+use crate::instructionlogics::{FrameHeader, FrameType};
+
+/// Copies of a frame averaged at most. More add little, and the per-channel
+/// sums of this many copies fit a `u16`.
+const MAX_COPIES: u16 = 64;
+
+/// Bytes of per-channel sums held at once: five 1080p frames of 1-pixel cells.
+/// When a capture fails more pages than fit, the copies of the frame seen least
+/// recently are dropped to make room for the new one.
+const MAX_PENDING_BYTES: usize = 64 << 20;
+
+/// Sampled values of the payload cells of a data or fountain frame, before any
+/// symbol decision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CellSamples {
+    /// Mean RGB of every payload cell, in content order.
+    pub cells: Vec<[u8; 3]>,
+    /// Mean RGB of every calibration ramp patch, empty when the frame has none.
+    pub ramp: Vec<[u8; 3]>,
+}
+
+/// Per-channel sums of the samples of every copy of one frame.
+struct Accumulated {
+    copies: u16,
+    cells: Vec<[u16; 3]>,
+    ramp: Vec<[u16; 3]>,
+    /// When a copy was last added, to find the least recently seen frame.
+    last_used: u64,
+}
+
+impl Accumulated {
+    fn new(samples: &CellSamples) -> Accumulated {
+        Accumulated {
+            copies: 0,
+            cells: vec![[0; 3]; samples.cells.len()],
+            ramp: vec![[0; 3]; samples.ramp.len()],
+            last_used: 0,
+        }
+    }
+
+    /// Bytes held by the sums of the samples of a frame.
+    fn bytes_for(samples: &CellSamples) -> usize {
+        (samples.cells.len() + samples.ramp.len()) * std::mem::size_of::<[u16; 3]>()
+    }
+
+    fn bytes(&self) -> usize {
+        (self.cells.len() + self.ramp.len()) * std::mem::size_of::<[u16; 3]>()
+    }
+
+    fn add(&mut self, samples: &CellSamples) {
+        for (sum, rgb) in self
+            .cells
+            .iter_mut()
+            .chain(self.ramp.iter_mut())
+            .zip(samples.cells.iter().chain(samples.ramp.iter()))
+        {
+            for (channel, value) in sum.iter_mut().zip(rgb) {
+                *channel += u16::from(*value);
+            }
+        }
+        self.copies += 1;
+    }
+
+    /// The samples of the average copy, rounded to the nearest value.
+    fn average(&self) -> CellSamples {
+        let copies = self.copies.max(1);
+        let mean = |sums: &[[u16; 3]]| -> Vec<[u8; 3]> {
+            sums.iter()
+                .map(|sum| sum.map(|channel| ((channel + copies / 2) / copies) as u8))
+                .collect()
+        };
+        CellSamples {
+            cells: mean(&self.cells),
+            ramp: mean(&self.ramp),
+        }
+    }
+}
+
+/// The copies of frames that failed their CRC, keyed by the header they all
+/// carry (a copy whose header was misread starts a key of its own).
+pub(crate) struct SoftCombiner {
+    pending: HashMap<FrameHeader, Accumulated>,
+    /// Bytes held by `pending`, kept under `max_bytes`.
+    bytes: usize,
+    max_bytes: usize,
+    /// Number of copies added so far, the clock of `Accumulated::last_used`.
+    clock: u64,
+}
+
+impl Default for SoftCombiner {
+    fn default() -> SoftCombiner {
+        SoftCombiner::with_max_bytes(MAX_PENDING_BYTES)
+    }
+}
+
+impl SoftCombiner {
+    pub fn new() -> SoftCombiner {
+        SoftCombiner::default()
+    }
+
+    /// A combiner holding at most `max_bytes` of sums (always at least one
+    /// frame).
+    pub fn with_max_bytes(max_bytes: usize) -> SoftCombiner {
+        SoftCombiner {
+            pending: HashMap::new(),
+            bytes: 0,
+            max_bytes,
+            clock: 0,
+        }
+    }
+
+    /// Add a copy of the frame with `header` that failed its CRC. Returns the
+    /// average of every copy so far once there are at least two of them.
+    /// Copies past [`MAX_COPIES`] are ignored. A new frame that does not fit
+    /// evicts the frames seen least recently.
+    pub fn add(&mut self, header: FrameHeader, samples: &CellSamples) -> Option<CellSamples> {
+        if !self.pending.contains_key(&header) {
+            let needed = Accumulated::bytes_for(samples);
+            while self.bytes + needed > self.max_bytes && self.evict_least_recent() {}
+            self.bytes += needed;
+        }
+        self.clock += 1;
+        let accumulated = self
+            .pending
+            .entry(header)
+            .or_insert_with(|| Accumulated::new(samples));
+        accumulated.last_used = self.clock;
+        // Copies sampled from another geometry cannot be lined up.
+        if accumulated.cells.len() != samples.cells.len()
+            || accumulated.ramp.len() != samples.ramp.len()
+            || accumulated.copies >= MAX_COPIES
+        {
+            return None;
+        }
+        accumulated.add(samples);
+        (accumulated.copies >= 2).then(|| accumulated.average())
+    }
+
+    /// Drop the frame seen least recently. False when there is none.
+    fn evict_least_recent(&mut self) -> bool {
+        let Some(header) = self
+            .pending
+            .iter()
+            .min_by_key(|(_, accumulated)| accumulated.last_used)
+            .map(|(header, _)| *header)
+        else {
+            return false;
+        };
+        self.remove(&header);
+        true
+    }
+
+    /// Drop the copies of the frame with `header`, e.g. once their average
+    /// passed its CRC.
+    pub fn remove(&mut self, header: &FrameHeader) {
+        if let Some(accumulated) = self.pending.remove(header) {
+            self.bytes -= accumulated.bytes();
+        }
+    }
+
+    /// Drop the copies of the page or fountain symbol `value` once it has been
+    /// received.
+    pub fn forget(&mut self, frame_type: FrameType, value: u64) {
+        let bytes = &mut self.bytes;
+        self.pending.retain(|header, accumulated| {
+            let keep = header.frame_type != frame_type || header.value != value;
+            if !keep {
+                *bytes -= accumulated.bytes();
+            }
+            keep
+        });
+    }
+}
+
+#[cfg(test)]
+mod softcombining_tests {
+    use super::*;
+
+    fn header(value: u64) -> FrameHeader {
+        FrameHeader {
+            frame_type: FrameType::Data,
+            value,
+            fec: 0,
+            crc: 7,
+            session: 0,
+        }
+    }
+
+    fn samples(cells: &[u8]) -> CellSamples {
+        CellSamples {
+            cells: cells.iter().map(|&v| [v, v / 2, v]).collect(),
+            ramp: vec![[10, 20, 30]],
+        }
+    }
+
+    #[test]
+    fn test_copies_are_averaged_from_the_second_one() {
+        let mut combiner = SoftCombiner::new();
+        assert_eq!(combiner.add(header(3), &samples(&[0, 255, 255])), None);
+        assert_eq!(
+            combiner.add(header(3), &samples(&[255, 255, 0])),
+            Some(samples(&[128, 255, 128]))
+        );
+        assert_eq!(
+            combiner.add(header(3), &samples(&[0, 255, 0])),
+            Some(samples(&[85, 255, 85]))
+        );
+    }
+
+    #[test]
+    fn test_only_copies_with_the_same_header_are_combined() {
+        let mut combiner = SoftCombiner::new();
+        assert_eq!(combiner.add(header(3), &samples(&[0])), None);
+        assert_eq!(combiner.add(header(4), &samples(&[255])), None);
+        let misread = FrameHeader {
+            crc: 8,
+            ..header(3)
+        };
+        assert_eq!(combiner.add(misread, &samples(&[255])), None);
+        // Nor copies of another layout.
+        assert_eq!(combiner.add(header(3), &samples(&[0, 0])), None);
+        assert_eq!(combiner.add(header(3), &samples(&[0])), Some(samples(&[0])));
+    }
+
+    #[test]
+    fn test_received_pages_are_forgotten() {
+        let mut combiner = SoftCombiner::new();
+        combiner.add(header(3), &samples(&[0]));
+        combiner.add(header(4), &samples(&[0]));
+        combiner.forget(FrameType::Data, 3);
+        assert_eq!(combiner.add(header(3), &samples(&[255])), None);
+        assert!(combiner.add(header(4), &samples(&[0])).is_some());
+    }
+
+    #[test]
+    fn test_a_full_pool_evicts_the_least_recent_frame() {
+        // Room for three frames of one cell and one ramp patch.
+        let mut combiner = SoftCombiner::with_max_bytes(3 * Accumulated::bytes_for(&samples(&[0])));
+        for page in 0..3 {
+            combiner.add(header(page), &samples(&[0]));
+        }
+        // Page 0 is seen again, so page 1 is now the least recent.
+        assert!(combiner.add(header(0), &samples(&[0])).is_some());
+        // A new page still gets combined once the pool is full.
+        assert_eq!(combiner.add(header(3), &samples(&[0])), None);
+        assert_eq!(
+            combiner.add(header(3), &samples(&[255])),
+            Some(samples(&[128]))
+        );
+        assert_eq!(combiner.pending.len(), 3);
+        assert!(!combiner.pending.contains_key(&header(1)));
+        assert!(combiner.add(header(0), &samples(&[0])).is_some());
+        assert!(combiner.add(header(2), &samples(&[0])).is_some());
+    }
+
+    #[test]
+    fn test_removed_frames_free_their_bytes() {
+        let mut combiner = SoftCombiner::new();
+        combiner.add(header(3), &samples(&[0, 0]));
+        combiner.add(header(4), &samples(&[0]));
+        combiner.remove(&header(3));
+        combiner.forget(FrameType::Data, 4);
+        assert!(combiner.pending.is_empty());
+        assert_eq!(combiner.bytes, 0);
+        // Still fine when nothing matches.
+        combiner.remove(&header(3));
+        assert_eq!(combiner.bytes, 0);
+    }
+}
@@ -0,0 +1,576 @@
+//! Per-frame timeline of a capture, to tell dropped frames from unreadable
+//! ones.
+//!
+//! A capture card that drops frames (`frame dropped!` in its log) and an
+//! encoding too dense for the capture both end with missing pages, but call for
+//! different fixes. Extraction records what became of every captured frame: no
+//! markers found, no header read, a CRC failure, the Start frame, the page
+//! (or fountain symbol) it carried, or a frame of another transfer session. From that timeline:
+//!
+//! - every Start frame begins a loop of the video;
+//! - a page number that jumps forward between two decoded frames means the
+//!   pages in between were never read. When fewer captured frames lie between
+//!   the two than pages were skipped, the capture cannot have shown them all:
+//!   at least the difference was dropped;
+//! - each loop reports how many distinct pages it covered.
+//!
+//! When the transfer is incomplete, or with `--show-progress`, the result is
+//! written next to the extracted file (`<output>.timeline.json`):
+//!
+//! ```text
+//! {
+//!   "frames": 240,
+//!   "no_markers": 0,
+//!   "no_header": 2,
+//!   "crc_fail": 1,
+//!   "other_session": 0,
+//!   "dropped_pages": 12,
+//!   "diagnosis": "capture dropped frames",
+//!   "loops": [
+//!     {"first_frame": 0, "frames": 118, "from_start": true, "pages": 104, "expected_pages": 110}
+//!   ],
+//!   "gaps": [
+//!     {"after_frame": 40, "before_frame": 41, "skipped": "38-40", "captured_between": 0}
+//!   ],
+//!   "timeline": ["0-1 start", "2-3 page 0", "4 crc_fail", ...]
+//! }
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::HdmiError;
+use crate::extractionlogics::PushOutcome;
+use crate::instructionlogics::{format_session, FrameType};
+use crate::pagereport::format_page_ranges;
+
+/// Extension appended to the extracted file path to name the report.
+const TIMELINE_EXTENSION: &str = "timeline.json";
+
+/// What became of one captured frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOutcome {
+    /// The finder patterns could not be found, so the frame was not registered.
+    NoMarkers,
+    /// Registered, but no header could be read (or the Start frame had not
+    /// revealed the geometry yet).
+    NoHeader,
+    /// The header was read but the CRC does not match.
+    CrcFail,
+    /// A valid Start frame.
+    Start,
+    /// A valid Metadata frame.
+    Metadata,
+    /// A valid data frame carrying this page.
+    Page(u64),
+    /// A valid fountain frame carrying the symbol of this seed.
+    Symbol(u64),
+    /// A frame of another transfer session, ignored.
+    OtherSession(u32),
+}
+
+impl FrameOutcome {
+    /// The outcome of a registered frame handed to the reassembler.
+    pub fn of_push(outcome: PushOutcome) -> FrameOutcome {
+        match outcome {
+            PushOutcome::Start { .. } => FrameOutcome::Start,
+            PushOutcome::NewPage { page } => FrameOutcome::Page(page),
+            PushOutcome::NewSymbol { seed } => FrameOutcome::Symbol(seed),
+            PushOutcome::Metadata => FrameOutcome::Metadata,
+            PushOutcome::Duplicate { frame_type, value } => match frame_type {
+                FrameType::Start => FrameOutcome::Start,
+                FrameType::Metadata => FrameOutcome::Metadata,
+                FrameType::Data => FrameOutcome::Page(value),
+                FrameType::Fountain => FrameOutcome::Symbol(value),
+            },
+            PushOutcome::CrcFail => FrameOutcome::CrcFail,
+            PushOutcome::NoHeader => FrameOutcome::NoHeader,
+            PushOutcome::OtherSession { session } => FrameOutcome::OtherSession(session),
+        }
+    }
+
+    /// Page or seed carried by a decoded data or fountain frame.
+    fn sequence(self) -> Option<u64> {
+        match self {
+            FrameOutcome::Page(value) | FrameOutcome::Symbol(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn is_undecoded(self) -> bool {
+        matches!(
+            self,
+            FrameOutcome::NoMarkers | FrameOutcome::NoHeader | FrameOutcome::CrcFail
+        )
+    }
+}
+
+impl std::fmt::Display for FrameOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameOutcome::NoMarkers => write!(f, "no_markers"),
+            FrameOutcome::NoHeader => write!(f, "no_header"),
+            FrameOutcome::CrcFail => write!(f, "crc_fail"),
+            FrameOutcome::Start => write!(f, "start"),
+            FrameOutcome::Metadata => write!(f, "metadata"),
+            FrameOutcome::Page(page) => write!(f, "page {page}"),
+            FrameOutcome::Symbol(seed) => write!(f, "symbol {seed}"),
+            FrameOutcome::OtherSession(session) => {
+                write!(f, "session {}", format_session(*session))
+            }
+        }
+    }
+}
+
+/// One pass of the looped video, from a Start frame to the next one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopCoverage {
+    /// Index of the first captured frame of the loop.
+    pub first_frame: u64,
+    pub frames: u64,
+    /// False for the frames captured before the first Start frame.
+    pub from_start: bool,
+    /// Distinct pages (or fountain symbols) decoded in this loop.
+    pub pages: u64,
+}
+
+/// Pages never read between two consecutive decoded frames of a loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageGap {
+    /// Index of the decoded frame before the gap.
+    pub after_frame: u64,
+    /// Index of the decoded frame after the gap, or of the frame that ended
+    /// the loop.
+    pub before_frame: u64,
+    /// The pages (or seeds) skipped, in order.
+    pub skipped: Vec<u64>,
+    /// Captured frames between the two that could not be decoded.
+    pub captured_between: u64,
+}
+
+impl PageGap {
+    /// Pages of the gap that no captured frame can have shown.
+    pub fn dropped(&self) -> u64 {
+        (self.skipped.len() as u64).saturating_sub(self.captured_between)
+    }
+}
+
+/// The outcome of every captured frame, in capture order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CaptureTimeline {
+    outcomes: Vec<FrameOutcome>,
+}
+
+impl CaptureTimeline {
+    pub fn new() -> CaptureTimeline {
+        CaptureTimeline::default()
+    }
+
+    /// Record the outcome of the next captured frame.
+    pub fn record(&mut self, outcome: FrameOutcome) {
+        self.outcomes.push(outcome);
+    }
+
+    pub fn outcomes(&self) -> &[FrameOutcome] {
+        &self.outcomes
+    }
+
+    fn count(&self, outcome: FrameOutcome) -> u64 {
+        self.outcomes.iter().filter(|o| **o == outcome).count() as u64
+    }
+
+    /// Frames of other transfer sessions, ignored.
+    pub fn other_session(&self) -> u64 {
+        self.outcomes
+            .iter()
+            .filter(|o| matches!(o, FrameOutcome::OtherSession(_)))
+            .count() as u64
+    }
+
+    /// Frames captured but not decoded: no markers, no header or a CRC failure.
+    pub fn undecoded(&self) -> u64 {
+        self.outcomes.iter().filter(|o| o.is_undecoded()).count() as u64
+    }
+
+    /// The loops of the video, split at every Start frame.
+    pub fn loops(&self) -> Vec<LoopCoverage> {
+        let mut loops: Vec<LoopCoverage> = Vec::new();
+        let mut seen: HashSet<u64> = HashSet::new();
+        for (index, outcome) in self.outcomes.iter().enumerate() {
+            let index = index as u64;
+            // A run of Start frames (a held or repeated frame) opens one loop.
+            let repeated_start = *outcome == FrameOutcome::Start
+                && index > 0
+                && self.outcomes[index as usize - 1] == FrameOutcome::Start;
+            if loops.is_empty() || (*outcome == FrameOutcome::Start && !repeated_start) {
+                seen.clear();
+                loops.push(LoopCoverage {
+                    first_frame: index,
+                    frames: 0,
+                    from_start: *outcome == FrameOutcome::Start,
+                    pages: 0,
+                });
+            }
+            let current = loops.last_mut().expect("a loop was just opened");
+            current.frames += 1;
+            if let Some(page) = outcome.sequence() {
+                if seen.insert(page) {
+                    current.pages += 1;
+                }
+            }
+        }
+        loops
+    }
+
+    /// The pages skipped within each loop. A Start frame stands before page 0,
+    /// and, when `expected_pages` is known, after the last page of the loop it
+    /// closes. A page number going backwards (a loop whose Start frame was not
+    /// read) starts over without a gap.
+    pub fn gaps(&self, expected_pages: Option<u64>) -> Vec<PageGap> {
+        let mut gaps = Vec::new();
+        // Index of the last decoded frame, and the page it carried (`None` for
+        // a Start frame, which sits before page 0).
+        let mut last: Option<(u64, Option<u64>)> = None;
+        let mut captured_between = 0;
+        let mut push_gap = |after: u64, before: u64, skipped: Vec<u64>, captured: u64| {
+            if !skipped.is_empty() {
+                gaps.push(PageGap {
+                    after_frame: after,
+                    before_frame: before,
+                    skipped,
+                    captured_between: captured,
+                });
+            }
+        };
+        for (index, outcome) in self.outcomes.iter().enumerate() {
+            let index = index as u64;
+            if outcome.is_undecoded() {
+                captured_between += 1;
+                continue;
+            }
+            // Neither a page nor the start of a loop, and a frame of another
+            // transfer cannot have shown one of our pages.
+            if matches!(
+                outcome,
+                FrameOutcome::Metadata | FrameOutcome::OtherSession(_)
+            ) {
+                continue;
+            }
+            match (last, *outcome) {
+                (Some((after, previous)), FrameOutcome::Start) => {
+                    // The end of a data loop: its last pages should come first.
+                    if let (Some(page), Some(expected)) = (previous, expected_pages) {
+                        if matches!(self.outcomes[after as usize], FrameOutcome::Page(_)) {
+                            push_gap(
+                                after,
+                                index,
+                                (page + 1..expected).collect(),
+                                captured_between,
+                            );
+                        }
+                    }
+                }
+                (Some((after, previous)), _) => {
+                    let next = outcome.sequence().expect("a decoded frame");
+                    let first = previous.map_or(0, |page| page + 1);
+                    if next > first {
+                        push_gap(after, index, (first..next).collect(), captured_between);
+                    }
+                }
+                (None, _) => {}
+            }
+            last = Some((index, outcome.sequence()));
+            captured_between = 0;
+        }
+        gaps
+    }
+
+    /// Pages that the capture cannot have shown, over every gap.
+    pub fn dropped_pages(&self, expected_pages: Option<u64>) -> u64 {
+        self.gaps(expected_pages).iter().map(PageGap::dropped).sum()
+    }
+
+    /// One line verdict: whether pages went missing because the capture
+    /// dropped frames or because captured frames could not be decoded.
+    pub fn diagnosis(&self, expected_pages: Option<u64>) -> &'static str {
+        let dropped = self.dropped_pages(expected_pages);
+        let undecoded = self.undecoded();
+        if dropped == 0 && undecoded == 0 {
+            "clean"
+        } else if dropped >= undecoded {
+            "capture dropped frames"
+        } else {
+            "frames captured but not decoded: encoding too dense or capture too noisy"
+        }
+    }
+
+    /// Runs of equal outcomes, as `first[-last] outcome`.
+    fn runs(&self) -> Vec<String> {
+        let mut runs = Vec::new();
+        let mut index = 0;
+        while index < self.outcomes.len() {
+            let outcome = self.outcomes[index];
+            let mut end = index;
+            while end + 1 < self.outcomes.len() && self.outcomes[end + 1] == outcome {
+                end += 1;
+            }
+            runs.push(if end == index {
+                format!("{index} {outcome}")
+            } else {
+                format!("{index}-{end} {outcome}")
+            });
+            index = end + 1;
+        }
+        runs
+    }
+
+    pub fn to_json(&self, expected_pages: Option<u64>) -> String {
+        let expected = expected_pages.map_or("null".to_string(), |pages| pages.to_string());
+        let loops: Vec<String> = self
+            .loops()
+            .iter()
+            .map(|l| {
+                format!(
+                    "    {{\"first_frame\": {}, \"frames\": {}, \"from_start\": {}, \"pages\": {}, \"expected_pages\": {}}}",
+                    l.first_frame, l.frames, l.from_start, l.pages, expected
+                )
+            })
+            .collect();
+        let gaps: Vec<String> = self
+            .gaps(expected_pages)
+            .iter()
+            .map(|gap| {
+                format!(
+                    "    {{\"after_frame\": {}, \"before_frame\": {}, \"skipped\": \"{}\", \"captured_between\": {}}}",
+                    gap.after_frame,
+                    gap.before_frame,
+                    format_page_ranges(&gap.skipped),
+                    gap.captured_between
+                )
+            })
+            .collect();
+        let runs: Vec<String> = self.runs().iter().map(|run| format!("\"{run}\"")).collect();
+        format!(
+            "{{\n  \"frames\": {},\n  \"no_markers\": {},\n  \"no_header\": {},\n  \"crc_fail\": {},\n  \"other_session\": {},\n  \"dropped_pages\": {},\n  \"diagnosis\": \"{}\",\n  \"loops\": [\n{}\n  ],\n  \"gaps\": [\n{}\n  ],\n  \"timeline\": [{}]\n}}\n",
+            self.outcomes.len(),
+            self.count(FrameOutcome::NoMarkers),
+            self.count(FrameOutcome::NoHeader),
+            self.count(FrameOutcome::CrcFail),
+            self.other_session(),
+            self.dropped_pages(expected_pages),
+            self.diagnosis(expected_pages),
+            loops.join(",\n"),
+            gaps.join(",\n"),
+            runs.join(", ")
+        )
+    }
+
+    pub fn save(&self, path: &Path, expected_pages: Option<u64>) -> Result<(), HdmiError> {
+        fs::write(path, self.to_json(expected_pages)).map_err(|source| HdmiError::WriteFile {
+            path: path.to_string_lossy().to_string(),
+            source,
+        })
+    }
+}
+
+/// Timeline report of the transfer extracted to `extracted_file_path`, or
+/// `None` when no output path is set.
+pub fn timeline_report_path(extracted_file_path: &str) -> Option<PathBuf> {
+    if extracted_file_path.is_empty() {
+        return None;
+    }
+    Some(PathBuf::from(format!(
+        "{}.{}",
+        extracted_file_path, TIMELINE_EXTENSION
+    )))
+}
+
+#[cfg(test)]
+mod timeline_tests {
+    use super::*;
+    use FrameOutcome::*;
+
+    fn timeline(outcomes: &[FrameOutcome]) -> CaptureTimeline {
+        let mut timeline = CaptureTimeline::new();
+        for &outcome in outcomes {
+            timeline.record(outcome);
+        }
+        timeline
+    }
+
+    #[test]
+    fn test_push_outcomes_keep_their_page() {
+        assert_eq!(
+            FrameOutcome::of_push(PushOutcome::NewPage { page: 4 }),
+            Page(4)
+        );
+        assert_eq!(
+            FrameOutcome::of_push(PushOutcome::Duplicate {
+                frame_type: FrameType::Data,
+                value: 4
+            }),
+            Page(4)
+        );
+        assert_eq!(
+            FrameOutcome::of_push(PushOutcome::Duplicate {
+                frame_type: FrameType::Start,
+                value: 600
+            }),
+            Start
+        );
+        assert_eq!(FrameOutcome::of_push(PushOutcome::CrcFail), CrcFail);
+    }
+
+    #[test]
+    fn test_loops_start_at_each_start_frame() {
+        let t = timeline(&[
+            Page(3),
+            Start,
+            Start,
+            Page(0),
+            Page(1),
+            Page(1),
+            Start,
+            Page(0),
+            CrcFail,
+        ]);
+        assert_eq!(
+            t.loops(),
+            vec![
+                LoopCoverage {
+                    first_frame: 0,
+                    frames: 1,
+                    from_start: false,
+                    pages: 1
+                },
+                LoopCoverage {
+                    first_frame: 1,
+                    frames: 5,
+                    from_start: true,
+                    pages: 2
+                },
+                LoopCoverage {
+                    first_frame: 6,
+                    frames: 3,
+                    from_start: true,
+                    pages: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skipped_pages_without_captured_frames_were_dropped() {
+        // Pages 2-3 vanish between two consecutive frames; page 6 was captured
+        // but unreadable; 8-9 never came before the next Start.
+        let t = timeline(&[
+            Start,
+            Page(0),
+            Page(1),
+            Page(4),
+            Page(5),
+            CrcFail,
+            Page(7),
+            Start,
+            Page(1),
+        ]);
+        let gaps = t.gaps(Some(10));
+        assert_eq!(
+            gaps,
+            vec![
+                PageGap {
+                    after_frame: 2,
+                    before_frame: 3,
+                    skipped: vec![2, 3],
+                    captured_between: 0
+                },
+                PageGap {
+                    after_frame: 4,
+                    before_frame: 6,
+                    skipped: vec![6],
+                    captured_between: 1
+                },
+                PageGap {
+                    after_frame: 6,
+                    before_frame: 7,
+                    skipped: vec![8, 9],
+                    captured_between: 0
+                },
+                PageGap {
+                    after_frame: 7,
+                    before_frame: 8,
+                    skipped: vec![0],
+                    captured_between: 0
+                },
+            ]
+        );
+        assert_eq!(t.dropped_pages(Some(10)), 5);
+        assert_eq!(t.diagnosis(Some(10)), "capture dropped frames");
+        // Without the page count the end of a loop is unknown.
+        assert_eq!(t.dropped_pages(None), 3);
+    }
+
+    #[test]
+    fn test_unreadable_frames_point_at_the_encoding() {
+        let t = timeline(&[
+            Start,
+            Metadata,
+            Page(0),
+            NoHeader,
+            CrcFail,
+            Page(3),
+            NoMarkers,
+            CrcFail,
+            Page(4),
+        ]);
+        assert_eq!(t.dropped_pages(Some(5)), 0);
+        assert_eq!(t.undecoded(), 4);
+        assert!(t
+            .diagnosis(Some(5))
+            .starts_with("frames captured but not decoded"));
+        assert_eq!(timeline(&[Start, Page(0)]).diagnosis(Some(1)), "clean");
+    }
+
+    #[test]
+    fn test_frames_of_other_sessions_neither_open_loops_nor_fill_gaps() {
+        let t = timeline(&[
+            OtherSession(7),
+            OtherSession(7),
+            Start,
+            Page(0),
+            OtherSession(7),
+            Page(2),
+        ]);
+        assert_eq!(t.loops().len(), 2);
+        assert_eq!(t.other_session(), 3);
+        assert_eq!(t.gaps(Some(3))[0].captured_between, 0);
+        assert_eq!(t.dropped_pages(Some(3)), 1);
+        assert!(t.to_json(Some(3)).contains("\"other_session\": 3,"));
+        assert!(t.to_json(Some(3)).contains("\"0-1 session 00000007\""));
+    }
+
+    #[test]
+    fn test_report_lists_runs_of_outcomes() {
+        let t = timeline(&[Start, Start, Page(0), Page(0), CrcFail, Page(2)]);
+        let json = t.to_json(Some(3));
+        assert!(json.contains("\"frames\": 6,"));
+        assert!(json.contains("\"crc_fail\": 1,"));
+        assert!(json.contains("\"dropped_pages\": 0,"));
+        assert!(json.contains(
+            "{\"after_frame\": 3, \"before_frame\": 5, \"skipped\": \"1\", \"captured_between\": 1}"
+        ));
+        assert!(json.contains(
+            "\"timeline\": [\"0-1 start\", \"2-3 page 0\", \"4 crc_fail\", \"5 page 2\"]"
+        ));
+    }
+
+    #[test]
+    fn test_report_path_sits_next_to_the_output() {
+        assert_eq!(
+            timeline_report_path("out/file.zip"),
+            Some(PathBuf::from("out/file.zip.timeline.json"))
+        );
+        assert_eq!(timeline_report_path(""), None);
+    }
+}
@@ -0,0 +1,345 @@
+//! On-disk state of a partially received transfer.
+//!
+//! When a capture misses some pages, the verified ones are saved to a sidecar
+//! file next to the extracted file so that a later `extract` run, on another
+//! capture of the same looped video, only needs the pages still missing. The
+//! Metadata frame's record is saved with them once received. The
+//! state is keyed by the transfer identity, i.e. what the Start frame declares
+//! (total byte count, encoding configuration including the FEC, and file
+//! hash): the state of another transfer is ignored.
+//!
+//! Layout (integers big-endian):
+//!
+//! ```text
+//!   bytes 0..8    magic "HDMIFTST"
+//!   byte  8       state format version
+//!   bytes 9..17   total byte count (Start frame value)
+//!   bytes 17..68  encoding configuration, file size and file hash
+//!                 (EncodingConfig::to_bytes)
+//!   bytes 68..72  number of pages
+//!   bytes 72..74  length M of the metadata record, 0 before the Metadata
+//!                 frame is received
+//!   M bytes       metadata record (FileMetadata::to_bytes)
+//!   4 bytes       CRC32 over every byte before it
+//!   then per page: page number (u64), length (u32), CRC32 over the page
+//!   number and bytes (u32), page bytes
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::HdmiError;
+use crate::filemetadata::FileMetadata;
+use crate::instructionlogics::{EncodingConfig, ENCODING_CONFIG_BYTES};
+
+const STATE_MAGIC: &[u8; 8] = b"HDMIFTST";
+const STATE_VERSION: u8 = 5;
+/// Bytes of the header before the metadata record.
+const STATE_HEADER_BYTES: usize = 8 + 1 + 8 + ENCODING_CONFIG_BYTES + 4 + 2;
+const PAGE_HEADER_BYTES: usize = 8 + 4 + 4;
+
+/// Extension appended to the extracted file path to name the state file.
+const STATE_EXTENSION: &str = "hdmift-state";
+
+/// Verified data pages of one transfer, identified by its Start frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferState {
+    pub total_bytes: u64,
+    pub config: EncodingConfig,
+    /// What the Metadata frame says about the file, once received.
+    pub metadata: Option<FileMetadata>,
+    /// `(page number, page bytes)`, in page order.
+    pub pages: Vec<(u64, Vec<u8>)>,
+}
+
+impl TransferState {
+    /// True when the Start frame declaring `total_bytes` and `config` belongs
+    /// to the transfer this state was saved for.
+    pub fn is_same_transfer(&self, total_bytes: u64, config: &EncodingConfig) -> bool {
+        self.total_bytes == total_bytes && self.config == *config
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let payload: usize = self.pages.iter().map(|(_, page)| page.len()).sum();
+        let record = self
+            .metadata
+            .as_ref()
+            .map_or_else(Vec::new, FileMetadata::to_bytes);
+        let mut bytes = Vec::with_capacity(
+            STATE_HEADER_BYTES + record.len() + 4 + self.pages.len() * PAGE_HEADER_BYTES + payload,
+        );
+        bytes.extend_from_slice(STATE_MAGIC);
+        bytes.push(STATE_VERSION);
+        bytes.extend_from_slice(&self.total_bytes.to_be_bytes());
+        bytes.extend_from_slice(&self.config.to_bytes());
+        bytes.extend_from_slice(&(self.pages.len() as u32).to_be_bytes());
+        // A record is at most a few hundred bytes (its name and MIME are cut to
+        // 255 bytes each).
+        bytes.extend_from_slice(&(record.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&record);
+        let crc = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+        for (page, data) in &self.pages {
+            bytes.extend_from_slice(&page.to_be_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&page_crc(*page, data).to_be_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    /// Parse a saved state. Returns `None` when the header is not a valid state
+    /// header; otherwise the state and the number of pages dropped because
+    /// their CRC did not match (or the file was cut short).
+    pub fn from_bytes(bytes: &[u8]) -> Option<(TransferState, usize)> {
+        if bytes.len() < STATE_HEADER_BYTES + 4
+            || &bytes[..8] != STATE_MAGIC
+            || bytes[8] != STATE_VERSION
+        {
+            return None;
+        }
+        let record_len = u16::from_be_bytes(
+            bytes[STATE_HEADER_BYTES - 2..STATE_HEADER_BYTES]
+                .try_into()
+                .ok()?,
+        ) as usize;
+        let header_end = STATE_HEADER_BYTES + record_len;
+        let header = bytes.get(..header_end)?;
+        let crc = u32::from_be_bytes(bytes.get(header_end..header_end + 4)?.try_into().ok()?);
+        if crc32fast::hash(header) != crc {
+            return None;
+        }
+        let total_bytes = u64::from_be_bytes(bytes[9..17].try_into().ok()?);
+        let config = EncodingConfig::from_bytes(&bytes[17..17 + ENCODING_CONFIG_BYTES])?;
+        let count = u32::from_be_bytes(
+            bytes[STATE_HEADER_BYTES - 6..STATE_HEADER_BYTES - 2]
+                .try_into()
+                .ok()?,
+        ) as usize;
+        let metadata = match record_len {
+            0 => None,
+            _ => Some(FileMetadata::from_bytes(&header[STATE_HEADER_BYTES..])?),
+        };
+
+        let mut pages = Vec::new();
+        let mut offset = header_end + 4;
+        for _ in 0..count {
+            let Some(entry) = bytes.get(offset..offset + PAGE_HEADER_BYTES) else {
+                break;
+            };
+            let page = u64::from_be_bytes(entry[..8].try_into().ok()?);
+            let len = u32::from_be_bytes(entry[8..12].try_into().ok()?) as usize;
+            let crc = u32::from_be_bytes(entry[12..16].try_into().ok()?);
+            let start = offset + PAGE_HEADER_BYTES;
+            let Some(data) = bytes.get(start..start + len) else {
+                break;
+            };
+            if page_crc(page, data) == crc {
+                pages.push((page, data.to_vec()));
+            }
+            offset = start + len;
+        }
+        let dropped = count - pages.len();
+        Some((
+            TransferState {
+                total_bytes,
+                config,
+                metadata,
+                pages,
+            },
+            dropped,
+        ))
+    }
+
+    /// Write the state to `path`, through a temporary file so an interrupted
+    /// write never leaves a truncated state behind.
+    pub fn save(&self, path: &Path) -> Result<(), HdmiError> {
+        let temporary = path.with_extension(format!("{}.tmp", STATE_EXTENSION));
+        fs::write(&temporary, self.to_bytes())
+            .and_then(|()| fs::rename(&temporary, path))
+            .map_err(|source| HdmiError::WriteFile {
+                path: path.to_string_lossy().to_string(),
+                source,
+            })
+    }
+
+    /// Read the state saved at `path`. `Ok(None)` when there is no state file;
+    /// an error when it exists but is not a valid state file.
+    pub fn load(path: &Path) -> Result<Option<(TransferState, usize)>, HdmiError> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(HdmiError::ReadFile {
+                    path: path.to_string_lossy().to_string(),
+                    source,
+                })
+            }
+        };
+        TransferState::from_bytes(&bytes)
+            .map(Some)
+            .ok_or_else(|| HdmiError::InvalidState {
+                path: path.to_string_lossy().to_string(),
+            })
+    }
+}
+
+/// CRC32 over a page number and its bytes.
+fn page_crc(page: u64, data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&page.to_be_bytes());
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Sidecar state file of the transfer extracted to `extracted_file_path`, or
+/// `None` when no output path is set.
+pub fn state_file_path(extracted_file_path: &str) -> Option<PathBuf> {
+    if extracted_file_path.is_empty() {
+        return None;
+    }
+    Some(PathBuf::from(format!(
+        "{}.{}",
+        extracted_file_path, STATE_EXTENSION
+    )))
+}
+
+#[cfg(test)]
+mod transferstate_tests {
+    use super::*;
+    use crate::compression::Compression;
+    use crate::instructionlogics::file_sha256;
+    use crate::options::AlgoFrame;
+
+    fn sample_state() -> TransferState {
+        TransferState {
+            total_bytes: 1000,
+            config: EncodingConfig {
+                algo: AlgoFrame::Quantized(4),
+                size: 2,
+                width: 640,
+                height: 480,
+                lattice: false,
+                metadata: false,
+                compression: Compression::None,
+                fec: 0,
+                file_size: 1000,
+                sha256: file_sha256(b"transfer"),
+            },
+            metadata: None,
+            pages: vec![(0, vec![1, 2, 3]), (3, vec![4, 5, 6]), (4, vec![7, 8, 9])],
+        }
+    }
+
+    #[test]
+    fn test_state_round_trips() {
+        let state = sample_state();
+        assert_eq!(
+            TransferState::from_bytes(&state.to_bytes()),
+            Some((state, 0))
+        );
+    }
+
+    #[test]
+    fn test_state_keeps_the_metadata_record() {
+        let state = TransferState {
+            metadata: Some(FileMetadata {
+                name: "photos.tar".to_string(),
+                size: 1000,
+                modified_secs: 1_700_000_000,
+                modified_nanos: 5,
+                mode: 0o100644,
+                mime: "application/x-tar".to_string(),
+            }),
+            ..sample_state()
+        };
+        let bytes = state.to_bytes();
+        assert_eq!(TransferState::from_bytes(&bytes), Some((state.clone(), 0)));
+
+        // The record is covered by the header CRC.
+        let mut corrupted = bytes.clone();
+        corrupted[STATE_HEADER_BYTES + 3] ^= 0x01;
+        assert_eq!(TransferState::from_bytes(&corrupted), None);
+        assert_eq!(
+            TransferState::from_bytes(&bytes[..STATE_HEADER_BYTES + 4]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_state_drops_corrupted_pages() {
+        let state = sample_state();
+        let mut bytes = state.to_bytes();
+        // Flip a byte of the second page's data.
+        let second_page = STATE_HEADER_BYTES + 4 + (PAGE_HEADER_BYTES + 3) + PAGE_HEADER_BYTES;
+        bytes[second_page] ^= 0xFF;
+        let (parsed, dropped) = TransferState::from_bytes(&bytes).unwrap();
+        assert_eq!(dropped, 1);
+        assert_eq!(parsed.pages, vec![(0, vec![1, 2, 3]), (4, vec![7, 8, 9])]);
+
+        // A file cut short keeps the pages before the cut.
+        let bytes = state.to_bytes();
+        let (parsed, dropped) = TransferState::from_bytes(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(dropped, 1);
+        assert_eq!(parsed.pages.len(), 2);
+    }
+
+    #[test]
+    fn test_state_rejects_corrupted_header() {
+        let mut bytes = sample_state().to_bytes();
+        bytes[10] ^= 0x01; // total byte count
+        assert_eq!(TransferState::from_bytes(&bytes), None);
+        assert_eq!(TransferState::from_bytes(b"not a state file"), None);
+    }
+
+    #[test]
+    fn test_state_identifies_its_transfer() {
+        let state = sample_state();
+        assert!(state.is_same_transfer(1000, &state.config));
+        assert!(!state.is_same_transfer(999, &state.config));
+        let other = EncodingConfig {
+            algo: AlgoFrame::BW,
+            ..state.config
+        };
+        assert!(!state.is_same_transfer(1000, &other));
+        // The same bytes, compressed.
+        let other = EncodingConfig {
+            compression: Compression::Zstd,
+            ..state.config
+        };
+        assert!(!state.is_same_transfer(1000, &other));
+        // The same bytes, paged around more parity.
+        let other = EncodingConfig {
+            fec: 16,
+            ..state.config
+        };
+        assert!(!state.is_same_transfer(1000, &other));
+        // Another file of the same size.
+        let other = EncodingConfig {
+            sha256: file_sha256(b"another transfer"),
+            ..state.config
+        };
+        assert!(!state.is_same_transfer(1000, &other));
+    }
+
+    #[test]
+    fn test_state_saves_and_loads() {
+        let dir = std::env::temp_dir().join(format!("hdmift_state_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = state_file_path(&dir.join("out.bin").to_string_lossy()).unwrap();
+        assert!(TransferState::load(&path).unwrap().is_none());
+
+        let state = sample_state();
+        state.save(&path).unwrap();
+        assert_eq!(TransferState::load(&path).unwrap(), Some((state, 0)));
+
+        fs::write(&path, b"garbage").unwrap();
+        assert!(matches!(
+            TransferState::load(&path),
+            Err(HdmiError::InvalidState { .. })
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(state_file_path(""), None);
+    }
+}
@@ -0,0 +1,711 @@
+//! Reading frames from a video file or a live capture device and registering
+//! them: each captured image is re-aligned to canonical pixels from its finder
+//! and timing patterns before [`Reassembler`](crate::Reassembler) decodes it.
+
+use opencv::core::{Mat, Point, Point2f, Scalar, Vec4i, Vector, BORDER_CONSTANT, DECOMP_LU};
+use opencv::imgproc::{
+    adaptive_threshold, contour_area, cvt_color_def, find_contours_with_hierarchy,
+    get_affine_transform_slice, get_perspective_transform_slice, moments, threshold, warp_affine,
+    warp_perspective, ADAPTIVE_THRESH_GAUSSIAN_C, ADAPTIVE_THRESH_MEAN_C, CHAIN_APPROX_SIMPLE,
+    COLOR_BGR2GRAY, INTER_LINEAR, RETR_TREE, THRESH_BINARY_INV, THRESH_OTSU,
+};
+use opencv::prelude::*;
+use opencv::videoio::{VideoCapture, CAP_ANY, CAP_V4L2};
+use std::io::Read;
+
+use crate::capturereassembly::{reassemble_capture, CapturedFrames};
+use crate::error::HdmiError;
+use crate::extractionlogics::{candidate_geometries, start_config_at};
+use crate::filemetadata::ExtractedFile;
+use crate::injectionextraction::{
+    alignment_center_px, map_to_size, marker_centers_px, ALIGNMENT_CELLS, MARKER_CELLS,
+    MARKER_CENTER_CELLS, TIMING_CELL,
+};
+use crate::instructionlogics::EncodingConfig;
+use crate::options::{Binarization, ExtractOptions, RawVideoFormat};
+use crate::rawpipe::RawFrameReader;
+use crate::timeline::{CaptureTimeline, FrameOutcome};
+use crate::videoframe::VideoFrame;
+
+/// A source of captured frames: a video file, a live capture device, a
+/// directory of images, raw frames on stdin, or a stand-in.
+pub trait FrameSource {
+    /// The next captured frame, or `None` once the source is exhausted (end of
+    /// the file, device gone).
+    fn next_image(&mut self) -> Option<Mat>;
+}
+
+impl<S: FrameSource + ?Sized> FrameSource for &mut S {
+    fn next_image(&mut self) -> Option<Mat> {
+        (**self).next_image()
+    }
+}
+
+impl FrameSource for VideoCapture {
+    fn next_image(&mut self) -> Option<Mat> {
+        let mut frame = Mat::default();
+        // A failed read (device unplugged, truncated file) ends the capture like
+        // its last frame: what was received so far is still reassembled.
+        self.read(&mut frame).ok()?;
+        (frame.cols() > 0).then_some(frame)
+    }
+}
+
+impl<R: Read> FrameSource for RawFrameReader<R> {
+    fn next_image(&mut self) -> Option<Mat> {
+        VideoFrame {
+            buffer: self.next_frame()?,
+        }
+        .to_mat()
+        .ok()
+    }
+}
+
+/// Open the capture named by `path`: a capture device index (`0`), a V4L2
+/// device (`/dev/video0`) or a video file.
+pub fn open_capture(path: &str) -> Result<VideoCapture, HdmiError> {
+    let open_error = |reason: String| HdmiError::VideoOpen {
+        path: path.to_string(),
+        reason,
+    };
+    let video = match path.parse::<i32>() {
+        Ok(index) => VideoCapture::new(index, CAP_ANY),
+        Err(_) if path.starts_with("/dev/video") => VideoCapture::from_file(path, CAP_V4L2),
+        Err(_) => VideoCapture::from_file(path, CAP_ANY),
+    }
+    .map_err(|error| open_error(format!("{:?}", error)))?;
+    let opened = video
+        .is_opened()
+        .map_err(|error| open_error(format!("{:?}", error)))?;
+    if !opened {
+        return Err(open_error(
+            "no such file or device, or no backend can read it".to_string(),
+        ));
+    }
+    Ok(video)
+}
+
+/// A capture being read frame by frame, registering each frame to canonical
+/// pixels. The geometry comes from the options when all of it is given,
+/// otherwise from the first Start frame found in the capture.
+struct CaptureReader<S: FrameSource> {
+    source: S,
+    geometry: Option<(u16, u16, u8)>,
+    /// Configuration and session of the Start frame found while probing for
+    /// the geometry.
+    config: Option<(EncodingConfig, u32)>,
+    /// Frames registered so far with each strategy, in [`Binarization::ALL`]
+    /// order.
+    registered: [u64; Binarization::ALL.len()],
+}
+
+impl<S: FrameSource> CaptureReader<S> {
+    fn new(source: S, extract_options: &ExtractOptions) -> CaptureReader<S> {
+        let geometry = match (
+            extract_options.width,
+            extract_options.height,
+            extract_options.size,
+        ) {
+            (Some(width), Some(height), Some(size)) => Some((width, height, size)),
+            _ => None,
+        };
+        CaptureReader {
+            source,
+            geometry,
+            config: None,
+            registered: [0; Binarization::ALL.len()],
+        }
+    }
+}
+
+impl<S: FrameSource> CapturedFrames for CaptureReader<S> {
+    /// The next frame that could be registered, or `None` once the source is
+    /// exhausted. Frames that could not be registered are recorded in
+    /// `timeline`.
+    fn next_frame(
+        &mut self,
+        extract_options: &ExtractOptions,
+        timeline: &mut CaptureTimeline,
+    ) -> Option<VideoFrame> {
+        loop {
+            let frame = self.source.next_image()?;
+
+            let (width, height, size) = match self.geometry {
+                Some(geometry) => geometry,
+                None => match probe_start_frame(&frame, extract_options) {
+                    Some((config, session)) => {
+                        if extract_options.show_progress {
+                            eprintln!(
+                                "Start frame declares {}x{} pixels, cell size {}, algo {}",
+                                config.width, config.height, config.size, config.algo
+                            );
+                        }
+                        self.geometry = Some((config.width, config.height, config.size));
+                        self.config = Some((config, session));
+                        (config.width, config.height, config.size)
+                    }
+                    // Frames before the first Start frame cannot be registered yet;
+                    // in a looped HDMI stream they come around again.
+                    None => {
+                        timeline.record(FrameOutcome::NoHeader);
+                        continue;
+                    }
+                },
+            };
+
+            // Re-align the captured frame to canonical pixels using the calibration
+            // markers. Frames where the markers cannot be located are skipped; in a
+            // looped HDMI stream they will be captured cleanly on another pass.
+            let binarization = extract_options.binarization;
+            if let Some((registered, used)) =
+                register_frame_with(&frame, width, height, size, binarization)
+            {
+                if let Some(index) = Binarization::ALL.iter().position(|b| *b == used) {
+                    self.registered[index] += 1;
+                }
+                return Some(registered);
+            }
+            timeline.record(FrameOutcome::NoMarkers);
+        }
+    }
+
+    fn take_config(&mut self) -> Option<(EncodingConfig, u32)> {
+        self.config.take()
+    }
+
+    /// How many frames each strategy registered, e.g. `fixed 118, otsu 2`.
+    /// Strategies that registered none are left out.
+    fn registration_summary(&self) -> Option<String> {
+        Some(
+            Binarization::ALL
+                .iter()
+                .zip(self.registered)
+                .filter(|(_, count)| *count > 0)
+                .map(|(binarization, count)| format!("{binarization} {count}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+}
+
+/// Read and register every frame of the capture (a video file or a capture
+/// device, see [`open_capture`]). Holds the whole capture in memory;
+/// [`video_to_data`] decodes it frame by frame instead.
+pub fn video_to_frames(extract_options: &ExtractOptions) -> Result<Vec<VideoFrame>, HdmiError> {
+    let source = open_capture(&extract_options.video_file_path)?;
+    Ok(source_to_frames(source, extract_options))
+}
+
+/// Read and register every frame of `source`.
+pub(crate) fn source_to_frames(
+    source: impl FrameSource,
+    extract_options: &ExtractOptions,
+) -> Vec<VideoFrame> {
+    let mut capture = CaptureReader::new(source, extract_options);
+    let mut timeline = CaptureTimeline::new();
+    let mut all_frames = Vec::new();
+    while let Some(frame) = capture.next_frame(extract_options, &mut timeline) {
+        all_frames.push(frame);
+    }
+    all_frames
+}
+
+/// Decode the capture at `extract_options.video_file_path` (a video file or a
+/// capture device, see [`open_capture`]) with [`source_to_data`].
+pub fn video_to_data(extract_options: &ExtractOptions) -> Result<Vec<u8>, HdmiError> {
+    video_to_file(extract_options).map(|file| file.data)
+}
+
+/// Like [`video_to_data`], with what the Metadata frame says about the file.
+pub fn video_to_file(extract_options: &ExtractOptions) -> Result<ExtractedFile, HdmiError> {
+    source_to_file(
+        open_capture(&extract_options.video_file_path)?,
+        extract_options,
+    )
+}
+
+/// Decode the raw frames of `format` read from `reader` (usually stdin, fed by
+/// `ffmpeg ... -f rawvideo -pix_fmt bgr24 -`) with [`source_to_data`].
+pub fn raw_to_data(
+    reader: impl Read,
+    format: RawVideoFormat,
+    extract_options: &ExtractOptions,
+) -> Result<Vec<u8>, HdmiError> {
+    raw_to_file(reader, format, extract_options).map(|file| file.data)
+}
+
+/// Like [`raw_to_data`], with what the Metadata frame says about the file.
+pub fn raw_to_file(
+    reader: impl Read,
+    format: RawVideoFormat,
+    extract_options: &ExtractOptions,
+) -> Result<ExtractedFile, HdmiError> {
+    source_to_file(RawFrameReader::new(reader, format), extract_options)
+}
+
+/// Read `source` frame by frame and reassemble the file as it goes: each frame
+/// is registered, decoded and dropped, keeping only the verified page bytes.
+/// Reading stops as soon as the Start frame and every page (or enough fountain
+/// symbols) have been seen, so a live capture device is released as soon as the
+/// transfer is complete. With `show_progress`, the page coverage is shown live.
+///
+/// An incomplete transfer returns [`HdmiError::MissingPages`] (or
+/// [`HdmiError::NotEnoughSymbols`], [`HdmiError::MissingStart`]) once its
+/// verified pages and the list of missing ones are saved next to the output.
+/// Those of a complete transfer are left for [`data_to_files`](crate::data_to_files)
+/// to remove once it has written the file.
+pub fn source_to_data(
+    source: impl FrameSource,
+    extract_options: &ExtractOptions,
+) -> Result<Vec<u8>, HdmiError> {
+    source_to_file(source, extract_options).map(|file| file.data)
+}
+
+/// Like [`source_to_data`], with what the Metadata frame says about the file.
+pub fn source_to_file(
+    source: impl FrameSource,
+    extract_options: &ExtractOptions,
+) -> Result<ExtractedFile, HdmiError> {
+    reassemble_capture(CaptureReader::new(source, extract_options), extract_options)
+}
+
+/// Try to read a captured (unregistered) frame as a Start frame, registering it
+/// with every candidate geometry in turn, starting with the one estimated from
+/// its finder and timing patterns. Returns the configuration it carries and
+/// its session.
+fn probe_start_frame(image: &Mat, options: &ExtractOptions) -> Option<(EncodingConfig, u32)> {
+    let frame_width = u16::try_from(image.cols()).ok()?;
+    let frame_height = u16::try_from(image.rows()).ok()?;
+    estimate_geometry_with(image, options.binarization)
+        .into_iter()
+        .chain(candidate_geometries(options, frame_width, frame_height))
+        .find_map(|(width, height, size)| {
+            let (registered, _) =
+                register_frame_with(image, width, height, size, options.binarization)?;
+            start_config_at(options, &registered, width, size)
+        })
+}
+
+/// How far below the mean of its neighbourhood a pixel must be to count as
+/// dark with the adaptive strategies, so flat areas (the quiet zone, a finder
+/// centre) do not turn into noise.
+const ADAPTIVE_OFFSET: f64 = 8.0;
+
+/// Binarise a captured frame for marker and timing detection with `strategy`.
+fn binarize(image: &Mat, strategy: Binarization) -> Option<Mat> {
+    let mut gray = Mat::default();
+    // OpenCV 4.11+ added a trailing `hint` arg to cvtColor; `cvt_color_def`
+    // keeps the pre-4.11 behavior (dst_cn = 0, default algorithm hint).
+    cvt_color_def(image, &mut gray, COLOR_BGR2GRAY).ok()?;
+    let mut thresh = Mat::default();
+    // Dark finder rings become foreground; the white quiet-zone border drops out.
+    let adaptive = match strategy {
+        Binarization::Fixed => {
+            threshold(&gray, &mut thresh, 128.0, 255.0, THRESH_BINARY_INV).ok()?;
+            return Some(thresh);
+        }
+        Binarization::Otsu => {
+            let flags = THRESH_BINARY_INV | THRESH_OTSU;
+            threshold(&gray, &mut thresh, 0.0, 255.0, flags).ok()?;
+            return Some(thresh);
+        }
+        Binarization::AdaptiveMean => ADAPTIVE_THRESH_MEAN_C,
+        Binarization::AdaptiveGaussian => ADAPTIVE_THRESH_GAUSSIAN_C,
+    };
+    // The neighbourhood (odd-sized) spans a tenth of the frame: wider than a
+    // finder centre at the usual cell sizes, so it never reads as flat.
+    let block = (image.cols().min(image.rows()) / 10).max(3) | 1;
+    adaptive_threshold(
+        &gray,
+        &mut thresh,
+        255.0,
+        adaptive,
+        THRESH_BINARY_INV,
+        block,
+        ADAPTIVE_OFFSET,
+    )
+    .ok()?;
+    Some(thresh)
+}
+
+/// Binarise `image` with `first`, then with the other strategies in turn, until
+/// its three finder patterns are found. Returns the thresholded image, the
+/// markers and the strategy that found them.
+fn find_markers(image: &Mat, first: Binarization) -> Option<(Mat, Markers, Binarization)> {
+    first.fallbacks().find_map(|strategy| {
+        let thresh = binarize(image, strategy)?;
+        let markers = detect_markers(&thresh, image.cols(), image.rows())?;
+        Some((thresh, markers, strategy))
+    })
+}
+
+/// Estimate the canonical `(width, height, size)` of a captured frame whose
+/// scale is unknown, from its finder and timing patterns.
+///
+/// The outer area of a finder (`MARKER_CELLS` cells across) bounds a walk out
+/// from its centre that measures a coarse cell pitch along each axis. The timing
+/// patterns running between the finders refine that pitch, and the distance
+/// between finder centres divided by it gives the exact number of cells across
+/// and down. The cell size is the rounded mean pitch, so a 1:1 capture gets its
+/// original geometry back and a rescaled one an equivalent grid that
+/// `register_frame` can warp to. Returns `None` when the finders or the timing
+/// patterns cannot be read.
+pub fn estimate_geometry(image: &Mat) -> Option<(u16, u16, u8)> {
+    estimate_geometry_with(image, Binarization::Fixed)
+}
+
+/// [`estimate_geometry`], looking for the markers with `binarization` first.
+pub fn estimate_geometry_with(image: &Mat, binarization: Binarization) -> Option<(u16, u16, u8)> {
+    let (thresh, markers, _) = find_markers(image, binarization)?;
+    let [(tl, area), (tr, _), (bl, _)] = markers.finders;
+    let across = (tr.0 - tl.0, tr.1 - tl.1);
+    let down = (bl.0 - tl.0, bl.1 - tl.1);
+
+    // Allow for anisotropic scaling: the finder may be longer along one axis
+    // than the side of a square of the same area.
+    let limit = 1.5 * (area as f32).sqrt();
+    let coarse_x = finder_half_side(&thresh, tl, across, limit)? / (MARKER_CELLS as f32 / 2.0);
+    let coarse_y = finder_half_side(&thresh, tl, down, limit)? / (MARKER_CELLS as f32 / 2.0);
+
+    // Each timing pattern runs parallel to the line joining two finder centres,
+    // this many cells inside it.
+    let inset = TIMING_CELL as f32 + 0.5 - MARKER_CENTER_CELLS;
+    let pitch_x = timing_pitch(&thresh, tl, across, down, inset * coarse_y, coarse_x)?;
+    let pitch_y = timing_pitch(&thresh, tl, down, across, inset * coarse_x, coarse_y)?;
+
+    let cells = |span: (f32, f32), pitch: f32| {
+        (span.0.hypot(span.1) / pitch + 2.0 * MARKER_CENTER_CELLS).round() as usize
+    };
+    let size = ((pitch_x + pitch_y) / 2.0).round().clamp(1.0, 255.0) as u8;
+    let width = u16::try_from(cells(across, pitch_x) * size as usize).ok()?;
+    let height = u16::try_from(cells(down, pitch_y) * size as usize).ok()?;
+    Some((width, height, size))
+}
+
+/// Step, in captured pixels, used when sampling along a line.
+const SAMPLE_STEP: f32 = 0.25;
+
+/// Whether the thresholded pixel nearest to `origin + dir * t` is dark.
+/// Positions outside the image read as light.
+fn dark_at(thresh: &Mat, origin: (f32, f32), dir: (f32, f32), t: f32) -> bool {
+    let x = (origin.0 + dir.0 * t).round() as i32;
+    let y = (origin.1 + dir.1 * t).round() as i32;
+    thresh.at_2d::<u8>(y, x).is_ok_and(|v| *v > 0)
+}
+
+/// Unit vector along `v`, or `None` for a degenerate one.
+fn unit(v: (f32, f32)) -> Option<(f32, f32)> {
+    let length = v.0.hypot(v.1);
+    (length >= 1.0).then(|| (v.0 / length, v.1 / length))
+}
+
+/// Distance, in captured pixels, from a finder centre to the outer edge of its
+/// black ring along `towards`: the second dark-to-light transition met walking
+/// out from the (dark) centre, half a finder side. `None` past `limit`.
+fn finder_half_side(
+    thresh: &Mat,
+    centre: (f32, f32),
+    towards: (f32, f32),
+    limit: f32,
+) -> Option<f32> {
+    let dir = unit(towards)?;
+    let mut edges = 0;
+    let mut was_dark = true;
+    for i in 0..=(limit / SAMPLE_STEP) as usize {
+        let t = i as f32 * SAMPLE_STEP;
+        let dark = dark_at(thresh, centre, dir, t);
+        if was_dark && !dark {
+            edges += 1;
+            if edges == 2 {
+                return Some(t);
+            }
+        }
+        was_dark = dark;
+    }
+    None
+}
+
+/// Cell pitch, in captured pixels, of the timing pattern that runs from finder
+/// centre `origin` along `span`, offset by `inset` pixels towards `side`.
+///
+/// The finder rings the line crosses at both ends show up as runs touching the
+/// ends or longer than two cells and are dropped; the remaining dark runs are
+/// timing cells, two cells apart. Each run is indexed by the number of cell
+/// pairs since the first one, refining the pitch as it goes from `coarse`, so a
+/// run lost to blur does not throw the estimate off.
+fn timing_pitch(
+    thresh: &Mat,
+    origin: (f32, f32),
+    span: (f32, f32),
+    side: (f32, f32),
+    inset: f32,
+    coarse: f32,
+) -> Option<f32> {
+    let dir = unit(span)?;
+    let offset = unit(side)?;
+    let start = (origin.0 + offset.0 * inset, origin.1 + offset.1 * inset);
+
+    let mut runs: Vec<(f32, f32)> = Vec::new();
+    let mut run_start: Option<f32> = None;
+    for i in 0..=(span.0.hypot(span.1) / SAMPLE_STEP) as usize {
+        let t = i as f32 * SAMPLE_STEP;
+        match (dark_at(thresh, start, dir, t), run_start) {
+            (true, None) => run_start = Some(t),
+            (false, Some(begin)) => {
+                runs.push((begin, t));
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let centres: Vec<f32> = runs
+        .into_iter()
+        .filter(|&(begin, end)| {
+            begin > 0.0 && end - begin >= 0.3 * coarse && end - begin <= 2.0 * coarse
+        })
+        .map(|(begin, end)| (begin + end) / 2.0)
+        .collect();
+    if centres.len() < 2 {
+        return None;
+    }
+
+    let mut pitch = coarse;
+    let mut pairs = 0.0f32;
+    for gap in centres.windows(2) {
+        pairs += ((gap[1] - gap[0]) / (2.0 * pitch)).round().max(1.0);
+        pitch = (gap[1] - centres[0]) / (2.0 * pairs);
+    }
+    Some(pitch)
+}
+
+/// Locate the finder patterns in a (possibly offset/scaled/compressed)
+/// captured frame and warp it back to canonical `width` x `height` pixels so the
+/// cell grid lines up with what the encoder wrote. When the bottom-right
+/// alignment marker is found as well, the four points give a perspective
+/// transform that also undoes keystone distortion (a camera filming the screen
+/// off-axis); otherwise the three finders give an affine one. Returns `None`
+/// when the finders cannot be found with any binarisation strategy.
+pub fn register_frame(image: &Mat, width: u16, height: u16, size: u8) -> Option<VideoFrame> {
+    register_frame_with(image, width, height, size, Binarization::Fixed).map(|(frame, _)| frame)
+}
+
+/// [`register_frame`], looking for the markers with `binarization` first and
+/// the other strategies after it. Also returns the strategy that found them.
+pub fn register_frame_with(
+    image: &Mat,
+    width: u16,
+    height: u16,
+    size: u8,
+    binarization: Binarization,
+) -> Option<(VideoFrame, Binarization)> {
+    if image.cols() == 0 || image.rows() == 0 {
+        return None;
+    }
+
+    let (_, markers, used) = find_markers(image, binarization)?;
+    let [(tl, _), (tr, _), (bl, _)] = markers.finders;
+    let mut src = vec![
+        Point2f::new(tl.0, tl.1),
+        Point2f::new(tr.0, tr.1),
+        Point2f::new(bl.0, bl.1),
+    ];
+    let mut dst: Vec<Point2f> = marker_centers_px(width, height, size)
+        .iter()
+        .map(|&(x, y)| Point2f::new(x, y))
+        .collect();
+
+    let mut warped = Mat::default();
+    match markers.alignment {
+        Some(br) => {
+            let (x, y) = alignment_center_px(width, height, size);
+            src.push(Point2f::new(br.0, br.1));
+            dst.push(Point2f::new(x, y));
+            let transform = get_perspective_transform_slice(&src, &dst, DECOMP_LU).ok()?;
+            warp_perspective(
+                image,
+                &mut warped,
+                &transform,
+                map_to_size(width, height),
+                INTER_LINEAR,
+                BORDER_CONSTANT,
+                Scalar::default(),
+            )
+            .ok()?;
+        }
+        None => {
+            let transform = get_affine_transform_slice(&src, &dst).ok()?;
+            warp_affine(
+                image,
+                &mut warped,
+                &transform,
+                map_to_size(width, height),
+                INTER_LINEAR,
+                BORDER_CONSTANT,
+                Scalar::default(),
+            )
+            .ok()?;
+        }
+    }
+
+    let frame = VideoFrame::from(warped, size).ok()?;
+    Some((frame, used))
+}
+
+/// Centroid and (absolute) enclosed area of contour `idx`, or `None` when it is
+/// degenerate.
+fn contour_centroid_area(contours: &Vector<Vector<Point>>, idx: usize) -> Option<(f32, f32, f64)> {
+    let contour = contours.get(idx).ok()?;
+    let area = contour_area(&contour, false).unwrap_or(0.0).abs();
+    if area < f64::EPSILON {
+        return None;
+    }
+    let m = moments(&contour, false).ok()?;
+    if m.m00.abs() < f64::EPSILON {
+        return None;
+    }
+    Some(((m.m10 / m.m00) as f32, (m.m01 / m.m00) as f32, area))
+}
+
+/// The registration marks located in a captured frame.
+struct Markers {
+    /// Centre and outer area of each finder pattern, in TL/TR/BL order.
+    finders: [((f32, f32), f64); 3],
+    /// Centre of the bottom-right alignment marker, when it was found.
+    alignment: Option<(f32, f32)>,
+}
+
+/// Find the centre of the best finder pattern in each of the three expected
+/// corners (top-left, top-right, bottom-left), and the alignment marker in the
+/// fourth if it can be told apart. Returns `None` without all three finders.
+///
+/// A finder pattern is a QR-style triple of concentric squares: a black outer
+/// ring, a white middle ring, and a black centre. After `THRESH_BINARY_INV`
+/// (black -> foreground) that yields a very specific contour-tree signature:
+/// an outer foreground contour whose child is a hole (the white ring) whose
+/// child is an inner foreground contour (the centre), with all three sharing a
+/// centre and an outer/inner area ratio near (7/3)^2 ~= 5.4.
+///
+/// The previous heuristic ("largest contour with >= 2 ancestors in the quadrant")
+/// was not specific: dense payload content (black BW cells / dark RGB cells)
+/// forms larger nested blobs in the corner quadrants and was selected instead of
+/// the real markers, producing a misaligned warp that corrupted every frame -
+/// even unperturbed ones. Validating the concentric triple makes detection
+/// scale-invariant and robust to arbitrary payload content.
+///
+/// The alignment marker has the same signature with a single-cell centre, so an
+/// area ratio near (5/1)^2 = 25. It is only accepted near where the finders put
+/// the fourth corner of their parallelogram, and at a plausible size, so a stray
+/// nesting in the payload cannot stand in for it.
+fn detect_markers(thresh: &Mat, w: i32, h: i32) -> Option<Markers> {
+    let mut contours: Vector<Vector<Point>> = Vector::new();
+    let mut hierarchy: Vector<Vec4i> = Vector::new();
+    find_contours_with_hierarchy(
+        thresh,
+        &mut contours,
+        &mut hierarchy,
+        RETR_TREE,
+        CHAIN_APPROX_SIMPLE,
+        Point::new(0, 0),
+    )
+    .ok()?;
+
+    let mut candidates: Vec<((f32, f32), f64)> = Vec::new();
+    let mut alignment_candidates: Vec<((f32, f32), f64)> = Vec::new();
+    for i in 0..contours.len() {
+        // hierarchy entry is [next, prev, first_child, parent].
+        let node = hierarchy.get(i).ok()?;
+        let child = node[2];
+        if child < 0 {
+            continue; // no middle-ring hole -> not a finder
+        }
+        let hole = hierarchy.get(child as usize).ok()?;
+        let grand = hole[2];
+        if grand < 0 {
+            continue; // no inner centre -> not a finder
+        }
+
+        let (ox, oy, outer_area) = match contour_centroid_area(&contours, i) {
+            Some(v) => v,
+            None => continue,
+        };
+        let (gx, gy, inner_area) = match contour_centroid_area(&contours, grand as usize) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        // Reject sub-pixel noise; finders are several cells across.
+        if outer_area < 16.0 || inner_area < f64::EPSILON {
+            continue;
+        }
+
+        // Concentric: the inner centre must sit on top of the outer centre,
+        // tolerant to within ~30% of the outer square's side (sqrt of its area).
+        let outer_side = (outer_area as f32).sqrt();
+        let dx = ox - gx;
+        let dy = oy - gy;
+        if (dx * dx + dy * dy).sqrt() > 0.3 * outer_side {
+            continue;
+        }
+
+        // Outer/inner area ratio: ideal (7/3)^2 ~= 5.44. A generous window keeps
+        // it tolerant to warp/blur while still rejecting unrelated nestings.
+        // Contours run through boundary pixel centres, which shrinks the small
+        // alignment centre most at small cell sizes, so its window runs high.
+        let ratio = outer_area / inner_area;
+        if (2.5..=12.0).contains(&ratio) {
+            candidates.push(((ox, oy), outer_area));
+        } else if (15.0..=150.0).contains(&ratio) {
+            alignment_candidates.push(((ox, oy), outer_area));
+        }
+    }
+
+    let wf = w as f32;
+    let hf = h as f32;
+    let tl = best_in_region(&candidates, |cx, cy| cx < wf * 0.4 && cy < hf * 0.4)?;
+    let tr = best_in_region(&candidates, |cx, cy| cx > wf * 0.6 && cy < hf * 0.4)?;
+    let bl = best_in_region(&candidates, |cx, cy| cx < wf * 0.4 && cy > hf * 0.6)?;
+    let alignment = find_alignment_marker(&alignment_candidates, [tl, tr, bl]);
+    Some(Markers {
+        finders: [tl, tr, bl],
+        alignment,
+    })
+}
+
+/// Pick the alignment candidate nearest the fourth corner of the parallelogram
+/// spanned by the finder centres, within a quarter of the shorter side of it,
+/// whose outer area is close to the expected (5/7)^2 of a finder's.
+fn find_alignment_marker(
+    candidates: &[((f32, f32), f64)],
+    finders: [((f32, f32), f64); 3],
+) -> Option<(f32, f32)> {
+    let [(tl, tl_area), (tr, tr_area), (bl, bl_area)] = finders;
+    let expected = (tr.0 + bl.0 - tl.0, tr.1 + bl.1 - tl.1);
+    let reach = 0.25
+        * (tr.0 - tl.0)
+            .hypot(tr.1 - tl.1)
+            .min((bl.0 - tl.0).hypot(bl.1 - tl.1));
+    let scale = (ALIGNMENT_CELLS as f64 / MARKER_CELLS as f64).powi(2);
+    let area = scale * (tl_area + tr_area + bl_area) / 3.0;
+    let distance = |(cx, cy): (f32, f32)| (cx - expected.0).hypot(cy - expected.1);
+    candidates
+        .iter()
+        .filter(|&&(centre, outer_area)| {
+            distance(centre) <= reach && (0.4 * area..=2.0 * area).contains(&outer_area)
+        })
+        .map(|&(centre, _)| centre)
+        .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+}
+
+/// Pick the largest-area candidate whose centre satisfies `pred`, returning its
+/// centre and outer area.
+fn best_in_region(
+    candidates: &[((f32, f32), f64)],
+    pred: impl Fn(f32, f32) -> bool,
+) -> Option<((f32, f32), f64)> {
+    candidates
+        .iter()
+        .filter(|((cx, cy), _)| pred(*cx, *cy))
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .copied()
+}
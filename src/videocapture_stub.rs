@@ -0,0 +1,122 @@
+use std::io::Read;
+
+use crate::capturereassembly::{reassemble_capture, CanonicalFrames};
+use crate::error::HdmiError;
+use crate::filemetadata::ExtractedFile;
+use crate::options::{ExtractOptions, RawVideoFormat};
+use crate::rawpipe::RawFrameReader;
+use crate::videoframe::VideoFrame;
+
+/// Video decoding requires OpenCV.
+///
+/// Enable the default `opencv-backend` feature to read and register the frames
+/// of a video file or capture device. Frames already held in memory are decoded
+/// with `frames_to_data` in every build.
+pub fn video_to_frames(_extract_options: &ExtractOptions) -> Result<Vec<VideoFrame>, HdmiError> {
+    Err(HdmiError::NoVideoBackend {
+        operation: "video_to_frames",
+    })
+}
+
+/// Video decoding requires OpenCV.
+pub fn video_to_data(_extract_options: &ExtractOptions) -> Result<Vec<u8>, HdmiError> {
+    Err(HdmiError::NoVideoBackend {
+        operation: "video_to_data",
+    })
+}
+
+/// Video decoding requires OpenCV.
+pub fn video_to_file(_extract_options: &ExtractOptions) -> Result<ExtractedFile, HdmiError> {
+    Err(HdmiError::NoVideoBackend {
+        operation: "video_to_file",
+    })
+}
+
+/// Decode the raw frames of `format` read from `reader` (usually stdin).
+///
+/// Without OpenCV the frames are not registered: this reads frames at the
+/// canonical geometry, such as those written by `file_to_raw`, not a capture of
+/// the screen. Enable the default `opencv-backend` feature to register captured
+/// frames.
+pub fn raw_to_data(
+    reader: impl Read,
+    format: RawVideoFormat,
+    extract_options: &ExtractOptions,
+) -> Result<Vec<u8>, HdmiError> {
+    raw_to_file(reader, format, extract_options).map(|file| file.data)
+}
+
+/// Like [`raw_to_data`], with what the Metadata frame says about the file.
+pub fn raw_to_file(
+    reader: impl Read,
+    format: RawVideoFormat,
+    extract_options: &ExtractOptions,
+) -> Result<ExtractedFile, HdmiError> {
+    let mut reader = RawFrameReader::new(reader, format);
+    let frames =
+        std::iter::from_fn(move || reader.next_frame()).map(|buffer| VideoFrame { buffer });
+    reassemble_capture(CanonicalFrames(frames), extract_options)
+}
+
+#[cfg(test)]
+mod videocapture_stub_tests {
+    use super::*;
+    use crate::injectionlogics::transfer_to_frames;
+    use crate::options::{AlgoFrame, InjectOptions, PixelFormat};
+    use crate::pagereport::missing_report_path;
+    use crate::rawpipe::frames_to_raw;
+    use crate::transferstate::state_file_path;
+    use std::fs;
+
+    #[test]
+    fn test_raw_frames_are_decoded_and_resumed_without_opencv() {
+        let dir = std::env::temp_dir().join(format!("hdmift_stub_raw_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let inject_options = InjectOptions {
+            width: 96,
+            height: 96,
+            size: 2,
+            algo: AlgoFrame::BW,
+            session: 5,
+            ..Default::default()
+        };
+        let data: Vec<u8> = (0..1500u32).map(|i| (i % 241) as u8).collect();
+        let frames = transfer_to_frames(&inject_options, data.clone()).unwrap();
+        let frame_count = frames.len();
+        assert!(frame_count > 3);
+        let mut stream = Vec::new();
+        frames_to_raw(&inject_options, frames, &mut stream, PixelFormat::Rgb24).unwrap();
+        let format = RawVideoFormat {
+            pixel_format: PixelFormat::Rgb24,
+            width: 96,
+            height: 96,
+        };
+        let output = dir.join("out.bin").to_string_lossy().to_string();
+        let extract_options = ExtractOptions {
+            extracted_file_path: output.clone(),
+            raw_input: Some(format),
+            ..Default::default()
+        };
+
+        // A stream cut before its last page leaves the verified pages and the
+        // missing one next to the output, like a video extraction.
+        let frame_bytes = 96 * 96 * 3;
+        let cut = &stream[..(frame_count - 1) * frame_bytes];
+        assert!(matches!(
+            raw_to_data(cut, format, &extract_options),
+            Err(HdmiError::MissingPages { .. })
+        ));
+        assert!(state_file_path(&output).unwrap().exists());
+        assert!(missing_report_path(&output).unwrap().exists());
+
+        // The Start frame and the last page complete it.
+        let mut repair = stream[..frame_bytes].to_vec();
+        repair.extend_from_slice(&stream[(frame_count - 1) * frame_bytes..]);
+        assert_eq!(
+            raw_to_data(repair.as_slice(), format, &extract_options).unwrap(),
+            data
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,102 +1,87 @@
-use opencv::core::{Mat, Size, CV_8UC3};
-use opencv::prelude::*;
-
 use crate::bitlogics::get_rgb_for_bit;
+use crate::error::HdmiError;
+use crate::framebuffer::FrameBuffer;
 use crate::injectionextraction::{
-    cells_high, cells_wide, content_cell_xy, marker_cell_origins, Color, BORDER_CELLS, MARKER_CELLS,
+    alignment_cell_origin, calibration_ramp, cells_high, cells_wide, concentric_cell_is_dark,
+    content_cell_xy, lattice_cells, marker_cell_origins, ramp_patch_col, timing_cell_is_dark,
+    Color, ALIGNMENT_CELLS, BORDER_CELLS, MARKER_CELLS, RAMP_PATCH_CELLS, RAMP_ROW, TIMING_CELL,
 };
 use crate::instructionlogics::FrameHeader;
+use crate::options::AlgoFrame;
+#[cfg(feature = "opencv-backend")]
+use opencv::core::{Mat, CV_8UC3};
+#[cfg(feature = "opencv-backend")]
+use opencv::prelude::*;
 
 /// Define a single frame that the video will play
 /// E.g. on a 30fps video, there will be 30 VideoFrame every second
 ///
 /// Original source: <https://github.com/DvorakDwarf/Infinite-Storage-Glitch/blob/master/src/embedsource.rs>
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VideoFrame {
-    /// A Mat is a dense array to store color
-    ///
-    /// Reference: <https://docs.opencv.org/3.4/d3/d63/classcv_1_1Mat.html>
-    pub image: Mat,
-
-    /// Each frame has as width and height. This is the multiplication of both.
-    /// The frame_size is the resolution of the video. We expect each frame of
-    /// the video to have the same frame size
-    pub frame_size: Size,
+    /// The RGB pixels of the frame. Every frame of a video has the same
+    /// resolution.
+    pub buffer: FrameBuffer,
 }
 
 impl VideoFrame {
+    /// A black frame.
     pub fn new(width: u16, height: u16) -> VideoFrame {
-        let frame_size = Size::new(width.into(), height.into());
-        unsafe {
-            let image = Mat::new_rows_cols(frame_size.height, frame_size.width, CV_8UC3)
-                .expect("Failed to create new Mat");
-
-            VideoFrame { image, frame_size }
+        VideoFrame {
+            buffer: FrameBuffer::new(width, height),
         }
     }
 
-    pub fn write(&mut self, r: u8, g: u8, b: u8, x: u16, y: u16, size: u8) {
-        for i in 0..size {
-            for j in 0..size {
-                let result = self.image.at_2d_mut::<opencv::core::Vec3b>(
-                    i32::from(y) + i32::from(i),
-                    i32::from(x) + i32::from(j),
-                );
-                match result {
-                    Ok(bgr) => {
-                        // Opencv works with bgr format instead of rgb
-                        bgr[2] = r;
-                        bgr[1] = g;
-                        bgr[0] = b;
-                    }
-                    Err(e) => panic!("x:{}, y:{}, i:{}, j:{}, Error Message:{:?}", x, y, i, j, e),
-                }
-            }
-        }
+    /// A frame over the row-major RGB `pixels` of a `width` x `height` image,
+    /// e.g. one frame of an image sequence or of a raw video stream.
+    pub fn from_rgb(width: u16, height: u16, pixels: Vec<u8>) -> Result<VideoFrame, HdmiError> {
+        Ok(VideoFrame {
+            buffer: FrameBuffer::from_rgb(width, height, pixels)?,
+        })
     }
 
-    pub fn from(image: Mat, size: u8) -> Result<VideoFrame, String> {
-        let width = image.cols();
-        let height = image.rows();
-        let frame_size = Size::new(width, height);
+    pub fn width(&self) -> u16 {
+        self.buffer.width()
+    }
 
-        if height % i32::from(size) != 0 {
-            return Err("Image size is not a multiple of the size".to_string());
-        }
+    pub fn height(&self) -> u16 {
+        self.buffer.height()
+    }
 
-        Ok(VideoFrame { image, frame_size })
+    /// Fill the `size` x `size` cell whose top-left pixel is `(x, y)`. The part
+    /// of a cell past the frame edge is not drawn.
+    pub fn write(&mut self, r: u8, g: u8, b: u8, x: u16, y: u16, size: u8) {
+        self.buffer
+            .fill_rect(x, y, size.into(), size.into(), [r, g, b]);
     }
 
     pub fn read_coordinate_color(&self, x: u16, y: u16) -> Color {
-        let bgr = self
-            .image
-            .at_2d::<opencv::core::Vec3b>(y.into(), x.into())
-            .unwrap();
-
-        Color {
-            r: bgr[2],
-            g: bgr[1],
-            b: bgr[0],
-        }
+        let [r, g, b] = self.buffer.pixel(x, y);
+        Color { r, g, b }
     }
 
     /// Draw the calibration ring used by the extractor to re-align a captured
     /// frame: a white quiet-zone border with three QR-style finder patterns in
     /// the top-left, top-right and bottom-left corners. The asymmetry (only
-    /// three corners) lets the decoder recover orientation.
-    pub fn write_calibration(&mut self, size: u8) {
-        let width = self.frame_size.width as u16;
-        let height = self.frame_size.height as u16;
+    /// three corners) lets the decoder recover orientation. The bottom-right
+    /// corner holds a smaller alignment marker; when the decoder finds it too
+    /// it corrects perspective (keystone) distortion as well.
+    ///
+    /// For the multi-level algos the top border strip also carries a reference
+    /// ramp of every level in use (see `injectionextraction::calibration_ramp`),
+    /// which the decoder measures to derive per-frame decision thresholds.
+    pub fn write_calibration(&mut self, size: u8, algo: AlgoFrame) {
+        let width = self.width();
+        let height = self.height();
         let cols = cells_wide(width, size);
         let rows = cells_high(height, size);
+        let right = cols.saturating_sub(BORDER_CELLS);
+        let bottom = rows.saturating_sub(BORDER_CELLS);
 
         // White quiet-zone border ring.
         for cy in 0..rows {
             for cx in 0..cols {
-                let in_ring = cx < BORDER_CELLS
-                    || cx >= cols - BORDER_CELLS
-                    || cy < BORDER_CELLS
-                    || cy >= rows - BORDER_CELLS;
+                let in_ring = cx < BORDER_CELLS || cx >= right || cy < BORDER_CELLS || cy >= bottom;
                 if in_ring {
                     let x = (cx * size as usize) as u16;
                     let y = (cy * size as usize) as u16;
@@ -104,22 +89,68 @@ impl VideoFrame {
                 }
             }
         }
+        // A frame narrower than two rings is all ring: the markers do not fit.
+        if right < BORDER_CELLS || bottom < BORDER_CELLS {
+            return;
+        }
 
         // Finder patterns at the three corners.
         for (ox, oy) in marker_cell_origins(width, height, size) {
-            self.draw_finder_pattern(ox, oy, size);
+            self.draw_concentric_pattern(ox, oy, MARKER_CELLS, size);
+        }
+
+        // Alignment marker in the fourth corner.
+        let (ox, oy) = alignment_cell_origin(width, height, size);
+        self.draw_concentric_pattern(ox, oy, ALIGNMENT_CELLS, size);
+
+        // Timing patterns between the finders, along the top and left strips.
+        for cx in BORDER_CELLS..right {
+            let v = if timing_cell_is_dark(cx - BORDER_CELLS) {
+                0
+            } else {
+                255
+            };
+            let x = (cx * size as usize) as u16;
+            let y = (TIMING_CELL * size as usize) as u16;
+            self.write(v, v, v, x, y, size);
+        }
+        for cy in BORDER_CELLS..bottom {
+            let v = if timing_cell_is_dark(cy - BORDER_CELLS) {
+                0
+            } else {
+                255
+            };
+            let x = (TIMING_CELL * size as usize) as u16;
+            let y = (cy * size as usize) as u16;
+            self.write(v, v, v, x, y, size);
+        }
+
+        // Level reference ramp, three rows tall, centred on `RAMP_ROW`.
+        for (index, rgb) in calibration_ramp(algo, width, size).iter().enumerate() {
+            let left = ramp_patch_col(index);
+            for cy in RAMP_ROW - 1..=RAMP_ROW + 1 {
+                for cx in left..left + RAMP_PATCH_CELLS {
+                    let x = (cx * size as usize) as u16;
+                    let y = (cy * size as usize) as u16;
+                    self.write(rgb[0], rgb[1], rgb[2], x, y, size);
+                }
+            }
         }
     }
 
-    /// Draw a single `MARKER_CELLS` x `MARKER_CELLS` concentric-square finder
-    /// pattern with its top-left at the given cell coordinate.
-    fn draw_finder_pattern(&mut self, origin_cx: usize, origin_cy: usize, size: u8) {
-        let last = MARKER_CELLS - 1;
-        for r in 0..MARKER_CELLS {
-            for c in 0..MARKER_CELLS {
-                let outer_ring = r == 0 || r == last || c == 0 || c == last;
-                let center = r >= 2 && r <= MARKER_CELLS - 3 && c >= 2 && c <= MARKER_CELLS - 3;
-                let (rr, gg, bb) = if outer_ring || center {
+    /// Draw a single `cells` x `cells` concentric-square pattern (a finder
+    /// pattern, or the smaller alignment marker) with its top-left at the given
+    /// cell coordinate.
+    fn draw_concentric_pattern(
+        &mut self,
+        origin_cx: usize,
+        origin_cy: usize,
+        cells: usize,
+        size: u8,
+    ) {
+        for r in 0..cells {
+            for c in 0..cells {
+                let (rr, gg, bb) = if concentric_cell_is_dark(r, c, cells) {
                     (0, 0, 0) // black
                 } else {
                     (255, 255, 255) // white middle ring
@@ -131,39 +162,132 @@ impl VideoFrame {
         }
     }
 
+    /// Draw the alignment patterns of the lattice inside the content rectangle
+    /// (see `injectionextraction::lattice_cells`), which the extractor locates
+    /// to correct non-linear distortion the corner markers cannot see.
+    pub fn write_lattice(&mut self, size: u8) {
+        for (col, row) in lattice_cells(self.width(), self.height(), size, true) {
+            self.draw_concentric_pattern(
+                BORDER_CELLS + col,
+                BORDER_CELLS + row,
+                ALIGNMENT_CELLS,
+                size,
+            );
+        }
+    }
+
     /// Write the per-frame header (black/white) into the first `HEADER_BITS`
-    /// content cells (just inside the calibration ring).
+    /// content cells (just inside the calibration ring). They come before the
+    /// lattice rows, so the layout is the same with or without the lattice.
     pub fn write_header(&mut self, header: &FrameHeader, size: u8) {
-        let width = self.frame_size.width as u16;
+        let width = self.width();
+        let height = self.height();
         let bits = header.to_bits();
         for (index, bit) in bits.iter().enumerate() {
-            let (x, y) = content_cell_xy(index, width, size);
+            let (x, y) = content_cell_xy(index, width, height, size, false);
             let (r, g, b) = get_rgb_for_bit(*bit);
             self.write(r, g, b, x, y, size);
         }
     }
 }
 
+/// Conversion from and to the BGR images OpenCV reads from and writes to video
+/// containers.
+#[cfg(feature = "opencv-backend")]
+impl VideoFrame {
+    /// Copy a BGR 8-bit image into a frame. Fails when the image is not 8-bit
+    /// 3-channel, or its height is not a whole number of `size`-pixel cells.
+    pub fn from(image: Mat, size: u8) -> Result<VideoFrame, HdmiError> {
+        let width = image.cols();
+        let height = image.rows();
+        let invalid = |reason: String| HdmiError::InvalidImage {
+            width: width.max(0) as u32,
+            height: height.max(0) as u32,
+            reason,
+        };
+
+        if height % i32::from(size) != 0 {
+            return Err(HdmiError::UnalignedSize {
+                dimension: "height",
+                pixels: height as u32,
+                size,
+            });
+        }
+        if image.typ() != CV_8UC3 {
+            return Err(invalid("not an 8-bit 3-channel image".to_string()));
+        }
+        let (Ok(frame_width), Ok(frame_height)) = (u16::try_from(width), u16::try_from(height))
+        else {
+            return Err(invalid("larger than 65535 pixels".to_string()));
+        };
+        // `data_bytes` needs the rows stored back to back; a cropped view is not.
+        let image = if image.is_continuous() {
+            image
+        } else {
+            image
+                .try_clone()
+                .map_err(|error| invalid(format!("{:?}", error)))?
+        };
+        let bgr = image
+            .data_bytes()
+            .map_err(|error| invalid(format!("{:?}", error)))?;
+        let mut pixels = Vec::with_capacity(bgr.len());
+        for pixel in bgr.chunks_exact(3) {
+            pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+        VideoFrame::from_rgb(frame_width, frame_height, pixels)
+    }
+
+    /// Copy the frame into a BGR 8-bit image, as written to a video.
+    pub fn to_mat(&self) -> Result<Mat, HdmiError> {
+        let invalid = |error: opencv::Error| HdmiError::InvalidImage {
+            width: u32::from(self.width()),
+            height: u32::from(self.height()),
+            reason: format!("{:?}", error),
+        };
+        // Every byte is overwritten below.
+        let mut image = unsafe {
+            Mat::new_rows_cols(self.height().into(), self.width().into(), CV_8UC3)
+                .map_err(invalid)?
+        };
+        let bgr = image.data_bytes_mut().map_err(invalid)?;
+        for (target, pixel) in bgr
+            .chunks_exact_mut(3)
+            .zip(self.buffer.rgb().chunks_exact(3))
+        {
+            target.copy_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+        Ok(image)
+    }
+}
+
 #[cfg(test)]
 mod videoframe_tests {
-    use crate::injectionextraction::{content_cell_xy, BORDER_CELLS};
+    #[cfg(feature = "opencv-backend")]
+    use crate::error::HdmiError;
+    use crate::injectionextraction::{content_cell_xy, ramp_patch_xy, BORDER_CELLS, TIMING_CELL};
     use crate::instructionlogics::{FrameHeader, FrameType};
+    use crate::options::AlgoFrame;
 
     use super::VideoFrame;
+    #[cfg(feature = "opencv-backend")]
     use opencv::core::prelude::*;
+    #[cfg(feature = "opencv-backend")]
     use opencv::core::{Mat, CV_8UC3};
 
     #[test]
     fn test_new_create_image_size() {
         let result = VideoFrame::new(100, 50);
-        assert_eq!(result.frame_size.width, 100);
-        assert_eq!(result.frame_size.height, 50);
+        assert_eq!(result.width(), 100);
+        assert_eq!(result.height(), 50);
+        assert_eq!(result.buffer.rgb().len(), 100 * 50 * 3);
     }
 
+    #[cfg(feature = "opencv-backend")]
     #[test]
     fn test_new_create_image_mat_size() {
         let result = VideoFrame::new(100, 50);
-        let s = result.image.size().unwrap();
+        let s = result.to_mat().unwrap().size().unwrap();
         assert_eq!(s.width, 100);
         assert_eq!(s.height, 50);
     }
@@ -172,10 +296,16 @@ mod videoframe_tests {
     fn test_write_image_color() {
         let mut videoframe = VideoFrame::new(100, 50);
         videoframe.write(10, 20, 30, 0, 0, 1);
-        let pixel = videoframe.image.at_2d::<opencv::core::Vec3b>(0, 0).unwrap();
-        assert_eq!(pixel[0], 30);
-        assert_eq!(pixel[1], 20);
-        assert_eq!(pixel[2], 10);
+        assert_eq!(videoframe.buffer.pixel(0, 0), [10, 20, 30]);
+        assert_eq!(videoframe.buffer.pixel(1, 0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_write_clips_a_cell_past_the_frame_edge() {
+        let mut videoframe = VideoFrame::new(10, 10);
+        videoframe.write(255, 255, 255, 8, 8, 4);
+        assert_eq!(videoframe.buffer.pixel(9, 9), [255, 255, 255]);
+        assert_eq!(videoframe.buffer.pixel(7, 9), [0, 0, 0]);
     }
 
     #[test]
@@ -188,24 +318,48 @@ mod videoframe_tests {
         assert_eq!(color.r, 10);
     }
 
+    #[cfg(feature = "opencv-backend")]
     #[test]
     fn test_from_define_size() {
         unsafe {
             let mat = Mat::new_rows_cols(100, 200, CV_8UC3).unwrap();
             let videoframe = VideoFrame::from(mat, 1);
             let unwrapped = videoframe.unwrap();
-            assert_eq!(unwrapped.frame_size.width, 200);
-            assert_eq!(unwrapped.frame_size.height, 100);
+            assert_eq!(unwrapped.width(), 200);
+            assert_eq!(unwrapped.height(), 100);
         }
     }
 
+    #[cfg(feature = "opencv-backend")]
+    #[test]
+    fn test_mat_round_trip_keeps_the_pixels() {
+        let mut videoframe = VideoFrame::new(40, 30);
+        videoframe.write(10, 20, 30, 4, 6, 3);
+        let mat = videoframe.to_mat().unwrap();
+        // OpenCV stores the channels in BGR order.
+        let bgr = mat.at_2d::<opencv::core::Vec3b>(6, 4).unwrap();
+        assert_eq!((bgr[0], bgr[1], bgr[2]), (30, 20, 10));
+        assert_eq!(VideoFrame::from(mat, 1).unwrap(), videoframe);
+    }
+
+    #[cfg(feature = "opencv-backend")]
     #[test]
     fn test_from_rejects_height_not_multiple_of_cell_size() {
         unsafe {
             let mat = Mat::new_rows_cols(101, 200, CV_8UC3).unwrap();
             match VideoFrame::from(mat, 2) {
                 Ok(_) => panic!("Expected frame size validation to fail"),
-                Err(err) => assert_eq!(err, "Image size is not a multiple of the size"),
+                Err(err) => assert!(
+                    matches!(
+                        err,
+                        HdmiError::UnalignedSize {
+                            dimension: "height",
+                            pixels: 101,
+                            size: 2
+                        }
+                    ),
+                    "{err}"
+                ),
             }
         }
     }
@@ -214,7 +368,7 @@ mod videoframe_tests {
     fn test_write_calibration_draws_white_corner_and_finder() {
         // Large enough to hold the border ring and finder patterns.
         let mut videoframe = VideoFrame::new(128, 128);
-        videoframe.write_calibration(1);
+        videoframe.write_calibration(1, AlgoFrame::RGB);
 
         // The very top-left pixel is the quiet zone (white).
         let color = videoframe.read_coordinate_color(0, 0);
@@ -241,16 +395,84 @@ mod videoframe_tests {
         assert_eq!(color.b, 255);
     }
 
+    #[test]
+    fn test_write_calibration_draws_bottom_right_alignment_marker() {
+        let mut videoframe = VideoFrame::new(128, 128);
+        videoframe.write_calibration(2, AlgoFrame::RGB);
+        let grey = |cx: u16, cy: u16| videoframe.read_coordinate_color(cx * 2, cy * 2).r;
+
+        // 64x64 cells: the 5x5 marker spans cells 57..=61, centred on cell 59.
+        assert_eq!(grey(63, 63), 255);
+        assert_eq!(grey(62, 62), 255);
+        assert_eq!(grey(61, 61), 0);
+        assert_eq!(grey(57, 59), 0);
+        assert_eq!(grey(58, 59), 255);
+        assert_eq!(grey(59, 59), 0);
+        assert_eq!(grey(60, 58), 255);
+        // The quiet moat before the content rectangle stays white.
+        assert_eq!(grey(56, 56), 255);
+        assert_eq!(grey(55, 55), 255);
+    }
+
+    #[test]
+    fn test_write_calibration_draws_level_ramp() {
+        let mut videoframe = VideoFrame::new(256, 128);
+        videoframe.write_calibration(1, AlgoFrame::Brightness(4));
+
+        for (index, expected) in [0u8, 85, 170, 255].iter().enumerate() {
+            let (x, y) = ramp_patch_xy(index, 1);
+            let color = videoframe.read_coordinate_color(x, y);
+            assert_eq!(color.r, *expected);
+            assert_eq!(color.g, *expected);
+            assert_eq!(color.b, *expected);
+        }
+
+        // Past the last patch the strip stays white.
+        let (x, y) = ramp_patch_xy(4, 1);
+        let color = videoframe.read_coordinate_color(x, y);
+        assert_eq!(color.r, 255);
+        assert_eq!(color.b, 255);
+    }
+
+    #[test]
+    fn test_write_calibration_draws_timing_patterns() {
+        // 127 cells wide: an odd number of timing cells, so the last one is black.
+        let mut videoframe = VideoFrame::new(127, 96);
+        videoframe.write_calibration(1, AlgoFrame::RGB);
+
+        // Alternating cells between the finders, starting black next to the moat.
+        let timing = TIMING_CELL as u16;
+        let first = BORDER_CELLS as u16;
+        for (x, y, black) in [
+            (first, timing, true),
+            (first + 1, timing, false),
+            (timing, first, true),
+            (timing, first + 1, false),
+        ] {
+            let expected = if black { 0 } else { 255 };
+            assert_eq!(videoframe.read_coordinate_color(x, y).r, expected);
+        }
+
+        // The moat cell between a finder and its timing pattern stays white.
+        assert_eq!(videoframe.read_coordinate_color(first - 1, timing).r, 255);
+        assert_eq!(videoframe.read_coordinate_color(timing, first - 1).r, 255);
+        assert_eq!(
+            videoframe.read_coordinate_color(127 - first - 1, timing).r,
+            0
+        );
+        assert_eq!(videoframe.read_coordinate_color(127 - first, timing).r, 255);
+    }
+
     #[test]
     fn test_write_header_round_trips_into_content_cells() {
         let mut videoframe = VideoFrame::new(128, 128);
-        let header = FrameHeader::new(FrameType::Data, 7, &[1, 2, 3]);
+        let header = FrameHeader::new(FrameType::Data, 7, 0, 0, &[1, 2, 3]);
         videoframe.write_header(&header, 1);
 
         // Read the HEADER_BITS cells back and parse them.
         let bits: Vec<bool> = (0..crate::injectionextraction::HEADER_BITS)
             .map(|i| {
-                let (x, y) = content_cell_xy(i, 128, 1);
+                let (x, y) = content_cell_xy(i, 128, 128, 1, false);
                 let c = videoframe.read_coordinate_color(x, y);
                 // White (>=128 average) means bit set.
                 (c.r as u32 + c.g as u32 + c.b as u32) >= 382
@@ -259,7 +481,7 @@ mod videoframe_tests {
         let parsed = FrameHeader::from_bits(&bits).expect("header should parse");
         assert_eq!(parsed, header);
         // First content cell is just inside the border ring.
-        let (x, y) = content_cell_xy(0, 128, 1);
+        let (x, y) = content_cell_xy(0, 128, 128, 1, false);
         assert_eq!(x as usize, BORDER_CELLS);
         assert_eq!(y as usize, BORDER_CELLS);
     }
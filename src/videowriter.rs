@@ -0,0 +1,152 @@
+//! Writing frames to a lossless video file.
+
+use opencv::{
+    core::Size,
+    videoio::{VideoWriter, VideoWriterTrait, VideoWriterTraitConst},
+};
+use std::fs;
+
+use crate::error::HdmiError;
+use crate::injectionlogics::{file_to_sink, frames_to_sink, FrameSink};
+use crate::options::InjectOptions;
+use crate::videoframe::VideoFrame;
+
+/// Encode the file at `options.file_path` into the video at
+/// `options.output_video_file`: the Start frame, then the data frames.
+///
+/// The file is read one page at a time and each frame is written to the video as
+/// soon as it is rendered, so memory use stays at a few frames whatever the file
+/// size. Fountain mode is the exception: its symbols mix blocks from across the
+/// whole file, so the file bytes (not the frames) are buffered.
+pub fn file_to_video(options: &InjectOptions) -> Result<(), HdmiError> {
+    file_to_sink(options, VideoSink::create)?;
+    if options.show_progress {
+        eprintln!("Video saved:{}", options.output_video_file.as_str());
+    }
+    Ok(())
+}
+
+pub fn frames_to_video(options: InjectOptions, frames: Vec<VideoFrame>) -> Result<(), HdmiError> {
+    frames_to_sink(&options, frames, VideoSink::create(&options)?)?;
+    if options.show_progress {
+        eprintln!("Video saved:{}", options.output_video_file.as_str());
+    }
+    Ok(())
+}
+
+/// Lossless video file that frames are written to as they are produced.
+struct VideoSink {
+    video: VideoWriter,
+    path: String,
+}
+
+impl VideoSink {
+    /// Open `options.output_video_file` for writing, creating its folder.
+    fn create(options: &InjectOptions) -> Result<VideoSink, HdmiError> {
+        let open_error = |reason: String| HdmiError::VideoOpen {
+            path: options.output_video_file.clone(),
+            reason,
+        };
+        let frame_size = Size {
+            height: options.height as i32,
+            width: options.width as i32,
+        };
+
+        // Make sure the destination folder exists, otherwise the video writer
+        // silently fails to open the file.
+        if let Some(parent) = std::path::Path::new(&options.output_video_file).parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).map_err(|err| {
+                    open_error(format!(
+                        "unable to create output directory {:?}: {}",
+                        parent, err
+                    ))
+                })?;
+            }
+        }
+
+        // Fourcc is a code for video codecs. The data is encoded directly into pixel
+        // values, so the codec MUST be lossless otherwise the recovered bytes differ
+        // from the injected ones. FFV1 is a lossless codec; it requires a container
+        // such as .mkv or .avi (a lossy .mp4 would corrupt the data).
+        // See list of codec here: https://learn.fotoware.com/On-Premises/Getting_started/Metadata_in_the_FotoWare_system/04_Operators_to_search_in_specific_fields/FourCC_codes
+        // Careful, codec and file extension must match.
+        let fourcc = VideoWriter::fourcc('F', 'F', 'V', '1')
+            .map_err(|error| open_error(format!("unable to build the fourcc code: {:?}", error)))?;
+
+        let video = VideoWriter::new(
+            options.output_video_file.as_str(),
+            fourcc,
+            options.fps.into(),
+            frame_size,
+            true,
+        )
+        .map_err(|error_video| open_error(format!("error with video writer: {:?}", error_video)))?;
+
+        if !video.is_opened().map_err(|error| {
+            open_error(format!(
+                "error checking the video writer state: {:?}",
+                error
+            ))
+        })? {
+            return Err(open_error(
+                "the video writer did not open the file".to_string(),
+            ));
+        }
+        Ok(VideoSink {
+            video,
+            path: options.output_video_file.clone(),
+        })
+    }
+}
+
+impl FrameSink for VideoSink {
+    fn write(&mut self, frame: &VideoFrame) -> Result<(), HdmiError> {
+        self.video
+            .write(&frame.to_mat()?)
+            .map_err(|error| HdmiError::VideoWrite {
+                path: self.path.clone(),
+                reason: format!("{:?}", error),
+            })
+    }
+
+    fn finish(mut self) -> Result<(), HdmiError> {
+        self.video
+            .release()
+            .map_err(|error_release| HdmiError::VideoWrite {
+                path: self.path.clone(),
+                reason: format!("error saving the video: {:?}", error_release),
+            })
+    }
+}
+
+#[cfg(test)]
+mod videowriter_tests {
+    use super::*;
+    use crate::options::AlgoFrame;
+
+    fn opts(algo: AlgoFrame, width: u16, height: u16, size: u8) -> InjectOptions {
+        InjectOptions {
+            width,
+            height,
+            size,
+            algo,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_file_to_video_errors_for_missing_file() {
+        let mut options = opts(AlgoFrame::BW, 64, 64, 1);
+        let missing_path = std::env::temp_dir().join(format!(
+            "hdmift_missing_{}_{}.bin",
+            std::process::id(),
+            "file_to_video"
+        ));
+        options.file_path = missing_path.to_string_lossy().to_string();
+
+        let error = file_to_video(&options).unwrap_err();
+        assert!(matches!(error, HdmiError::ReadFile { .. }), "{error}");
+        assert!(error.to_string().contains("Unable to read file"), "{error}");
+    }
+}
@@ -0,0 +1,20 @@
+use crate::error::HdmiError;
+use crate::options::InjectOptions;
+use crate::videoframe::VideoFrame;
+
+/// Video writing requires OpenCV.
+///
+/// Enable the default `opencv-backend` feature to write frames to a video
+/// file. Frames are encoded in memory with `data_to_frames` in every build.
+pub fn frames_to_video(_options: InjectOptions, _frames: Vec<VideoFrame>) -> Result<(), HdmiError> {
+    Err(HdmiError::NoVideoBackend {
+        operation: "frames_to_video",
+    })
+}
+
+/// Video writing requires OpenCV.
+pub fn file_to_video(_options: &InjectOptions) -> Result<(), HdmiError> {
+    Err(HdmiError::NoVideoBackend {
+        operation: "file_to_video",
+    })
+}
@@ -6,14 +6,18 @@
 //! compression. We build frames with the real encoder, distort each frame, then
 //! feed them back through `register_frame` + `frames_to_data` and assert the
 //! original bytes are recovered exactly in black/white mode (the HDMI-grade mode).
+#![cfg(feature = "opencv-backend")]
 
 use hdmifiletransporter::{
-    create_starting_frame, data_to_frames, frames_to_data, options::AlgoFrame, register_frame,
-    ExtractOptions, InjectOptions, VideoFrame,
+    create_starting_frame, data_to_frames, estimate_geometry, frames_to_data,
+    options::{AlgoFrame, Binarization},
+    register_frame, register_frame_with, ExtractOptions, InjectOptions, VideoFrame,
+};
+use opencv::core::{
+    copy_make_border, Mat, Point2f, Scalar, Size, Vector, BORDER_CONSTANT, DECOMP_LU,
 };
-use opencv::core::{copy_make_border, Mat, Scalar, Size, Vector, BORDER_CONSTANT};
 use opencv::imgcodecs::{imdecode, imencode, IMREAD_COLOR, IMWRITE_JPEG_QUALITY};
-use opencv::imgproc::{resize, INTER_LINEAR};
+use opencv::imgproc::{get_perspective_transform_slice, resize, warp_perspective, INTER_LINEAR};
 use opencv::prelude::*;
 
 // Cells are 6x6 pixels so the 7-cell finder patterns (42 px) and their 6 px
@@ -24,34 +28,28 @@ const SIZE: u8 = 6;
 
 fn inject_options(algo: AlgoFrame) -> InjectOptions {
     InjectOptions {
-        file_path: String::new(),
-        output_video_file: String::new(),
-        fps: 30,
         width: WIDTH,
         height: HEIGHT,
         size: SIZE,
         algo,
-        show_progress: false,
+        ..Default::default()
     }
 }
 
 fn extract_options(algo: AlgoFrame) -> ExtractOptions {
     ExtractOptions {
-        video_file_path: String::new(),
-        extracted_file_path: String::new(),
-        fps: 30,
-        width: WIDTH,
-        height: HEIGHT,
-        size: SIZE,
-        algo,
-        show_progress: false,
+        width: Some(WIDTH),
+        height: Some(HEIGHT),
+        size: Some(SIZE),
+        algo: Some(algo),
+        ..Default::default()
     }
 }
 
 fn build_frames(data: &[u8], algo: AlgoFrame) -> Vec<VideoFrame> {
     let io = inject_options(algo);
-    let mut frames = vec![create_starting_frame(data.len() as u64, &io)];
-    frames.extend(data_to_frames(&io, data.to_vec()));
+    let mut frames = vec![create_starting_frame(data, &io).unwrap()];
+    frames.extend(data_to_frames(&io, data.to_vec()).unwrap());
     frames
 }
 
@@ -86,6 +84,38 @@ fn simulate_capture(canonical: &Mat) -> Mat {
     imdecode(&buf, IMREAD_COLOR).expect("jpeg decode")
 }
 
+/// Mimic a camera filming the screen off-axis: the frame lands on a white
+/// background as a trapezoid, its top edge narrower than its bottom one.
+fn simulate_keystone(canonical: &Mat) -> Mat {
+    let w = canonical.cols() as f32;
+    let h = canonical.rows() as f32;
+    let src = [
+        Point2f::new(0.0, 0.0),
+        Point2f::new(w, 0.0),
+        Point2f::new(0.0, h),
+        Point2f::new(w, h),
+    ];
+    let dst = [
+        Point2f::new(40.0, 20.0),
+        Point2f::new(w - 10.0, 30.0),
+        Point2f::new(10.0, h + 10.0),
+        Point2f::new(w + 30.0, h + 25.0),
+    ];
+    let transform = get_perspective_transform_slice(&src, &dst, DECOMP_LU).expect("homography");
+    let mut warped = Mat::default();
+    warp_perspective(
+        canonical,
+        &mut warped,
+        &transform,
+        Size::new(canonical.cols() + 60, canonical.rows() + 50),
+        INTER_LINEAR,
+        BORDER_CONSTANT,
+        Scalar::new(255.0, 255.0, 255.0, 0.0),
+    )
+    .expect("warp");
+    warped
+}
+
 #[test]
 fn test_clean_registration_round_trip_bw() {
     let data: Vec<u8> = (0..200u32).map(|i| (i * 7 % 251) as u8).collect();
@@ -94,12 +124,12 @@ fn test_clean_registration_round_trip_bw() {
     // Feed the canonical frames straight through registration (identity warp).
     let mut registered = Vec::new();
     for f in &frames {
-        let vf = register_frame(&f.image, WIDTH, HEIGHT, SIZE)
+        let vf = register_frame(&f.to_mat().unwrap(), WIDTH, HEIGHT, SIZE)
             .expect("markers must be found in a clean frame");
         registered.push(vf);
     }
 
-    let result = frames_to_data(&extract_options(AlgoFrame::BW), registered);
+    let result = frames_to_data(&extract_options(AlgoFrame::BW), registered).unwrap();
     assert_eq!(result, data);
 }
 
@@ -110,7 +140,7 @@ fn test_capture_simulation_bw_recovers_exact_bytes() {
 
     let mut registered = Vec::new();
     for f in &frames {
-        let perturbed = simulate_capture(&f.image);
+        let perturbed = simulate_capture(&f.to_mat().unwrap());
         if let Some(vf) = register_frame(&perturbed, WIDTH, HEIGHT, SIZE) {
             registered.push(vf);
         }
@@ -121,7 +151,7 @@ fn test_capture_simulation_bw_recovers_exact_bytes() {
         "registration should recover the Start frame and at least one data frame"
     );
 
-    let result = frames_to_data(&extract_options(AlgoFrame::BW), registered);
+    let result = frames_to_data(&extract_options(AlgoFrame::BW), registered).unwrap();
     assert_eq!(
         result, data,
         "BW mode must recover the exact bytes through a simulated capture"
@@ -139,7 +169,7 @@ fn test_capture_simulation_quantized_2_levels_recovers_exact_bytes() {
 
     let mut registered = Vec::new();
     for f in &frames {
-        let perturbed = simulate_capture(&f.image);
+        let perturbed = simulate_capture(&f.to_mat().unwrap());
         if let Some(vf) = register_frame(&perturbed, WIDTH, HEIGHT, SIZE) {
             registered.push(vf);
         }
@@ -150,7 +180,7 @@ fn test_capture_simulation_quantized_2_levels_recovers_exact_bytes() {
         "registration should recover the Start frame and at least one data frame"
     );
 
-    let result = frames_to_data(&extract_options(algo), registered);
+    let result = frames_to_data(&extract_options(algo), registered).unwrap();
     assert_eq!(
         result, data,
         "Quantized(2) must recover the exact bytes through a simulated capture"
@@ -164,7 +194,7 @@ fn assert_brightness_capture_round_trip(levels: u32) {
 
     let mut registered = Vec::new();
     for f in &frames {
-        let perturbed = simulate_capture(&f.image);
+        let perturbed = simulate_capture(&f.to_mat().unwrap());
         if let Some(vf) = register_frame(&perturbed, WIDTH, HEIGHT, SIZE) {
             registered.push(vf);
         }
@@ -175,7 +205,7 @@ fn assert_brightness_capture_round_trip(levels: u32) {
         "registration should recover the Start frame and at least one data frame"
     );
 
-    let result = frames_to_data(&extract_options(algo), registered);
+    let result = frames_to_data(&extract_options(algo), registered).unwrap();
     assert_eq!(
         result, data,
         "Brightness({levels}) must recover the exact bytes through a simulated capture"
@@ -194,3 +224,172 @@ fn test_capture_simulation_brightness_4_levels_recovers_exact_bytes() {
     // brightness mode versus packing the same levels into the subsampled chroma.
     assert_brightness_capture_round_trip(4);
 }
+
+#[test]
+fn test_estimate_geometry_of_clean_frame() {
+    let frames = build_frames(&[42u8; 100], AlgoFrame::BW);
+    for f in &frames {
+        assert_eq!(
+            estimate_geometry(&f.to_mat().unwrap()),
+            Some((WIDTH, HEIGHT, SIZE))
+        );
+    }
+}
+
+#[test]
+fn test_capture_simulation_with_estimated_geometry() {
+    let data: Vec<u8> = (0..200u32).map(|i| (i * 17 % 251) as u8).collect();
+    let frames = build_frames(&data, AlgoFrame::BW);
+
+    // No geometry given: estimate it from each captured frame, then register.
+    let mut registered = Vec::new();
+    for f in &frames {
+        let perturbed = simulate_capture(&f.to_mat().unwrap());
+        let (width, height, size) =
+            estimate_geometry(&perturbed).expect("geometry must be estimated from a capture");
+        assert_eq!((width, height, size), (WIDTH, HEIGHT, SIZE));
+        if let Some(vf) = register_frame(&perturbed, width, height, size) {
+            registered.push(vf);
+        }
+    }
+
+    let options = ExtractOptions {
+        width: None,
+        height: None,
+        size: None,
+        algo: None,
+        ..extract_options(AlgoFrame::BW)
+    };
+    let result = frames_to_data(&options, registered).unwrap();
+    assert_eq!(result, data);
+}
+
+#[test]
+fn test_keystone_capture_is_registered_with_the_alignment_marker() {
+    let data: Vec<u8> = (0..200u32).map(|i| (i * 19 % 251) as u8).collect();
+    let frames = build_frames(&data, AlgoFrame::BW);
+
+    // Three finders alone cannot undo a perspective warp; the fourth marker must.
+    let registered: Vec<VideoFrame> = frames
+        .iter()
+        .map(|f| {
+            let captured = simulate_keystone(&f.to_mat().unwrap());
+            register_frame(&captured, WIDTH, HEIGHT, SIZE).expect("markers must be found")
+        })
+        .collect();
+
+    let result = frames_to_data(&extract_options(AlgoFrame::BW), registered).unwrap();
+    assert_eq!(result, data);
+}
+
+#[test]
+fn test_registration_falls_back_to_the_finders_without_the_alignment_marker() {
+    let data: Vec<u8> = (0..200u32).map(|i| (i * 23 % 251) as u8).collect();
+    let frames = build_frames(&data, AlgoFrame::BW);
+
+    let mut registered = Vec::new();
+    for f in &frames {
+        // Paint over the bottom-right corner of the ring, as if a frame from
+        // before the marker existed had been captured.
+        let mut frame = f.clone();
+        let corner = u16::from(SIZE) * 9;
+        frame
+            .buffer
+            .fill_rect(WIDTH - corner, HEIGHT - corner, corner, corner, [255; 3]);
+        let perturbed = simulate_capture(&frame.to_mat().unwrap());
+        if let Some(vf) = register_frame(&perturbed, WIDTH, HEIGHT, SIZE) {
+            registered.push(vf);
+        }
+    }
+
+    let result = frames_to_data(&extract_options(AlgoFrame::BW), registered).unwrap();
+    assert_eq!(result, data);
+}
+
+/// `frame` with each pixel channel mapped through `light(x, value)`.
+fn relight(frame: &VideoFrame, light: impl Fn(u16, f32) -> f32) -> VideoFrame {
+    let mut lit = frame.clone();
+    for y in 0..frame.height() {
+        for x in 0..frame.width() {
+            let rgb = frame
+                .buffer
+                .pixel(x, y)
+                .map(|v| light(x, f32::from(v)).round() as u8);
+            lit.buffer.set_pixel(x, y, rgb);
+        }
+    }
+    lit
+}
+
+/// Register every frame of `captured` with `binarization` tried first, undo
+/// `restore` on the result, and decode it.
+fn assert_registered_with(
+    captured: &[VideoFrame],
+    binarization: Binarization,
+    expected: impl Fn(Binarization) -> bool,
+    restore: impl Fn(u16, f32) -> f32,
+    data: &[u8],
+) {
+    let registered: Vec<VideoFrame> = captured
+        .iter()
+        .map(|frame| {
+            let (vf, used) =
+                register_frame_with(&frame.to_mat().unwrap(), WIDTH, HEIGHT, SIZE, binarization)
+                    .expect("markers must be found");
+            assert!(expected(used), "registered with {used}");
+            relight(&vf, &restore)
+        })
+        .collect();
+    let result = frames_to_data(&extract_options(AlgoFrame::BW), registered).unwrap();
+    assert_eq!(result, data);
+}
+
+#[test]
+fn test_dim_capture_falls_back_to_otsu() {
+    let data: Vec<u8> = (0..200u32).map(|i| (i * 29 % 251) as u8).collect();
+    // Limited range and low brightness: even white stays below mid-grey, so
+    // the fixed threshold sees one dark frame without any marker.
+    let captured: Vec<VideoFrame> = build_frames(&data, AlgoFrame::BW)
+        .iter()
+        .map(|frame| relight(frame, |_, v| 16.0 + v * 0.35))
+        .collect();
+    // `register_frame` retries too.
+    for frame in &captured {
+        assert!(register_frame(&frame.to_mat().unwrap(), WIDTH, HEIGHT, SIZE).is_some());
+    }
+    assert_registered_with(
+        &captured,
+        Binarization::Fixed,
+        |used| used == Binarization::Otsu,
+        |_, v| (v - 16.0) / 0.35,
+        &data,
+    );
+}
+
+#[test]
+fn test_unevenly_lit_capture_is_registered_with_an_adaptive_threshold() {
+    let data: Vec<u8> = (0..200u32).map(|i| (i * 31 % 251) as u8).collect();
+    // Backlighting falling off towards the left edge.
+    let gain = |x: u16| 0.2 + 0.8 * f32::from(x) / f32::from(WIDTH);
+    let captured: Vec<VideoFrame> = build_frames(&data, AlgoFrame::BW)
+        .iter()
+        .map(|frame| relight(frame, |x, v| v * gain(x)))
+        .collect();
+    for binarization in [Binarization::AdaptiveMean, Binarization::AdaptiveGaussian] {
+        assert_registered_with(
+            &captured,
+            binarization,
+            |used| used == binarization,
+            |x, v| (v / gain(x)).min(255.0),
+            &data,
+        );
+    }
+    // Whatever the first strategy, some fallback finds the markers.
+    assert_registered_with(
+        &captured,
+        Binarization::Fixed,
+        |_| true,
+        |x, v| (v / gain(x)).min(255.0),
+        &data,
+    );
+}
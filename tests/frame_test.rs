@@ -1,7 +1,16 @@
+#![cfg(feature = "opencv-backend")]
+
+use clap::Parser;
 use hdmifiletransporter::{
-    create_starting_frame, data_to_frames, execute_with_video_options, frames_to_data,
-    options::AlgoFrame, ExtractOptions, InjectOptions, VideoOptions,
+    create_starting_frame, data_to_files, data_to_frames, execute_with_video_options,
+    extract_options, frames_to_data, frames_to_images, frames_to_raw, images_to_frames,
+    missing_report_path, options::AlgoFrame, raw_to_data, read_page_list, source_to_data,
+    source_to_file, state_file_path, CliData, Compression, ExtractOptions, FrameSource, HdmiError,
+    InjectOptions, PixelFormat, RawVideoFormat, VideoOptions, MANIFEST_FILE,
 };
+use opencv::core::Mat;
+use opencv::prelude::*;
+use opencv::videoio::{VideoCapture, CAP_ANY};
 use std::fs;
 use std::path::PathBuf;
 
@@ -12,14 +21,13 @@ const TEST_HEIGHT: u16 = 64;
 
 fn get_unit_test_injection_option(size: u8, width: u16, height: u16) -> InjectOptions {
     InjectOptions {
-        fps: 30,
         width,
         height,
         size,
         algo: AlgoFrame::BW,
-        show_progress: false,
         file_path: "".to_string(),
         output_video_file: "".to_string(),
+        ..Default::default()
     }
 }
 
@@ -27,12 +35,11 @@ fn get_unit_test_extraction_option(size: u8, width: u16, height: u16) -> Extract
     ExtractOptions {
         video_file_path: "".to_string(),
         extracted_file_path: "".to_string(),
-        fps: 30,
-        width,
-        height,
-        size,
-        algo: AlgoFrame::BW,
-        show_progress: false,
+        width: Some(width),
+        height: Some(height),
+        size: Some(size),
+        algo: Some(AlgoFrame::BW),
+        ..Default::default()
     }
 }
 
@@ -56,14 +63,13 @@ fn test_frames_to_data_all_frames_good_order() {
 
     // Enough bytes to span several data frames.
     let number_bytes = 1000u64;
-    let starting_frame = create_starting_frame(number_bytes, &inject_options);
-
     let frame_data = get_unit_test_data(number_bytes);
-    let frames = data_to_frames(&inject_options, frame_data);
+    let starting_frame = create_starting_frame(&frame_data, &inject_options).unwrap();
+    let frames = data_to_frames(&inject_options, frame_data).unwrap();
     let mut merged_frames = vec![starting_frame];
     merged_frames.extend(frames);
 
-    let data_from_frames = frames_to_data(&extract_options, merged_frames);
+    let data_from_frames = frames_to_data(&extract_options, merged_frames).unwrap();
 
     assert_eq!(data_from_frames.len(), number_bytes as usize);
     // The extracted bytes must be identical to what was injected, not only the same length.
@@ -77,10 +83,9 @@ fn test_frames_to_data_all_frames_mixed_order() {
     let extract_options = get_unit_test_extraction_option(size, TEST_WIDTH, TEST_HEIGHT);
 
     let number_bytes = 1000u64;
-    let starting_frame = create_starting_frame(number_bytes, &inject_options);
-
     let frame_data = get_unit_test_data(number_bytes);
-    let frames = data_to_frames(&inject_options, frame_data);
+    let starting_frame = create_starting_frame(&frame_data, &inject_options).unwrap();
+    let frames = data_to_frames(&inject_options, frame_data).unwrap();
 
     // Mix the order of the frames.
     let mut merged_frames = vec![starting_frame];
@@ -88,7 +93,7 @@ fn test_frames_to_data_all_frames_mixed_order() {
     swap_elements(&mut merged_frames, 0, 1);
     swap_elements(&mut merged_frames, 2, 3);
 
-    let data_from_frames = frames_to_data(&extract_options, merged_frames);
+    let data_from_frames = frames_to_data(&extract_options, merged_frames).unwrap();
 
     assert_eq!(data_from_frames.len(), number_bytes as usize);
     // Page numbers must reassemble the data in the right order despite shuffling.
@@ -102,16 +107,15 @@ fn test_frames_to_data_all_frames_repeting_frame() {
     let extract_options = get_unit_test_extraction_option(size, TEST_WIDTH, TEST_HEIGHT);
 
     let number_bytes = 1000u64;
-    let starting_frame = create_starting_frame(number_bytes, &inject_options);
-
     let frame_data = get_unit_test_data(number_bytes);
-    let frames = data_to_frames(&inject_options, frame_data);
+    let starting_frame = create_starting_frame(&frame_data, &inject_options).unwrap();
+    let frames = data_to_frames(&inject_options, frame_data).unwrap();
     let clone1 = frames[0].clone();
     let mut merged_frames = vec![starting_frame];
     merged_frames.extend(frames);
     merged_frames.push(clone1); // Add the first frame twice
 
-    let data_from_frames = frames_to_data(&extract_options, merged_frames);
+    let data_from_frames = frames_to_data(&extract_options, merged_frames).unwrap();
 
     assert_eq!(data_from_frames.len(), number_bytes as usize);
     // The duplicated frame must be de-duplicated and not corrupt the content.
@@ -119,28 +123,30 @@ fn test_frames_to_data_all_frames_repeting_frame() {
 }
 
 #[test]
-#[should_panic]
 fn test_frames_to_data_missing_one_frame() {
     let size = 1;
     let inject_options = get_unit_test_injection_option(size, TEST_WIDTH, TEST_HEIGHT);
     let extract_options = get_unit_test_extraction_option(size, TEST_WIDTH, TEST_HEIGHT);
 
     let number_bytes = 1000u64;
-    let starting_frame = create_starting_frame(number_bytes, &inject_options);
-
     let frame_data = get_unit_test_data(number_bytes);
-    let frames = data_to_frames(&inject_options, frame_data);
+    let starting_frame = create_starting_frame(&frame_data, &inject_options).unwrap();
+    let frames = data_to_frames(&inject_options, frame_data).unwrap();
     let mut merged_frames = vec![starting_frame];
     merged_frames.extend(frames);
     merged_frames.remove(2); // Drop a data frame -> a page is missing.
 
-    let data_from_frames = frames_to_data(&extract_options, merged_frames);
+    let result = frames_to_data(&extract_options, merged_frames);
 
-    assert_eq!(data_from_frames.len(), number_bytes as usize)
+    match result {
+        Err(HdmiError::MissingPages { missing, .. }) => assert_eq!(missing, vec![1]),
+        other => panic!("expected a missing page, got {:?}", other.err()),
+    }
 }
 
-/// Inject `data` into a real video file and extract it back, asserting the round
-/// trip reproduces the bytes exactly. This proves the codec used by
+/// Inject `data` into a real video file (or, when `destination` has no
+/// extension, a directory of PNG frames) and extract it back, asserting the
+/// round trip reproduces the bytes exactly. This proves the codec used by
 /// `frames_to_video` is lossless and that registration works on clean frames.
 fn assert_video_round_trip(
     algo: AlgoFrame,
@@ -149,6 +155,7 @@ fn assert_video_round_trip(
     size: u8,
     data: Vec<u8>,
     label: &str,
+    destination: &str,
 ) {
     let dir: PathBuf = std::env::temp_dir().join(format!(
         "hdmift_{}_{}_{}",
@@ -158,7 +165,7 @@ fn assert_video_round_trip(
     ));
     fs::create_dir_all(&dir).expect("create temp dir");
     let input_path = dir.join("input.bin");
-    let video_path = dir.join("video.mkv");
+    let video_path = dir.join(destination);
     let output_path = dir.join("output.bin");
 
     fs::write(&input_path, &data).expect("write input file");
@@ -166,24 +173,22 @@ fn assert_video_round_trip(
     execute_with_video_options(VideoOptions::InjectInVideo(InjectOptions {
         file_path: input_path.to_string_lossy().to_string(),
         output_video_file: video_path.to_string_lossy().to_string(),
-        fps: 30,
         width,
         height,
         size,
         algo,
-        show_progress: false,
+        ..Default::default()
     }))
     .expect("injection should succeed");
 
     execute_with_video_options(VideoOptions::ExtractFromVideo(ExtractOptions {
         video_file_path: video_path.to_string_lossy().to_string(),
         extracted_file_path: output_path.to_string_lossy().to_string(),
-        fps: 30,
-        width,
-        height,
-        size,
-        algo,
-        show_progress: false,
+        width: Some(width),
+        height: Some(height),
+        size: Some(size),
+        algo: Some(algo),
+        ..Default::default()
     }))
     .expect("extraction should succeed");
 
@@ -203,17 +208,100 @@ const RT_SIZE: u8 = 6;
 #[test]
 fn test_video_round_trip_bw() {
     let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
-    assert_video_round_trip(AlgoFrame::BW, RT_WIDTH, RT_HEIGHT, RT_SIZE, data, "bw");
+    assert_video_round_trip(
+        AlgoFrame::BW,
+        RT_WIDTH,
+        RT_HEIGHT,
+        RT_SIZE,
+        data,
+        "bw",
+        "video.mkv",
+    );
 }
 
 #[test]
 fn test_video_round_trip_rgb() {
     let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
-    assert_video_round_trip(AlgoFrame::RGB, RT_WIDTH, RT_HEIGHT, RT_SIZE, data, "rgb");
+    assert_video_round_trip(
+        AlgoFrame::RGB,
+        RT_WIDTH,
+        RT_HEIGHT,
+        RT_SIZE,
+        data,
+        "rgb",
+        "video.mkv",
+    );
+}
+
+#[test]
+fn test_image_sequence_round_trip_rgb() {
+    let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    assert_video_round_trip(
+        AlgoFrame::RGB,
+        RT_WIDTH,
+        RT_HEIGHT,
+        RT_SIZE,
+        data,
+        "png_rgb",
+        "frames",
+    );
+}
+
+#[test]
+fn test_image_sequence_lists_frames_in_a_manifest() {
+    let dir = std::env::temp_dir().join(format!("hdmift_png_manifest_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let mut inject_options = get_unit_test_injection_option(RT_SIZE, RT_WIDTH, RT_HEIGHT);
+    inject_options.output_video_file = dir.to_string_lossy().to_string();
+    let data = get_unit_test_data(500);
+    let mut frames = vec![create_starting_frame(&data, &inject_options).unwrap()];
+    frames.extend(data_to_frames(&inject_options, data.clone()).unwrap());
+    let frame_count = frames.len();
+    frames_to_images(inject_options, frames).expect("write the frames");
+
+    let manifest = fs::read_to_string(dir.join(MANIFEST_FILE)).expect("read the manifest");
+    assert!(
+        manifest.contains(&format!("frames={}", frame_count)),
+        "{manifest}"
+    );
+    assert!(dir.join("frame_000000.png").is_file());
+
+    // A stray image that the manifest does not list is ignored.
+    fs::copy(dir.join("frame_000000.png"), dir.join("zz_extra.png")).unwrap();
+    let mut extract_options = get_unit_test_extraction_option(RT_SIZE, RT_WIDTH, RT_HEIGHT);
+    extract_options.video_file_path = dir.to_string_lossy().to_string();
+    let received = images_to_frames(&extract_options).expect("read the frames");
+    assert_eq!(received.len(), frame_count);
+    assert_eq!(frames_to_data(&extract_options, received).unwrap(), data);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_raw_pipe_frames_are_registered_and_decoded() {
+    let inject_options = get_unit_test_injection_option(RT_SIZE, RT_WIDTH, RT_HEIGHT);
+    let data = get_unit_test_data(800);
+    let mut frames = vec![create_starting_frame(&data, &inject_options).unwrap()];
+    frames.extend(data_to_frames(&inject_options, data.clone()).unwrap());
+    let mut stream = Vec::new();
+    frames_to_raw(&inject_options, frames, &mut stream, PixelFormat::Bgr24).unwrap();
+
+    // No geometry: it is probed from the Start frame, as for a video file.
+    let mut extract_options = get_unit_test_extraction_option(RT_SIZE, RT_WIDTH, RT_HEIGHT);
+    extract_options.width = None;
+    extract_options.height = None;
+    extract_options.size = None;
+    let format = RawVideoFormat {
+        pixel_format: PixelFormat::Bgr24,
+        width: RT_WIDTH,
+        height: RT_HEIGHT,
+    };
+    assert_eq!(
+        raw_to_data(stream.as_slice(), format, &extract_options).unwrap(),
+        data
+    );
 }
 
 #[test]
-#[should_panic(expected = "Instruction not found while extracting data from video")]
 fn test_frames_to_data_missing_instruction_frame() {
     let size = 1;
     let inject_options = get_unit_test_injection_option(size, TEST_WIDTH, TEST_HEIGHT);
@@ -221,10 +309,432 @@ fn test_frames_to_data_missing_instruction_frame() {
 
     let number_bytes = 1000u64;
     let frame_data = get_unit_test_data(number_bytes);
-    let frames = data_to_frames(&inject_options, frame_data);
+    let frames = data_to_frames(&inject_options, frame_data).unwrap();
 
     // No Start frame -> the total byte count is unknown.
-    let data_from_frames = frames_to_data(&extract_options, frames);
+    let result = frames_to_data(&extract_options, frames);
 
-    assert_eq!(data_from_frames.len(), number_bytes as usize)
+    assert!(
+        matches!(result, Err(HdmiError::MissingStart)),
+        "{:?}",
+        result.err()
+    );
+}
+
+/// File-backed stand-in for a live capture device: plays a video in a loop, as
+/// the sender does over HDMI, joining it part-way through, and counts the
+/// frames handed out. It never runs dry on its own, so decoding only ends when
+/// the reassembler has every page (or the safety limit is hit).
+struct LoopingCapture {
+    path: String,
+    video: VideoCapture,
+    served: usize,
+    limit: usize,
+}
+
+impl LoopingCapture {
+    fn new(path: &str, skip: usize, limit: usize) -> LoopingCapture {
+        let mut capture = LoopingCapture {
+            path: path.to_string(),
+            video: VideoCapture::from_file(path, CAP_ANY).expect("open video"),
+            served: 0,
+            limit,
+        };
+        for _ in 0..skip {
+            capture.read_looped();
+        }
+        capture
+    }
+
+    fn read_looped(&mut self) -> Mat {
+        let mut frame = Mat::default();
+        if !self.video.read(&mut frame).expect("read frame") || frame.cols() == 0 {
+            self.video = VideoCapture::from_file(&self.path, CAP_ANY).expect("reopen video");
+            self.video.read(&mut frame).expect("read frame");
+        }
+        frame
+    }
+}
+
+impl FrameSource for LoopingCapture {
+    fn next_image(&mut self) -> Option<Mat> {
+        if self.served >= self.limit {
+            return None;
+        }
+        self.served += 1;
+        Some(self.read_looped())
+    }
+}
+
+#[test]
+fn test_live_source_stops_once_every_page_is_received() {
+    let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    let dir: PathBuf = std::env::temp_dir().join(format!("hdmift_live_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("create temp dir");
+    let input_path = dir.join("input.bin");
+    let video_path = dir.join("video.mkv");
+    fs::write(&input_path, &data).expect("write input file");
+
+    let mut inject_options = get_unit_test_injection_option(RT_SIZE, RT_WIDTH, RT_HEIGHT);
+    inject_options.file_path = input_path.to_string_lossy().to_string();
+    inject_options.output_video_file = video_path.to_string_lossy().to_string();
+    execute_with_video_options(VideoOptions::InjectInVideo(inject_options.clone()))
+        .expect("injection should succeed");
+
+    // Start, metadata and data frames in the video; join the loop after the
+    // Start frame so the pages before it have to come around again.
+    let video_frames = 2 + data_to_frames(&inject_options, data.clone()).unwrap().len();
+    assert!(video_frames >= 3);
+    let mut source = LoopingCapture::new(&video_path.to_string_lossy(), 2, 10 * video_frames);
+    let extract_options = ExtractOptions {
+        width: None,
+        height: None,
+        size: None,
+        algo: None,
+        ..get_unit_test_extraction_option(RT_SIZE, RT_WIDTH, RT_HEIGHT)
+    };
+    let extracted = source_to_data(&mut source, &extract_options).expect("every page received");
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(extracted, data);
+    // One pass to find the Start frame, at most one more to collect the rest.
+    assert!(
+        source.served <= 2 * video_frames,
+        "served {}",
+        source.served
+    );
+}
+
+/// Captures played one after the other.
+struct Spliced(Vec<LoopingCapture>);
+
+impl FrameSource for Spliced {
+    fn next_image(&mut self) -> Option<Mat> {
+        while !self.0.is_empty() {
+            if let Some(image) = self.0[0].next_image() {
+                return Some(image);
+            }
+            self.0.remove(0);
+        }
+        None
+    }
+}
+
+#[test]
+fn test_extraction_resumes_from_saved_state_on_a_second_capture() {
+    let data: Vec<u8> = (0..3000u32).map(|i| (i % 253) as u8).collect();
+    let dir: PathBuf = std::env::temp_dir().join(format!("hdmift_resume_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("create temp dir");
+    let input_path = dir.join("input.bin");
+    let video_path = dir.join("video.mkv");
+    let output_path = dir.join("output.bin");
+    fs::write(&input_path, &data).expect("write input file");
+
+    let mut inject_options = get_unit_test_injection_option(RT_SIZE, RT_WIDTH, RT_HEIGHT);
+    inject_options.file_path = input_path.to_string_lossy().to_string();
+    inject_options.output_video_file = video_path.to_string_lossy().to_string();
+    execute_with_video_options(VideoOptions::InjectInVideo(inject_options.clone()))
+        .expect("injection should succeed");
+    let video_frames = 2 + data_to_frames(&inject_options, data.clone()).unwrap().len();
+    let half = video_frames / 2;
+
+    let extract_options = ExtractOptions {
+        extracted_file_path: output_path.to_string_lossy().to_string(),
+        width: None,
+        height: None,
+        size: None,
+        algo: None,
+        ..get_unit_test_extraction_option(RT_SIZE, RT_WIDTH, RT_HEIGHT)
+    };
+    let state_path = state_file_path(&extract_options.extracted_file_path).unwrap();
+
+    // First capture: the Start frame and the first half of the pages only.
+    let video = video_path.to_string_lossy().to_string();
+    let first = source_to_data(LoopingCapture::new(&video, 0, half), &extract_options);
+    assert!(
+        matches!(first, Err(HdmiError::MissingPages { .. })),
+        "an incomplete capture must fail"
+    );
+    assert!(state_path.exists(), "the verified pages must be saved");
+
+    // Second capture: the Start frame, then the second half only. The pages of
+    // the first capture are not seen again.
+    let second = Spliced(vec![
+        LoopingCapture::new(&video, 0, 1),
+        LoopingCapture::new(&video, half, video_frames - half),
+    ]);
+    let extracted = source_to_file(second, &extract_options).expect("every page received");
+    assert_eq!(extracted.data, data);
+    assert!(
+        state_path.exists(),
+        "the state is kept until the file is written"
+    );
+    data_to_files(&extract_options, extracted).expect("write the output file");
+    assert_eq!(fs::read(&output_path).expect("read output file"), data);
+    assert!(
+        !state_path.exists(),
+        "the state is dropped once the file is written"
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_repair_video_completes_an_incomplete_extraction() {
+    let data: Vec<u8> = (0..3000u32).map(|i| (i % 247) as u8).collect();
+    let dir: PathBuf = std::env::temp_dir().join(format!("hdmift_repair_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("create temp dir");
+    let input_path = dir.join("input.bin");
+    let video_path = dir.join("video.mkv");
+    let repair_path = dir.join("repair.mkv");
+    let output_path = dir.join("output.bin");
+    fs::write(&input_path, &data).expect("write input file");
+
+    let mut inject_options = get_unit_test_injection_option(RT_SIZE, RT_WIDTH, RT_HEIGHT);
+    inject_options.file_path = input_path.to_string_lossy().to_string();
+    inject_options.output_video_file = video_path.to_string_lossy().to_string();
+    execute_with_video_options(VideoOptions::InjectInVideo(inject_options.clone()))
+        .expect("injection should succeed");
+    let pages = data_to_frames(&inject_options, data.clone()).unwrap().len();
+    assert!(pages >= 3);
+
+    // A capture that only saw the Start frame, the metadata frame and the first
+    // page.
+    let extract_options = ExtractOptions {
+        video_file_path: repair_path.to_string_lossy().to_string(),
+        extracted_file_path: output_path.to_string_lossy().to_string(),
+        width: None,
+        height: None,
+        size: None,
+        algo: None,
+        ..get_unit_test_extraction_option(RT_SIZE, RT_WIDTH, RT_HEIGHT)
+    };
+    let video = video_path.to_string_lossy().to_string();
+    let first = source_to_data(LoopingCapture::new(&video, 0, 3), &extract_options);
+    assert!(
+        matches!(first, Err(HdmiError::MissingPages { .. })),
+        "an incomplete capture must fail"
+    );
+    let report_path = missing_report_path(&extract_options.extracted_file_path).unwrap();
+    let missing = read_page_list(&report_path.to_string_lossy()).expect("read the report");
+    assert_eq!(missing, (1..pages as u64).collect::<Vec<u64>>());
+
+    // The repair video holds the Start and metadata frames and the missing
+    // pages only.
+    inject_options.output_video_file = repair_path.to_string_lossy().to_string();
+    inject_options.pages = Some(missing);
+    execute_with_video_options(VideoOptions::InjectInVideo(inject_options))
+        .expect("repair injection should succeed");
+    let mut repair = VideoCapture::from_file(&repair_path.to_string_lossy(), CAP_ANY)
+        .expect("open the repair video");
+    let mut repair_frames = 0;
+    let mut frame = Mat::default();
+    while repair.read(&mut frame).expect("read frame") && frame.cols() > 0 {
+        repair_frames += 1;
+    }
+    assert_eq!(repair_frames, 1 + pages);
+
+    execute_with_video_options(VideoOptions::ExtractFromVideo(extract_options))
+        .expect("extraction from the repair video should succeed");
+    assert_eq!(fs::read(&output_path).expect("read output file"), data);
+    assert!(!report_path.exists(), "the report is dropped once complete");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// Inject options parsed from an `inject` command line, as the CLI does.
+fn cli_inject_options(args: &[&str]) -> InjectOptions {
+    let command = ["hdmifiletransporter", "-m", "inject"].iter().chain(args);
+    match extract_options(CliData::parse_from(command)).expect("valid inject options") {
+        VideoOptions::InjectInVideo(options) => options,
+        VideoOptions::ExtractFromVideo(_) => panic!("Expected inject options"),
+    }
+}
+
+#[test]
+fn test_repair_video_from_a_report_keeps_the_session_of_the_transfer() {
+    let data: Vec<u8> = (0..3000u32).map(|i| (i % 241) as u8).collect();
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("hdmift_repair_session_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("create temp dir");
+    let input = dir.join("input.bin").to_string_lossy().to_string();
+    let video = dir.join("video.mkv").to_string_lossy().to_string();
+    let repair = dir.join("repair.mkv").to_string_lossy().to_string();
+    let output = dir.join("output.bin").to_string_lossy().to_string();
+    fs::write(&input, &data).expect("write input file");
+    let geometry = [
+        "-s",
+        &RT_SIZE.to_string(),
+        "-w",
+        &RT_WIDTH.to_string(),
+        "-g",
+        &RT_HEIGHT.to_string(),
+        "-a",
+        "bw",
+    ]
+    .map(str::to_string);
+    let geometry: Vec<&str> = geometry.iter().map(String::as_str).collect();
+
+    // The original transfer, in a session drawn by the CLI.
+    let original = cli_inject_options(&[&["-i", &input, "-o", &video], &geometry[..]].concat());
+    execute_with_video_options(VideoOptions::InjectInVideo(original.clone()))
+        .expect("injection should succeed");
+    let pages = data_to_frames(&original, data.clone()).unwrap().len();
+    let extract_options = ExtractOptions {
+        extracted_file_path: output.clone(),
+        width: None,
+        height: None,
+        size: None,
+        algo: None,
+        ..get_unit_test_extraction_option(RT_SIZE, RT_WIDTH, RT_HEIGHT)
+    };
+    let first = source_to_data(LoopingCapture::new(&video, 0, 3), &extract_options);
+    assert!(matches!(first, Err(HdmiError::MissingPages { .. })));
+
+    // The repair video, from the report alone: no `--session`.
+    let report = missing_report_path(&output).unwrap();
+    let report = report.to_string_lossy();
+    let repair_options = cli_inject_options(
+        &[
+            &["-i", &input, "-o", &repair, "--pages", &report],
+            &geometry[..],
+        ]
+        .concat(),
+    );
+    assert_eq!(repair_options.session, original.session);
+    execute_with_video_options(VideoOptions::InjectInVideo(repair_options))
+        .expect("repair injection should succeed");
+
+    // A capture that joins on the Start frame of the original loop, then sees
+    // the repair video past its own Start frame: its pages belong to the
+    // session locked onto.
+    let capture = Spliced(vec![
+        LoopingCapture::new(&video, 0, 1),
+        LoopingCapture::new(&repair, 1, pages),
+    ]);
+    let extracted = source_to_data(capture, &extract_options).expect("every page received");
+    assert_eq!(extracted, data);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_extraction_into_a_directory_restores_the_file_name_and_mtime() {
+    let data: Vec<u8> = (0..2000u32).map(|i| (i % 239) as u8).collect();
+    let dir: PathBuf = std::env::temp_dir().join(format!("hdmift_meta_{}", std::process::id()));
+    let out_dir = dir.join("received");
+    fs::create_dir_all(&out_dir).expect("create temp dirs");
+    let input_path = dir.join("notes.txt");
+    let video_path = dir.join("video.mkv");
+    fs::write(&input_path, &data).expect("write input file");
+    let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+    fs::File::options()
+        .write(true)
+        .open(&input_path)
+        .and_then(|file| file.set_modified(mtime))
+        .expect("set the input mtime");
+
+    let mut inject_options = get_unit_test_injection_option(RT_SIZE, RT_WIDTH, RT_HEIGHT);
+    inject_options.file_path = input_path.to_string_lossy().to_string();
+    inject_options.output_video_file = video_path.to_string_lossy().to_string();
+    execute_with_video_options(VideoOptions::InjectInVideo(inject_options))
+        .expect("injection should succeed");
+
+    // `-o` names a directory: the file comes back under its own name.
+    let extract_options = ExtractOptions {
+        video_file_path: video_path.to_string_lossy().to_string(),
+        extracted_file_path: out_dir.to_string_lossy().to_string(),
+        ..get_unit_test_extraction_option(RT_SIZE, RT_WIDTH, RT_HEIGHT)
+    };
+    execute_with_video_options(VideoOptions::ExtractFromVideo(extract_options))
+        .expect("extraction should succeed");
+
+    let output_path = out_dir.join("notes.txt");
+    let extracted = fs::read(&output_path).expect("read the restored file");
+    let modified = fs::metadata(&output_path)
+        .and_then(|metadata| metadata.modified())
+        .expect("read the restored mtime");
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(extracted, data);
+    assert_eq!(modified, mtime);
+}
+
+#[test]
+fn test_directory_is_rebuilt_under_the_output_directory() {
+    let dir: PathBuf = std::env::temp_dir().join(format!("hdmift_tree_{}", std::process::id()));
+    let tree = dir.join("project");
+    fs::create_dir_all(tree.join("src")).expect("create the input tree");
+    let big: Vec<u8> = (0..3000u32).map(|i| (i % 233) as u8).collect();
+    fs::write(tree.join("src/big.bin"), &big).expect("write input file");
+    fs::write(tree.join("README"), b"read me").expect("write input file");
+    let extra = dir.join("extra.txt");
+    fs::write(&extra, b"sent alongside").expect("write input file");
+    let video_path = dir.join("video.mkv");
+    let out_dir = dir.join("received");
+
+    let mut inject_options = get_unit_test_injection_option(RT_SIZE, RT_WIDTH, RT_HEIGHT);
+    inject_options.file_path = tree.to_string_lossy().to_string();
+    inject_options.extra_file_paths = vec![extra.to_string_lossy().to_string()];
+    inject_options.output_video_file = video_path.to_string_lossy().to_string();
+    execute_with_video_options(VideoOptions::InjectInVideo(inject_options))
+        .expect("injection should succeed");
+
+    let extract_options = ExtractOptions {
+        video_file_path: video_path.to_string_lossy().to_string(),
+        extracted_file_path: out_dir.to_string_lossy().to_string(),
+        ..get_unit_test_extraction_option(RT_SIZE, RT_WIDTH, RT_HEIGHT)
+    };
+    execute_with_video_options(VideoOptions::ExtractFromVideo(extract_options))
+        .expect("extraction should succeed");
+
+    let read = |path: &str| fs::read(out_dir.join(path)).expect("read a rebuilt file");
+    let (big_out, readme, extra_out) = (
+        read("project/src/big.bin"),
+        read("project/README"),
+        read("extra.txt"),
+    );
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(big_out, big);
+    assert_eq!(readme, b"read me");
+    assert_eq!(extra_out, b"sent alongside");
+}
+
+#[test]
+fn test_compressed_file_round_trips_through_a_shorter_video() {
+    let data: Vec<u8> = (0..400u32)
+        .flat_map(|i| format!("{},sensor-{},{}\n", 1_700_000_000 + i, i % 5, i % 40).into_bytes())
+        .collect();
+    let dir: PathBuf = std::env::temp_dir().join(format!("hdmift_compress_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("create temp dir");
+    let input_path = dir.join("readings.csv");
+    let video_path = dir.join("video.mkv");
+    let output_path = dir.join("output.csv");
+    fs::write(&input_path, &data).expect("write input file");
+
+    let mut inject_options = get_unit_test_injection_option(RT_SIZE, RT_WIDTH, RT_HEIGHT);
+    inject_options.file_path = input_path.to_string_lossy().to_string();
+    inject_options.output_video_file = video_path.to_string_lossy().to_string();
+    let raw_pages = data_to_frames(&inject_options, data.clone()).unwrap().len();
+    inject_options.compression = Compression::Zstd;
+    execute_with_video_options(VideoOptions::InjectInVideo(inject_options.clone()))
+        .expect("injection should succeed");
+    let compressed_pages = data_to_frames(&inject_options, data.clone()).unwrap().len();
+    assert!(compressed_pages * 3 < raw_pages);
+
+    let mut video =
+        VideoCapture::from_file(&video_path.to_string_lossy(), CAP_ANY).expect("open the video");
+    let mut video_frames = 0;
+    let mut frame = Mat::default();
+    while video.read(&mut frame).expect("read frame") && frame.cols() > 0 {
+        video_frames += 1;
+    }
+    assert_eq!(video_frames, 2 + compressed_pages);
+
+    // The codec comes from the Start frame: no extraction flag.
+    let extract_options = ExtractOptions {
+        video_file_path: video_path.to_string_lossy().to_string(),
+        extracted_file_path: output_path.to_string_lossy().to_string(),
+        ..get_unit_test_extraction_option(RT_SIZE, RT_WIDTH, RT_HEIGHT)
+    };
+    execute_with_video_options(VideoOptions::ExtractFromVideo(extract_options))
+        .expect("extraction should succeed");
+    let extracted = fs::read(&output_path).expect("read output file");
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(extracted, data);
 }
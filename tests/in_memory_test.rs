@@ -0,0 +1,177 @@
+//! Encoding and decoding frames held in memory. None of this needs OpenCV, so
+//! these tests also run with `--no-default-features`.
+
+use hdmifiletransporter::{
+    create_starting_frame, data_to_frames, frames_to_data, frames_to_raw, options::AlgoFrame,
+    ExtractOptions, FrameBuffer, InjectOptions, PixelFormat, PushOutcome, RawFrameReader,
+    RawVideoFormat, Reassembler, VideoFrame,
+};
+
+fn inject_options(algo: AlgoFrame) -> InjectOptions {
+    InjectOptions {
+        width: 128,
+        height: 96,
+        size: 2,
+        algo,
+        fec: 8,
+        ..Default::default()
+    }
+}
+
+fn extract_options() -> ExtractOptions {
+    // Geometry and algo are read from the Start frame.
+    ExtractOptions::default()
+}
+
+#[test]
+fn test_frames_round_trip_through_raw_rgb_buffers() {
+    let data: Vec<u8> = (0..2000u32).map(|i| (i * 31 % 256) as u8).collect();
+    for algo in [
+        AlgoFrame::BW,
+        AlgoFrame::RGB,
+        AlgoFrame::Quantized(4),
+        AlgoFrame::Brightness(4),
+    ] {
+        let options = inject_options(algo);
+        let mut frames = vec![create_starting_frame(&data, &options).unwrap()];
+        frames.extend(data_to_frames(&options, data.clone()).unwrap());
+
+        // What an image sequence or a raw video pipe would carry: plain RGB bytes.
+        let received: Vec<VideoFrame> = frames
+            .into_iter()
+            .rev()
+            .map(|frame| {
+                let rgb = frame.buffer.into_rgb();
+                assert_eq!(rgb.len(), 128 * 96 * 3);
+                VideoFrame::from_rgb(128, 96, rgb).unwrap()
+            })
+            .collect();
+
+        assert_eq!(
+            frames_to_data(&extract_options(), received).unwrap(),
+            data,
+            "{algo}"
+        );
+    }
+}
+
+#[test]
+fn test_start_frame_pixels_are_readable_from_the_buffer() {
+    let options = inject_options(AlgoFrame::BW);
+    let frame = create_starting_frame(&[0; 10], &options).unwrap();
+    let buffer: &FrameBuffer = &frame.buffer;
+    assert_eq!((buffer.width(), buffer.height()), (128, 96));
+    // White quiet zone in the corner, red fill in the unused content cells
+    // (the last content pixel sits just inside the 9-cell border).
+    assert_eq!(buffer.pixel(0, 0), [255, 255, 255]);
+    assert_eq!(buffer.pixel(128 - 19, 96 - 19), [255, 0, 0]);
+}
+
+#[test]
+fn test_frames_round_trip_through_a_raw_pipe() {
+    let data: Vec<u8> = (0..1500u32).map(|i| (i * 7 % 256) as u8).collect();
+    let options = inject_options(AlgoFrame::Quantized(4));
+    let mut frames = vec![create_starting_frame(&data, &options).unwrap()];
+    frames.extend(data_to_frames(&options, data.clone()).unwrap());
+    let frame_count = frames.len();
+
+    // What `inject -o -` writes and `ffmpeg -f rawvideo -pix_fmt bgr24` reads.
+    let mut stream = Vec::new();
+    frames_to_raw(&options, frames, &mut stream, PixelFormat::Bgr24).unwrap();
+    assert_eq!(stream.len(), frame_count * 128 * 96 * 3);
+
+    let mut reader = RawFrameReader::new(
+        stream.as_slice(),
+        RawVideoFormat {
+            pixel_format: PixelFormat::Bgr24,
+            width: 128,
+            height: 96,
+        },
+    );
+    let received: Vec<VideoFrame> = std::iter::from_fn(|| reader.next_frame())
+        .map(|buffer| VideoFrame { buffer })
+        .collect();
+    assert_eq!(received.len(), frame_count);
+    assert_eq!(frames_to_data(&extract_options(), received).unwrap(), data);
+}
+
+/// Bend `frame` the way a lens or a scaler that is not linear across the
+/// picture would: the middle drifts down and right by `amplitude` pixels while
+/// the edges of the frame (and so the corner markers) stay in place.
+fn bend(frame: &VideoFrame, amplitude: f32) -> VideoFrame {
+    let (width, height) = (frame.buffer.width(), frame.buffer.height());
+    let mut bent = VideoFrame::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let u = f32::from(x) / f32::from(width) * std::f32::consts::PI;
+            let v = f32::from(y) / f32::from(height) * std::f32::consts::PI;
+            let dx = amplitude * u.sin() * v.sin();
+            let dy = amplitude * u.sin() * v.sin();
+            let sx = (f32::from(x) - dx).round().clamp(0.0, f32::from(width - 1)) as u16;
+            let sy = (f32::from(y) - dy)
+                .round()
+                .clamp(0.0, f32::from(height - 1)) as u16;
+            bent.buffer.set_pixel(x, y, frame.buffer.pixel(sx, sy));
+        }
+    }
+    bent
+}
+
+#[test]
+fn test_lattice_corrects_a_bent_grid() {
+    let data: Vec<u8> = (0..3000u32).map(|i| (i * 13 % 256) as u8).collect();
+    let decode = |lattice: bool| {
+        let options = InjectOptions {
+            width: 512,
+            height: 384,
+            size: 4,
+            lattice,
+            ..inject_options(AlgoFrame::BW)
+        };
+        let mut frames = vec![create_starting_frame(&data, &options).unwrap()];
+        frames.extend(data_to_frames(&options, data.clone()).unwrap());
+        // Three quarters of a cell in the middle of the frame, too much to read
+        // blindly (the Start frame rows near the top still read).
+        let received = frames.iter().map(|frame| bend(frame, 3.0)).collect();
+        frames_to_data(&extract_options(), received)
+    };
+
+    assert_eq!(decode(true).unwrap(), data);
+    assert!(decode(false).is_err());
+}
+
+#[test]
+fn test_pages_no_single_copy_got_right_are_combined() {
+    let data: Vec<u8> = (0..1500u32).map(|i| (i * 17 % 256) as u8).collect();
+    let options = InjectOptions {
+        fec: 0,
+        ..inject_options(AlgoFrame::BW)
+    };
+    let start = create_starting_frame(&data, &options).unwrap();
+    let pages = data_to_frames(&options, data.clone()).unwrap();
+
+    // Five loops of the video, each capture of a page with a few cells of its
+    // lower half flipped: every copy fails its CRC.
+    let mut state = 7u32;
+    let mut random = |below: u16| {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        (state >> 16) as u16 % below
+    };
+    let mut received = vec![start];
+    for _ in 0..5 {
+        for page in &pages {
+            let mut copy = page.clone();
+            for _ in 0..4 {
+                let (x, y) = (18 + random(46) * 2, 48 + random(15) * 2);
+                let flipped = copy.buffer.pixel(x, y).map(|v| 255 - v);
+                copy.buffer.fill_rect(x, y, 2, 2, flipped);
+            }
+            let mut alone = Reassembler::new(&extract_options());
+            alone.push(&received[0]);
+            assert_eq!(alone.push(&copy), PushOutcome::CrcFail);
+            received.push(copy);
+        }
+    }
+
+    assert_eq!(frames_to_data(&extract_options(), received).unwrap(), data);
+}